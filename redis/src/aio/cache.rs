@@ -0,0 +1,657 @@
+use crate::cmd::Cmd;
+use crate::types::{HashMap, Value};
+use std::collections::VecDeque;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Commands whose replies can be served from the client-side cache.
+///
+/// Every command in this list is read-only and takes a single key as its first argument,
+/// which is what the server tracks and later invalidates.
+fn is_cacheable_cmd(cmd: &[u8]) -> bool {
+    matches!(
+        cmd,
+        b"GET"
+            | b"GETRANGE"
+            | b"STRLEN"
+            | b"HGET"
+            | b"HGETALL"
+            | b"HMGET"
+            | b"HKEYS"
+            | b"HVALS"
+            | b"HLEN"
+            | b"HEXISTS"
+            | b"LINDEX"
+            | b"LLEN"
+            | b"LRANGE"
+            | b"SCARD"
+            | b"SISMEMBER"
+            | b"SMEMBERS"
+            | b"ZCARD"
+            | b"ZSCORE"
+            | b"ZRANGE"
+    )
+}
+
+/// Commands after which the connection sees a different keyspace, so that the cached replies
+/// no longer apply.
+fn changes_keyspace(cmd: &[u8]) -> bool {
+    matches!(cmd, b"SELECT" | b"SWAPDB" | b"RESET")
+}
+
+/// Configuration for server-assisted client-side caching.
+///
+/// When a connection is created with a cache, it sends `CLIENT TRACKING ON` during setup,
+/// and serves repeated reads of tracked keys from local memory until the server invalidates
+/// them. Client-side caching requires RESP3, since invalidations arrive as push messages.
+///
+/// Every connection has its own cache, which is dropped together with the connection, and is
+/// flushed when the connection switches to another database.
+///
+/// ```rust,no_run
+/// # async fn do_something() -> redis::RedisResult<()> {
+/// use redis::aio::CacheConfig;
+/// use std::time::Duration;
+///
+/// let client = redis::Client::open("redis://127.0.0.1/?protocol=resp3")?;
+/// let config = redis::AsyncConnectionConfig::new().with_cache(
+///     CacheConfig::new()
+///         .with_max_entries(1_000)
+///         .with_ttl(Duration::from_secs(60)),
+/// );
+/// let mut con = client.get_multiplexed_async_connection_with_config(&config).await?;
+/// # Ok(()) }
+/// ```
+#[derive(Clone, Debug)]
+pub struct CacheConfig {
+    max_entries: usize,
+    ttl: Option<Duration>,
+    // Set by connection managers, so that their statistics cover all of their connections.
+    pub(crate) counters: Option<Arc<CacheCounters>>,
+}
+
+impl CacheConfig {
+    const DEFAULT_MAX_ENTRIES: usize = 10_000;
+
+    /// Creates a new cache configuration, holding up to 10,000 entries with no TTL.
+    pub fn new() -> Self {
+        Self {
+            max_entries: Self::DEFAULT_MAX_ENTRIES,
+            ttl: None,
+            counters: None,
+        }
+    }
+
+    /// Sets the maximal number of replies held by the cache. Once the cache is full, the oldest
+    /// entries are evicted to make room for new ones.
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries;
+        self
+    }
+
+    /// Sets the maximal time a reply is served from the cache, even if the server didn't
+    /// invalidate it.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Hit/miss counters of a client-side cache.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStatistics {
+    /// Number of cacheable requests that were answered from the cache.
+    pub hits: u64,
+    /// Number of cacheable requests that were sent to the server.
+    pub misses: u64,
+    /// Number of entries removed due to server invalidations, reconnects or expired TTLs.
+    pub invalidations: u64,
+    /// Number of entries removed in order to keep the cache within its size limit.
+    pub evictions: u64,
+}
+
+/// The hit/miss counters of caches, which may be shared by the caches of several connections.
+#[derive(Debug, Default)]
+pub(crate) struct CacheCounters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    invalidations: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl CacheCounters {
+    pub(crate) fn statistics(&self) -> CacheStatistics {
+        CacheStatistics {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            invalidations: self.invalidations.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A request that affects the cache once its reply arrives.
+pub(crate) enum CacheableRequest {
+    /// A single-key read, whose reply should be inserted into the cache.
+    Read {
+        key: Vec<u8>,
+        packed_command: Vec<u8>,
+    },
+    /// A request after which the connection sees a different keyspace, so that the cache
+    /// should be flushed.
+    ChangesKeyspace,
+}
+
+impl CacheableRequest {
+    /// Returns a cacheable request if `cmd` is a single-key read that the cache can serve, or
+    /// changes the keyspace of the connection.
+    pub(crate) fn for_cmd(cmd: &Cmd) -> Option<Self> {
+        if cmd.is_no_response() || cmd.in_scan_mode() {
+            return None;
+        }
+        let name = cmd.arg_idx(0)?.to_ascii_uppercase();
+        if changes_keyspace(&name) {
+            return Some(CacheableRequest::ChangesKeyspace);
+        }
+        if !is_cacheable_cmd(&name) {
+            return None;
+        }
+        let key = cmd.arg_idx(1)?.to_vec();
+        Some(CacheableRequest::Read {
+            key,
+            packed_command: cmd.get_packed_command(),
+        })
+    }
+
+    pub(crate) fn packed_command(&self) -> Option<&[u8]> {
+        match self {
+            CacheableRequest::Read { packed_command, .. } => Some(packed_command),
+            CacheableRequest::ChangesKeyspace => None,
+        }
+    }
+}
+
+struct CacheEntry {
+    value: Value,
+    key: Vec<u8>,
+    inserted_at: Instant,
+    id: u64,
+}
+
+#[derive(Default)]
+struct CacheStore {
+    // Replies, keyed by the packed command that produced them.
+    entries: HashMap<Vec<u8>, CacheEntry>,
+    // Maps each redis key to the packed commands whose replies depend on it.
+    commands_by_key: HashMap<Vec<u8>, Vec<Vec<u8>>>,
+    // Insertion order, used for eviction. May contain entries that were already removed, which
+    // are recognized by a mismatching id.
+    insertion_order: VecDeque<(Vec<u8>, u64)>,
+    next_id: u64,
+}
+
+impl CacheStore {
+    fn remove(&mut self, packed_command: &[u8]) -> bool {
+        let entry = match self.entries.remove(packed_command) {
+            Some(entry) => entry,
+            None => return false,
+        };
+        if let Some(commands) = self.commands_by_key.get_mut(&entry.key) {
+            commands.retain(|command| command != packed_command);
+            if commands.is_empty() {
+                self.commands_by_key.remove(&entry.key);
+            }
+        }
+        true
+    }
+
+    fn remove_key(&mut self, key: &[u8]) -> u64 {
+        let commands = match self.commands_by_key.remove(key) {
+            Some(commands) => commands,
+            None => return 0,
+        };
+        let mut removed = 0;
+        for command in commands {
+            if self.entries.remove(&command).is_some() {
+                removed += 1;
+            }
+        }
+        removed
+    }
+
+    fn evict_oldest(&mut self) -> bool {
+        while let Some((packed_command, id)) = self.insertion_order.pop_front() {
+            let is_live = self
+                .entries
+                .get(&packed_command)
+                .map_or(false, |entry| entry.id == id);
+            if is_live {
+                return self.remove(&packed_command);
+            }
+        }
+        false
+    }
+
+    fn clear(&mut self) -> u64 {
+        let removed = self.entries.len() as u64;
+        self.entries.clear();
+        self.commands_by_key.clear();
+        self.insertion_order.clear();
+        removed
+    }
+}
+
+// The transaction that the connection is in, if any.
+#[derive(Default)]
+struct Transaction {
+    changes_keyspace: bool,
+}
+
+/// The local cache of the replies that a single connection received, kept coherent by the
+/// server's invalidation messages to that connection.
+pub(crate) struct ClientSideCache {
+    store: Mutex<CacheStore>,
+    max_entries: usize,
+    ttl: Option<Duration>,
+    // Commands are queued rather than executed inside transactions, so their replies can
+    // neither be served from the cache nor inserted into it.
+    transaction: Mutex<Option<Transaction>>,
+    counters: Arc<CacheCounters>,
+}
+
+impl ClientSideCache {
+    pub(crate) fn new(config: &CacheConfig) -> Self {
+        Self {
+            store: Mutex::new(CacheStore::default()),
+            max_entries: config.max_entries,
+            ttl: config.ttl,
+            transaction: Mutex::new(None),
+            counters: config.counters.clone().unwrap_or_default(),
+        }
+    }
+
+    /// Returns how the reply of `cmd` affects the cache, given the commands that were sent
+    /// before it.
+    pub(crate) fn request_for(&self, cmd: &Cmd) -> Option<CacheableRequest> {
+        let request = CacheableRequest::for_cmd(cmd);
+        let mut transaction = self.transaction.lock().unwrap();
+        match cmd
+            .arg_idx(0)
+            .map(|name| name.to_ascii_uppercase())
+            .as_deref()
+        {
+            Some(b"MULTI") => {
+                *transaction = Some(Transaction::default());
+                None
+            }
+            Some(b"EXEC") => match transaction.take() {
+                Some(Transaction {
+                    changes_keyspace: true,
+                }) => Some(CacheableRequest::ChangesKeyspace),
+                _ => None,
+            },
+            Some(b"DISCARD") => {
+                *transaction = None;
+                None
+            }
+            Some(b"RESET") => {
+                *transaction = None;
+                request
+            }
+            _ => match (transaction.as_mut(), request) {
+                (Some(transaction), Some(CacheableRequest::ChangesKeyspace)) => {
+                    transaction.changes_keyspace = true;
+                    None
+                }
+                (Some(_), _) => None,
+                (None, request) => request,
+            },
+        }
+    }
+
+    /// Returns how the replies of the commands in `pipeline` affect the cache. Pipelines aren't
+    /// served from the cache, but the cache is flushed if they change the keyspace.
+    pub(crate) fn request_for_pipeline(
+        &self,
+        pipeline: &crate::Pipeline,
+    ) -> Option<CacheableRequest> {
+        let mut changes_keyspace = false;
+        for cmd in pipeline.cmd_iter() {
+            if let Some(CacheableRequest::ChangesKeyspace) = self.request_for(cmd) {
+                changes_keyspace = true;
+            }
+        }
+        changes_keyspace.then_some(CacheableRequest::ChangesKeyspace)
+    }
+
+    /// Returns the cached reply of `request`, if there's a fresh one.
+    pub(crate) fn get(&self, request: &CacheableRequest) -> Option<Value> {
+        let packed_command = request.packed_command()?;
+        let mut store = self.store.lock().unwrap();
+        let value = match store.entries.get(packed_command) {
+            Some(entry) if self.is_expired(entry) => {
+                store.remove(packed_command);
+                self.counters.invalidations.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+            Some(entry) => Some(entry.value.clone()),
+            None => None,
+        };
+        drop(store);
+
+        match value {
+            Some(value) => {
+                self.counters.hits.fetch_add(1, Ordering::Relaxed);
+                Some(value)
+            }
+            None => {
+                self.counters.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// Applies the reply of `request`: stores the reply of a read, or flushes the cache if the
+    /// request changed the keyspace.
+    ///
+    /// This must be called in the order replies are read from the connection, so that an
+    /// invalidation that follows the reply can't be applied before the reply is inserted.
+    pub(crate) fn apply_reply(&self, request: CacheableRequest, value: &Value) {
+        let (key, packed_command) = match request {
+            CacheableRequest::Read {
+                key,
+                packed_command,
+            } => (key, packed_command),
+            CacheableRequest::ChangesKeyspace => return self.flush(),
+        };
+        // None of the cacheable commands reply with a status, so such a reply, like the
+        // `QUEUED` of a command inside a transaction, isn't the value of the key.
+        if self.max_entries == 0 || matches!(value, Value::SimpleString(_) | Value::Okay) {
+            return;
+        }
+        let mut store = self.store.lock().unwrap();
+        store.remove(&packed_command);
+        while store.entries.len() >= self.max_entries && store.evict_oldest() {
+            self.counters.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let id = store.next_id;
+        store.next_id += 1;
+        store
+            .commands_by_key
+            .entry(key.clone())
+            .or_default()
+            .push(packed_command.clone());
+        store
+            .insertion_order
+            .push_back((packed_command.clone(), id));
+        store.entries.insert(
+            packed_command,
+            CacheEntry {
+                value: value.clone(),
+                key,
+                inserted_at: Instant::now(),
+                id,
+            },
+        );
+
+        // Stale positions are left behind by invalidations, so occasionally drop them.
+        if store.insertion_order.len() > self.max_entries.saturating_mul(2) {
+            let CacheStore {
+                entries,
+                insertion_order,
+                ..
+            } = &mut *store;
+            insertion_order.retain(|(packed_command, id)| {
+                entries
+                    .get(packed_command)
+                    .map_or(false, |entry| entry.id == *id)
+            });
+        }
+    }
+
+    /// Applies the data of an `invalidate` push message. A nil payload means that the server
+    /// flushed its keyspace, so every entry is dropped.
+    pub(crate) fn invalidate(&self, data: &[Value]) {
+        let removed = match data.first() {
+            Some(Value::Array(keys)) | Some(Value::Set(keys)) => {
+                let mut store = self.store.lock().unwrap();
+                keys.iter()
+                    .map(|key| match key {
+                        Value::BulkString(key) => store.remove_key(key),
+                        Value::SimpleString(key) => store.remove_key(key.as_bytes()),
+                        _ => 0,
+                    })
+                    .sum()
+            }
+            _ => self.store.lock().unwrap().clear(),
+        };
+        self.counters
+            .invalidations
+            .fetch_add(removed, Ordering::Relaxed);
+    }
+
+    /// Drops all entries.
+    pub(crate) fn flush(&self) {
+        let removed = self.store.lock().unwrap().clear();
+        self.counters
+            .invalidations
+            .fetch_add(removed, Ordering::Relaxed);
+    }
+
+    pub(crate) fn statistics(&self) -> CacheStatistics {
+        self.counters.statistics()
+    }
+
+    fn is_expired(&self, entry: &CacheEntry) -> bool {
+        self.ttl
+            .map_or(false, |ttl| entry.inserted_at.elapsed() >= ttl)
+    }
+}
+
+/// The cache of a connection, as held by the connection's driver. The cache is flushed once the
+/// driver stops for any reason, since invalidations can't be received anymore.
+pub(crate) struct DriverCache(Arc<ClientSideCache>);
+
+impl DriverCache {
+    pub(crate) fn new(cache: Arc<ClientSideCache>) -> Self {
+        Self(cache)
+    }
+}
+
+impl Deref for DriverCache {
+    type Target = ClientSideCache;
+
+    fn deref(&self) -> &ClientSideCache {
+        &self.0
+    }
+}
+
+impl Drop for DriverCache {
+    fn drop(&mut self) {
+        self.0.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd;
+
+    fn get(key: &str) -> CacheableRequest {
+        CacheableRequest::for_cmd(cmd("GET").arg(key)).unwrap()
+    }
+
+    fn invalidation(keys: &[&str]) -> Vec<Value> {
+        vec![Value::Array(
+            keys.iter()
+                .map(|key| Value::BulkString(key.as_bytes().to_vec()))
+                .collect(),
+        )]
+    }
+
+    #[test]
+    fn test_only_single_key_reads_are_cacheable() {
+        assert!(CacheableRequest::for_cmd(cmd("GET").arg("foo")).is_some());
+        assert!(CacheableRequest::for_cmd(cmd("hgetall").arg("foo")).is_some());
+        assert!(CacheableRequest::for_cmd(cmd("SET").arg("foo").arg("bar")).is_none());
+        assert!(CacheableRequest::for_cmd(&cmd("GET")).is_none());
+        assert!(CacheableRequest::for_cmd(cmd("MGET").arg("foo").arg("bar")).is_none());
+    }
+
+    #[test]
+    fn test_hit_after_insert_and_miss_after_invalidation() {
+        let cache = ClientSideCache::new(&CacheConfig::new());
+        assert_eq!(cache.get(&get("foo")), None);
+
+        cache.apply_reply(get("foo"), &Value::BulkString(b"bar".to_vec()));
+        assert_eq!(
+            cache.get(&get("foo")),
+            Some(Value::BulkString(b"bar".to_vec()))
+        );
+
+        cache.invalidate(&invalidation(&["foo"]));
+        assert_eq!(cache.get(&get("foo")), None);
+
+        assert_eq!(
+            cache.statistics(),
+            CacheStatistics {
+                hits: 1,
+                misses: 2,
+                invalidations: 1,
+                evictions: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_invalidation_removes_all_commands_of_key() {
+        let cache = ClientSideCache::new(&CacheConfig::new());
+        let hget = || CacheableRequest::for_cmd(cmd("HGET").arg("hash").arg("field")).unwrap();
+        let hgetall = || CacheableRequest::for_cmd(cmd("HGETALL").arg("hash")).unwrap();
+        cache.apply_reply(hget(), &Value::Int(1));
+        cache.apply_reply(hgetall(), &Value::Map(vec![]));
+        cache.apply_reply(get("other"), &Value::Int(2));
+
+        cache.invalidate(&invalidation(&["hash"]));
+
+        assert_eq!(cache.get(&hget()), None);
+        assert_eq!(cache.get(&hgetall()), None);
+        assert_eq!(cache.get(&get("other")), Some(Value::Int(2)));
+    }
+
+    #[test]
+    fn test_nil_invalidation_flushes_everything() {
+        let cache = ClientSideCache::new(&CacheConfig::new());
+        cache.apply_reply(get("foo"), &Value::Int(1));
+        cache.apply_reply(get("bar"), &Value::Int(2));
+
+        cache.invalidate(&[Value::Nil]);
+
+        assert_eq!(cache.get(&get("foo")), None);
+        assert_eq!(cache.get(&get("bar")), None);
+        assert_eq!(cache.statistics().invalidations, 2);
+    }
+
+    #[test]
+    fn test_oldest_entries_are_evicted_when_full() {
+        let cache = ClientSideCache::new(&CacheConfig::new().with_max_entries(2));
+        cache.apply_reply(get("a"), &Value::Int(1));
+        cache.apply_reply(get("b"), &Value::Int(2));
+        cache.invalidate(&invalidation(&["a"]));
+        cache.apply_reply(get("c"), &Value::Int(3));
+        cache.apply_reply(get("d"), &Value::Int(4));
+
+        assert_eq!(cache.get(&get("b")), None);
+        assert_eq!(cache.get(&get("c")), Some(Value::Int(3)));
+        assert_eq!(cache.get(&get("d")), Some(Value::Int(4)));
+        assert_eq!(cache.statistics().evictions, 1);
+    }
+
+    #[test]
+    fn test_expired_entries_are_not_served() {
+        let cache = ClientSideCache::new(&CacheConfig::new().with_ttl(Duration::from_millis(1)));
+        cache.apply_reply(get("foo"), &Value::Int(1));
+        std::thread::sleep(Duration::from_millis(5));
+
+        assert_eq!(cache.get(&get("foo")), None);
+        assert_eq!(cache.statistics().invalidations, 1);
+    }
+
+    #[test]
+    fn test_replies_inside_transactions_are_not_cached() {
+        let cache = ClientSideCache::new(&CacheConfig::new());
+        assert!(cache.request_for(&cmd("MULTI")).is_none());
+        assert!(cache.request_for(cmd("GET").arg("foo")).is_none());
+        assert!(cache.request_for(&cmd("EXEC")).is_none());
+        assert!(cache.request_for(cmd("GET").arg("foo")).is_some());
+
+        // A status can't be the value of a key.
+        cache.apply_reply(get("foo"), &Value::SimpleString("QUEUED".to_string()));
+        assert_eq!(cache.get(&get("foo")), None);
+    }
+
+    #[test]
+    fn test_switching_databases_flushes_the_cache() {
+        let cache = ClientSideCache::new(&CacheConfig::new());
+        cache.apply_reply(get("foo"), &Value::Int(1));
+        let select = cache.request_for(cmd("SELECT").arg(2)).unwrap();
+        assert_eq!(cache.get(&select), None);
+        assert_eq!(cache.get(&get("foo")), Some(Value::Int(1)));
+        cache.apply_reply(select, &Value::Okay);
+        assert_eq!(cache.get(&get("foo")), None);
+
+        cache.apply_reply(get("foo"), &Value::Int(2));
+        assert!(cache.request_for(&cmd("MULTI")).is_none());
+        assert!(cache.request_for(cmd("SELECT").arg(0)).is_none());
+        let exec = cache.request_for(&cmd("EXEC")).unwrap();
+        cache.apply_reply(exec, &Value::Array(vec![Value::Okay]));
+        assert_eq!(cache.get(&get("foo")), None);
+
+        cache.apply_reply(get("foo"), &Value::Int(3));
+        let pipeline = crate::pipe().get("foo").cmd("SELECT").arg(1).clone();
+        let pipeline_request = cache.request_for_pipeline(&pipeline).unwrap();
+        cache.apply_reply(pipeline_request, &Value::Okay);
+        assert_eq!(cache.get(&get("foo")), None);
+    }
+
+    #[test]
+    fn test_statistics_can_be_shared() {
+        let mut config = CacheConfig::new();
+        config.counters = Some(Arc::default());
+        let first = ClientSideCache::new(&config);
+        first.apply_reply(get("foo"), &Value::Int(1));
+        assert!(first.get(&get("foo")).is_some());
+        let second = ClientSideCache::new(&config);
+        assert!(second.get(&get("foo")).is_none());
+        assert_eq!(
+            (second.statistics().hits, second.statistics().misses),
+            (1, 1)
+        );
+    }
+
+    #[test]
+    fn test_cache_is_flushed_when_driver_stops() {
+        let cache = Arc::new(ClientSideCache::new(&CacheConfig::new()));
+        let driver_cache = DriverCache::new(cache.clone());
+        driver_cache.apply_reply(get("foo"), &Value::Int(1));
+        drop(driver_cache);
+        assert_eq!(cache.get(&get("foo")), None);
+    }
+
+    #[test]
+    fn test_flush() {
+        let cache = ClientSideCache::new(&CacheConfig::new());
+        cache.apply_reply(get("foo"), &Value::Int(1));
+        cache.flush();
+        assert_eq!(cache.get(&get("foo")), None);
+    }
+}
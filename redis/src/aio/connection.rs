@@ -5,6 +5,8 @@ use super::async_std;
 use super::ConnectionLike;
 use super::{setup_connection, AsyncStream, RedisRuntime};
 use crate::cmd::{cmd, Cmd};
+#[cfg(any(feature = "tls-native-tls", feature = "tls-rustls"))]
+use crate::connection::tls_server_name;
 use crate::connection::{
     resp2_is_pub_sub_state_cleared, resp3_is_pub_sub_state_cleared, ConnectionAddr, ConnectionInfo,
     Msg, RedisConnectionInfo,
@@ -457,11 +459,14 @@ pub(crate) async fn connect_simple<T: RedisRuntime>(
             ref tls_params,
         } => {
             let socket_addrs = get_socket_addrs(host, port).await?;
-            select_ok(
-                socket_addrs.map(|socket_addr| {
-                    <T>::connect_tcp_tls(host, socket_addr, insecure, tls_params)
-                }),
-            )
+            select_ok(socket_addrs.map(|socket_addr| {
+                <T>::connect_tcp_tls(
+                    tls_server_name(host, tls_params),
+                    socket_addr,
+                    insecure,
+                    tls_params,
+                )
+            }))
             .await?
             .0
         }
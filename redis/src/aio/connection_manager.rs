@@ -1,23 +1,113 @@
-use super::RedisFuture;
+use super::{CacheConfig, CacheStatistics, RedisFuture, SubscriptionKind};
 use crate::cmd::Cmd;
+use crate::instrumentation::{self, Instrumentation};
 use crate::push_manager::PushManager;
-use crate::types::{RedisError, RedisResult, Value};
+#[cfg(feature = "sentinel")]
+use crate::sentinel::SentinelClient;
+use crate::types::{ErrorKind, HashSet, ProtocolVersion, RedisError, RedisResult, Value};
 use crate::{
     aio::{ConnectionLike, MultiplexedConnection, Runtime},
-    Client,
+    cmd, AsyncConnectionConfig, Client, RedisConnectionInfo, ToRedisArgs,
 };
 #[cfg(all(not(feature = "tokio-comp"), feature = "async-std-comp"))]
 use ::async_std::net::ToSocketAddrs;
 use arc_swap::ArcSwap;
 use futures::{
     future::{self, Shared},
-    FutureExt,
+    Future, FutureExt,
 };
 use futures_util::future::BoxFuture;
-use std::sync::Arc;
+#[cfg(feature = "sentinel")]
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use tokio_retry::strategy::{jitter, ExponentialBackoff};
 use tokio_retry::Retry;
 
+/// Options for creation of a [`ConnectionManager`].
+#[derive(Clone, Debug)]
+pub struct ConnectionManagerConfig {
+    exponent_base: u64,
+    factor: u64,
+    number_of_retries: usize,
+    response_timeout: std::time::Duration,
+    connection_timeout: std::time::Duration,
+    cache: Option<CacheConfig>,
+    instrumentation: Option<Arc<dyn Instrumentation>>,
+}
+
+impl ConnectionManagerConfig {
+    const DEFAULT_CONNECTION_RETRY_EXPONENT_BASE: u64 = 2;
+    const DEFAULT_CONNECTION_RETRY_FACTOR: u64 = 100;
+    const DEFAULT_NUMBER_OF_CONNECTION_RETRIESE: usize = 6;
+
+    /// Creates a new instance of the options with the default reconnection backoff and no timeouts
+    pub fn new() -> Self {
+        Self {
+            exponent_base: Self::DEFAULT_CONNECTION_RETRY_EXPONENT_BASE,
+            factor: Self::DEFAULT_CONNECTION_RETRY_FACTOR,
+            number_of_retries: Self::DEFAULT_NUMBER_OF_CONNECTION_RETRIESE,
+            response_timeout: std::time::Duration::MAX,
+            connection_timeout: std::time::Duration::MAX,
+            cache: None,
+            instrumentation: None,
+        }
+    }
+
+    /// Sets the exponent base of the reconnection backoff
+    pub fn with_exponent_base(mut self, exponent_base: u64) -> Self {
+        self.exponent_base = exponent_base;
+        self
+    }
+
+    /// Sets the factor of the reconnection backoff
+    pub fn with_factor(mut self, factor: u64) -> Self {
+        self.factor = factor;
+        self
+    }
+
+    /// Sets the number of reconnection attempts
+    pub fn with_number_of_retries(mut self, number_of_retries: usize) -> Self {
+        self.number_of_retries = number_of_retries;
+        self
+    }
+
+    /// Sets the response timeout
+    pub fn with_response_timeout(mut self, response_timeout: std::time::Duration) -> Self {
+        self.response_timeout = response_timeout;
+        self
+    }
+
+    /// Sets the connection timeout
+    pub fn with_connection_timeout(mut self, connection_timeout: std::time::Duration) -> Self {
+        self.connection_timeout = connection_timeout;
+        self
+    }
+
+    /// Enables server-assisted client-side caching, see [`CacheConfig`].
+    ///
+    /// Every connection of the manager has its own cache, so the cache is empty whenever the
+    /// manager reconnects, while the statistics cover all of the manager's connections.
+    /// This requires RESP3.
+    pub fn with_cache(mut self, cache_config: CacheConfig) -> Self {
+        self.cache = Some(cache_config);
+        self
+    }
+
+    /// Registers an [`Instrumentation`] that is notified of every request that is sent through the
+    /// manager, and of its reconnections. It replaces the instrumentation of the client that the
+    /// manager connects with.
+    pub fn with_instrumentation(mut self, instrumentation: Arc<dyn Instrumentation>) -> Self {
+        self.instrumentation = Some(instrumentation);
+        self
+    }
+}
+
+impl Default for ConnectionManagerConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// A `ConnectionManager` is a proxy that wraps a [multiplexed
 /// connection][multiplexed-connection] and automatically reconnects to the
 /// server when necessary.
@@ -41,12 +131,18 @@ use tokio_retry::Retry;
 ///   initiated, will have to await the connection future.
 /// - If reconnecting fails, all pending commands will be failed as well. A
 ///   new reconnection attempt will be triggered if the error is an I/O error.
+/// - Subscriptions made through [`subscribe`](ConnectionManager::subscribe),
+///   [`psubscribe`](ConnectionManager::psubscribe) and
+///   [`ssubscribe`](ConnectionManager::ssubscribe) are re-issued on the new
+///   connection. Since messages published while disconnected are lost, a
+///   [`PushKind::Disconnection`](crate::PushKind::Disconnection) message is
+///   sent through the [`PushManager`] before the new subscription confirmations.
 ///
 /// [multiplexed-connection]: struct.MultiplexedConnection.html
 #[derive(Clone)]
 pub struct ConnectionManager {
     /// Information used for the connection. This is needed to be able to reconnect.
-    client: Client,
+    source: ConnectionSource,
     /// The connection future.
     ///
     /// The `ArcSwap` is required to be able to replace the connection
@@ -59,6 +155,118 @@ pub struct ConnectionManager {
     response_timeout: std::time::Duration,
     connection_timeout: std::time::Duration,
     push_manager: PushManager,
+    cache: Option<CacheConfig>,
+    instrumentation: Option<Arc<dyn Instrumentation>>,
+    subscriptions: Arc<Mutex<Subscriptions>>,
+    #[cfg(feature = "sentinel")]
+    master_switch: Option<Arc<MasterSwitchSignal>>,
+}
+
+/// Where new connections are created from.
+#[derive(Clone)]
+enum ConnectionSource {
+    Client(Client),
+    /// The master or replica is looked up again for every new connection.
+    #[cfg(feature = "sentinel")]
+    Sentinel {
+        client: Arc<futures::lock::Mutex<SentinelClient>>,
+        redis_connection_info: RedisConnectionInfo,
+    },
+}
+
+impl ConnectionSource {
+    fn instrumentation(&self) -> Option<&Arc<dyn Instrumentation>> {
+        match self {
+            ConnectionSource::Client(client) => client.instrumentation.as_ref(),
+            #[cfg(feature = "sentinel")]
+            ConnectionSource::Sentinel { .. } => None,
+        }
+    }
+
+    fn redis_connection_info(&self) -> &RedisConnectionInfo {
+        match self {
+            ConnectionSource::Client(client) => &client.connection_info().redis,
+            #[cfg(feature = "sentinel")]
+            ConnectionSource::Sentinel {
+                redis_connection_info,
+                ..
+            } => redis_connection_info,
+        }
+    }
+
+    async fn get_multiplexed_async_connection(
+        &self,
+        config: &AsyncConnectionConfig,
+    ) -> RedisResult<MultiplexedConnection> {
+        match self {
+            ConnectionSource::Client(client) => {
+                client
+                    .get_multiplexed_async_connection_with_config(config)
+                    .await
+            }
+            #[cfg(feature = "sentinel")]
+            ConnectionSource::Sentinel { client, .. } => {
+                client
+                    .lock()
+                    .await
+                    .get_async_connection_with_config(config)
+                    .await
+            }
+        }
+    }
+}
+
+/// Set by the task listening to the sentinels when the master was switched, so that the
+/// manager moves to the new master before sending its next request.
+#[cfg(feature = "sentinel")]
+pub(crate) struct MasterSwitchSignal {
+    switched: AtomicBool,
+    // Dropped together with the last manager, which stops the listening task.
+    _stop: futures::channel::oneshot::Sender<()>,
+}
+
+#[cfg(feature = "sentinel")]
+impl MasterSwitchSignal {
+    pub(crate) fn notify(&self) {
+        self.switched.store(true, Ordering::Relaxed);
+    }
+}
+
+/// The subscriptions that should be re-issued after reconnecting.
+#[derive(Default)]
+struct Subscriptions {
+    channels: HashSet<Vec<u8>>,
+    patterns: HashSet<Vec<u8>>,
+    shard_channels: HashSet<Vec<u8>>,
+}
+
+impl Subscriptions {
+    fn of_kind(&mut self, kind: SubscriptionKind) -> &mut HashSet<Vec<u8>> {
+        match kind {
+            SubscriptionKind::Exact => &mut self.channels,
+            SubscriptionKind::Pattern => &mut self.patterns,
+            SubscriptionKind::Sharded => &mut self.shard_channels,
+        }
+    }
+
+    // Each subscription is sent separately, since the multiplexed connection expects a single
+    // confirmation per command.
+    fn resubscribe_cmds(&self) -> Vec<Cmd> {
+        [
+            (SubscriptionKind::Exact, &self.channels),
+            (SubscriptionKind::Pattern, &self.patterns),
+            (SubscriptionKind::Sharded, &self.shard_channels),
+        ]
+        .into_iter()
+        .flat_map(|(kind, names)| {
+            names.iter().map(move |name| {
+                let mut cmd = cmd(kind.subscribe_cmd());
+                cmd.arg(name);
+                cmd
+            })
+        })
+        .collect()
+    }
 }
 
 /// A `RedisResult` that can be cloned because `RedisError` is behind an `Arc`.
@@ -92,22 +300,12 @@ macro_rules! reconnect_if_io_error {
 }
 
 impl ConnectionManager {
-    const DEFAULT_CONNECTION_RETRY_EXPONENT_BASE: u64 = 2;
-    const DEFAULT_CONNECTION_RETRY_FACTOR: u64 = 100;
-    const DEFAULT_NUMBER_OF_CONNECTION_RETRIESE: usize = 6;
-
     /// Connect to the server and store the connection inside the returned `ConnectionManager`.
     ///
     /// This requires the `connection-manager` feature, which will also pull in
     /// the Tokio executor.
     pub async fn new(client: Client) -> RedisResult<Self> {
-        Self::new_with_backoff(
-            client,
-            Self::DEFAULT_CONNECTION_RETRY_EXPONENT_BASE,
-            Self::DEFAULT_CONNECTION_RETRY_FACTOR,
-            Self::DEFAULT_NUMBER_OF_CONNECTION_RETRIESE,
-        )
-        .await
+        Self::new_with_config(client, ConnectionManagerConfig::new()).await
     }
 
     /// Connect to the server and store the connection inside the returned `ConnectionManager`.
@@ -153,24 +351,106 @@ impl ConnectionManager {
         number_of_retries: usize,
         response_timeout: std::time::Duration,
         connection_timeout: std::time::Duration,
+    ) -> RedisResult<Self> {
+        Self::new_with_config(
+            client,
+            ConnectionManagerConfig::new()
+                .with_exponent_base(exponent_base)
+                .with_factor(factor)
+                .with_number_of_retries(number_of_retries)
+                .with_response_timeout(response_timeout)
+                .with_connection_timeout(connection_timeout),
+        )
+        .await
+    }
+
+    /// Connect to the server and store the connection inside the returned `ConnectionManager`.
+    ///
+    /// This requires the `connection-manager` feature, which will also pull in
+    /// the Tokio executor.
+    ///
+    /// In case of reconnection issues, the manager will retry reconnection
+    /// number_of_retries times, with an exponentially increasing delay, calculated as
+    /// rand(0 .. factor * (exponent_base ^ current-try)).
+    pub async fn new_with_config(
+        client: Client,
+        config: ConnectionManagerConfig,
+    ) -> RedisResult<Self> {
+        Self::new_with_source(ConnectionSource::Client(client), config).await
+    }
+
+    /// Connect to the master or replica described by `sentinel_client`, and store the
+    /// connection inside the returned `ConnectionManager`.
+    ///
+    /// The sentinels are asked for the current address of the server on each reconnection.
+    /// The manager also subscribes to the sentinels' `+switch-master` events, and moves to the
+    /// new master as soon as a failover of its service is announced.
+    #[cfg(feature = "sentinel")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "sentinel")))]
+    pub async fn new_with_sentinel(
+        sentinel_client: SentinelClient,
+        config: ConnectionManagerConfig,
+    ) -> RedisResult<Self> {
+        let sentinels = sentinel_client.sentinels_connection_info().to_vec();
+        let service_name = sentinel_client.service_name().to_string();
+        let redis_connection_info = sentinel_client.redis_connection_info();
+        let source = ConnectionSource::Sentinel {
+            client: Arc::new(futures::lock::Mutex::new(sentinel_client)),
+            redis_connection_info,
+        };
+        let mut manager = Self::new_with_source(source, config).await?;
+
+        let (stop_sender, stop_receiver) = futures::channel::oneshot::channel();
+        let signal = Arc::new(MasterSwitchSignal {
+            switched: AtomicBool::new(false),
+            _stop: stop_sender,
+        });
+        manager
+            .runtime
+            .spawn(crate::sentinel::listen_for_master_switch(
+                sentinels,
+                service_name,
+                Arc::downgrade(&signal),
+                stop_receiver,
+            ));
+        manager.master_switch = Some(signal);
+        Ok(manager)
+    }
+
+    async fn new_with_source(
+        source: ConnectionSource,
+        config: ConnectionManagerConfig,
     ) -> RedisResult<Self> {
         // Create a MultiplexedConnection and wait for it to be established
         let push_manager = PushManager::default();
         let runtime = Runtime::locate();
-        let retry_strategy = ExponentialBackoff::from_millis(exponent_base).factor(factor);
+        let retry_strategy =
+            ExponentialBackoff::from_millis(config.exponent_base).factor(config.factor);
+        let number_of_retries = config.number_of_retries;
+        let response_timeout = config.response_timeout;
+        let connection_timeout = config.connection_timeout;
+        let cache = config.cache.map(|mut cache_config| {
+            cache_config.counters = Some(Arc::default());
+            cache_config
+        });
+        let instrumentation = config
+            .instrumentation
+            .or_else(|| source.instrumentation().cloned());
         let mut connection = Self::new_connection(
-            client.clone(),
+            source.clone(),
             retry_strategy.clone(),
             number_of_retries,
             response_timeout,
             connection_timeout,
+            cache.clone(),
+            instrumentation.clone(),
         )
         .await?;
 
         // Wrap the connection in an `ArcSwap` instance for fast atomic access
         connection.set_push_manager(push_manager.clone()).await;
         Ok(Self {
-            client,
+            source,
             connection: Arc::new(ArcSwap::from_pointee(
                 future::ok(connection).boxed().shared(),
             )),
@@ -180,24 +460,34 @@ impl ConnectionManager {
             response_timeout,
             connection_timeout,
             push_manager,
+            cache,
+            instrumentation,
+            subscriptions: Default::default(),
+            #[cfg(feature = "sentinel")]
+            master_switch: None,
         })
     }
 
     async fn new_connection(
-        client: Client,
+        source: ConnectionSource,
         exponential_backoff: ExponentialBackoff,
         number_of_retries: usize,
         response_timeout: std::time::Duration,
         connection_timeout: std::time::Duration,
+        cache: Option<CacheConfig>,
+        instrumentation: Option<Arc<dyn Instrumentation>>,
     ) -> RedisResult<MultiplexedConnection> {
         let retry_strategy = exponential_backoff.map(jitter).take(number_of_retries);
-        Retry::spawn(retry_strategy, || {
-            client.get_multiplexed_async_connection_with_timeouts(
-                response_timeout,
-                connection_timeout,
-            )
+        let mut config = AsyncConnectionConfig::new()
+            .with_response_timeout(response_timeout)
+            .with_connection_timeout(connection_timeout);
+        config.cache = cache;
+        let mut connection = Retry::spawn(retry_strategy, || {
+            source.get_multiplexed_async_connection(&config)
         })
-        .await
+        .await?;
+        connection.instrumentation = instrumentation;
+        Ok(connection)
     }
 
     /// Reconnect and overwrite the old connection.
@@ -205,27 +495,42 @@ impl ConnectionManager {
     /// The `current` guard points to the shared future that was active
     /// when the connection loss was detected.
     fn reconnect(&self, current: arc_swap::Guard<Arc<SharedRedisFuture<MultiplexedConnection>>>) {
-        let client = self.client.clone();
+        let source = self.source.clone();
         let retry_strategy = self.retry_strategy.clone();
         let number_of_retries = self.number_of_retries;
         let response_timeout = self.response_timeout;
         let connection_timeout = self.connection_timeout;
         let pmc = self.push_manager.clone();
+        let cache = self.cache.clone();
+        let instrumentation = self.instrumentation.clone();
+        let subscriptions = self.subscriptions.clone();
         let new_connection: SharedRedisFuture<MultiplexedConnection> = async move {
             let mut con = Self::new_connection(
-                client,
+                source,
                 retry_strategy,
                 number_of_retries,
                 response_timeout,
                 connection_timeout,
+                cache,
+                instrumentation.clone(),
             )
             .await?;
+            instrumentation::report_reconnect(instrumentation.as_deref(), con.address());
             con.set_push_manager(pmc).await;
+            let resubscribe_cmds = subscriptions.lock().unwrap().resubscribe_cmds();
+            for cmd in resubscribe_cmds {
+                con.send_packed_command(&cmd).await?;
+            }
             Ok(con)
         }
         .boxed()
         .shared();
 
+        // Let the consumers know that messages might have been lost.
+        if let Some(Ok(connection)) = current.peek() {
+            connection.report_disconnection();
+        }
+
         // Update the connection in the connection manager
         let new_connection_arc = Arc::new(new_connection.clone());
         let prev = self
@@ -239,11 +544,39 @@ impl ConnectionManager {
         }
     }
 
+    /// Returns the current connection future, first moving to the new master if the sentinels
+    /// announced a failover.
+    fn load_connection(&self) -> arc_swap::Guard<Arc<SharedRedisFuture<MultiplexedConnection>>> {
+        #[cfg(feature = "sentinel")]
+        if let Some(signal) = &self.master_switch {
+            if signal.switched.swap(false, Ordering::Relaxed) {
+                self.reconnect(self.connection.load());
+            }
+        }
+        self.connection.load()
+    }
+
+    /// Whether the current connection is established, rather than being reconnected or having
+    /// failed to reconnect.
+    pub(crate) fn is_connected(&self) -> bool {
+        matches!(self.connection.load().peek(), Some(Ok(_)))
+    }
+
     /// Sends an already encoded (packed) command into the TCP socket and
     /// reads the single response from it.
+    ///
+    /// A timeout that was set with [`Cmd::set_timeout`] also bounds the time spent waiting for
+    /// the connection to be reestablished.
     pub async fn send_packed_command(&mut self, cmd: &Cmd) -> RedisResult<Value> {
+        match cmd.get_timeout() {
+            Some(timeout) => with_timeout(self.runtime.clone(), timeout, self.send_cmd(cmd)).await,
+            None => self.send_cmd(cmd).await,
+        }
+    }
+
+    async fn send_cmd(&mut self, cmd: &Cmd) -> RedisResult<Value> {
         // Clone connection to avoid having to lock the ArcSwap in write mode
-        let guard = self.connection.load();
+        let guard = self.load_connection();
         let connection_result = (**guard)
             .clone()
             .await
@@ -257,14 +590,32 @@ impl ConnectionManager {
     /// Sends multiple already encoded (packed) command into the TCP socket
     /// and reads `count` responses from it.  This is used to implement
     /// pipelining.
+    ///
+    /// A timeout that was set with [`crate::Pipeline::set_timeout`] also bounds the time spent
+    /// waiting for the connection to be reestablished.
     pub async fn send_packed_commands(
         &mut self,
         cmd: &crate::Pipeline,
         offset: usize,
         count: usize,
+    ) -> RedisResult<Vec<Value>> {
+        match cmd.get_timeout() {
+            Some(timeout) => {
+                let runtime = self.runtime.clone();
+                with_timeout(runtime, timeout, self.send_pipeline(cmd, offset, count)).await
+            }
+            None => self.send_pipeline(cmd, offset, count).await,
+        }
+    }
+
+    async fn send_pipeline(
+        &mut self,
+        cmd: &crate::Pipeline,
+        offset: usize,
+        count: usize,
     ) -> RedisResult<Vec<Value>> {
         // Clone shared connection future to avoid having to lock the ArcSwap in write mode
-        let guard = self.connection.load();
+        let guard = self.load_connection();
         let connection_result = (**guard)
             .clone()
             .await
@@ -281,6 +632,129 @@ impl ConnectionManager {
     pub fn get_push_manager(&self) -> PushManager {
         self.push_manager.clone()
     }
+
+    /// Returns the hit/miss statistics of the client-side cache, or `None` if the manager
+    /// was created without one.
+    pub fn get_cache_statistics(&self) -> Option<CacheStatistics> {
+        self.cache
+            .as_ref()
+            .and_then(|cache_config| cache_config.counters.as_ref())
+            .map(|counters| counters.statistics())
+    }
+
+    // Like when resubscribing, every name is sent in its own command, since each name is
+    // confirmed separately, and the multiplexed connection expects a single confirmation per
+    // command.
+    async fn subscribe_inner(
+        &mut self,
+        kind: SubscriptionKind,
+        names: impl ToRedisArgs,
+    ) -> RedisResult<()> {
+        self.check_resp3()?;
+        for name in names.to_redis_args() {
+            let mut cmd = cmd(kind.subscribe_cmd());
+            cmd.arg(&name);
+            self.send_packed_command(&cmd).await?;
+            self.subscriptions
+                .lock()
+                .unwrap()
+                .of_kind(kind)
+                .insert(name);
+        }
+        Ok(())
+    }
+
+    async fn unsubscribe_inner(
+        &mut self,
+        kind: SubscriptionKind,
+        names: impl ToRedisArgs,
+    ) -> RedisResult<()> {
+        self.check_resp3()?;
+        let mut names = names.to_redis_args();
+        // No names means all of the manager's subscriptions of this kind.
+        if names.is_empty() {
+            names = self
+                .subscriptions
+                .lock()
+                .unwrap()
+                .of_kind(kind)
+                .iter()
+                .cloned()
+                .collect();
+        }
+        for name in names {
+            self.subscriptions
+                .lock()
+                .unwrap()
+                .of_kind(kind)
+                .remove(&name);
+            let mut cmd = cmd(kind.unsubscribe_cmd());
+            cmd.arg(&name);
+            self.send_packed_command(&cmd).await?;
+        }
+        Ok(())
+    }
+
+    fn check_resp3(&self) -> RedisResult<()> {
+        if self.source.redis_connection_info().protocol == ProtocolVersion::RESP2 {
+            fail!((
+                ErrorKind::InvalidClientConfig,
+                "RESP3 is required for this command"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Subscribes to a new channel. The subscription is renewed after reconnecting.
+    pub async fn subscribe(&mut self, channel_name: impl ToRedisArgs) -> RedisResult<()> {
+        self.subscribe_inner(SubscriptionKind::Exact, channel_name)
+            .await
+    }
+
+    /// Unsubscribes from channel. Without any channel names, unsubscribes from all of the
+    /// channels that the manager subscribed to.
+    pub async fn unsubscribe(&mut self, channel_name: impl ToRedisArgs) -> RedisResult<()> {
+        self.unsubscribe_inner(SubscriptionKind::Exact, channel_name)
+            .await
+    }
+
+    /// Subscribes to a new channel with pattern. The subscription is renewed after reconnecting.
+    pub async fn psubscribe(&mut self, channel_pattern: impl ToRedisArgs) -> RedisResult<()> {
+        self.subscribe_inner(SubscriptionKind::Pattern, channel_pattern)
+            .await
+    }
+
+    /// Unsubscribes from channel pattern. Without any patterns, unsubscribes from all of the
+    /// patterns that the manager subscribed to.
+    pub async fn punsubscribe(&mut self, channel_pattern: impl ToRedisArgs) -> RedisResult<()> {
+        self.unsubscribe_inner(SubscriptionKind::Pattern, channel_pattern)
+            .await
+    }
+
+    /// Subscribes to a new shard channel. The subscription is renewed after reconnecting.
+    pub async fn ssubscribe(&mut self, channel_name: impl ToRedisArgs) -> RedisResult<()> {
+        self.subscribe_inner(SubscriptionKind::Sharded, channel_name)
+            .await
+    }
+
+    /// Unsubscribes from shard channel. Without any channel names, unsubscribes from all of the
+    /// shard channels that the manager subscribed to.
+    pub async fn sunsubscribe(&mut self, channel_name: impl ToRedisArgs) -> RedisResult<()> {
+        self.unsubscribe_inner(SubscriptionKind::Sharded, channel_name)
+            .await
+    }
+}
+
+// Fails the request with a timeout error if it isn't done within the given timeout.
+async fn with_timeout<T>(
+    runtime: Runtime,
+    timeout: std::time::Duration,
+    request: impl Future<Output = RedisResult<T>>,
+) -> RedisResult<T> {
+    runtime
+        .timeout(timeout, request)
+        .await
+        .unwrap_or_else(|elapsed| Err(elapsed.into()))
 }
 
 impl ConnectionLike for ConnectionManager {
@@ -298,6 +772,40 @@ impl ConnectionLike for ConnectionManager {
     }
 
     fn get_db(&self) -> i64 {
-        self.client.connection_info().redis.db
+        self.source.redis_connection_info().db
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resubscribe_cmds_cover_all_kinds() {
+        let mut subscriptions = Subscriptions::default();
+        subscriptions
+            .of_kind(SubscriptionKind::Exact)
+            .insert(b"foo".to_vec());
+        subscriptions
+            .of_kind(SubscriptionKind::Pattern)
+            .insert(b"bar*".to_vec());
+        subscriptions
+            .of_kind(SubscriptionKind::Sharded)
+            .insert(b"baz".to_vec());
+
+        let packed: Vec<Vec<u8>> = subscriptions
+            .resubscribe_cmds()
+            .iter()
+            .map(|cmd| cmd.get_packed_command())
+            .collect();
+
+        assert_eq!(
+            packed,
+            vec![
+                cmd("SUBSCRIBE").arg("foo").get_packed_command(),
+                cmd("PSUBSCRIBE").arg("bar*").get_packed_command(),
+                cmd("SSUBSCRIBE").arg("baz").get_packed_command(),
+            ]
+        );
     }
 }
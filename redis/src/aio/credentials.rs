@@ -0,0 +1,194 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use ::tokio::sync::oneshot;
+use futures_util::future::select;
+
+use super::runtime::Runtime;
+use super::MultiplexedConnection;
+use crate::cmd::cmd;
+use crate::connection::RedisConnectionInfo;
+use crate::types::{RedisFuture, RedisResult};
+
+// Credentials are refreshed once this fraction of their remaining lifetime has passed, but not
+// more often than once every `MIN_REFRESH_DELAY`.
+const REFRESH_FRACTION: f64 = 0.75;
+const MIN_REFRESH_DELAY: Duration = Duration::from_secs(1);
+
+/// The credentials that a connection authenticates with.
+#[derive(Clone)]
+pub struct Credentials {
+    username: Option<String>,
+    password: String,
+    expires_at: Option<Instant>,
+}
+
+impl Credentials {
+    /// Creates credentials for the default user, with the given password.
+    pub fn new(password: impl Into<String>) -> Self {
+        Self {
+            username: None,
+            password: password.into(),
+            expires_at: None,
+        }
+    }
+
+    /// Sets the user that the credentials belong to.
+    pub fn with_username(mut self, username: impl Into<String>) -> Self {
+        self.username = Some(username.into());
+        self
+    }
+
+    /// Sets when the credentials expire. Multiplexed connections that authenticated with them
+    /// authenticate again with new credentials before then.
+    pub fn with_expiration(mut self, expires_at: Instant) -> Self {
+        self.expires_at = Some(expires_at);
+        self
+    }
+
+    /// Returns the user that the credentials belong to, if it isn't the default user.
+    pub fn username(&self) -> Option<&str> {
+        self.username.as_deref()
+    }
+
+    /// Returns when the credentials expire, if they expire.
+    pub fn expires_at(&self) -> Option<Instant> {
+        self.expires_at
+    }
+
+    pub(crate) fn apply(&self, connection_info: &mut RedisConnectionInfo) {
+        connection_info.username.clone_from(&self.username);
+        connection_info.password = Some(self.password.clone());
+    }
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("username", &self.username)
+            .field("expires_at", &self.expires_at)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Provides the credentials that async connections authenticate with, for servers whose
+/// credentials change over time, such as servers that authenticate with short-lived tokens.
+///
+/// The provider is asked for credentials whenever a connection is created, including when
+/// [`ConnectionManager`](crate::aio::ConnectionManager)s and cluster connections reconnect.
+/// Multiplexed connections that are created by a [`Client`](crate::Client) also authenticate
+/// again with new credentials before their credentials [expire](Credentials::with_expiration), for
+/// as long as any of their clones is alive.
+pub trait CredentialsProvider: Send + Sync {
+    /// Returns the current credentials.
+    fn credentials(&self) -> RedisFuture<'_, Credentials>;
+}
+
+impl fmt::Debug for dyn CredentialsProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CredentialsProvider")
+            .finish_non_exhaustive()
+    }
+}
+
+/// Keeps a multiplexed connection authenticated. Dropped together with the last clone of the
+/// connection, which stops re-authenticating it.
+pub(crate) struct CredentialsRefresh {
+    _stop: oneshot::Sender<()>,
+}
+
+impl CredentialsRefresh {
+    pub(crate) fn spawn(
+        connection: MultiplexedConnection,
+        provider: Arc<dyn CredentialsProvider>,
+        expires_at: Instant,
+    ) -> Self {
+        let (stop_sender, stop_receiver) = oneshot::channel();
+        let runtime = Runtime::locate();
+        runtime.clone().spawn(async move {
+            let refresh = Box::pin(refresh(runtime, connection, provider, expires_at));
+            let _ = select(refresh, stop_receiver).await;
+        });
+        Self { _stop: stop_sender }
+    }
+}
+
+async fn refresh(
+    runtime: Runtime,
+    mut connection: MultiplexedConnection,
+    provider: Arc<dyn CredentialsProvider>,
+    mut expires_at: Instant,
+) {
+    loop {
+        runtime
+            .sleep(refresh_delay(expires_at, Instant::now()))
+            .await;
+        // Failures are retried until the connection is lost, since the server may still accept
+        // the connection's credentials, and if it doesn't, the connection is lost anyway.
+        let credentials = match provider.credentials().await {
+            Ok(credentials) => credentials,
+            Err(_) => continue,
+        };
+        match authenticate(&mut connection, &credentials).await {
+            Ok(()) => {}
+            Err(err) if err.is_unrecoverable_error() => return,
+            Err(_) => continue,
+        }
+        expires_at = match credentials.expires_at {
+            Some(expires_at) => expires_at,
+            None => return,
+        };
+    }
+}
+
+async fn authenticate(
+    connection: &mut MultiplexedConnection,
+    credentials: &Credentials,
+) -> RedisResult<()> {
+    let mut command = cmd("AUTH");
+    if let Some(username) = &credentials.username {
+        command.arg(username);
+    }
+    command
+        .arg(&credentials.password)
+        .query_async(connection)
+        .await
+}
+
+fn refresh_delay(expires_at: Instant, now: Instant) -> Duration {
+    expires_at
+        .saturating_duration_since(now)
+        .mul_f64(REFRESH_FRACTION)
+        .max(MIN_REFRESH_DELAY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn credentials_are_refreshed_before_they_expire() {
+        let now = Instant::now();
+        assert_eq!(
+            refresh_delay(now + Duration::from_secs(60), now),
+            Duration::from_secs(45)
+        );
+        assert_eq!(
+            refresh_delay(now + Duration::from_millis(100), now),
+            MIN_REFRESH_DELAY
+        );
+        assert_eq!(
+            refresh_delay(now, now + Duration::from_secs(5)),
+            MIN_REFRESH_DELAY
+        );
+    }
+
+    #[test]
+    fn passwords_are_not_printed() {
+        let credentials = Credentials::new("secret").with_username("user");
+        let printed = format!("{credentials:?}");
+        assert!(printed.contains("user"));
+        assert!(!printed.contains("secret"));
+    }
+}
@@ -156,6 +156,40 @@ where
     Ok(())
 }
 
+/// The kinds of pub/sub subscriptions that are tracked in order to be renewed.
+#[cfg(any(feature = "connection-manager", feature = "cluster-async"))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum SubscriptionKind {
+    Exact,
+    Pattern,
+    Sharded,
+}
+
+#[cfg(any(feature = "connection-manager", feature = "cluster-async"))]
+impl SubscriptionKind {
+    pub(crate) fn subscribe_cmd(self) -> &'static str {
+        match self {
+            SubscriptionKind::Exact => "SUBSCRIBE",
+            SubscriptionKind::Pattern => "PSUBSCRIBE",
+            SubscriptionKind::Sharded => "SSUBSCRIBE",
+        }
+    }
+
+    pub(crate) fn unsubscribe_cmd(self) -> &'static str {
+        match self {
+            SubscriptionKind::Exact => "UNSUBSCRIBE",
+            SubscriptionKind::Pattern => "PUNSUBSCRIBE",
+            SubscriptionKind::Sharded => "SUNSUBSCRIBE",
+        }
+    }
+}
+
+mod cache;
+pub use cache::{CacheConfig, CacheStatistics};
+mod credentials;
+pub(crate) use cache::{CacheableRequest, ClientSideCache, DriverCache};
+pub(crate) use credentials::CredentialsRefresh;
+pub use credentials::{Credentials, CredentialsProvider};
 mod connection;
 pub use connection::*;
 mod multiplexed_connection;
@@ -165,6 +199,19 @@ mod connection_manager;
 #[cfg(feature = "connection-manager")]
 #[cfg_attr(docsrs, doc(cfg(feature = "connection-manager")))]
 pub use connection_manager::*;
+#[cfg(feature = "connection-manager")]
+mod striped_connection_manager;
+#[cfg(feature = "connection-manager")]
+#[cfg_attr(docsrs, doc(cfg(feature = "connection-manager")))]
+pub use striped_connection_manager::*;
+#[cfg(any(feature = "tokio-comp", feature = "async-std-comp"))]
+mod pool;
+#[cfg(any(feature = "tokio-comp", feature = "async-std-comp"))]
+#[cfg_attr(
+    docsrs,
+    doc(cfg(any(feature = "tokio-comp", feature = "async-std-comp")))
+)]
+pub use pool::*;
 mod runtime;
 use crate::commands::resp3_hello;
 pub(super) use runtime::*;
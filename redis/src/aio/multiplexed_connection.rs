@@ -1,14 +1,18 @@
-use super::{ConnectionLike, Runtime};
+use super::{
+    CacheStatistics, CacheableRequest, ClientSideCache, ConnectionLike, CredentialsProvider,
+    CredentialsRefresh, DriverCache, Runtime,
+};
 use crate::aio::setup_connection;
 use crate::cmd::Cmd;
+use crate::instrumentation::{self, Instrumentation, RequestInfo};
 #[cfg(any(feature = "tokio-comp", feature = "async-std-comp"))]
 use crate::parser::ValueCodec;
 use crate::push_manager::PushManager;
 use crate::types::{RedisError, RedisFuture, RedisResult, Value};
-use crate::{cmd, ConnectionInfo, ProtocolVersion, PushKind, ToRedisArgs};
+use crate::{cmd, AsyncConnectionConfig, ConnectionInfo, ProtocolVersion, PushKind, ToRedisArgs};
 use ::tokio::{
     io::{AsyncRead, AsyncWrite},
-    sync::{mpsc, oneshot},
+    sync::{mpsc, oneshot, OwnedSemaphorePermit, Semaphore},
 };
 use arc_swap::ArcSwap;
 use futures_util::{
@@ -23,9 +27,10 @@ use std::fmt;
 use std::fmt::Debug;
 use std::io;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::task::{self, Poll};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 #[cfg(any(feature = "tokio-comp", feature = "async-std-comp"))]
 use tokio_util::codec::Decoder;
 
@@ -59,6 +64,8 @@ impl ResponseAggregate {
 struct InFlight {
     output: PipelineOutput,
     response_aggregate: ResponseAggregate,
+    cacheable_request: Option<CacheableRequest>,
+    _guard: InFlightGuard,
 }
 
 // A single message sent through the pipeline
@@ -67,6 +74,272 @@ struct PipelineMessage {
     output: PipelineOutput,
     // If `None`, this is a single request, not a pipeline of multiple requests.
     pipeline_response_count: Option<usize>,
+    // If set, the response is applied to the client-side cache.
+    cacheable_request: Option<CacheableRequest>,
+    // Counts the request as in flight until it's answered, or dropped without being sent.
+    guard: InFlightGuard,
+}
+
+/// Options for automatic batching of the requests that are sent over a [`MultiplexedConnection`].
+///
+/// Without batching, requests are written to the socket as soon as the connection's task handles
+/// them. With batching, the task waits up to the flush window after the first request of a batch
+/// for more requests, and writes all of them at once, which saves system calls when many tasks
+/// send requests concurrently. A batch is written right away once it reaches the maximal number of
+/// commands or bytes. Responses are matched to their requests in order either way.
+#[derive(Clone, Copy, Debug)]
+pub struct BatchingConfig {
+    max_commands: usize,
+    max_bytes: usize,
+    flush_window: Duration,
+}
+
+impl BatchingConfig {
+    const DEFAULT_MAX_COMMANDS: usize = 128;
+    const DEFAULT_MAX_BYTES: usize = 64 * 1024;
+    const DEFAULT_FLUSH_WINDOW: Duration = Duration::from_micros(50);
+
+    /// Creates a new instance of the options with the default limits: batches of up to 128
+    /// commands or 64 KiB, and a flush window of 50 microseconds.
+    pub fn new() -> Self {
+        Self {
+            max_commands: Self::DEFAULT_MAX_COMMANDS,
+            max_bytes: Self::DEFAULT_MAX_BYTES,
+            flush_window: Self::DEFAULT_FLUSH_WINDOW,
+        }
+    }
+
+    /// Sets the number of commands after which a batch is written. Every command of a pipeline is
+    /// counted.
+    pub fn with_max_commands(mut self, max_commands: usize) -> Self {
+        self.max_commands = max_commands;
+        self
+    }
+
+    /// Sets the number of bytes after which a batch is written.
+    pub fn with_max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// Sets how long to wait for more requests after the first request of a batch. This delays
+    /// every request by up to the given time when the connection is idle.
+    pub fn with_flush_window(mut self, flush_window: Duration) -> Self {
+        self.flush_window = flush_window;
+        self
+    }
+}
+
+impl Default for BatchingConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The requests that were queued since the last write, when automatic batching is enabled.
+struct Batch {
+    config: BatchingConfig,
+    runtime: Runtime,
+    commands: usize,
+    bytes: usize,
+    // Started by the first request of the batch.
+    flush_timer: Option<Pin<Box<dyn Future<Output = ()> + Send>>>,
+    window_elapsed: bool,
+}
+
+impl Batch {
+    fn new(config: BatchingConfig) -> Self {
+        Batch {
+            config,
+            runtime: Runtime::locate(),
+            commands: 0,
+            bytes: 0,
+            flush_timer: None,
+            window_elapsed: false,
+        }
+    }
+
+    fn add(&mut self, commands: usize, bytes: usize) {
+        self.commands += commands;
+        self.bytes += bytes;
+    }
+
+    fn is_full(&self) -> bool {
+        self.commands >= self.config.max_commands || self.bytes >= self.config.max_bytes
+    }
+
+    // Whether the batch should wait for more requests before it's written. Registers the task to
+    // be woken up when the flush window elapses.
+    fn poll_wait(&mut self, cx: &mut task::Context) -> bool {
+        if self.commands == 0
+            || self.window_elapsed
+            || self.is_full()
+            || self.config.flush_window.is_zero()
+        {
+            return false;
+        }
+        let timer = self.flush_timer.get_or_insert_with(|| {
+            let runtime = self.runtime.clone();
+            let flush_window = self.config.flush_window;
+            Box::pin(async move { runtime.sleep(flush_window).await })
+        });
+        if timer.as_mut().poll(cx).is_ready() {
+            self.window_elapsed = true;
+            return false;
+        }
+        true
+    }
+
+    fn reset(&mut self) {
+        self.commands = 0;
+        self.bytes = 0;
+        self.flush_timer = None;
+        self.window_elapsed = false;
+    }
+}
+
+/// What happens to a request that's sent over a [`MultiplexedConnection`] that already has the
+/// maximal number of requests or bytes in flight.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverloadBehavior {
+    /// Wait until enough of the requests in flight are answered.
+    Wait,
+    /// Fail right away, with an error of kind
+    /// [`ErrorKind::ClientOverloaded`](crate::ErrorKind::ClientOverloaded).
+    Fail,
+}
+
+/// Limits on the requests that are in flight over a [`MultiplexedConnection`], meaning that they
+/// were queued or sent, but weren't answered yet.
+///
+/// Without limits, requests are queued without bound, so a server that slows down causes the
+/// memory of the client to grow. A pipeline counts as a single request.
+#[derive(Clone, Copy, Debug)]
+pub struct InFlightLimits {
+    max_requests: Option<usize>,
+    max_bytes: Option<usize>,
+    overload_behavior: OverloadBehavior,
+}
+
+impl InFlightLimits {
+    /// Creates a new instance of the options with no limits, where requests wait when a limit is
+    /// reached.
+    pub fn new() -> Self {
+        Self {
+            max_requests: None,
+            max_bytes: None,
+            overload_behavior: OverloadBehavior::Wait,
+        }
+    }
+
+    /// Sets the maximal number of requests in flight.
+    pub fn with_max_requests(mut self, max_requests: usize) -> Self {
+        self.max_requests = Some(max_requests);
+        self
+    }
+
+    /// Sets the maximal number of bytes of the encoded requests in flight. A request that's larger
+    /// than the limit is sent once no other request is in flight.
+    pub fn with_max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Sets what happens to requests that are sent once a limit is reached.
+    pub fn with_overload_behavior(mut self, overload_behavior: OverloadBehavior) -> Self {
+        self.overload_behavior = overload_behavior;
+        self
+    }
+}
+
+impl Default for InFlightLimits {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Counts the requests that are in flight over a connection, and enforces its limits.
+struct InFlightTracker {
+    requests: AtomicUsize,
+    bytes: AtomicUsize,
+    request_permits: Option<Arc<Semaphore>>,
+    // Holds a permit per byte, up to `max_bytes`.
+    byte_permits: Option<(Arc<Semaphore>, u32)>,
+    overload_behavior: OverloadBehavior,
+}
+
+impl InFlightTracker {
+    fn new(limits: InFlightLimits) -> Self {
+        InFlightTracker {
+            requests: AtomicUsize::new(0),
+            bytes: AtomicUsize::new(0),
+            request_permits: limits
+                .max_requests
+                .map(|max_requests| Arc::new(Semaphore::new(max_requests))),
+            byte_permits: limits.max_bytes.map(|max_bytes| {
+                let max_bytes = u32::try_from(max_bytes).unwrap_or(u32::MAX);
+                (Arc::new(Semaphore::new(max_bytes as usize)), max_bytes)
+            }),
+            overload_behavior: limits.overload_behavior,
+        }
+    }
+
+    async fn track(self: &Arc<Self>, bytes: usize) -> RedisResult<InFlightGuard> {
+        let request_permit = match &self.request_permits {
+            Some(permits) => Some(self.acquire(permits, 1).await?),
+            None => None,
+        };
+        let byte_permits = match &self.byte_permits {
+            Some((permits, max_bytes)) => {
+                let count = u32::try_from(bytes).unwrap_or(u32::MAX).min(*max_bytes);
+                Some(self.acquire(permits, count).await?)
+            }
+            None => None,
+        };
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        Ok(InFlightGuard {
+            tracker: self.clone(),
+            bytes,
+            _request_permit: request_permit,
+            _byte_permits: byte_permits,
+        })
+    }
+
+    async fn acquire(
+        &self,
+        permits: &Arc<Semaphore>,
+        count: u32,
+    ) -> RedisResult<OwnedSemaphorePermit> {
+        match self.overload_behavior {
+            OverloadBehavior::Wait => Ok(permits
+                .clone()
+                .acquire_many_owned(count)
+                .await
+                .expect("In-flight semaphores are never closed")),
+            OverloadBehavior::Fail => permits.clone().try_acquire_many_owned(count).map_err(|_| {
+                RedisError::from((
+                    crate::ErrorKind::ClientOverloaded,
+                    "Too many requests in flight",
+                ))
+            }),
+        }
+    }
+}
+
+// Counts a request as in flight until it's dropped.
+struct InFlightGuard {
+    tracker: Arc<InFlightTracker>,
+    bytes: usize,
+    _request_permit: Option<OwnedSemaphorePermit>,
+    _byte_permits: Option<OwnedSemaphorePermit>,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.tracker.requests.fetch_sub(1, Ordering::Relaxed);
+        self.tracker.bytes.fetch_sub(self.bytes, Ordering::Relaxed);
+    }
 }
 
 /// Wrapper around a `Stream + Sink` where each item sent through the `Sink` results in one or more
@@ -77,6 +350,8 @@ struct Pipeline {
     sender: mpsc::Sender<PipelineMessage>,
 
     push_manager: Arc<ArcSwap<PushManager>>,
+
+    in_flight: Arc<InFlightTracker>,
 }
 
 impl Clone for Pipeline {
@@ -84,6 +359,7 @@ impl Clone for Pipeline {
         Pipeline {
             sender: self.sender.clone(),
             push_manager: self.push_manager.clone(),
+            in_flight: self.in_flight.clone(),
         }
     }
 }
@@ -101,6 +377,8 @@ pin_project! {
         in_flight: VecDeque<InFlight>,
         error: Option<RedisError>,
         push_manager: Arc<ArcSwap<PushManager>>,
+        cache: Option<DriverCache>,
+        batch: Option<Batch>,
     }
 }
 
@@ -108,7 +386,12 @@ impl<T> PipelineSink<T>
 where
     T: Stream<Item = RedisResult<Value>> + 'static,
 {
-    fn new(sink_stream: T, push_manager: Arc<ArcSwap<PushManager>>) -> Self
+    fn new(
+        sink_stream: T,
+        push_manager: Arc<ArcSwap<PushManager>>,
+        cache: Option<Arc<ClientSideCache>>,
+        batching: Option<BatchingConfig>,
+    ) -> Self
     where
         T: Sink<Vec<u8>, Error = RedisError> + Stream<Item = RedisResult<Value>> + 'static,
     {
@@ -117,6 +400,8 @@ where
             in_flight: VecDeque::new(),
             error: None,
             push_manager,
+            cache: cache.map(DriverCache::new),
+            batch: batching.map(Batch::new),
         }
     }
 
@@ -137,7 +422,10 @@ where
         let self_ = self.project();
         let mut skip_value = false;
         if let Ok(res) = &result {
-            if let Value::Push { kind, data: _data } = res {
+            if let Value::Push { kind, data } = res {
+                if let (PushKind::Invalidate, Some(cache)) = (kind, self_.cache.as_ref()) {
+                    cache.invalidate(data);
+                }
                 self_.push_manager.load().try_send_raw(res);
                 if !kind.has_reply() {
                     // If it's not true then push kind is converted to reply of a command
@@ -158,6 +446,15 @@ where
 
         match &mut entry.response_aggregate {
             ResponseAggregate::SingleCommand => {
+                // The value is cached before reading the next message, so that any invalidation
+                // that follows it will be applied.
+                if let (Some(request), Some(cache), Ok(value)) = (
+                    entry.cacheable_request.take(),
+                    self_.cache.as_ref(),
+                    &result,
+                ) {
+                    cache.apply_reply(request, value);
+                }
                 entry.output.send(result).ok();
             }
             ResponseAggregate::Pipeline {
@@ -188,6 +485,12 @@ where
                     Some(err) => Err(err),
                     None => Ok(Value::Array(std::mem::take(buffer))),
                 };
+                // Pipelines are never served from the cache, but may switch databases.
+                if let (Some(CacheableRequest::ChangesKeyspace), Some(cache)) =
+                    (&entry.cacheable_request, self_.cache.as_ref())
+                {
+                    cache.flush();
+                }
 
                 // `Err` means that the receiver was dropped in which case it does not
                 // care about the output and we can continue by just dropping the value
@@ -209,6 +512,13 @@ where
         mut self: Pin<&mut Self>,
         cx: &mut task::Context,
     ) -> Poll<Result<(), Self::Error>> {
+        let mut this = self.as_mut().project();
+        if let Some(batch) = this.batch.as_mut().filter(|batch| batch.is_full()) {
+            if let Err(err) = ready!(this.sink_stream.as_mut().poll_flush(cx)) {
+                *this.error = Some(err);
+            }
+            batch.reset();
+        }
         match ready!(self.as_mut().project().sink_stream.poll_ready(cx)) {
             Ok(()) => Ok(()).into(),
             Err(err) => {
@@ -224,6 +534,8 @@ where
             input,
             output,
             pipeline_response_count,
+            cacheable_request,
+            guard,
         }: PipelineMessage,
     ) -> Result<(), Self::Error> {
         // If there is nothing to receive our output we do not need to send the message as it is
@@ -240,12 +552,18 @@ where
             return Err(());
         }
 
+        let bytes = input.len();
         match self_.sink_stream.start_send(input) {
             Ok(()) => {
+                if let Some(batch) = self_.batch {
+                    batch.add(pipeline_response_count.unwrap_or(1), bytes);
+                }
                 let response_aggregate = ResponseAggregate::new(pipeline_response_count);
                 let entry = InFlight {
                     output,
                     response_aggregate,
+                    cacheable_request,
+                    _guard: guard,
                 };
 
                 self_.in_flight.push_back(entry);
@@ -262,6 +580,16 @@ where
         mut self: Pin<&mut Self>,
         cx: &mut task::Context,
     ) -> Poll<Result<(), Self::Error>> {
+        if let Some(batch) = self.as_mut().project().batch {
+            if batch.poll_wait(cx) {
+                // Keep handling the responses to the requests that were already written, while
+                // the batch waits for more requests.
+                return match self.poll_read(cx) {
+                    Poll::Ready(Err(())) => Poll::Ready(Err(())),
+                    _ => Poll::Pending,
+                };
+            }
+        }
         ready!(self
             .as_mut()
             .project()
@@ -270,6 +598,9 @@ where
             .map_err(|err| {
                 self.as_mut().send_result(Err(err));
             }))?;
+        if let Some(batch) = self.as_mut().project().batch {
+            batch.reset();
+        }
         self.poll_read(cx)
     }
 
@@ -290,7 +621,12 @@ where
 }
 
 impl Pipeline {
-    fn new<T>(sink_stream: T) -> (Self, impl Future<Output = ()>)
+    fn new<T>(
+        sink_stream: T,
+        cache: Option<Arc<ClientSideCache>>,
+        batching: Option<BatchingConfig>,
+        in_flight_limits: InFlightLimits,
+    ) -> (Self, impl Future<Output = ()>)
     where
         T: Sink<Vec<u8>, Error = RedisError> + Stream<Item = RedisResult<Value>> + 'static,
         T: Send + 'static,
@@ -302,7 +638,7 @@ impl Pipeline {
         let (sender, mut receiver) = mpsc::channel(BUFFER_SIZE);
         let push_manager: Arc<ArcSwap<PushManager>> =
             Arc::new(ArcSwap::new(Arc::new(PushManager::default())));
-        let sink = PipelineSink::new(sink_stream, push_manager.clone());
+        let sink = PipelineSink::new(sink_stream, push_manager.clone(), cache, batching);
         let f = stream::poll_fn(move |cx| receiver.poll_recv(cx))
             .map(Ok)
             .forward(sink)
@@ -311,6 +647,7 @@ impl Pipeline {
             Pipeline {
                 sender,
                 push_manager,
+                in_flight: Arc::new(InFlightTracker::new(in_flight_limits)),
             },
             f,
         )
@@ -321,8 +658,9 @@ impl Pipeline {
         &mut self,
         item: Vec<u8>,
         timeout: Option<Duration>,
+        cacheable_request: Option<CacheableRequest>,
     ) -> Result<Value, Option<RedisError>> {
-        self.send_recv(item, None, timeout).await
+        self.send_recv(item, None, timeout, cacheable_request).await
     }
 
     async fn send_recv(
@@ -331,7 +669,9 @@ impl Pipeline {
         // If `None`, this is a single request, not a pipeline of multiple requests.
         pipeline_response_count: Option<usize>,
         timeout: Option<Duration>,
+        cacheable_request: Option<CacheableRequest>,
     ) -> Result<Value, Option<RedisError>> {
+        let guard = self.in_flight.track(input.len()).await.map_err(Some)?;
         let (sender, receiver) = oneshot::channel();
 
         self.sender
@@ -339,6 +679,8 @@ impl Pipeline {
                 input,
                 pipeline_response_count,
                 output: sender,
+                cacheable_request,
+                guard,
             })
             .await
             .map_err(|_| None)?;
@@ -357,7 +699,7 @@ impl Pipeline {
     }
 
     /// Sets `PushManager` of Pipeline
-    async fn set_push_manager(&mut self, push_manager: PushManager) {
+    fn set_push_manager(&mut self, push_manager: PushManager) {
         self.push_manager.store(Arc::new(push_manager));
     }
 }
@@ -371,6 +713,21 @@ pub struct MultiplexedConnection {
     response_timeout: Option<Duration>,
     protocol: ProtocolVersion,
     push_manager: PushManager,
+    cache: Option<Arc<ClientSideCache>>,
+    // Shared by all clones, so that a lost connection is reported only once.
+    disconnection_reported: Arc<AtomicBool>,
+    // If set, blocking commands are sent over dedicated connections from this pool.
+    #[cfg(any(feature = "tokio-comp", feature = "async-std-comp"))]
+    pub(crate) blocking_commands_pool: Option<super::Pool<MultiplexedConnection>>,
+    // Notified of every request that is sent over the connection.
+    pub(crate) instrumentation: Option<Arc<dyn Instrumentation>>,
+    // Unset for the node connections of cluster connections, which report their requests
+    // themselves, so that requests aren't reported twice.
+    pub(crate) reports_requests: bool,
+    // The address that the connection is connected to, which is reported to `instrumentation`.
+    address: Arc<str>,
+    // Re-authenticates the connection before its credentials expire, while any clone is alive.
+    credentials_refresh: Option<Arc<CredentialsRefresh>>,
 }
 
 impl Debug for MultiplexedConnection {
@@ -402,6 +759,23 @@ impl MultiplexedConnection {
         stream: C,
         response_timeout: Option<std::time::Duration>,
     ) -> RedisResult<(Self, impl Future<Output = ()>)>
+    where
+        C: Unpin + AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let mut config = AsyncConnectionConfig::new();
+        config.response_timeout = response_timeout;
+        Self::new_with_config(connection_info, stream, &config).await
+    }
+
+    /// Constructs a new `MultiplexedConnection` out of a `AsyncRead + AsyncWrite` object,
+    /// a `ConnectionInfo` and an `AsyncConnectionConfig`.
+    ///
+    /// The connection timeout in `config` isn't used, since the stream is already connected.
+    pub async fn new_with_config<C>(
+        connection_info: &ConnectionInfo,
+        stream: C,
+        config: &AsyncConnectionConfig,
+    ) -> RedisResult<(Self, impl Future<Output = ()>)>
     where
         C: Unpin + AsyncRead + AsyncWrite + Send + 'static,
     {
@@ -415,22 +789,66 @@ impl MultiplexedConnection {
         compile_error!("tokio-comp or async-std-comp features required for aio feature");
 
         let redis_connection_info = &connection_info.redis;
+        if config.cache.is_some() && redis_connection_info.protocol == ProtocolVersion::RESP2 {
+            fail!((
+                crate::ErrorKind::InvalidClientConfig,
+                "RESP3 is required for client-side caching"
+            ));
+        }
+        let limits = &config.in_flight_limits;
+        if limits.max_requests == Some(0) || limits.max_bytes == Some(0) {
+            fail!((
+                crate::ErrorKind::InvalidClientConfig,
+                "The in-flight limits should be non-zero"
+            ));
+        }
+        if let Some(batching) = &config.batching {
+            if batching.max_commands == 0 || batching.max_bytes == 0 {
+                fail!((
+                    crate::ErrorKind::InvalidClientConfig,
+                    "The batch limits should be non-zero"
+                ));
+            }
+        }
+        let cache = config
+            .cache
+            .as_ref()
+            .map(|cache_config| Arc::new(ClientSideCache::new(cache_config)));
         let codec = ValueCodec::default()
             .framed(stream)
             .and_then(|msg| async move { msg });
-        let (mut pipeline, driver) = Pipeline::new(codec);
+        let (mut pipeline, driver) = Pipeline::new(
+            codec,
+            cache.clone(),
+            config.batching,
+            config.in_flight_limits,
+        );
         let driver = boxed(driver);
         let pm = PushManager::default();
-        pipeline.set_push_manager(pm.clone()).await;
+        pipeline.set_push_manager(pm.clone());
         let mut con = MultiplexedConnection {
             pipeline,
             db: connection_info.redis.db,
-            response_timeout,
+            response_timeout: config.response_timeout,
             push_manager: pm,
             protocol: redis_connection_info.protocol,
+            cache,
+            disconnection_reported: Arc::new(AtomicBool::new(false)),
+            #[cfg(any(feature = "tokio-comp", feature = "async-std-comp"))]
+            blocking_commands_pool: None,
+            instrumentation: None,
+            reports_requests: true,
+            address: connection_info.addr.to_string().into(),
+            credentials_refresh: None,
         };
         let driver = {
-            let auth = setup_connection(&connection_info.redis, &mut con);
+            let auth = async {
+                setup_connection(&connection_info.redis, &mut con).await?;
+                if con.cache.is_some() {
+                    enable_client_tracking(&mut con).await?;
+                }
+                Ok::<_, RedisError>(())
+            };
 
             futures_util::pin_mut!(auth);
 
@@ -450,6 +868,32 @@ impl MultiplexedConnection {
         Ok((con, driver))
     }
 
+    /// Returns the number of requests that are in flight over the connection, meaning that they
+    /// were queued or sent, but weren't answered yet. A pipeline counts as a single request.
+    pub fn in_flight_requests(&self) -> usize {
+        self.pipeline.in_flight.requests.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of bytes of the encoded requests that are in flight over the connection.
+    pub fn in_flight_bytes(&self) -> usize {
+        self.pipeline.in_flight.bytes.load(Ordering::Relaxed)
+    }
+
+    // Authenticates the connection again with credentials from `provider` before the current
+    // credentials expire at `expires_at`, and before each of the following credentials expire.
+    pub(crate) fn refresh_credentials(
+        &mut self,
+        provider: Arc<dyn CredentialsProvider>,
+        expires_at: Instant,
+    ) {
+        // The refreshing task's own clone doesn't keep the refresh alive.
+        let mut connection = self.clone();
+        connection.credentials_refresh = None;
+        self.credentials_refresh = Some(Arc::new(CredentialsRefresh::spawn(
+            connection, provider, expires_at,
+        )));
+    }
+
     /// Sets the time that the multiplexer will wait for responses on operations before failing.
     pub fn set_response_timeout(&mut self, timeout: std::time::Duration) {
         self.response_timeout = Some(timeout);
@@ -457,23 +901,73 @@ impl MultiplexedConnection {
 
     /// Sends an already encoded (packed) command into the TCP socket and
     /// reads the single response from it.
+    ///
+    /// The response timeout of blocking commands, such as `BLPOP` or `XREAD` with `BLOCK`, is
+    /// extended by the time that they're allowed to block. Commands that block indefinitely are
+    /// still bound by the response timeout.
+    ///
+    /// A timeout that was set with [`Cmd::set_timeout`] replaces the response timeout. If a request
+    /// times out or is dropped before its response arrives, the response is discarded once it
+    /// arrives, so the responses of the following requests aren't affected.
     pub async fn send_packed_command(&mut self, cmd: &Cmd) -> RedisResult<Value> {
+        #[cfg(any(feature = "tokio-comp", feature = "async-std-comp"))]
+        if let Some(pool) = &self.blocking_commands_pool {
+            if blocking_timeout(cmd).is_some() {
+                return send_blocking_command(pool, cmd, self.response_timeout).await;
+            }
+        }
+        if !self.reports_requests || !instrumentation::is_enabled(&self.instrumentation) {
+            return self.send_command(cmd).await;
+        }
+        let instrumentation = self.instrumentation.clone();
+        let address = self.address.clone();
+        let request = RequestInfo::for_cmd(cmd)
+            .with_node(Some(&address))
+            .with_db(self.db);
+        instrumentation::instrument_async(
+            instrumentation.as_deref(),
+            &request,
+            self.send_command(cmd),
+        )
+        .await
+    }
+
+    async fn send_command(&mut self, cmd: &Cmd) -> RedisResult<Value> {
+        let cacheable_request = match &self.cache {
+            Some(cache) => match cache.request_for(cmd) {
+                Some(request) => match cache.get(&request) {
+                    Some(value) => return Ok(value),
+                    None => Some(request),
+                },
+                None => None,
+            },
+            None => None,
+        };
+        let packed_command = match cacheable_request
+            .as_ref()
+            .and_then(CacheableRequest::packed_command)
+        {
+            Some(packed_command) => packed_command.to_vec(),
+            None => cmd.get_packed_command(),
+        };
         let result = self
             .pipeline
-            .send_single(cmd.get_packed_command(), self.response_timeout)
+            .send_single(
+                packed_command,
+                response_timeout_for(
+                    cmd.get_timeout(),
+                    self.response_timeout,
+                    std::iter::once(cmd),
+                ),
+                cacheable_request,
+            )
             .await
             .map_err(|err| {
                 err.unwrap_or_else(|| RedisError::from(io::Error::from(io::ErrorKind::BrokenPipe)))
             });
-        if self.protocol != ProtocolVersion::RESP2 {
-            if let Err(e) = &result {
-                if e.is_connection_dropped() {
-                    // Notify the PushManager that the connection was lost
-                    self.push_manager.try_send_raw(&Value::Push {
-                        kind: PushKind::Disconnection,
-                        data: vec![],
-                    });
-                }
+        if let Err(e) = &result {
+            if e.is_connection_dropped() {
+                self.report_disconnection();
             }
         }
         result
@@ -487,28 +981,47 @@ impl MultiplexedConnection {
         cmd: &crate::Pipeline,
         offset: usize,
         count: usize,
+    ) -> RedisResult<Vec<Value>> {
+        if !self.reports_requests || !instrumentation::is_enabled(&self.instrumentation) {
+            return self.send_commands(cmd, offset, count).await;
+        }
+        let instrumentation = self.instrumentation.clone();
+        let address = self.address.clone();
+        let request = RequestInfo::for_pipeline(cmd)
+            .with_node(Some(&address))
+            .with_db(self.db);
+        instrumentation::instrument_async(
+            instrumentation.as_deref(),
+            &request,
+            self.send_commands(cmd, offset, count),
+        )
+        .await
+    }
+
+    async fn send_commands(
+        &mut self,
+        cmd: &crate::Pipeline,
+        offset: usize,
+        count: usize,
     ) -> RedisResult<Vec<Value>> {
         let result = self
             .pipeline
             .send_recv(
                 cmd.get_packed_pipeline(),
                 Some(offset + count),
-                self.response_timeout,
+                response_timeout_for(cmd.get_timeout(), self.response_timeout, cmd.cmd_iter()),
+                self.cache
+                    .as_ref()
+                    .and_then(|cache| cache.request_for_pipeline(cmd)),
             )
             .await
             .map_err(|err| {
                 err.unwrap_or_else(|| RedisError::from(io::Error::from(io::ErrorKind::BrokenPipe)))
             });
 
-        if self.protocol != ProtocolVersion::RESP2 {
-            if let Err(e) = &result {
-                if e.is_connection_dropped() {
-                    // Notify the PushManager that the connection was lost
-                    self.push_manager.try_send_raw(&Value::Push {
-                        kind: PushKind::Disconnection,
-                        data: vec![],
-                    });
-                }
+        if let Err(e) = &result {
+            if e.is_connection_dropped() {
+                self.report_disconnection();
             }
         }
         let value = result?;
@@ -523,8 +1036,12 @@ impl MultiplexedConnection {
 
     /// Sets `PushManager` of connection
     pub async fn set_push_manager(&mut self, push_manager: PushManager) {
+        self.replace_push_manager(push_manager);
+    }
+
+    pub(crate) fn replace_push_manager(&mut self, push_manager: PushManager) {
         self.push_manager = push_manager.clone();
-        self.pipeline.set_push_manager(push_manager).await;
+        self.pipeline.set_push_manager(push_manager);
     }
 }
 
@@ -603,8 +1120,328 @@ impl MultiplexedConnection {
         Ok(())
     }
 
+    /// Subscribes to a new shard channel.
+    pub async fn ssubscribe(&mut self, channel_name: impl ToRedisArgs) -> RedisResult<()> {
+        if self.protocol == ProtocolVersion::RESP2 {
+            return Err(RedisError::from((
+                crate::ErrorKind::InvalidClientConfig,
+                "RESP3 is required for this command",
+            )));
+        }
+        let mut cmd = cmd("SSUBSCRIBE");
+        cmd.arg(channel_name);
+        cmd.query_async(self).await?;
+        Ok(())
+    }
+
+    /// Unsubscribes from shard channel.
+    pub async fn sunsubscribe(&mut self, channel_name: impl ToRedisArgs) -> RedisResult<()> {
+        if self.protocol == ProtocolVersion::RESP2 {
+            return Err(RedisError::from((
+                crate::ErrorKind::InvalidClientConfig,
+                "RESP3 is required for this command",
+            )));
+        }
+        let mut cmd = cmd("SUNSUBSCRIBE");
+        cmd.arg(channel_name);
+        cmd.query_async(self).await?;
+        Ok(())
+    }
+
     /// Returns `PushManager` of Connection, this method is used to subscribe/unsubscribe from Push types
     pub fn get_push_manager(&self) -> PushManager {
         self.push_manager.clone()
     }
+
+    // The address that the connection is connected to.
+    #[cfg(feature = "connection-manager")]
+    pub(crate) fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Notifies the `PushManager` that the connection was lost. This happens at most once per
+    /// connection, and only on RESP3 connections.
+    pub(crate) fn report_disconnection(&self) {
+        if self.protocol != ProtocolVersion::RESP2
+            && !self.disconnection_reported.swap(true, Ordering::Relaxed)
+        {
+            self.push_manager.try_send_raw(&Value::Push {
+                kind: PushKind::Disconnection,
+                data: vec![],
+            });
+        }
+    }
+
+    /// Returns the hit/miss statistics of the client-side cache, or `None` if the connection
+    /// was created without one.
+    pub fn get_cache_statistics(&self) -> Option<CacheStatistics> {
+        self.cache.as_ref().map(|cache| cache.statistics())
+    }
+}
+
+// How long the server might block before replying to the command, if it's a blocking command.
+// Commands that block indefinitely return `Duration::ZERO`, so that they're still bound by the
+// response timeout.
+fn blocking_timeout(cmd: &Cmd) -> Option<Duration> {
+    fn seconds(arg: &[u8]) -> Option<Duration> {
+        let seconds: f64 = std::str::from_utf8(arg).ok()?.parse().ok()?;
+        match seconds {
+            seconds if seconds == 0.0 => Some(Duration::ZERO),
+            seconds if seconds > 0.0 && seconds < u64::MAX as f64 => {
+                Some(Duration::from_secs_f64(seconds))
+            }
+            _ => None,
+        }
+    }
+    fn millis(arg: &[u8]) -> Option<Duration> {
+        Some(Duration::from_millis(
+            std::str::from_utf8(arg).ok()?.parse().ok()?,
+        ))
+    }
+
+    let name = cmd.arg_idx(0)?.to_ascii_uppercase();
+    let last = cmd.args_iter().len().checked_sub(1)?;
+    match name.as_slice() {
+        b"BLPOP" | b"BRPOP" | b"BRPOPLPUSH" | b"BLMOVE" | b"BZPOPMIN" | b"BZPOPMAX" => {
+            seconds(cmd.arg_idx(last)?)
+        }
+        b"BLMPOP" | b"BZMPOP" => seconds(cmd.arg_idx(1)?),
+        b"WAIT" => millis(cmd.arg_idx(2)?),
+        b"WAITAOF" => millis(cmd.arg_idx(3)?),
+        // The keys and IDs that follow `STREAMS` aren't options, even if one of them is `BLOCK`.
+        b"XREAD" | b"XREADGROUP" => (1..last)
+            .map_while(|idx| cmd.arg_idx(idx))
+            .take_while(|arg| !arg.eq_ignore_ascii_case(b"STREAMS"))
+            .position(|arg| arg.eq_ignore_ascii_case(b"BLOCK"))
+            .and_then(|position| millis(cmd.arg_idx(position + 2)?)),
+        _ => None,
+    }
+}
+
+// Sends a blocking command over a dedicated connection, so that it doesn't delay the requests
+// that are sent after it over the multiplexed connection.
+#[cfg(any(feature = "tokio-comp", feature = "async-std-comp"))]
+async fn send_blocking_command(
+    pool: &super::Pool<MultiplexedConnection>,
+    cmd: &Cmd,
+    response_timeout: Option<Duration>,
+) -> RedisResult<Value> {
+    let mut con = pool.get().await?;
+    match response_timeout_for(cmd.get_timeout(), response_timeout, std::iter::once(cmd)) {
+        // A connection whose request timed out is closed by the pool, so its late response is
+        // never read by another request.
+        Some(timeout) => Runtime::locate()
+            .timeout(timeout, con.req_packed_command(cmd))
+            .await
+            .unwrap_or_else(|elapsed| Err(elapsed.into())),
+        None => con.req_packed_command(cmd).await,
+    }
+}
+
+// The response timeout of a request. A timeout that was set on the request itself is used as is,
+// otherwise the connection's response timeout is extended by the time that the request's blocking
+// commands might block.
+fn response_timeout_for<'a>(
+    request_timeout: Option<Duration>,
+    response_timeout: Option<Duration>,
+    cmds: impl Iterator<Item = &'a Cmd>,
+) -> Option<Duration> {
+    if request_timeout.is_some() {
+        return request_timeout;
+    }
+    let blocking = cmds
+        .filter_map(blocking_timeout)
+        .fold(Duration::ZERO, Duration::saturating_add);
+    Some(response_timeout?.saturating_add(blocking))
+}
+
+async fn enable_client_tracking(con: &mut MultiplexedConnection) -> RedisResult<()> {
+    match cmd("CLIENT")
+        .arg("TRACKING")
+        .arg("ON")
+        .query_async(con)
+        .await
+    {
+        Ok(Value::Okay) => Ok(()),
+        _ => fail!((
+            crate::ErrorKind::ResponseError,
+            "Redis server refused to enable client tracking"
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_in_flight_limits_reject_requests_when_overloaded() {
+        let tracker = Arc::new(InFlightTracker::new(
+            InFlightLimits::new()
+                .with_max_requests(2)
+                .with_max_bytes(100)
+                .with_overload_behavior(OverloadBehavior::Fail),
+        ));
+        let track = |bytes| tracker.track(bytes).now_or_never().unwrap();
+        let in_flight = || {
+            (
+                tracker.requests.load(Ordering::Relaxed),
+                tracker.bytes.load(Ordering::Relaxed),
+            )
+        };
+
+        let first = track(60).unwrap();
+        assert_eq!(in_flight(), (1, 60));
+        assert_eq!(
+            track(50).err().unwrap().kind(),
+            crate::ErrorKind::ClientOverloaded
+        );
+        let second = track(40).unwrap();
+        assert_eq!(
+            track(0).err().unwrap().kind(),
+            crate::ErrorKind::ClientOverloaded
+        );
+        assert_eq!(in_flight(), (2, 100));
+
+        drop(first);
+        drop(second);
+        assert_eq!(in_flight(), (0, 0));
+        // A request that's larger than the limit is let through once nothing else is in flight.
+        let large = track(1000).unwrap();
+        assert_eq!(in_flight(), (1, 1000));
+        drop(large);
+    }
+
+    #[test]
+    fn test_in_flight_limits_make_requests_wait_when_overloaded() {
+        let tracker = Arc::new(InFlightTracker::new(
+            InFlightLimits::new().with_max_requests(1),
+        ));
+        let first = tracker.track(10).now_or_never().unwrap().unwrap();
+        let mut second = Box::pin(tracker.track(10));
+        assert!((&mut second).now_or_never().is_none());
+        drop(first);
+        assert!(second.now_or_never().unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_batch_is_written_once_it_reaches_a_limit() {
+        let mut batch = Batch::new(
+            BatchingConfig::new()
+                .with_max_commands(3)
+                .with_max_bytes(100),
+        );
+        let mut cx = task::Context::from_waker(futures_util::task::noop_waker_ref());
+        // An empty batch has nothing to wait for.
+        assert!(!batch.poll_wait(&mut cx));
+
+        batch.add(2, 10);
+        assert!(!batch.is_full());
+        batch.add(1, 10);
+        assert!(batch.is_full());
+        assert!(!batch.poll_wait(&mut cx));
+
+        batch.reset();
+        assert!(!batch.is_full());
+        batch.add(1, 100);
+        assert!(batch.is_full());
+    }
+
+    #[test]
+    fn test_blocking_timeout_of_blocking_commands() {
+        let timeout = |cmd: &Cmd| blocking_timeout(cmd);
+        assert_eq!(
+            timeout(cmd("BLPOP").arg("a").arg("b").arg(30)),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            timeout(cmd("bzpopmin").arg("a").arg("0.5")),
+            Some(Duration::from_millis(500))
+        );
+        assert_eq!(
+            timeout(
+                cmd("BLMOVE")
+                    .arg("a")
+                    .arg("b")
+                    .arg("LEFT")
+                    .arg("RIGHT")
+                    .arg(2)
+            ),
+            Some(Duration::from_secs(2))
+        );
+        assert_eq!(
+            timeout(cmd("BLMPOP").arg(3).arg(1).arg("a").arg("LEFT")),
+            Some(Duration::from_secs(3))
+        );
+        assert_eq!(
+            timeout(cmd("WAIT").arg(1).arg(100)),
+            Some(Duration::from_millis(100))
+        );
+        assert_eq!(
+            timeout(cmd("WAITAOF").arg(1).arg(0).arg(100)),
+            Some(Duration::from_millis(100))
+        );
+        assert_eq!(timeout(cmd("BLPOP").arg("a").arg(0)), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_blocking_timeout_of_stream_reads() {
+        let timeout = |cmd: &Cmd| blocking_timeout(cmd);
+        assert_eq!(
+            timeout(
+                cmd("XREAD")
+                    .arg("COUNT")
+                    .arg(2)
+                    .arg("BLOCK")
+                    .arg(1500)
+                    .arg("STREAMS")
+                    .arg("s")
+                    .arg("$")
+            ),
+            Some(Duration::from_millis(1500))
+        );
+        assert_eq!(
+            timeout(
+                cmd("XREADGROUP")
+                    .arg("GROUP")
+                    .arg("g")
+                    .arg("c")
+                    .arg("block")
+                    .arg(10)
+                    .arg("STREAMS")
+                    .arg("s")
+                    .arg(">")
+            ),
+            Some(Duration::from_millis(10))
+        );
+        // A stream named `BLOCK` isn't an option.
+        assert_eq!(
+            timeout(cmd("XREAD").arg("STREAMS").arg("BLOCK").arg(5)),
+            None
+        );
+        assert_eq!(timeout(cmd("GET").arg("BLOCK")), None);
+    }
+
+    #[test]
+    fn test_response_timeout_is_extended_by_blocking_commands() {
+        let blpop = cmd("BLPOP").arg("a").arg(2).clone();
+        let get = cmd("GET").arg("a").clone();
+        assert_eq!(
+            response_timeout_for(
+                None,
+                Some(Duration::from_millis(100)),
+                [&blpop, &get].into_iter()
+            ),
+            Some(Duration::from_millis(2100))
+        );
+        assert_eq!(response_timeout_for(None, None, [&blpop].into_iter()), None);
+        assert_eq!(
+            response_timeout_for(
+                Some(Duration::from_millis(10)),
+                Some(Duration::from_millis(100)),
+                [&blpop].into_iter()
+            ),
+            Some(Duration::from_millis(10))
+        );
+    }
 }
@@ -0,0 +1,487 @@
+#![allow(deprecated)]
+
+use super::{Connection, ConnectionLike, MultiplexedConnection, Runtime};
+use crate::cmd::{cmd, Cmd};
+use crate::types::{ErrorKind, RedisFuture, RedisResult, Value};
+use crate::Client;
+use async_trait::async_trait;
+use futures_util::FutureExt;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// How often a pool drops its expired idle connections and opens the connections that are
+/// missing from its minimum size.
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A connection that can be kept in a [`Pool`].
+#[async_trait]
+pub trait PoolableConnection: ConnectionLike + Send + Sized + 'static {
+    /// Opens a new connection to the server of `client`.
+    async fn connect(client: &Client) -> RedisResult<Self>;
+}
+
+#[async_trait]
+impl PoolableConnection for MultiplexedConnection {
+    async fn connect(client: &Client) -> RedisResult<Self> {
+        client.get_multiplexed_async_connection().await
+    }
+}
+
+#[async_trait]
+impl PoolableConnection for Connection {
+    async fn connect(client: &Client) -> RedisResult<Self> {
+        client.get_async_connection().await
+    }
+}
+
+/// Options for creation of a [`Pool`].
+#[derive(Clone, Debug)]
+pub struct PoolConfig {
+    min_size: usize,
+    max_size: usize,
+    idle_timeout: Option<Duration>,
+    max_lifetime: Option<Duration>,
+    health_check_on_checkout: bool,
+}
+
+impl PoolConfig {
+    const DEFAULT_MAX_SIZE: usize = 10;
+    const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+    const DEFAULT_MAX_LIFETIME: Duration = Duration::from_secs(30 * 60);
+
+    /// Creates a new instance of the options with a maximum size of 10 connections, no minimum
+    /// size, an idle timeout of 10 minutes, a maximum lifetime of 30 minutes, and health checks on
+    /// checkout
+    pub fn new() -> Self {
+        Self {
+            min_size: 0,
+            max_size: Self::DEFAULT_MAX_SIZE,
+            idle_timeout: Some(Self::DEFAULT_IDLE_TIMEOUT),
+            max_lifetime: Some(Self::DEFAULT_MAX_LIFETIME),
+            health_check_on_checkout: true,
+        }
+    }
+
+    /// Sets the number of connections that the pool keeps open, even if they're idle
+    pub fn with_min_size(mut self, min_size: usize) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    /// Sets the maximum number of connections that can be checked out of the pool at once
+    pub fn with_max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Sets the time after which idle connections are closed, or disables it with `None`.
+    /// Connections are never closed below the minimum size of the pool due to being idle.
+    pub fn with_idle_timeout(mut self, idle_timeout: Option<Duration>) -> Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// Sets the time after which connections are closed once they're returned to the pool, or
+    /// disables it with `None`
+    pub fn with_max_lifetime(mut self, max_lifetime: Option<Duration>) -> Self {
+        self.max_lifetime = max_lifetime;
+        self
+    }
+
+    /// Sets whether idle connections are checked with a `PING` before they're checked out
+    pub fn with_health_check_on_checkout(mut self, health_check_on_checkout: bool) -> Self {
+        self.health_check_on_checkout = health_check_on_checkout;
+        self
+    }
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The number of connections of a [`Pool`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct PoolState {
+    /// The number of open connections, both idle and checked out.
+    pub connections: usize,
+    /// The number of idle connections.
+    pub idle_connections: usize,
+}
+
+/// A pool of dedicated async connections, for uses that can't share a
+/// [`MultiplexedConnection`], such as blocking commands (`BLPOP`,
+/// `XREAD BLOCK`, `WAIT`), `WATCH` transactions and pub/sub.
+///
+/// Like the [`MultiplexedConnection`], the pool can be cloned, and all clones
+/// share the same connections.
+///
+/// ## Behavior
+///
+/// - [`get`](Pool::get) checks out an idle connection if there is one, and
+///   opens a new connection otherwise. If [`PoolConfig::with_max_size`]
+///   connections are checked out, it waits until one of them is returned.
+/// - Idle connections are checked with a `PING` before they're checked out,
+///   and are closed if the check fails.
+/// - A connection is returned to the pool when its [`PooledConnection`] is
+///   dropped. If the connection started a transaction, watched keys or
+///   switched its database, it's reset with `DISCARD`, `UNWATCH` and
+///   `SELECT` first. Connections that failed with an I/O error, or that were
+///   dropped in the middle of a request, are closed instead.
+/// - Connections that were idle for longer than the idle timeout, or that
+///   were open for longer than their maximum lifetime, are closed.
+///
+/// ```rust,no_run
+/// # async fn run() -> redis::RedisResult<()> {
+/// use redis::aio::{MultiplexedConnection, Pool, PoolConfig};
+///
+/// let client = redis::Client::open("redis://127.0.0.1/")?;
+/// let pool: Pool<MultiplexedConnection> =
+///     Pool::new(client, PoolConfig::new().with_max_size(4)).await?;
+/// let mut con = pool.get().await?;
+/// let popped: Option<(String, String)> = redis::cmd("BLPOP")
+///     .arg("queue")
+///     .arg(1)
+///     .query_async(&mut con)
+///     .await?;
+/// # Ok(()) }
+/// ```
+pub struct Pool<C> {
+    inner: Arc<PoolInner<C>>,
+}
+
+impl<C> Clone for Pool<C> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+struct PoolInner<C> {
+    client: Client,
+    config: PoolConfig,
+    idle: Mutex<VecDeque<IdleConnection<C>>>,
+    // Every checked out connection holds a permit, until it's back in the pool.
+    permits: Arc<Semaphore>,
+    runtime: Runtime,
+}
+
+struct IdleConnection<C> {
+    conn: C,
+    created: Instant,
+    idle_since: Instant,
+}
+
+impl<C> PoolInner<C> {
+    fn is_expired(&self, created: Instant) -> bool {
+        self.config
+            .max_lifetime
+            .map_or(false, |max_lifetime| created.elapsed() >= max_lifetime)
+    }
+
+    fn put(&self, conn: C, created: Instant) {
+        if !self.is_expired(created) {
+            self.idle.lock().unwrap().push_back(IdleConnection {
+                conn,
+                created,
+                idle_since: Instant::now(),
+            });
+        }
+    }
+
+    fn checked_out(&self) -> usize {
+        self.config.max_size - self.permits.available_permits()
+    }
+
+    // Closes the idle connections that expired, while keeping the minimum size of the pool.
+    fn close_expired(&self) {
+        let mut idle = self.idle.lock().unwrap();
+        let checked_out = self.checked_out();
+        let mut connections = idle.len() + checked_out;
+        idle.retain(|conn| {
+            let idle_expired = self.config.idle_timeout.map_or(false, |idle_timeout| {
+                conn.idle_since.elapsed() >= idle_timeout && connections > self.config.min_size
+            });
+            let expired = idle_expired || self.is_expired(conn.created);
+            if expired {
+                connections -= 1;
+            }
+            !expired
+        });
+    }
+}
+
+impl<C> PoolInner<C>
+where
+    C: PoolableConnection,
+{
+    // Opens connections until the pool has its minimum size.
+    async fn fill(&self) -> RedisResult<()> {
+        while self.idle.lock().unwrap().len() + self.checked_out() < self.config.min_size {
+            let conn = C::connect(&self.client).await?;
+            self.put(conn, Instant::now());
+        }
+        Ok(())
+    }
+}
+
+impl<C> Pool<C>
+where
+    C: PoolableConnection,
+{
+    /// Creates a pool of connections to the server of `client`, and opens the minimum number of
+    /// connections that it should hold.
+    pub async fn new(client: Client, config: PoolConfig) -> RedisResult<Self> {
+        if config.max_size == 0 {
+            fail!((
+                ErrorKind::InvalidClientConfig,
+                "The maximum size of the pool should be non-zero"
+            ));
+        }
+        if config.min_size > config.max_size {
+            fail!((
+                ErrorKind::InvalidClientConfig,
+                "The minimum size of the pool can't exceed its maximum size"
+            ));
+        }
+
+        let inner = Arc::new(PoolInner {
+            client,
+            permits: Arc::new(Semaphore::new(config.max_size)),
+            config,
+            idle: Default::default(),
+            runtime: Runtime::locate(),
+        });
+        inner.fill().await?;
+        let config = &inner.config;
+        if config.min_size > 0 || config.idle_timeout.is_some() || config.max_lifetime.is_some() {
+            inner
+                .runtime
+                .spawn(maintain(inner.runtime.clone(), Arc::downgrade(&inner)));
+        }
+        Ok(Self { inner })
+    }
+
+    /// Checks out a connection, and waits for one to be returned if the pool is at its maximum
+    /// size.
+    pub async fn get(&self) -> RedisResult<PooledConnection<C>> {
+        let permit = self
+            .inner
+            .permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("The semaphore of a pool is never closed");
+        loop {
+            let idle = self.inner.idle.lock().unwrap().pop_back();
+            let mut idle = match idle {
+                Some(idle) => idle,
+                None => break,
+            };
+            if self.inner.is_expired(idle.created) {
+                continue;
+            }
+            if self.inner.config.health_check_on_checkout && check(&mut idle.conn).await.is_err() {
+                continue;
+            }
+            return Ok(PooledConnection::new(
+                idle.conn,
+                idle.created,
+                permit,
+                self.inner.clone(),
+            ));
+        }
+
+        let conn = C::connect(&self.inner.client).await?;
+        Ok(PooledConnection::new(
+            conn,
+            Instant::now(),
+            permit,
+            self.inner.clone(),
+        ))
+    }
+
+    /// Returns the number of connections of the pool.
+    pub fn state(&self) -> PoolState {
+        let idle_connections = self.inner.idle.lock().unwrap().len();
+        PoolState {
+            connections: idle_connections + self.inner.checked_out(),
+            idle_connections,
+        }
+    }
+}
+
+// Closes expired idle connections and keeps the minimum size of the pool, until the pool is
+// dropped.
+async fn maintain<C: PoolableConnection>(runtime: Runtime, inner: Weak<PoolInner<C>>) {
+    loop {
+        runtime.sleep(MAINTENANCE_INTERVAL).await;
+        let inner = match inner.upgrade() {
+            Some(inner) => inner,
+            None => return,
+        };
+        inner.close_expired();
+        // Failures are retried on the next interval.
+        let _ = inner.fill().await;
+    }
+}
+
+async fn check<C: ConnectionLike>(conn: &mut C) -> RedisResult<()> {
+    cmd("PING").query_async::<_, String>(conn).await?;
+    Ok(())
+}
+
+// Resets the state that the user of a connection might have left on it.
+async fn reset<C: ConnectionLike>(conn: &mut C, db: i64) -> RedisResult<()> {
+    // Fails if there's no transaction to discard, which is fine. The transaction is discarded
+    // first, since the rest of the commands would be queued in it otherwise.
+    let _: RedisResult<()> = cmd("DISCARD").query_async(conn).await;
+    cmd("UNWATCH").query_async::<_, ()>(conn).await?;
+    cmd("SELECT").arg(db).query_async::<_, ()>(conn).await
+}
+
+// Whether the command leaves state on the connection, which must be reset before the connection
+// is reused.
+fn changes_connection_state(cmd: &Cmd) -> bool {
+    cmd.arg_idx(0).map_or(false, |name| {
+        [&b"WATCH"[..], b"MULTI", b"SELECT"]
+            .iter()
+            .any(|stateful| name.eq_ignore_ascii_case(stateful))
+    })
+}
+
+/// A connection that was checked out of a [`Pool`], and is returned to it when
+/// it's dropped.
+pub struct PooledConnection<C: PoolableConnection> {
+    conn: Option<C>,
+    created: Instant,
+    permit: Option<OwnedSemaphorePermit>,
+    pool: Arc<PoolInner<C>>,
+    needs_reset: bool,
+    // Set while a request is sent, so that connections whose requests were dropped midway, and
+    // which might still receive their responses, aren't reused.
+    in_request: bool,
+    broken: bool,
+}
+
+impl<C: PoolableConnection> PooledConnection<C> {
+    fn new(
+        conn: C,
+        created: Instant,
+        permit: OwnedSemaphorePermit,
+        pool: Arc<PoolInner<C>>,
+    ) -> Self {
+        Self {
+            conn: Some(conn),
+            created,
+            permit: Some(permit),
+            pool,
+            needs_reset: false,
+            in_request: false,
+            broken: false,
+        }
+    }
+
+    /// Takes the connection out of the pool, for uses that need to own it, such as
+    /// [`Connection::into_pubsub`]. The pool opens a new connection in its place when needed.
+    pub fn detach(mut self) -> C {
+        self.conn.take().expect("The connection is only taken once")
+    }
+
+    fn conn(&mut self) -> &mut C {
+        self.conn
+            .as_mut()
+            .expect("The connection is only taken once")
+    }
+
+    fn handle_result<T>(&mut self, result: &RedisResult<T>) {
+        self.in_request = false;
+        if let Err(err) = result {
+            self.broken |= err.is_unrecoverable_error();
+        }
+    }
+}
+
+impl<C: PoolableConnection> Drop for PooledConnection<C> {
+    fn drop(&mut self) {
+        let mut conn = match self.conn.take() {
+            Some(conn) => conn,
+            None => return,
+        };
+        if self.broken || self.in_request {
+            return;
+        }
+        if !self.needs_reset {
+            self.pool.put(conn, self.created);
+            return;
+        }
+
+        let pool = self.pool.clone();
+        let created = self.created;
+        // The permit is held until the connection is back in the pool, so that the pool doesn't
+        // exceed its maximum size in the meantime.
+        let permit = self.permit.take();
+        let db = pool.client.get_connection_info().redis.db;
+        self.pool.runtime.spawn(async move {
+            if reset(&mut conn, db).await.is_ok() {
+                pool.put(conn, created);
+            }
+            drop(permit);
+        });
+    }
+}
+
+impl<C: PoolableConnection> ConnectionLike for PooledConnection<C> {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        (async move {
+            self.needs_reset |= changes_connection_state(cmd);
+            self.in_request = true;
+            let result = self.conn().req_packed_command(cmd).await;
+            self.handle_result(&result);
+            result
+        })
+        .boxed()
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a crate::Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        (async move {
+            self.needs_reset |= cmd.cmd_iter().any(changes_connection_state);
+            self.in_request = true;
+            let result = self.conn().req_packed_commands(cmd, offset, count).await;
+            self.handle_result(&result);
+            result
+        })
+        .boxed()
+    }
+
+    fn get_db(&self) -> i64 {
+        self.conn
+            .as_ref()
+            .expect("The connection is only taken once")
+            .get_db()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_stateful_commands_require_a_reset() {
+        assert!(changes_connection_state(cmd("WATCH").arg("foo")));
+        assert!(changes_connection_state(&cmd("multi")));
+        assert!(changes_connection_state(cmd("SELECT").arg(1)));
+        assert!(!changes_connection_state(cmd("GET").arg("foo")));
+        assert!(!changes_connection_state(cmd("BLPOP").arg("foo").arg(0)));
+    }
+}
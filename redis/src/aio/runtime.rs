@@ -45,7 +45,7 @@ impl Runtime {
     }
 
     #[allow(dead_code)]
-    pub(super) fn spawn(&self, f: impl Future<Output = ()> + Send + 'static) {
+    pub(crate) fn spawn(&self, f: impl Future<Output = ()> + Send + 'static) {
         match self {
             #[cfg(feature = "tokio-comp")]
             Runtime::Tokio => tokio::Tokio::spawn(f),
@@ -70,6 +70,15 @@ impl Runtime {
                 .map_err(|_| Elapsed(())),
         }
     }
+
+    pub(crate) async fn sleep(&self, duration: Duration) {
+        match self {
+            #[cfg(feature = "tokio-comp")]
+            Runtime::Tokio => ::tokio::time::sleep(duration).await,
+            #[cfg(feature = "async-std-comp")]
+            Runtime::AsyncStd => ::async_std::task::sleep(duration).await,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -0,0 +1,241 @@
+use super::{ConnectionLike, ConnectionManager, ConnectionManagerConfig, RedisFuture, Runtime};
+use crate::cmd::Cmd;
+use crate::types::{ErrorKind, RedisResult, Value};
+use crate::Client;
+use futures::future;
+use futures_util::FutureExt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+/// Options for creation of a [`StripedConnectionManager`].
+#[derive(Clone, Debug)]
+pub struct StripedConnectionManagerConfig {
+    connections: usize,
+    health_check_interval: Option<Duration>,
+    manager_config: ConnectionManagerConfig,
+}
+
+impl StripedConnectionManagerConfig {
+    const DEFAULT_CONNECTIONS: usize = 4;
+
+    /// Creates a new instance of the options with the default number of connections, no
+    /// periodic health checks, and the default [`ConnectionManagerConfig`]
+    pub fn new() -> Self {
+        Self {
+            connections: Self::DEFAULT_CONNECTIONS,
+            health_check_interval: None,
+            manager_config: ConnectionManagerConfig::new(),
+        }
+    }
+
+    /// Sets the number of underlying connections
+    pub fn with_connections(mut self, connections: usize) -> Self {
+        self.connections = connections;
+        self
+    }
+
+    /// Enables periodic health checks, which send a `PING` over every connection on the given
+    /// interval, so that dropped connections are reconnected before requests are sent over them.
+    pub fn with_health_check_interval(mut self, interval: Duration) -> Self {
+        self.health_check_interval = Some(interval);
+        self
+    }
+
+    /// Sets the config of every underlying [`ConnectionManager`], which controls the timeouts and
+    /// the reconnection backoff of each connection
+    pub fn with_connection_manager_config(
+        mut self,
+        manager_config: ConnectionManagerConfig,
+    ) -> Self {
+        self.manager_config = manager_config;
+        self
+    }
+}
+
+impl Default for StripedConnectionManagerConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A `StripedConnectionManager` spreads requests across several
+/// [`ConnectionManager`]s, each of which wraps its own multiplexed
+/// connection and reconnects it on its own.
+///
+/// A single multiplexed connection sends all requests through one socket, so a
+/// request with a large response delays every request that was sent after it.
+/// Striping the requests across several sockets keeps small, latency-sensitive
+/// requests from queueing behind large ones.
+///
+/// ## Behavior
+///
+/// - All the connections are established when the manager is created, and
+///   connection errors are returned directly.
+/// - Every request is sent over the connected connection with the fewest
+///   requests in flight. Connections that are being reconnected are only used
+///   if none of the connections is connected.
+/// - A dropped connection is reconnected in the background without affecting
+///   the rest of the connections, see [`ConnectionManager`] for details.
+/// - If periodic health checks are enabled, every connection is checked with a
+///   `PING` on the given interval, which detects dropped connections before
+///   requests are sent over them.
+///
+/// Since consecutive requests might be sent over different connections, commands
+/// that depend on the state of a connection, such as `SELECT`, `WATCH` or
+/// subscriptions, shouldn't be sent through a `StripedConnectionManager`.
+#[derive(Clone)]
+pub struct StripedConnectionManager {
+    stripes: Arc<Vec<Stripe>>,
+}
+
+struct Stripe {
+    manager: ConnectionManager,
+    in_flight: AtomicUsize,
+}
+
+/// Counts a request as in flight on a stripe until it's dropped.
+struct InFlight<'a>(&'a AtomicUsize);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl StripedConnectionManager {
+    /// Connect to the server with the given number of connections, and store them inside the
+    /// returned `StripedConnectionManager`.
+    ///
+    /// This requires the `connection-manager` feature, which will also pull in
+    /// the Tokio executor.
+    pub async fn new_with_config(
+        client: Client,
+        config: StripedConnectionManagerConfig,
+    ) -> RedisResult<Self> {
+        if config.connections == 0 {
+            fail!((
+                ErrorKind::InvalidClientConfig,
+                "The number of connections should be non-zero"
+            ));
+        }
+        if config.health_check_interval == Some(Duration::ZERO) {
+            fail!((
+                ErrorKind::InvalidClientConfig,
+                "The health check interval should be non-zero"
+            ));
+        }
+
+        let managers = future::try_join_all((0..config.connections).map(|_| {
+            ConnectionManager::new_with_config(client.clone(), config.manager_config.clone())
+        }))
+        .await?;
+        let stripes = Arc::new(
+            managers
+                .into_iter()
+                .map(|manager| Stripe {
+                    manager,
+                    in_flight: AtomicUsize::new(0),
+                })
+                .collect::<Vec<_>>(),
+        );
+        if let Some(interval) = config.health_check_interval {
+            let runtime = Runtime::locate();
+            runtime.spawn(check_health(
+                runtime.clone(),
+                Arc::downgrade(&stripes),
+                interval,
+            ));
+        }
+        Ok(Self { stripes })
+    }
+
+    /// Returns the number of underlying connections.
+    pub fn connections(&self) -> usize {
+        self.stripes.len()
+    }
+
+    fn acquire(&self) -> (ConnectionManager, InFlight<'_>) {
+        let index = least_loaded(self.stripes.iter().map(|stripe| {
+            (
+                stripe.manager.is_connected(),
+                stripe.in_flight.load(Ordering::Relaxed),
+            )
+        }));
+        let stripe = &self.stripes[index];
+        stripe.in_flight.fetch_add(1, Ordering::Relaxed);
+        (stripe.manager.clone(), InFlight(&stripe.in_flight))
+    }
+}
+
+// Returns the index of the stripe that a request should be sent over, given whether every stripe
+// is connected and its number of requests in flight.
+fn least_loaded(stripes: impl Iterator<Item = (bool, usize)>) -> usize {
+    stripes
+        .enumerate()
+        .min_by_key(|(_, (connected, in_flight))| (!connected, *in_flight))
+        .map(|(index, _)| index)
+        .expect("A striped connection manager always has at least one connection")
+}
+
+// Pings every connection on the given interval, until the manager is dropped. Connections that
+// fail the check are reconnected by their `ConnectionManager`.
+async fn check_health(runtime: Runtime, stripes: Weak<Vec<Stripe>>, interval: Duration) {
+    loop {
+        runtime.sleep(interval).await;
+        let managers = match stripes.upgrade() {
+            Some(stripes) => stripes
+                .iter()
+                .map(|stripe| stripe.manager.clone())
+                .collect::<Vec<_>>(),
+            None => return,
+        };
+        future::join_all(managers.into_iter().map(|mut manager| async move {
+            let _: RedisResult<String> = crate::cmd("PING").query_async(&mut manager).await;
+        }))
+        .await;
+    }
+}
+
+impl ConnectionLike for StripedConnectionManager {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        (async move {
+            let (mut manager, _in_flight) = self.acquire();
+            manager.send_packed_command(cmd).await
+        })
+        .boxed()
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a crate::Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        (async move {
+            let (mut manager, _in_flight) = self.acquire();
+            manager.send_packed_commands(cmd, offset, count).await
+        })
+        .boxed()
+    }
+
+    fn get_db(&self) -> i64 {
+        self.stripes[0].manager.get_db()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_least_loaded_prefers_connected_stripes() {
+        assert_eq!(
+            least_loaded([(true, 3), (true, 1), (true, 2)].into_iter()),
+            1
+        );
+        assert_eq!(least_loaded([(true, 0), (true, 0)].into_iter()), 0);
+        assert_eq!(least_loaded([(false, 0), (true, 5)].into_iter()), 1);
+        assert_eq!(least_loaded([(false, 2), (false, 1)].into_iter()), 1);
+    }
+}
@@ -1,7 +1,9 @@
+use std::sync::Arc;
 use std::time::Duration;
 
 use crate::{
     connection::{connect, Connection, ConnectionInfo, ConnectionLike, IntoConnectionInfo},
+    instrumentation::Instrumentation,
     types::{RedisResult, Value},
 };
 #[cfg(feature = "aio")]
@@ -14,6 +16,9 @@ use crate::tls::{inner_build_with_tls, TlsCertificates};
 #[derive(Debug, Clone)]
 pub struct Client {
     pub(crate) connection_info: ConnectionInfo,
+    pub(crate) instrumentation: Option<Arc<dyn Instrumentation>>,
+    #[cfg(feature = "aio")]
+    pub(crate) credentials_provider: Option<Arc<dyn crate::aio::CredentialsProvider>>,
 }
 
 /// The client acts as connector to the redis server.  By itself it does not
@@ -39,16 +44,46 @@ impl Client {
     pub fn open<T: IntoConnectionInfo>(params: T) -> RedisResult<Client> {
         Ok(Client {
             connection_info: params.into_connection_info()?,
+            instrumentation: None,
+            #[cfg(feature = "aio")]
+            credentials_provider: None,
         })
     }
 
+    /// Registers an [`Instrumentation`] that is notified of every request that is sent over the
+    /// connections that are created by this client.
+    ///
+    /// See [`instrumentation`](crate::instrumentation) for details.
+    pub fn with_instrumentation(mut self, instrumentation: Arc<dyn Instrumentation>) -> Self {
+        self.instrumentation = Some(instrumentation);
+        self
+    }
+
+    /// Sets a [`CredentialsProvider`](crate::aio::CredentialsProvider) that multiplexed connections
+    /// created by this client authenticate with, instead of the username and password in the
+    /// connection info. This includes the connections of
+    /// [`ConnectionManager`](crate::aio::ConnectionManager)s and pools created from the client.
+    /// Other connections still use the connection info.
+    #[cfg(feature = "aio")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "aio")))]
+    pub fn with_credentials_provider(
+        mut self,
+        provider: Arc<dyn crate::aio::CredentialsProvider>,
+    ) -> Self {
+        self.credentials_provider = Some(provider);
+        self
+    }
+
     /// Instructs the client to actually connect to redis and returns a
     /// connection object.  The connection object can be used to send
     /// commands to the server.  This can fail with a variety of errors
     /// (like unreachable host) so it's important that you handle those
     /// errors.
     pub fn get_connection(&self) -> RedisResult<Connection> {
-        connect(&self.connection_info, None)
+        Ok(
+            connect(&self.connection_info, None)?
+                .with_instrumentation(self.instrumentation.clone()),
+        )
     }
 
     /// Instructs the client to actually connect to redis with specified
@@ -57,7 +92,8 @@ impl Client {
     /// a variety of errors (like unreachable host) so it's important
     /// that you handle those errors.
     pub fn get_connection_with_timeout(&self, timeout: Duration) -> RedisResult<Connection> {
-        connect(&self.connection_info, Some(timeout))
+        Ok(connect(&self.connection_info, Some(timeout))?
+            .with_instrumentation(self.instrumentation.clone()))
     }
 
     /// Returns a reference of client connection info object.
@@ -67,11 +103,24 @@ impl Client {
 }
 
 /// Options for creation of async connection
+#[derive(Clone)]
 pub struct AsyncConnectionConfig {
     /// Maximum time to wait for a response from the server
-    response_timeout: Option<std::time::Duration>,
+    pub(crate) response_timeout: Option<std::time::Duration>,
     /// Maximum time to wait for a connection to be established
-    connection_timeout: Option<std::time::Duration>,
+    pub(crate) connection_timeout: Option<std::time::Duration>,
+    /// Configuration of the client-side cache of each connection created with this config
+    #[cfg(feature = "aio")]
+    pub(crate) cache: Option<crate::aio::CacheConfig>,
+    /// Automatic batching of the requests that are sent over multiplexed connections
+    #[cfg(feature = "aio")]
+    pub(crate) batching: Option<crate::aio::BatchingConfig>,
+    /// Limits on the requests that are in flight over multiplexed connections
+    #[cfg(feature = "aio")]
+    pub(crate) in_flight_limits: crate::aio::InFlightLimits,
+    /// The pool of the dedicated connections that blocking commands are sent over
+    #[cfg(any(feature = "tokio-comp", feature = "async-std-comp"))]
+    pub(crate) blocking_commands_pool: Option<crate::aio::PoolConfig>,
 }
 
 impl AsyncConnectionConfig {
@@ -80,6 +129,14 @@ impl AsyncConnectionConfig {
         Self {
             response_timeout: None,
             connection_timeout: None,
+            #[cfg(feature = "aio")]
+            cache: None,
+            #[cfg(feature = "aio")]
+            batching: None,
+            #[cfg(feature = "aio")]
+            in_flight_limits: crate::aio::InFlightLimits::new(),
+            #[cfg(any(feature = "tokio-comp", feature = "async-std-comp"))]
+            blocking_commands_pool: None,
         }
     }
 
@@ -94,6 +151,50 @@ impl AsyncConnectionConfig {
         self.response_timeout = Some(response_timeout);
         self
     }
+
+    /// Enables server-assisted client-side caching, see [`CacheConfig`](crate::aio::CacheConfig).
+    ///
+    /// Each connection created with this config has its own cache. This requires RESP3.
+    #[cfg(feature = "aio")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "aio")))]
+    pub fn with_cache(mut self, cache_config: crate::aio::CacheConfig) -> Self {
+        self.cache = Some(cache_config);
+        self
+    }
+
+    /// Enables automatic batching of the requests that are sent concurrently over a multiplexed
+    /// connection, see [`BatchingConfig`](crate::aio::BatchingConfig).
+    #[cfg(feature = "aio")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "aio")))]
+    pub fn with_auto_batching(mut self, batching_config: crate::aio::BatchingConfig) -> Self {
+        self.batching = Some(batching_config);
+        self
+    }
+
+    /// Limits the requests that are in flight over a multiplexed connection, see
+    /// [`InFlightLimits`](crate::aio::InFlightLimits).
+    #[cfg(feature = "aio")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "aio")))]
+    pub fn with_in_flight_limits(mut self, in_flight_limits: crate::aio::InFlightLimits) -> Self {
+        self.in_flight_limits = in_flight_limits;
+        self
+    }
+
+    /// Sends blocking commands, such as `BLPOP`, `WAIT` or `XREAD` with `BLOCK`, over dedicated
+    /// connections from a [`Pool`](crate::aio::Pool) with the given config, instead of over the
+    /// multiplexed connection.
+    ///
+    /// A blocking command delays every request that's sent after it over the same multiplexed
+    /// connection, until it's answered. Pipelines are always sent over the multiplexed connection.
+    #[cfg(any(feature = "tokio-comp", feature = "async-std-comp"))]
+    #[cfg_attr(
+        docsrs,
+        doc(cfg(any(feature = "tokio-comp", feature = "async-std-comp")))
+    )]
+    pub fn with_blocking_commands_pool(mut self, pool_config: crate::aio::PoolConfig) -> Self {
+        self.blocking_commands_pool = Some(pool_config);
+        self
+    }
 }
 
 impl Default for AsyncConnectionConfig {
@@ -211,15 +312,13 @@ impl Client {
                     rt.timeout(
                         connection_timeout,
                         self.get_multiplexed_async_connection_inner::<crate::aio::tokio::Tokio>(
-                            config.response_timeout,
+                            config,
                         ),
                     )
                     .await
                 } else {
                     Ok(self
-                        .get_multiplexed_async_connection_inner::<crate::aio::tokio::Tokio>(
-                            config.response_timeout,
-                        )
+                        .get_multiplexed_async_connection_inner::<crate::aio::tokio::Tokio>(config)
                         .await)
                 }
             }
@@ -229,14 +328,14 @@ impl Client {
                     rt.timeout(
                         connection_timeout,
                         self.get_multiplexed_async_connection_inner::<crate::aio::async_std::AsyncStd>(
-                            config.response_timeout,
+                            config,
                         ),
                     )
                     .await
                 } else {
                     Ok(self
                         .get_multiplexed_async_connection_inner::<crate::aio::async_std::AsyncStd>(
-                            config.response_timeout,
+                            config,
                         )
                         .await)
                 }
@@ -264,9 +363,9 @@ impl Client {
         let result = Runtime::locate()
             .timeout(
                 connection_timeout,
-                self.get_multiplexed_async_connection_inner::<crate::aio::tokio::Tokio>(Some(
-                    response_timeout,
-                )),
+                self.get_multiplexed_async_connection_inner::<crate::aio::tokio::Tokio>(
+                    &AsyncConnectionConfig::new().with_response_timeout(response_timeout),
+                ),
             )
             .await;
 
@@ -286,8 +385,10 @@ impl Client {
     pub async fn get_multiplexed_tokio_connection(
         &self,
     ) -> RedisResult<crate::aio::MultiplexedConnection> {
-        self.get_multiplexed_async_connection_inner::<crate::aio::tokio::Tokio>(None)
-            .await
+        self.get_multiplexed_async_connection_inner::<crate::aio::tokio::Tokio>(
+            &AsyncConnectionConfig::new(),
+        )
+        .await
     }
 
     /// Returns an async multiplexed connection from the client.
@@ -305,7 +406,7 @@ impl Client {
             .timeout(
                 connection_timeout,
                 self.get_multiplexed_async_connection_inner::<crate::aio::async_std::AsyncStd>(
-                    Some(response_timeout),
+                    &AsyncConnectionConfig::new().with_response_timeout(response_timeout),
                 ),
             )
             .await;
@@ -326,8 +427,10 @@ impl Client {
     pub async fn get_multiplexed_async_std_connection(
         &self,
     ) -> RedisResult<crate::aio::MultiplexedConnection> {
-        self.get_multiplexed_async_connection_inner::<crate::aio::async_std::AsyncStd>(None)
-            .await
+        self.get_multiplexed_async_connection_inner::<crate::aio::async_std::AsyncStd>(
+            &AsyncConnectionConfig::new(),
+        )
+        .await
     }
 
     /// Returns an async multiplexed connection from the client and a future which must be polled
@@ -345,9 +448,9 @@ impl Client {
         crate::aio::MultiplexedConnection,
         impl std::future::Future<Output = ()>,
     )> {
-        self.create_multiplexed_async_connection_inner::<crate::aio::tokio::Tokio>(Some(
-            response_timeout,
-        ))
+        self.create_multiplexed_async_connection_inner::<crate::aio::tokio::Tokio>(
+            &AsyncConnectionConfig::new().with_response_timeout(response_timeout),
+        )
         .await
     }
 
@@ -364,8 +467,10 @@ impl Client {
         crate::aio::MultiplexedConnection,
         impl std::future::Future<Output = ()>,
     )> {
-        self.create_multiplexed_async_connection_inner::<crate::aio::tokio::Tokio>(None)
-            .await
+        self.create_multiplexed_async_connection_inner::<crate::aio::tokio::Tokio>(
+            &AsyncConnectionConfig::new(),
+        )
+        .await
     }
 
     /// Returns an async multiplexed connection from the client and a future which must be polled
@@ -383,9 +488,9 @@ impl Client {
         crate::aio::MultiplexedConnection,
         impl std::future::Future<Output = ()>,
     )> {
-        self.create_multiplexed_async_connection_inner::<crate::aio::async_std::AsyncStd>(Some(
-            response_timeout,
-        ))
+        self.create_multiplexed_async_connection_inner::<crate::aio::async_std::AsyncStd>(
+            &AsyncConnectionConfig::new().with_response_timeout(response_timeout),
+        )
         .await
     }
 
@@ -402,8 +507,10 @@ impl Client {
         crate::aio::MultiplexedConnection,
         impl std::future::Future<Output = ()>,
     )> {
-        self.create_multiplexed_async_connection_inner::<crate::aio::async_std::AsyncStd>(None)
-            .await
+        self.create_multiplexed_async_connection_inner::<crate::aio::async_std::AsyncStd>(
+            &AsyncConnectionConfig::new(),
+        )
+        .await
     }
 
     /// Returns an async [`ConnectionManager`][connection-manager] from the client.
@@ -600,15 +707,48 @@ impl Client {
         .await
     }
 
+    /// Returns an async [`ConnectionManager`][connection-manager] from the client,
+    /// created with the given [`ConnectionManagerConfig`][connection-manager-config].
+    ///
+    /// Please refer to the [`ConnectionManager`][connection-manager] docs for
+    /// detailed reconnecting behavior.
+    ///
+    /// [connection-manager]: aio/struct.ConnectionManager.html
+    /// [connection-manager-config]: aio/struct.ConnectionManagerConfig.html
+    #[cfg(feature = "connection-manager")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "connection-manager")))]
+    pub async fn get_connection_manager_with_config(
+        &self,
+        config: crate::aio::ConnectionManagerConfig,
+    ) -> RedisResult<crate::aio::ConnectionManager> {
+        crate::aio::ConnectionManager::new_with_config(self.clone(), config).await
+    }
+
+    /// Returns an async [`StripedConnectionManager`][striped-connection-manager] from the
+    /// client, which spreads requests across several connections.
+    ///
+    /// Please refer to the [`StripedConnectionManager`][striped-connection-manager] docs for
+    /// detailed behavior.
+    ///
+    /// [striped-connection-manager]: aio/struct.StripedConnectionManager.html
+    #[cfg(feature = "connection-manager")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "connection-manager")))]
+    pub async fn get_striped_connection_manager(
+        &self,
+        config: crate::aio::StripedConnectionManagerConfig,
+    ) -> RedisResult<crate::aio::StripedConnectionManager> {
+        crate::aio::StripedConnectionManager::new_with_config(self.clone(), config).await
+    }
+
     async fn get_multiplexed_async_connection_inner<T>(
         &self,
-        response_timeout: Option<std::time::Duration>,
+        config: &AsyncConnectionConfig,
     ) -> RedisResult<crate::aio::MultiplexedConnection>
     where
         T: crate::aio::RedisRuntime,
     {
         let (connection, driver) = self
-            .create_multiplexed_async_connection_inner::<T>(response_timeout)
+            .create_multiplexed_async_connection_inner::<T>(config)
             .await?;
         T::spawn(driver);
         Ok(connection)
@@ -616,7 +756,7 @@ impl Client {
 
     async fn create_multiplexed_async_connection_inner<T>(
         &self,
-        response_timeout: Option<std::time::Duration>,
+        config: &AsyncConnectionConfig,
     ) -> RedisResult<(
         crate::aio::MultiplexedConnection,
         impl std::future::Future<Output = ()>,
@@ -624,13 +764,35 @@ impl Client {
     where
         T: crate::aio::RedisRuntime,
     {
+        let credentials = match &self.credentials_provider {
+            Some(provider) => Some(provider.credentials().await?),
+            None => None,
+        };
+        let connection_info = match &credentials {
+            Some(credentials) => {
+                let mut connection_info = self.connection_info.clone();
+                credentials.apply(&mut connection_info.redis);
+                std::borrow::Cow::Owned(connection_info)
+            }
+            None => std::borrow::Cow::Borrowed(&self.connection_info),
+        };
         let con = self.get_simple_async_connection::<T>().await?;
-        crate::aio::MultiplexedConnection::new_with_response_timeout(
-            &self.connection_info,
-            con,
-            response_timeout,
-        )
-        .await
+        let (mut connection, driver) =
+            crate::aio::MultiplexedConnection::new_with_config(&connection_info, con, config)
+                .await?;
+        connection.instrumentation.clone_from(&self.instrumentation);
+        if let (Some(provider), Some(expires_at)) = (
+            &self.credentials_provider,
+            credentials.and_then(|credentials| credentials.expires_at()),
+        ) {
+            connection.refresh_credentials(provider.clone(), expires_at);
+        }
+        #[cfg(any(feature = "tokio-comp", feature = "async-std-comp"))]
+        if let Some(pool_config) = &config.blocking_commands_pool {
+            connection.blocking_commands_pool =
+                Some(crate::aio::Pool::new(self.clone(), pool_config.clone()).await?);
+        }
+        Ok((connection, driver))
     }
 
     async fn get_simple_async_connection<T>(
@@ -1,8 +1,5 @@
 //! This module extends the library to support Redis Cluster.
 //!
-//! Note that this module does not currently provide pubsub
-//! functionality.
-//!
 //! # Example
 //! ```rust,no_run
 //! use redis::Commands;
@@ -35,32 +32,58 @@
 //!     .expire(key, 60).ignore()
 //!     .query(&mut connection).unwrap();
 //! ```
+//!
+//! # PubSub
+//! ```rust,no_run
+//! use redis::cluster::ClusterClient;
+//!
+//! let nodes = vec!["redis://127.0.0.1:6379/", "redis://127.0.0.1:6378/", "redis://127.0.0.1:6377/"];
+//! let client = ClusterClient::new(nodes).unwrap();
+//! let mut connection = client.get_connection().unwrap();
+//! let mut pubsub = connection.as_pubsub();
+//! pubsub.subscribe("channel").unwrap();
+//! pubsub.ssubscribe("shard_channel").unwrap();
+//!
+//! loop {
+//!     let msg = pubsub.get_message().unwrap();
+//!     let payload: String = msg.get_payload().unwrap();
+//!     println!("channel '{}': {}", msg.get_channel_name(), payload);
+//! }
+//! ```
 use std::cell::RefCell;
-use std::collections::HashSet;
+use std::collections::{HashSet, VecDeque};
+use std::io;
 use std::str::FromStr;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use crate::cluster_pipeline::UNROUTABLE_ERROR;
+use crate::cluster_pipeline::{SplitCommand, SplitPipeline, UNROUTABLE_ERROR};
 use crate::cluster_routing::{
-    MultipleNodeRoutingInfo, ResponsePolicy, Routable, SingleNodeRoutingInfo, SlotAddr,
+    get_slot, MultipleNodeRoutingInfo, ResponsePolicy, Routable, SingleNodeRoutingInfo, SlotAddr,
 };
+use crate::cluster_scan::ClusterScanState;
 use crate::cmd::{cmd, Cmd};
 use crate::connection::{
-    connect, Connection, ConnectionAddr, ConnectionInfo, ConnectionLike, RedisConnectionInfo,
+    connect, Connection, ConnectionAddr, ConnectionInfo, ConnectionLike, Msg, RedisConnectionInfo,
 };
 use crate::parser::parse_redis_value;
-use crate::types::{ErrorKind, HashMap, RedisError, RedisResult, Value};
+use crate::types::{
+    from_owned_redis_value, ErrorKind, FromRedisValue, HashMap, PushKind, RedisError, RedisResult,
+    ToRedisArgs, Value,
+};
 use crate::IntoConnectionInfo;
 pub use crate::TlsMode; // Pub for backwards compatibility
 use crate::{
     cluster_client::ClusterParams,
-    cluster_routing::{Redirect, Route, RoutingInfo, Slot, SlotMap, SLOT_SIZE},
+    cluster_routing::{NodeMetadata, Redirect, Route, RoutingInfo, Slot, SlotMap, SLOT_SIZE},
 };
 use rand::{seq::IteratorRandom, thread_rng, Rng};
 
-pub use crate::cluster_client::{ClusterClient, ClusterClientBuilder};
+pub use crate::cluster_client::{
+    AddressMapper, ClusterClient, ClusterClientBuilder, MappedAddress,
+};
 pub use crate::cluster_pipeline::{cluster_pipe, ClusterPipeline};
+pub use crate::cluster_scan::ScanOptions;
 
 #[cfg(feature = "tls-rustls")]
 use crate::tls::TlsConnParams;
@@ -224,7 +247,10 @@ where
     ) -> RedisResult<Self> {
         let connection = Self {
             connections: RefCell::new(HashMap::new()),
-            slots: RefCell::new(SlotMap::new(cluster_params.read_from_replicas)),
+            slots: RefCell::new(
+                SlotMap::new(cluster_params.read_from_replicas)
+                    .with_read_strategy(cluster_params.read_from_replica_strategy.clone()),
+            ),
             auto_reconnect: RefCell::new(true),
             read_timeout: RefCell::new(None),
             write_timeout: RefCell::new(None),
@@ -289,6 +315,62 @@ where
         Ok(())
     }
 
+    /// Creates a [`ClusterPubSub`], which subscribes through its own connections to the
+    /// cluster's nodes.
+    pub fn as_pubsub(&mut self) -> ClusterPubSub<'_, C> {
+        ClusterPubSub::new(self)
+    }
+
+    /// Iterates over the keys of the whole cluster, by running `SCAN` on every primary.
+    ///
+    /// The primaries are scanned one after another. The scan doesn't query the topology itself,
+    /// but slots that the connection learns have moved to another node while their primary is
+    /// scanned, such as from a redirect, are scanned again on their new owner. As with `SCAN`, keys
+    /// might be returned more than once.
+    ///
+    /// ```rust,no_run
+    /// use redis::cluster::{ClusterClient, ScanOptions};
+    ///
+    /// let client = ClusterClient::new(vec!["redis://127.0.0.1:6379/"]).unwrap();
+    /// let mut connection = client.get_connection().unwrap();
+    /// for key in connection.scan_cluster::<String>(ScanOptions::default().with_pattern("user:*")) {
+    ///     println!("{}", key.unwrap());
+    /// }
+    /// ```
+    pub fn scan_cluster<T: FromRedisValue>(
+        &mut self,
+        options: ScanOptions,
+    ) -> ClusterScanIter<'_, T, C> {
+        ClusterScanIter {
+            con: self,
+            state: ClusterScanState::new(options),
+            batch: Vec::new().into_iter(),
+            retries: 0,
+            done: false,
+            _marker: std::marker::PhantomData,
+        }
+    }
+
+    // Sends the next `SCAN` of a cluster-wide scan, and returns the keys that it found, or `None`
+    // if the scan is complete.
+    fn scan_next_batch(&self, state: &mut ClusterScanState) -> RedisResult<Option<Vec<Value>>> {
+        let (addr, scan) = match state.next_request(&self.slots.borrow()) {
+            Some(request) => request,
+            None => return Ok(None),
+        };
+        let keys = {
+            let mut connections = self.connections.borrow_mut();
+            let conn = self.get_connection_by_addr(&mut connections, &addr)?;
+            state.handle_response(scan.query(conn)?)?
+        };
+        if state.is_node_done() {
+            // Slots that are known to have moved away while the node was scanned are scanned on
+            // their new owner.
+            state.finish_node(&self.slots.borrow());
+        }
+        Ok(Some(keys))
+    }
+
     /// Check that all connections it has are available (`PING` internally).
     #[doc(hidden)]
     pub fn check_connection(&mut self) -> bool {
@@ -373,13 +455,19 @@ where
         let mut samples = connections.values_mut().choose_multiple(&mut rng, len);
 
         for conn in samples.iter_mut() {
-            let value = conn.req_command(&slot_cmd())?;
-            if let Ok(slots_data) = parse_slots(value, self.cluster_params.tls) {
-                new_slots = Some(SlotMap::from_slots(
-                    slots_data,
-                    self.cluster_params.read_from_replicas,
-                ));
-                break;
+            match query_topology(*conn, self.cluster_params.tls) {
+                Ok(slots_data) => {
+                    new_slots = Some(
+                        SlotMap::from_slots(slots_data, self.cluster_params.read_from_replicas)
+                            .with_read_strategy(
+                                self.cluster_params.read_from_replica_strategy.clone(),
+                            ),
+                    );
+                    break;
+                }
+                // A malformed response might be specific to this node, so ask the next one.
+                Err(err) if err.kind() == ErrorKind::TypeError => continue,
+                Err(err) => return Err(err),
             }
         }
 
@@ -440,41 +528,33 @@ where
         }
     }
 
-    fn get_addr_for_cmd(&self, cmd: &Cmd) -> RedisResult<String> {
-        let slots = self.slots.borrow();
-
-        let addr_for_slot = |route: Route| -> RedisResult<String> {
-            let slot_addr = slots
-                .slot_addr_for_route(&route)
-                .ok_or((ErrorKind::ClusterDown, "Missing slot coverage"))?;
-            Ok(slot_addr.to_string())
-        };
-
-        match RoutingInfo::for_routable(cmd) {
-            Some(RoutingInfo::SingleNode(SingleNodeRoutingInfo::Random)) => {
-                let mut rng = thread_rng();
-                Ok(addr_for_slot(Route::new(
-                    rng.gen_range(0..SLOT_SIZE),
-                    SlotAddr::Master,
-                ))?)
-            }
-            Some(RoutingInfo::SingleNode(SingleNodeRoutingInfo::SpecificNode(route))) => {
-                Ok(addr_for_slot(route)?)
+    fn get_addr_for_routing(&self, routing: &RoutingInfo) -> RedisResult<String> {
+        let route = match routing {
+            RoutingInfo::SingleNode(SingleNodeRoutingInfo::SpecificNode(route)) => *route,
+            RoutingInfo::SingleNode(SingleNodeRoutingInfo::Random) => {
+                Route::new(thread_rng().gen_range(0..SLOT_SIZE), SlotAddr::Master)
             }
             _ => fail!(UNROUTABLE_ERROR),
-        }
+        };
+        let slot_addr = self
+            .slots
+            .borrow()
+            .slot_addr_for_route(&route)
+            .ok_or((ErrorKind::ClusterDown, "Missing slot coverage"))?
+            .to_string();
+        Ok(slot_addr)
     }
 
-    fn map_cmds_to_nodes(&self, cmds: &[Cmd]) -> RedisResult<Vec<NodeCmd>> {
+    fn map_cmds_to_nodes(&self, cmds: &[SplitCommand]) -> RedisResult<Vec<NodeCmd>> {
         let mut cmd_map: HashMap<String, NodeCmd> = HashMap::new();
 
-        for (idx, cmd) in cmds.iter().enumerate() {
-            let addr = self.get_addr_for_cmd(cmd)?;
+        for (idx, split_cmd) in cmds.iter().enumerate() {
+            let addr = self.get_addr_for_routing(&split_cmd.routing)?;
             let nc = cmd_map
                 .entry(addr.clone())
                 .or_insert_with(|| NodeCmd::new(addr));
             nc.indexes.push(idx);
-            cmd.write_packed_command(&mut nc.pipe);
+            split_cmd.cmd.write_packed_command(&mut nc.pipe);
         }
 
         let mut result = Vec::new();
@@ -711,7 +791,10 @@ where
                         }
                     }
                 };
-                (addr, input.send(conn))
+                let started = Instant::now();
+                let rv = input.send(conn);
+                self.cluster_params.record_request(&addr, started, &rv);
+                (addr, rv)
             };
 
             match rv {
@@ -764,6 +847,11 @@ where
     }
 
     fn send_recv_and_retry_cmds(&self, cmds: &[Cmd]) -> RedisResult<Vec<Value>> {
+        // Multi-slot commands are split into a command per slot, and their responses are
+        // combined once all of them were received.
+        let split = SplitPipeline::new(cmds)?;
+        let cmds = split.commands();
+
         // Vector to hold the results, pre-populated with `Nil` values. This allows the original
         // cmd ordering to be re-established by inserting the response directly into the result
         // vector (e.g., results[10] = response).
@@ -774,7 +862,7 @@ where
             .and_then(|node_cmds| self.recv_all_commands(&mut results, &node_cmds))?;
 
         if to_retry.is_empty() {
-            return Ok(results);
+            return split.combine(results);
         }
 
         // Refresh the slots to ensure that we have a clean slate for the retry attempts.
@@ -784,14 +872,14 @@ where
         // topology changed. Execute each command seperately to take advantage of the existing
         // retry logic that handles these cases.
         for retry_idx in to_retry {
-            let cmd = &cmds[retry_idx];
+            let cmd = &cmds[retry_idx].cmd;
             results[retry_idx] = self.request(Input::Cmd(cmd))?.into();
         }
-        Ok(results)
+        split.combine(results)
     }
 
     // Build up a pipeline per node, then send it
-    fn send_all_commands(&self, cmds: &[Cmd]) -> RedisResult<Vec<NodeCmd>> {
+    fn send_all_commands(&self, cmds: &[SplitCommand]) -> RedisResult<Vec<NodeCmd>> {
         let mut connections = self.connections.borrow_mut();
 
         let node_cmds = self.map_cmds_to_nodes(cmds)?;
@@ -907,6 +995,450 @@ impl<C: Connect + ConnectionLike> ConnectionLike for ClusterConnection<C> {
     }
 }
 
+/// The iterator of a cluster-wide scan, created by [`ClusterConnection::scan_cluster`].
+///
+/// When a node fails, its scan is started over after the slots were refreshed, up to the
+/// configured number of retries. The iterator ends after returning an error.
+pub struct ClusterScanIter<'a, T, C = Connection> {
+    con: &'a mut ClusterConnection<C>,
+    state: ClusterScanState,
+    batch: std::vec::IntoIter<Value>,
+    retries: u32,
+    done: bool,
+    _marker: std::marker::PhantomData<T>,
+}
+
+impl<'a, T, C> Iterator for ClusterScanIter<'a, T, C>
+where
+    T: FromRedisValue,
+    C: ConnectionLike + Connect,
+{
+    type Item = RedisResult<T>;
+
+    fn next(&mut self) -> Option<RedisResult<T>> {
+        loop {
+            if let Some(key) = self.batch.next() {
+                return Some(from_owned_redis_value(key));
+            }
+            if self.done {
+                return None;
+            }
+            match self.con.scan_next_batch(&mut self.state) {
+                Ok(Some(keys)) => {
+                    self.retries = 0;
+                    self.batch = keys.into_iter();
+                }
+                Ok(None) => {
+                    self.done = true;
+                    return None;
+                }
+                Err(err) => {
+                    if self.retries >= self.con.cluster_params.retry_params.number_of_retries {
+                        self.done = true;
+                        return Some(Err(err));
+                    }
+                    self.retries += 1;
+                    self.state.restart_node();
+                    let _ = self.con.refresh_slots();
+                }
+            }
+        }
+    }
+}
+
+/// The time [`ClusterPubSub::get_message`] waits for a message from one node before checking the
+/// next one, when it's subscribed through several nodes.
+const PUBSUB_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The pubsub object of a Redis cluster, created by [`ClusterConnection::as_pubsub`].
+///
+/// Channel and pattern subscriptions are sent to a single node, since their messages are
+/// propagated to the whole cluster. Shard channel subscriptions are sent to the primary that owns
+/// the shard channel's slot, with one connection per shard. When a node fails, or a shard
+/// channel's slot moves to another shard, the affected subscriptions are renewed on the node that
+/// now serves them.
+pub struct ClusterPubSub<'a, C = Connection> {
+    con: &'a mut ClusterConnection<C>,
+    connections: HashMap<String, C>,
+    // The node that receives the channel and pattern subscriptions.
+    channels_node: Option<String>,
+    channels: HashSet<Vec<u8>>,
+    patterns: HashSet<Vec<u8>>,
+    // The node that each shard channel was subscribed on.
+    shard_channels: HashMap<Vec<u8>, String>,
+    waiting_messages: VecDeque<Msg>,
+    read_timeout: Option<Duration>,
+    next_node: usize,
+}
+
+impl<'a, C> ClusterPubSub<'a, C>
+where
+    C: ConnectionLike + Connect,
+{
+    fn new(con: &'a mut ClusterConnection<C>) -> Self {
+        let read_timeout = *con.read_timeout.borrow();
+        Self {
+            con,
+            connections: HashMap::new(),
+            channels_node: None,
+            channels: HashSet::new(),
+            patterns: HashSet::new(),
+            shard_channels: HashMap::new(),
+            waiting_messages: VecDeque::new(),
+            read_timeout,
+            next_node: 0,
+        }
+    }
+
+    /// Subscribes to a new channel.
+    pub fn subscribe<T: ToRedisArgs>(&mut self, channel: T) -> RedisResult<()> {
+        for channel in channel.to_redis_args() {
+            let node = self.channels_node()?;
+            self.send_to_node(&node, cmd("SUBSCRIBE").arg(&channel))?;
+            self.channels.insert(channel);
+        }
+        Ok(())
+    }
+
+    /// Subscribes to a new channel with a pattern.
+    pub fn psubscribe<T: ToRedisArgs>(&mut self, pchannel: T) -> RedisResult<()> {
+        for pattern in pchannel.to_redis_args() {
+            let node = self.channels_node()?;
+            self.send_to_node(&node, cmd("PSUBSCRIBE").arg(&pattern))?;
+            self.patterns.insert(pattern);
+        }
+        Ok(())
+    }
+
+    /// Subscribes to a new shard channel, on the primary that owns the channel's slot.
+    pub fn ssubscribe<T: ToRedisArgs>(&mut self, channel: T) -> RedisResult<()> {
+        for channel in channel.to_redis_args() {
+            self.ssubscribe_on_owner(channel)?;
+        }
+        Ok(())
+    }
+
+    /// Unsubscribes from a channel. Passing no channels unsubscribes from all channels.
+    pub fn unsubscribe<T: ToRedisArgs>(&mut self, channel: T) -> RedisResult<()> {
+        let channels = take_subscriptions(&mut self.channels, channel.to_redis_args());
+        self.unsubscribe_on_channels_node("UNSUBSCRIBE", channels)
+    }
+
+    /// Unsubscribes from a channel with a pattern. Passing no patterns unsubscribes from all
+    /// patterns.
+    pub fn punsubscribe<T: ToRedisArgs>(&mut self, pchannel: T) -> RedisResult<()> {
+        let patterns = take_subscriptions(&mut self.patterns, pchannel.to_redis_args());
+        self.unsubscribe_on_channels_node("PUNSUBSCRIBE", patterns)
+    }
+
+    /// Unsubscribes from a shard channel. Passing no channels unsubscribes from all shard
+    /// channels.
+    pub fn sunsubscribe<T: ToRedisArgs>(&mut self, channel: T) -> RedisResult<()> {
+        let channels = channel.to_redis_args();
+        let channels: Vec<_> = if channels.is_empty() {
+            self.shard_channels.drain().collect()
+        } else {
+            channels
+                .into_iter()
+                .filter_map(|channel| self.shard_channels.remove_entry(&channel))
+                .collect()
+        };
+        for (channel, node) in channels {
+            if self.connections.contains_key(&node) {
+                self.send_to_node(&node, cmd("SUNSUBSCRIBE").arg(&channel))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Fetches the next message from the pubsub connections. Blocks until a message is received
+    /// from any of the subscribed nodes, or until the read timeout is reached.
+    ///
+    /// Subscriptions on nodes that failed, and shard channels whose slot moved, are renewed on
+    /// the nodes that currently serve them while waiting for messages.
+    pub fn get_message(&mut self) -> RedisResult<Msg> {
+        let deadline = self.read_timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            if let Some(msg) = self.waiting_messages.pop_front() {
+                return Ok(msg);
+            }
+
+            for node in self.lost_nodes() {
+                self.renew_node_subscriptions(&node)?;
+            }
+            if self.connections.is_empty() {
+                fail!((
+                    ErrorKind::ClientError,
+                    "No subscriptions to receive messages from"
+                ));
+            }
+
+            let mut nodes: Vec<String> = self.connections.keys().cloned().collect();
+            nodes.sort_unstable();
+            self.next_node = (self.next_node + 1) % nodes.len();
+            nodes.rotate_left(self.next_node);
+            let poll_interval = (nodes.len() > 1).then_some(PUBSUB_POLL_INTERVAL);
+
+            for node in nodes {
+                let timeout = match deadline {
+                    Some(deadline) => {
+                        let remaining = deadline.saturating_duration_since(Instant::now());
+                        if remaining.is_zero() {
+                            return Err(io::Error::from(io::ErrorKind::TimedOut).into());
+                        }
+                        Some(poll_interval.map_or(remaining, |interval| interval.min(remaining)))
+                    }
+                    None => poll_interval,
+                };
+                self.receive_from_node(&node, timeout)?;
+                if !self.waiting_messages.is_empty() {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Sets the read timeout for [`ClusterPubSub::get_message`].
+    ///
+    /// If the provided value is `None`, then `get_message` call will
+    /// block indefinitely. It is an error to pass the zero `Duration` to this
+    /// method.
+    pub fn set_read_timeout(&mut self, dur: Option<Duration>) -> RedisResult<()> {
+        if dur.is_some() && dur.unwrap().is_zero() {
+            return Err(RedisError::from((
+                ErrorKind::InvalidClientConfig,
+                "Duration should be None or non-zero.",
+            )));
+        }
+        self.read_timeout = dur;
+        Ok(())
+    }
+
+    // Returns the node that receives channel and pattern subscriptions, choosing one if there's
+    // none yet.
+    fn channels_node(&mut self) -> RedisResult<String> {
+        if let Some(node) = &self.channels_node {
+            return Ok(node.clone());
+        }
+        let node = match self.connections.keys().next() {
+            Some(node) => node.clone(),
+            None => self
+                .con
+                .slots
+                .borrow()
+                .addresses_for_all_primaries()
+                .into_iter()
+                .choose(&mut thread_rng())
+                .ok_or((ErrorKind::ClusterDown, "Missing slot coverage"))?
+                .to_string(),
+        };
+        self.channels_node = Some(node.clone());
+        Ok(node)
+    }
+
+    fn ssubscribe_on_owner(&mut self, channel: Vec<u8>) -> RedisResult<()> {
+        let route = Route::new(get_slot(&channel), SlotAddr::Master);
+        let node = self
+            .con
+            .slots
+            .borrow()
+            .slot_addr_for_route(&route)
+            .ok_or((ErrorKind::ClusterDown, "Missing slot coverage"))?
+            .to_string();
+
+        let node = match self.send_to_node(&node, cmd("SSUBSCRIBE").arg(&channel)) {
+            Ok(()) => node,
+            Err(err) => {
+                let redirect = err.redirect_node().map(|(node, _)| node.to_string());
+                match (err.kind(), redirect) {
+                    (ErrorKind::Moved, Some(node)) => {
+                        self.con.refresh_slots()?;
+                        self.send_to_node(&node, cmd("SSUBSCRIBE").arg(&channel))?;
+                        node
+                    }
+                    _ => return Err(err),
+                }
+            }
+        };
+        self.shard_channels.insert(channel, node);
+        Ok(())
+    }
+
+    fn unsubscribe_on_channels_node(
+        &mut self,
+        unsubscribe_cmd: &str,
+        names: Vec<Vec<u8>>,
+    ) -> RedisResult<()> {
+        let node = match self.channels_node.clone() {
+            Some(node) => node,
+            None => return Ok(()),
+        };
+        for name in names {
+            self.send_to_node(&node, cmd(unsubscribe_cmd).arg(&name))?;
+        }
+        Ok(())
+    }
+
+    // Sends a (un)subscription command to `node`, and waits for its confirmation. Messages that
+    // arrive in the meantime are kept for `get_message`.
+    fn send_to_node(&mut self, node: &str, cmd: &Cmd) -> RedisResult<()> {
+        if !self.connections.contains_key(node) {
+            let conn = self.con.connect(node)?;
+            self.connections.insert(node.to_string(), conn);
+        }
+        let conn = self.connections.get_mut(node).unwrap();
+        // `receive_from_node` shortens the read timeout while it polls the node, so the
+        // confirmation is waited for with the configured one.
+        let read_timeout = *self.con.read_timeout.borrow();
+        let result = conn
+            .set_read_timeout(read_timeout)
+            .and_then(|_| send_and_confirm(conn, cmd, &mut self.waiting_messages));
+        if let Err(err) = &result {
+            if err.is_unrecoverable_error() {
+                self.connections.remove(node);
+            }
+        }
+        result
+    }
+
+    // Reads a single response from `node`, and renews the subscriptions that the node stopped
+    // serving.
+    fn receive_from_node(&mut self, node: &str, timeout: Option<Duration>) -> RedisResult<()> {
+        let conn = match self.connections.get_mut(node) {
+            Some(conn) => conn,
+            None => return Ok(()),
+        };
+        let response = conn
+            .set_read_timeout(timeout)
+            .and_then(|_| conn.recv_response());
+        match response {
+            Ok(value) => {
+                if let Some(msg) = Msg::from_value(&value) {
+                    self.waiting_messages.push_back(msg);
+                    return Ok(());
+                }
+                match sunsubscribed_channel(&value) {
+                    // Redis ends shard channel subscriptions when their slot is migrated.
+                    Some(channel)
+                        if self.shard_channels.get(&channel).map(String::as_str) == Some(node) =>
+                    {
+                        self.con.refresh_slots()?;
+                        self.ssubscribe_on_owner(channel)
+                    }
+                    _ => Ok(()),
+                }
+            }
+            Err(err) if err.is_timeout() => Ok(()),
+            Err(err) if err.is_unrecoverable_error() => self.renew_node_subscriptions(node),
+            Err(err) => Err(err),
+        }
+    }
+
+    // Returns the nodes that have subscriptions, but no connection.
+    fn lost_nodes(&self) -> Vec<String> {
+        let channels_node = self
+            .channels_node
+            .iter()
+            .filter(|_| !self.channels.is_empty() || !self.patterns.is_empty());
+        let mut nodes: Vec<String> = channels_node
+            .chain(self.shard_channels.values())
+            .filter(|node| !self.connections.contains_key(*node))
+            .cloned()
+            .collect();
+        nodes.sort_unstable();
+        nodes.dedup();
+        nodes
+    }
+
+    // Drops the connection to `node`, and renews its subscriptions on the nodes that serve them
+    // now. Subscriptions that couldn't be renewed remain assigned to `node`, and are retried by
+    // the next `get_message` call.
+    fn renew_node_subscriptions(&mut self, node: &str) -> RedisResult<()> {
+        self.connections.remove(node);
+        self.con.refresh_slots()?;
+
+        if self.channels_node.as_deref() == Some(node) {
+            self.channels_node = None;
+            let channels: Vec<_> = self.channels.iter().cloned().collect();
+            let patterns: Vec<_> = self.patterns.iter().cloned().collect();
+            let new_node = self.channels_node()?;
+            let result = channels
+                .iter()
+                .try_for_each(|channel| self.send_to_node(&new_node, cmd("SUBSCRIBE").arg(channel)))
+                .and_then(|_| {
+                    patterns.iter().try_for_each(|pattern| {
+                        self.send_to_node(&new_node, cmd("PSUBSCRIBE").arg(pattern))
+                    })
+                });
+            if result.is_err() {
+                self.connections.remove(&new_node);
+                self.channels_node = Some(node.to_string());
+            }
+            result?;
+        }
+
+        let shard_channels: Vec<_> = self
+            .shard_channels
+            .iter()
+            .filter(|(_, shard_node)| *shard_node == node)
+            .map(|(channel, _)| channel.clone())
+            .collect();
+        for channel in shard_channels {
+            self.ssubscribe_on_owner(channel)?;
+        }
+        Ok(())
+    }
+}
+
+// Removes `names` from `subscriptions`, or all subscriptions if `names` is empty, and returns the
+// names that should be unsubscribed.
+fn take_subscriptions(subscriptions: &mut HashSet<Vec<u8>>, names: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+    if names.is_empty() {
+        return subscriptions.drain().collect();
+    }
+    for name in names.iter() {
+        subscriptions.remove(name);
+    }
+    names
+}
+
+fn send_and_confirm<C: Connect>(
+    conn: &mut C,
+    cmd: &Cmd,
+    waiting_messages: &mut VecDeque<Msg>,
+) -> RedisResult<()> {
+    conn.send_packed_command(&cmd.get_packed_command())?;
+    loop {
+        let response = conn.recv_response()?;
+        match Msg::from_value(&response) {
+            Some(msg) => waiting_messages.push_back(msg),
+            None => return Ok(()),
+        }
+    }
+}
+
+// Returns the shard channel of a `sunsubscribe` notification.
+fn sunsubscribed_channel(value: &Value) -> Option<Vec<u8>> {
+    match value {
+        Value::Push {
+            kind: PushKind::SUnsubscribe,
+            data,
+        } => match data.first() {
+            Some(Value::BulkString(channel)) => Some(channel.clone()),
+            _ => None,
+        },
+        Value::Array(items) => match items.as_slice() {
+            [Value::BulkString(kind), Value::BulkString(channel), ..]
+                if kind.eq_ignore_ascii_case(b"sunsubscribe") =>
+            {
+                Some(channel.clone())
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
 #[derive(Debug)]
 struct NodeCmd {
     // The original command indexes
@@ -963,6 +1495,7 @@ pub(crate) fn parse_slots(raw_slot_resp: Value, tls: Option<TlsMode>) -> RedisRe
                 continue;
             };
 
+            let mut metadata = Vec::new();
             let mut nodes: Vec<String> = item
                 .into_iter()
                 .skip(2)
@@ -987,7 +1520,17 @@ pub(crate) fn parse_slots(raw_slot_resp: Value, tls: Option<TlsMode>) -> RedisRe
                             return None;
                         };
                         // This is only "stringifying" IP addresses, so `TLS parameters` are not required
-                        Some(get_connection_addr(ip.into_owned(), port, tls, None).to_string())
+                        let addr =
+                            get_connection_addr(ip.into_owned(), port, tls, None).to_string();
+                        // Since Redis 7, the node's ID and networking metadata follow its port.
+                        if let Some(Ok(node_metadata)) =
+                            node.get(3).map(NodeMetadata::from_redis_value)
+                        {
+                            if !node_metadata.is_empty() {
+                                metadata.push((addr.clone(), node_metadata));
+                            }
+                        }
+                        Some(addr)
                     } else {
                         None
                     }
@@ -999,13 +1542,122 @@ pub(crate) fn parse_slots(raw_slot_resp: Value, tls: Option<TlsMode>) -> RedisRe
             }
 
             let replicas = nodes.split_off(1);
-            result.push(Slot::new(start, end, nodes.pop().unwrap(), replicas));
+            result.push(
+                Slot::new(start, end, nodes.pop().unwrap(), replicas).with_metadata(metadata),
+            );
+        }
+    }
+
+    Ok(result)
+}
+
+// The fields of a shard, or of a node within a shard, in a `CLUSTER SHARDS` response.
+type ShardFields = std::collections::HashMap<String, Value>;
+
+// Node fields that are either part of the node's address, or change too often to be considered
+// part of the topology, and so aren't kept in the node's metadata.
+const SHARD_NODE_ADDRESS_FIELDS: &[&str] = &["ip", "endpoint", "port", "tls-port"];
+const SHARD_NODE_VOLATILE_FIELDS: &[&str] = &["replication-offset"];
+
+// Parse slot data from a raw `CLUSTER SHARDS` response. Replicas that aren't online, for example
+// because they're still loading or have failed, are skipped.
+pub(crate) fn parse_shards(raw_shards_resp: Value, tls: Option<TlsMode>) -> RedisResult<Vec<Slot>> {
+    let shards: Vec<ShardFields> = FromRedisValue::from_owned_redis_value(raw_shards_resp)?;
+    let mut result = Vec::with_capacity(shards.len());
+
+    for shard in shards {
+        let slots: Vec<u16> = match shard.get("slots") {
+            Some(slots) => FromRedisValue::from_redis_value(slots)?,
+            None => continue,
+        };
+        let nodes: Vec<ShardFields> = match shard.get("nodes") {
+            Some(nodes) => FromRedisValue::from_redis_value(nodes)?,
+            None => continue,
+        };
+
+        let mut primary = None;
+        let mut replicas = Vec::new();
+        let mut metadata = Vec::new();
+        for node in nodes {
+            let addr = match shard_node_addr(&node, tls) {
+                Some(addr) => addr,
+                None => continue,
+            };
+            let node_metadata: NodeMetadata = node
+                .iter()
+                .filter(|(field, _)| {
+                    !SHARD_NODE_ADDRESS_FIELDS.contains(&field.as_str())
+                        && !SHARD_NODE_VOLATILE_FIELDS.contains(&field.as_str())
+                })
+                .filter_map(|(field, value)| {
+                    String::from_redis_value(value)
+                        .ok()
+                        .filter(|value| !value.is_empty())
+                        .map(|value| (field.clone(), value))
+                })
+                .collect();
+
+            let is_primary = node_metadata
+                .get("role")
+                .map_or(false, |role| role == "master");
+            let is_online = node_metadata
+                .get("health")
+                .map_or(true, |health| health == "online");
+            if is_primary {
+                primary = Some(addr.clone());
+            } else if is_online {
+                replicas.push(addr.clone());
+            } else {
+                continue;
+            }
+            metadata.push((addr, node_metadata));
+        }
+
+        let primary = match primary {
+            Some(primary) => primary,
+            None => continue,
+        };
+        for range in slots.chunks_exact(2) {
+            result.push(
+                Slot::new(range[0], range[1], primary.clone(), replicas.clone())
+                    .with_metadata(metadata.clone()),
+            );
         }
     }
 
     Ok(result)
 }
 
+// Returns the address of a node in a `CLUSTER SHARDS` response, preferring its endpoint over its IP,
+// and its TLS port over its plain port when TLS is used.
+fn shard_node_addr(node: &ShardFields, tls: Option<TlsMode>) -> Option<String> {
+    let field = |name: &str| {
+        node.get(name)
+            .and_then(|value| String::from_redis_value(value).ok())
+            .filter(|value| !value.is_empty() && value != "?")
+    };
+    let host = field("endpoint").or_else(|| field("ip"))?;
+    let port_field = if tls.is_some() && node.contains_key("tls-port") {
+        "tls-port"
+    } else {
+        "port"
+    };
+    let port = node
+        .get(port_field)
+        .and_then(|port| u16::from_redis_value(port).ok())?;
+    // This is only "stringifying" IP addresses, so `TLS parameters` are not required
+    Some(get_connection_addr(host, port, tls, None).to_string())
+}
+
+// Queries the cluster's topology with `CLUSTER SHARDS`, falling back to `CLUSTER SLOTS` on servers
+// that don't support it.
+fn query_topology(conn: &mut impl ConnectionLike, tls: Option<TlsMode>) -> RedisResult<Vec<Slot>> {
+    match parse_shards_reply(conn.req_command(&shards_cmd()), tls) {
+        Some(slots) => slots,
+        None => parse_slots(conn.req_command(&slot_cmd())?, tls),
+    }
+}
+
 // The node string passed to this function will always be in the format host:port as it is either:
 // - Created by calling ConnectionAddr::to_string (unix connections are not supported in cluster mode)
 // - Returned from redis via the ASK/MOVED response
@@ -1024,13 +1676,24 @@ pub(crate) fn get_connection_info(
         })
         .ok_or_else(invalid_error)?;
 
+    let (host, port, tls_params) = match &cluster_params.address_mapper {
+        Some(mapper) => {
+            let mapped = mapper.map_address(host, port);
+            let tls_params = match mapped.tls_server_name() {
+                #[cfg(any(feature = "tls-native-tls", feature = "tls-rustls"))]
+                Some(server_name) => Some(TlsConnParams::with_server_name(
+                    cluster_params.tls_params,
+                    server_name.to_string(),
+                )),
+                _ => cluster_params.tls_params,
+            };
+            (mapped.host().to_string(), mapped.port(), tls_params)
+        }
+        None => (host.to_string(), port, cluster_params.tls_params),
+    };
+
     Ok(ConnectionInfo {
-        addr: get_connection_addr(
-            host.to_string(),
-            port,
-            cluster_params.tls,
-            cluster_params.tls_params,
-        ),
+        addr: get_connection_addr(host, port, cluster_params.tls, tls_params),
         redis: RedisConnectionInfo {
             password: cluster_params.password,
             username: cluster_params.username,
@@ -1069,9 +1732,284 @@ pub(crate) fn slot_cmd() -> Cmd {
     cmd
 }
 
+pub(crate) fn shards_cmd() -> Cmd {
+    let mut cmd = Cmd::new();
+    cmd.arg("CLUSTER").arg("SHARDS");
+    cmd
+}
+
+/// Parses the reply to [`shards_cmd`]. Returns `None` if the server doesn't know `CLUSTER SHARDS`,
+/// which is the case before Redis 7, so that the topology should be queried with [`slot_cmd`]
+/// instead. Any other error, such as a missing permission, is returned as is.
+pub(crate) fn parse_shards_reply(
+    reply: RedisResult<Value>,
+    tls: Option<TlsMode>,
+) -> Option<RedisResult<Vec<Slot>>> {
+    match reply {
+        Ok(value) => Some(parse_shards(value, tls)),
+        Err(err) if is_unknown_command_error(&err) => None,
+        Err(err) => Some(Err(err)),
+    }
+}
+
+fn is_unknown_command_error(err: &RedisError) -> bool {
+    err.kind() == ErrorKind::ResponseError
+        && err.detail().map_or(false, |detail| {
+            let detail = detail.to_ascii_lowercase();
+            detail.starts_with("unknown command") || detail.starts_with("unknown subcommand")
+        })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn parse_slots_with_node_metadata() {
+        let node = |port: i64, metadata: Vec<Value>| {
+            Value::Array(vec![
+                Value::BulkString(b"127.0.0.1".to_vec()),
+                Value::Int(port),
+                Value::BulkString(b"node-id".to_vec()),
+                Value::Array(metadata),
+            ])
+        };
+        let response = Value::Array(vec![Value::Array(vec![
+            Value::Int(0),
+            Value::Int(16383),
+            node(
+                6379,
+                vec![
+                    Value::BulkString(b"hostname".to_vec()),
+                    Value::BulkString(b"primary.example".to_vec()),
+                ],
+            ),
+            node(6380, vec![]),
+        ])]);
+
+        let slots = parse_slots(response, None).unwrap();
+        assert_eq!(slots.len(), 1);
+        assert_eq!(slots[0].master, "127.0.0.1:6379");
+        assert_eq!(slots[0].replicas, vec!["127.0.0.1:6380".to_string()]);
+        assert_eq!(
+            slots[0].metadata,
+            std::collections::HashMap::from([(
+                "127.0.0.1:6379".to_string(),
+                std::collections::HashMap::from([(
+                    "hostname".to_string(),
+                    "primary.example".to_string()
+                )])
+            )])
+        );
+    }
+
+    fn shard_node(fields: &[(&str, Value)]) -> Value {
+        Value::Array(
+            fields
+                .iter()
+                .flat_map(|(field, value)| {
+                    [Value::BulkString(field.as_bytes().to_vec()), value.clone()]
+                })
+                .collect(),
+        )
+    }
+
+    fn shard_node_with(port: i64, role: &str, health: &str) -> Value {
+        shard_node(&[
+            ("id", Value::BulkString(format!("node-{port}").into_bytes())),
+            ("port", Value::Int(port)),
+            ("ip", Value::BulkString(b"127.0.0.1".to_vec())),
+            ("endpoint", Value::BulkString(b"127.0.0.1".to_vec())),
+            ("role", Value::BulkString(role.as_bytes().to_vec())),
+            ("replication-offset", Value::Int(port * 100)),
+            ("health", Value::BulkString(health.as_bytes().to_vec())),
+        ])
+    }
+
+    #[test]
+    fn parse_shards_skips_replicas_that_are_not_online() {
+        let response = Value::Array(vec![shard_node(&[
+            (
+                "slots",
+                Value::Array(vec![
+                    Value::Int(0),
+                    Value::Int(100),
+                    Value::Int(200),
+                    Value::Int(16383),
+                ]),
+            ),
+            (
+                "nodes",
+                Value::Array(vec![
+                    shard_node_with(6380, "replica", "online"),
+                    shard_node_with(6379, "master", "online"),
+                    shard_node_with(6381, "replica", "loading"),
+                    shard_node_with(6382, "replica", "failed"),
+                ]),
+            ),
+        ])]);
+
+        let slots = parse_shards(response, None).unwrap();
+        assert_eq!(slots.len(), 2);
+        assert_eq!((slots[0].start, slots[0].end), (0, 100));
+        assert_eq!((slots[1].start, slots[1].end), (200, 16383));
+        for slot in slots {
+            assert_eq!(slot.master, "127.0.0.1:6379");
+            assert_eq!(slot.replicas, vec!["127.0.0.1:6380".to_string()]);
+            assert_eq!(
+                slot.metadata["127.0.0.1:6380"],
+                std::collections::HashMap::from([
+                    ("id".to_string(), "node-6380".to_string()),
+                    ("role".to_string(), "replica".to_string()),
+                    ("health".to_string(), "online".to_string()),
+                ])
+            );
+            assert!(!slot.metadata.contains_key("127.0.0.1:6381"));
+            assert!(!slot.metadata.contains_key("127.0.0.1:6382"));
+        }
+    }
+
+    #[test]
+    fn falls_back_to_cluster_slots_only_on_unknown_commands() {
+        let server_error = |kind, detail: &str| -> RedisResult<Value> {
+            Err(RedisError::from((
+                kind,
+                "An error was signalled by the server",
+                detail.to_string(),
+            )))
+        };
+        assert!(parse_shards_reply(
+            server_error(
+                ErrorKind::ResponseError,
+                "unknown subcommand 'SHARDS'. Try CLUSTER HELP."
+            ),
+            None
+        )
+        .is_none());
+        assert!(parse_shards_reply(
+            server_error(
+                ErrorKind::ResponseError,
+                "Unknown subcommand or wrong number of arguments for 'SHARDS'. Try CLUSTER HELP."
+            ),
+            None
+        )
+        .is_none());
+
+        let err = parse_shards_reply(
+            server_error(
+                ErrorKind::ResponseError,
+                "This instance has cluster support disabled",
+            ),
+            None,
+        )
+        .unwrap()
+        .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ResponseError);
+        let err = parse_shards_reply(
+            Err(crate::types::make_extension_error(
+                "NOPERM".to_string(),
+                Some(
+                    "this user has no permissions to run the 'cluster|shards' command".to_string(),
+                ),
+            )),
+            None,
+        )
+        .unwrap()
+        .unwrap_err();
+        assert_eq!(err.code(), Some("NOPERM"));
+    }
+
+    #[test]
+    fn parse_shards_from_resp3_maps() {
+        let node = |port: i64, role: &str, extra: Vec<(Value, Value)>| {
+            let mut fields = vec![
+                (Value::BulkString(b"port".to_vec()), Value::Int(port)),
+                (
+                    Value::BulkString(b"tls-port".to_vec()),
+                    Value::Int(port + 1000),
+                ),
+                (
+                    Value::BulkString(b"ip".to_vec()),
+                    Value::BulkString(b"10.0.0.1".to_vec()),
+                ),
+                (
+                    Value::BulkString(b"role".to_vec()),
+                    Value::BulkString(role.as_bytes().to_vec()),
+                ),
+            ];
+            fields.extend(extra);
+            Value::Map(fields)
+        };
+        let response = Value::Array(vec![Value::Map(vec![
+            (
+                Value::BulkString(b"slots".to_vec()),
+                Value::Array(vec![Value::Int(0), Value::Int(16383)]),
+            ),
+            (
+                Value::BulkString(b"nodes".to_vec()),
+                Value::Array(vec![
+                    node(
+                        6379,
+                        "master",
+                        vec![(
+                            Value::BulkString(b"endpoint".to_vec()),
+                            Value::BulkString(b"primary.example".to_vec()),
+                        )],
+                    ),
+                    node(
+                        6380,
+                        "replica",
+                        vec![(
+                            Value::BulkString(b"endpoint".to_vec()),
+                            Value::BulkString(b"?".to_vec()),
+                        )],
+                    ),
+                ]),
+            ),
+        ])]);
+
+        let slots = parse_shards(response.clone(), None).unwrap();
+        assert_eq!(slots.len(), 1);
+        assert_eq!(slots[0].master, "primary.example:6379");
+        assert_eq!(slots[0].replicas, vec!["10.0.0.1:6380".to_string()]);
+
+        let slots = parse_shards(response, Some(TlsMode::Secure)).unwrap();
+        assert_eq!(slots[0].master, "primary.example:7379");
+        assert_eq!(slots[0].replicas, vec!["10.0.0.1:7380".to_string()]);
+    }
+
+    #[test]
+    fn get_connection_info_uses_address_mapper() {
+        let params = ClusterParams {
+            address_mapper: Some(Arc::new(|host: &str, port: u16| {
+                MappedAddress::new(format!("proxy-for-{host}"), port + 1000)
+                    .with_tls_server_name(host)
+            })),
+            tls: Some(TlsMode::Secure),
+            ..Default::default()
+        };
+
+        let info = get_connection_info("10.0.0.1:6379", params).unwrap();
+        match info.addr {
+            ConnectionAddr::TcpTls {
+                host,
+                port,
+                tls_params,
+                ..
+            } => {
+                assert_eq!((host.as_str(), port), ("proxy-for-10.0.0.1", 7379));
+                #[cfg(any(feature = "tls-native-tls", feature = "tls-rustls"))]
+                assert_eq!(
+                    crate::connection::tls_server_name(&host, &tls_params),
+                    "10.0.0.1"
+                );
+                #[cfg(not(any(feature = "tls-native-tls", feature = "tls-rustls")))]
+                assert!(tls_params.is_none());
+            }
+            addr => panic!("Unexpected address {addr:?}"),
+        }
+    }
 
     #[test]
     fn parse_cluster_node_host_port() {
@@ -5,7 +5,12 @@
 //! the sync cluster module, certain commands do not route identically, due most notably to
 //! a current lack of support for routing commands to multiple nodes.
 //!
-//! Also note that pubsub functionality is not currently provided by this module.
+//! # Pub/Sub
+//! With RESP3, [`ClusterConnection`] can subscribe to channels, patterns and shard channels. Each
+//! subscription is sent to the primary that owns the channel's slot, and is moved to the new owner
+//! when the slot map changes - for example after a slot migration, a failover or a `MOVED`
+//! response. Messages from all nodes are delivered through the connection's
+//! [`PushManager`](crate::PushManager).
 //!
 //! # Example
 //! ```rust,no_run
@@ -25,21 +30,24 @@ use std::{
     collections::HashMap,
     fmt, io, mem,
     pin::Pin,
-    sync::{Arc, Mutex},
+    sync::{Arc, Mutex, Weak},
     task::{self, Poll},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use crate::{
-    aio::{ConnectionLike, MultiplexedConnection},
-    cluster::{get_connection_info, parse_slots, slot_cmd},
+    aio::{ConnectionLike, MultiplexedConnection, SubscriptionKind},
+    cluster::{get_connection_info, parse_shards_reply, parse_slots, shards_cmd, slot_cmd},
     cluster_client::{ClusterParams, RetryParams},
+    cluster_pipeline::SplitPipeline,
     cluster_routing::{
-        self, MultipleNodeRoutingInfo, Redirect, ResponsePolicy, Route, RoutingInfo,
+        self, get_slot, MultipleNodeRoutingInfo, Redirect, ResponsePolicy, Route, RoutingInfo,
         SingleNodeRoutingInfo, Slot, SlotAddr, SlotMap,
     },
-    Cmd, ConnectionInfo, ErrorKind, IntoConnectionInfo, RedisError, RedisFuture, RedisResult,
-    Value,
+    cluster_scan::{ClusterScanState, ScanOptions},
+    cmd, from_owned_redis_value, instrumentation, Cmd, ConnectionInfo, ErrorKind, FromRedisValue,
+    IntoConnectionInfo, ProtocolVersion, PushInfo, PushKind, PushManager, RedisError, RedisFuture,
+    RedisResult, ToRedisArgs, Value,
 };
 
 #[cfg(all(not(feature = "tokio-comp"), feature = "async-std-comp"))]
@@ -47,14 +55,26 @@ use crate::aio::{async_std::AsyncStd, RedisRuntime};
 use futures::{future::BoxFuture, prelude::*, ready};
 use log::{trace, warn};
 use pin_project_lite::pin_project;
-use rand::{seq::IteratorRandom, thread_rng};
+use rand::{seq::IteratorRandom, thread_rng, Rng};
 use tokio::sync::{mpsc, oneshot, RwLock};
 
+mod node_connections;
+use node_connections::{
+    ConnectionFuture, ConnectionKind, InFlightConnection, NodeConnection, NodeConnections,
+};
+
+mod pubsub;
+use pubsub::{subscription_route, ClusterSubscriptions, Relocation, SubscriptionOwner};
+
 /// This represents an async Redis Cluster connection. It stores the
 /// underlying connections maintained for each node in the cluster, as well
 /// as common parameters for connecting to nodes and executing commands.
 #[derive(Clone)]
-pub struct ClusterConnection<C = MultiplexedConnection>(mpsc::Sender<Message<C>>);
+pub struct ClusterConnection<C = MultiplexedConnection> {
+    sender: mpsc::Sender<Message<C>>,
+    core: Core<C>,
+    push_manager: PushManager,
+}
 
 impl<C> ClusterConnection<C>
 where
@@ -62,11 +82,20 @@ where
 {
     pub(crate) async fn new(
         initial_nodes: &[ConnectionInfo],
-        cluster_params: ClusterParams,
+        mut cluster_params: ClusterParams,
     ) -> RedisResult<ClusterConnection<C>> {
-        ClusterConnInner::new(initial_nodes, cluster_params)
+        // The node connections push into an internal channel, so that the connection can react
+        // to subscription changes before the messages are passed on to the user.
+        let (push_sender, push_receiver) = mpsc::unbounded_channel();
+        cluster_params.push_manager = PushManager::new();
+        cluster_params.push_manager.replace_sender(push_sender);
+        let (refresh_sender, refresh_receiver) = mpsc::unbounded_channel();
+
+        ClusterConnInner::new(initial_nodes, cluster_params, refresh_sender)
             .await
             .map(|inner| {
+                let core = inner.inner.clone();
+                let push_manager = PushManager::new();
                 let (tx, mut rx) = mpsc::channel::<Message<_>>(100);
                 let stream = async move {
                     let _ = stream::poll_fn(move |cx| rx.poll_recv(cx))
@@ -74,46 +103,141 @@ where
                         .forward(inner)
                         .await;
                 };
+                let pushes =
+                    forward_pushes(push_receiver, push_manager.clone(), Arc::downgrade(&core));
+                let topology_checks = core
+                    .cluster_params
+                    .topology_checks_interval
+                    .map(|interval| check_topology_periodically(Arc::downgrade(&core), interval));
+                let redirect_refreshes =
+                    refresh_slots_after_redirects(refresh_receiver, Arc::downgrade(&core));
                 #[cfg(feature = "tokio-comp")]
-                tokio::spawn(stream);
+                {
+                    tokio::spawn(stream);
+                    tokio::spawn(pushes);
+                    tokio::spawn(redirect_refreshes);
+                    if let Some(topology_checks) = topology_checks {
+                        tokio::spawn(topology_checks);
+                    }
+                }
                 #[cfg(all(not(feature = "tokio-comp"), feature = "async-std-comp"))]
-                AsyncStd::spawn(stream);
+                {
+                    AsyncStd::spawn(stream);
+                    AsyncStd::spawn(pushes);
+                    AsyncStd::spawn(redirect_refreshes);
+                    if let Some(topology_checks) = topology_checks {
+                        AsyncStd::spawn(topology_checks);
+                    }
+                }
 
-                ClusterConnection(tx)
+                ClusterConnection {
+                    sender: tx,
+                    core,
+                    push_manager,
+                }
             })
     }
 
+    /// Returns a stream over the keys of the whole cluster, by running `SCAN` on every primary.
+    ///
+    /// The primaries are scanned one after another. The scan doesn't query the topology itself,
+    /// but slots that the connection learns have moved to another node while their primary is
+    /// scanned, from a redirect or a periodic topology check, are scanned again on their new owner.
+    /// As with `SCAN`, keys might be returned more than once. When a node fails,
+    /// its scan is started over after the slots were refreshed, up to the configured number of
+    /// retries. The stream ends after returning an error.
+    ///
+    /// ```rust,no_run
+    /// use futures::StreamExt;
+    /// use redis::cluster::{ClusterClient, ScanOptions};
+    ///
+    /// # async fn do_something() -> redis::RedisResult<()> {
+    /// let client = ClusterClient::new(vec!["redis://127.0.0.1:6379/"]).unwrap();
+    /// let connection = client.get_async_connection().await?;
+    /// let mut keys = connection.scan_cluster::<String>(ScanOptions::default().with_type("hash"));
+    /// while let Some(key) = keys.next().await {
+    ///     println!("{}", key?);
+    /// }
+    /// # Ok(()) }
+    /// ```
+    pub fn scan_cluster<T>(
+        &self,
+        options: ScanOptions,
+    ) -> impl Stream<Item = RedisResult<T>> + Send + 'static
+    where
+        T: FromRedisValue + Send + 'static,
+    {
+        let scan = ClusterScan {
+            core: self.core.clone(),
+            state: ClusterScanState::new(options),
+            retries: 0,
+            done: false,
+        };
+        stream::unfold(scan, |mut scan| async move {
+            if scan.done {
+                return None;
+            }
+            loop {
+                match ClusterConnInner::scan_next_batch(scan.core.clone(), &mut scan.state).await {
+                    Ok(Some(keys)) => {
+                        scan.retries = 0;
+                        let keys: Vec<RedisResult<T>> =
+                            keys.into_iter().map(from_owned_redis_value).collect();
+                        return Some((keys, scan));
+                    }
+                    Ok(None) => return None,
+                    Err(err) => {
+                        if scan.retries >= scan.core.cluster_params.retry_params.number_of_retries {
+                            scan.done = true;
+                            return Some((vec![Err(err)], scan));
+                        }
+                        scan.retries += 1;
+                        scan.state.restart_node();
+                        let _ = ClusterConnInner::refresh_slots(scan.core.clone()).await;
+                    }
+                }
+            }
+        })
+        .flat_map(stream::iter)
+    }
+
     /// Send a command to the given `routing`, and aggregate the response according to `response_policy`.
+    ///
+    /// A timeout that was set with [`Cmd::set_timeout`] bounds the whole request, including its
+    /// retries and the refreshes of the slots that it waits for.
     pub async fn route_command(&mut self, cmd: &Cmd, routing: RoutingInfo) -> RedisResult<Value> {
         trace!("send_packed_command");
-        let (sender, receiver) = oneshot::channel();
-        self.0
-            .send(Message {
-                cmd: CmdArg::Cmd {
-                    cmd: Arc::new(cmd.clone()), // TODO Remove this clone?
-                    routing: routing.into(),
-                },
-                sender,
-            })
-            .await
-            .map_err(|_| {
-                RedisError::from(io::Error::new(
-                    io::ErrorKind::BrokenPipe,
-                    "redis_cluster: Unable to send command",
-                ))
-            })?;
-        receiver
-            .await
-            .unwrap_or_else(|_| {
-                Err(RedisError::from(io::Error::new(
-                    io::ErrorKind::BrokenPipe,
-                    "redis_cluster: Unable to receive command",
-                )))
-            })
-            .map(|response| match response {
-                Response::Single(value) => value,
-                Response::Multiple(_) => unreachable!(),
-            })
+        let request = async {
+            let (sender, receiver) = oneshot::channel();
+            self.sender
+                .send(Message {
+                    cmd: CmdArg::Cmd {
+                        cmd: Arc::new(cmd.clone()), // TODO Remove this clone?
+                        routing: routing.into(),
+                    },
+                    sender,
+                })
+                .await
+                .map_err(|_| {
+                    RedisError::from(io::Error::new(
+                        io::ErrorKind::BrokenPipe,
+                        "redis_cluster: Unable to send command",
+                    ))
+                })?;
+            receiver
+                .await
+                .unwrap_or_else(|_| {
+                    Err(RedisError::from(io::Error::new(
+                        io::ErrorKind::BrokenPipe,
+                        "redis_cluster: Unable to receive command",
+                    )))
+                })
+                .map(|response| match response {
+                    Response::Single(value) => value,
+                    Response::Multiple(_) => unreachable!(),
+                })
+        };
+        with_timeout(cmd.get_timeout(), request).await
     }
 
     /// Send commands in `pipeline` to the given `route`. If `route` is [None], it will be sent to a random node.
@@ -124,38 +248,478 @@ where
         count: usize,
         route: SingleNodeRoutingInfo,
     ) -> RedisResult<Vec<Value>> {
-        let (sender, receiver) = oneshot::channel();
-        self.0
-            .send(Message {
-                cmd: CmdArg::Pipeline {
-                    pipeline: Arc::new(pipeline.clone()), // TODO Remove this clone?
-                    offset,
-                    count,
-                    route: route.into(),
-                },
-                sender,
-            })
+        let request = async {
+            let (sender, receiver) = oneshot::channel();
+            self.sender
+                .send(Message {
+                    cmd: CmdArg::Pipeline {
+                        pipeline: Arc::new(pipeline.clone()), // TODO Remove this clone?
+                        offset,
+                        count,
+                        route: route.into(),
+                    },
+                    sender,
+                })
+                .await
+                .map_err(|_| RedisError::from(io::Error::from(io::ErrorKind::BrokenPipe)))?;
+
+            receiver
+                .await
+                .unwrap_or_else(|_| {
+                    Err(RedisError::from(io::Error::from(io::ErrorKind::BrokenPipe)))
+                })
+                .map(|response| match response {
+                    Response::Multiple(values) => values,
+                    Response::Single(_) => unreachable!(),
+                })
+        };
+        with_timeout(pipeline.get_timeout(), request).await
+    }
+
+    // Sends the commands of a pipeline to the nodes that serve them, after splitting multi-slot
+    // commands into a command per slot. The commands of each node are sent in order over its
+    // connection, and only the commands that failed with a cluster error, such as `MOVED` or
+    // `ASK`, are retried, each on its own.
+    async fn route_split_pipeline(
+        &mut self,
+        pipeline: &crate::Pipeline,
+    ) -> RedisResult<Vec<Value>> {
+        let split = SplitPipeline::new(pipeline.cmd_iter())?;
+        let mut node_cmds: HashMap<String, Vec<usize>> = HashMap::new();
+        // Commands that are sent through the regular request handling, one by one.
+        let mut single_cmds = Vec::new();
+        {
+            let read_guard = self.core.conn_lock.read().await;
+            for (index, split_cmd) in split.commands().iter().enumerate() {
+                let addr = match &split_cmd.routing {
+                    RoutingInfo::SingleNode(SingleNodeRoutingInfo::SpecificNode(route)) => {
+                        read_guard.1.slot_addr_for_route(route).map(str::to_string)
+                    }
+                    RoutingInfo::SingleNode(_) => {
+                        get_random_connection(&read_guard.0).map(|(addr, _)| addr)
+                    }
+                    RoutingInfo::MultiNode(_) => None,
+                };
+                match addr {
+                    Some(addr) => node_cmds.entry(addr).or_default().push(index),
+                    None => single_cmds.push(index),
+                }
+            }
+        }
+
+        let core = &self.core;
+        let split_cmds = split.commands();
+        let node_results =
+            future::join_all(node_cmds.into_iter().map(|(addr, indices)| async move {
+                let conn = match ClusterConnInner::get_connection_by_addr(
+                    &addr,
+                    core.clone(),
+                    ConnectionKind::Requests,
+                )
+                .await
+                {
+                    Ok(conn) => conn,
+                    // The commands weren't sent, so they can be retried safely.
+                    Err(_) => return (indices, None),
+                };
+                let responses = future::join_all(indices.iter().map(|index| {
+                    let mut conn = C::clone(&conn);
+                    async move { conn.req_packed_command(&split_cmds[*index].cmd).await }
+                }))
+                .await;
+                (indices, Some(responses))
+            }))
+            .await;
+
+        let mut results = vec![Value::Nil; split_cmds.len()];
+        let mut first_err = None;
+        for (indices, responses) in node_results {
+            let responses = match responses {
+                Some(responses) => responses,
+                None => {
+                    single_cmds.extend(indices);
+                    continue;
+                }
+            };
+            for (index, response) in indices.into_iter().zip(responses) {
+                match response {
+                    Ok(value) => results[index] = value,
+                    Err(err) if err.is_cluster_error() => single_cmds.push(index),
+                    Err(err) => first_err = first_err.or(Some(err)),
+                }
+            }
+        }
+        if let Some(err) = first_err {
+            return Err(err);
+        }
+
+        single_cmds.sort_unstable();
+        for index in single_cmds {
+            let split_cmd = &split_cmds[index];
+            results[index] = self
+                .route_command(&split_cmd.cmd, split_cmd.routing.clone())
+                .await?;
+        }
+        split.combine(results)
+    }
+
+    /// Runs a transaction that watches `keys`, retrying it until none of the keys was modified
+    /// before the transaction was executed, like [`transaction`](crate::transaction) does for
+    /// synchronous connections.
+    ///
+    /// Since `WATCH` applies to the connection that sent it, the transaction uses a dedicated
+    /// connection to the primary that owns the keys' slot, instead of the connection that is
+    /// shared with other requests. `func` receives a clone of that connection, which should be
+    /// used to read the watched keys, and an atomic pipeline, and returns the pipeline to execute.
+    /// All watched keys, and all keys in the returned pipeline, must belong to the same slot. If
+    /// the slot moves to another node, the slots are refreshed and the transaction is retried on
+    /// the new owner.
+    ///
+    /// ```rust,no_run
+    /// use redis::AsyncCommands;
+    /// # async fn do_something() -> redis::RedisResult<()> {
+    /// # let client = redis::cluster::ClusterClient::new(vec!["redis://127.0.0.1/"]).unwrap();
+    /// # let mut connection = client.get_async_connection().await?;
+    /// let key = "the_key";
+    /// let (new_val,): (isize,) = connection
+    ///     .transaction(&[key], |mut con, mut pipe| async move {
+    ///         let old_val: isize = con.get(key).await?;
+    ///         pipe.set(key, old_val + 1).ignore().get(key);
+    ///         Ok(pipe)
+    ///     })
+    ///     .await?;
+    /// println!("The incremented number is: {}", new_val);
+    /// # Ok(()) }
+    /// ```
+    pub async fn transaction<K, T, F, Fut>(&mut self, keys: &[K], mut func: F) -> RedisResult<T>
+    where
+        K: ToRedisArgs,
+        T: FromRedisValue,
+        F: FnMut(C, crate::Pipeline) -> Fut,
+        Fut: Future<Output = RedisResult<crate::Pipeline>>,
+    {
+        let route = Route::new(transaction_slot(keys)?, SlotAddr::Master);
+        let mut connection = None;
+        let mut retries = 0;
+        loop {
+            match self
+                .try_transaction(&route, keys, &mut connection, &mut func)
+                .await
+            {
+                Ok(Some(value)) => return from_owned_redis_value(value),
+                // One of the watched keys was modified.
+                Ok(None) => continue,
+                Err(err)
+                    if err.kind() == ErrorKind::Moved
+                        && retries < self.core.cluster_params.retry_params.number_of_retries =>
+                {
+                    retries += 1;
+                    connection = None;
+                    ClusterConnInner::refresh_slots(self.core.clone()).await?;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    // Executes the transaction once, on a connection to the current owner of `route`. Returns
+    // `None` if the transaction was aborted because a watched key was modified.
+    async fn try_transaction<K, F, Fut>(
+        &self,
+        route: &Route,
+        keys: &[K],
+        connection: &mut Option<(String, C)>,
+        func: &mut F,
+    ) -> RedisResult<Option<Value>>
+    where
+        K: ToRedisArgs,
+        F: FnMut(C, crate::Pipeline) -> Fut,
+        Fut: Future<Output = RedisResult<crate::Pipeline>>,
+    {
+        let addr = match self
+            .core
+            .conn_lock
+            .read()
+            .await
+            .1
+            .slot_addr_for_route(route)
+        {
+            Some(addr) => addr.to_string(),
+            None => fail!((ErrorKind::ClusterDown, "Missing slot coverage")),
+        };
+        if connection.as_ref().map(|(conn_addr, _)| conn_addr) != Some(&addr) {
+            let conn = connect_and_check::<C>(&addr, self.core.cluster_params.clone()).await?;
+            *connection = Some((addr, conn));
+        }
+        let conn = &mut connection.as_mut().unwrap().1;
+
+        cmd("WATCH").arg(keys).query_async::<_, ()>(conn).await?;
+        let mut pipeline = crate::Pipeline::new();
+        pipeline.atomic();
+        let mut pipeline = func(conn.clone(), pipeline).await?;
+        if let Some(pipeline_route) = route_for_pipeline(&pipeline)? {
+            if pipeline_route.slot() != route.slot() {
+                fail!((
+                    ErrorKind::CrossSlot,
+                    "Transaction keys belong to different slots"
+                ));
+            }
+        }
+        match pipeline.atomic().query_async(conn).await? {
+            Value::Nil => Ok(None),
+            value => Ok(Some(value)),
+        }
+    }
+
+    /// Returns the `PushManager` of the connection, which receives the push messages of all the
+    /// nodes in the cluster.
+    pub fn get_push_manager(&self) -> PushManager {
+        self.push_manager.clone()
+    }
+
+    fn check_resp3(&self) -> RedisResult<()> {
+        if self.core.cluster_params.protocol == ProtocolVersion::RESP2 {
+            fail!((
+                ErrorKind::InvalidClientConfig,
+                "RESP3 is required for this command"
+            ));
+        }
+        Ok(())
+    }
+
+    async fn subscribe_inner(
+        &mut self,
+        kind: SubscriptionKind,
+        names: impl ToRedisArgs,
+    ) -> RedisResult<()> {
+        self.check_resp3()?;
+        // Each name is sent separately, since the names might belong to different slots.
+        for name in names.to_redis_args() {
+            let route = subscription_route(&name);
+            let mut cmd = cmd(kind.subscribe_cmd());
+            cmd.arg(&name);
+            self.route_command(
+                &cmd,
+                RoutingInfo::SingleNode(SingleNodeRoutingInfo::SpecificNode(route)),
+            )
+            .await?;
+            let owner = {
+                let read_guard = self.core.conn_lock.read().await;
+                read_guard
+                    .1
+                    .slot_addr_for_route(&route)
+                    .map(|address| SubscriptionOwner {
+                        address: address.to_string(),
+                        generation: read_guard
+                            .0
+                            .get(address)
+                            .map(NodeConnections::subscriptions_generation),
+                    })
+            };
+            self.core
+                .subscriptions
+                .lock()
+                .unwrap()
+                .insert(kind, name, owner);
+        }
+        Ok(())
+    }
+
+    async fn unsubscribe_inner(
+        &mut self,
+        kind: SubscriptionKind,
+        names: impl ToRedisArgs,
+    ) -> RedisResult<()> {
+        self.check_resp3()?;
+        let names = {
+            let mut subscriptions = self.core.subscriptions.lock().unwrap();
+            let names = names.to_redis_args();
+            if names.is_empty() {
+                subscriptions.remove_kind(kind)
+            } else {
+                for name in names.iter() {
+                    subscriptions.remove(kind, name);
+                }
+                names
+            }
+        };
+        for name in names {
+            let mut cmd = cmd(kind.unsubscribe_cmd());
+            cmd.arg(&name);
+            self.route_command(
+                &cmd,
+                RoutingInfo::SingleNode(SingleNodeRoutingInfo::SpecificNode(subscription_route(
+                    &name,
+                ))),
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Subscribes to a new channel. The subscription is sent to the primary that owns the
+    /// channel's slot, and follows it when the slot moves.
+    pub async fn subscribe(&mut self, channel_name: impl ToRedisArgs) -> RedisResult<()> {
+        self.subscribe_inner(SubscriptionKind::Exact, channel_name)
             .await
-            .map_err(|_| RedisError::from(io::Error::from(io::ErrorKind::BrokenPipe)))?;
+    }
 
-        receiver
+    /// Unsubscribes from channel. Without any channels, unsubscribes from all channels that were
+    /// subscribed through this connection.
+    pub async fn unsubscribe(&mut self, channel_name: impl ToRedisArgs) -> RedisResult<()> {
+        self.unsubscribe_inner(SubscriptionKind::Exact, channel_name)
             .await
-            .unwrap_or_else(|_| Err(RedisError::from(io::Error::from(io::ErrorKind::BrokenPipe))))
-            .map(|response| match response {
-                Response::Multiple(values) => values,
-                Response::Single(_) => unreachable!(),
-            })
+    }
+
+    /// Subscribes to a new channel with pattern. The subscription is sent to the primary that
+    /// owns the pattern's slot, and follows it when the slot moves.
+    pub async fn psubscribe(&mut self, channel_pattern: impl ToRedisArgs) -> RedisResult<()> {
+        self.subscribe_inner(SubscriptionKind::Pattern, channel_pattern)
+            .await
+    }
+
+    /// Unsubscribes from channel pattern. Without any patterns, unsubscribes from all patterns
+    /// that were subscribed through this connection.
+    pub async fn punsubscribe(&mut self, channel_pattern: impl ToRedisArgs) -> RedisResult<()> {
+        self.unsubscribe_inner(SubscriptionKind::Pattern, channel_pattern)
+            .await
+    }
+
+    /// Subscribes to a new shard channel. The subscription is sent to the primary that owns the
+    /// channel's slot, and follows it when the slot moves.
+    pub async fn ssubscribe(&mut self, channel_name: impl ToRedisArgs) -> RedisResult<()> {
+        self.subscribe_inner(SubscriptionKind::Sharded, channel_name)
+            .await
+    }
+
+    /// Unsubscribes from shard channel. Without any channels, unsubscribes from all shard
+    /// channels that were subscribed through this connection.
+    pub async fn sunsubscribe(&mut self, channel_name: impl ToRedisArgs) -> RedisResult<()> {
+        self.unsubscribe_inner(SubscriptionKind::Sharded, channel_name)
+            .await
+    }
+}
+
+// The progress of a stream returned by `ClusterConnection::scan_cluster`.
+struct ClusterScan<C> {
+    core: Core<C>,
+    state: ClusterScanState,
+    retries: u32,
+    done: bool,
+}
+
+// Passes the push messages of the node connections on to the user. When a node stops serving
+// subscriptions - either because its connection was lost, or because a shard channel's slot was
+// migrated away from it - the slots are refreshed, which renews the affected subscriptions.
+async fn forward_pushes<C>(
+    mut receiver: mpsc::UnboundedReceiver<PushInfo>,
+    push_manager: PushManager,
+    core: Weak<InnerCore<C>>,
+) where
+    C: ConnectionLike + Connect + Clone + Send + Sync + 'static,
+{
+    while let Some(PushInfo { kind, data }) = receiver.recv().await {
+        let core = match core.upgrade() {
+            Some(core) => core,
+            None => return,
+        };
+        let renew_subscriptions = match (&kind, data.first()) {
+            (PushKind::Disconnection, _) => !core.subscriptions.lock().unwrap().is_empty(),
+            // A user's unsubscription is removed before it's sent, so a tracked shard channel
+            // means that the server ended the subscription.
+            (PushKind::SUnsubscribe, Some(Value::BulkString(channel))) => {
+                let mut subscriptions = core.subscriptions.lock().unwrap();
+                let ended = subscriptions.contains(SubscriptionKind::Sharded, channel);
+                if ended {
+                    subscriptions.forget_owner(SubscriptionKind::Sharded, channel);
+                }
+                ended
+            }
+            _ => false,
+        };
+        push_manager.try_send_raw(&Value::Push { kind, data });
+        if renew_subscriptions {
+            if let Err(err) = ClusterConnInner::refresh_slots(core).await {
+                warn!("Can't refresh slots to renew subscriptions: `{err}`");
+            }
+        }
     }
 }
 
-type ConnectionFuture<C> = future::Shared<BoxFuture<'static, C>>;
-type ConnectionMap<C> = HashMap<String, ConnectionFuture<C>>;
+// Checks the cluster's topology every `interval`, with jitter, and refreshes the slots when they
+// changed, until the connection is dropped.
+async fn check_topology_periodically<C>(core: Weak<InnerCore<C>>, interval: Duration)
+where
+    C: ConnectionLike + Connect + Clone + Send + Sync + 'static,
+{
+    loop {
+        let jittered_interval = interval.mul_f64(thread_rng().gen_range(0.9..1.1));
+        boxed_sleep(jittered_interval).await;
+        let core = match core.upgrade() {
+            Some(core) => core,
+            None => return,
+        };
+        match ClusterConnInner::topology_changed(&core).await {
+            Ok(false) => continue,
+            Ok(true) => trace!("Topology changed, refreshing slots"),
+            Err(err) => warn!("Can't check topology, refreshing slots: `{err}`"),
+        }
+        if let Err(err) = ClusterConnInner::refresh_slots(core).await {
+            warn!("Can't refresh slots after a topology check: `{err}`");
+        }
+    }
+}
+
+// The minimal interval between the slot refreshes that follow `MOVED` redirects.
+const REDIRECT_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+// Refreshes the slots after `MOVED` redirects, at most once per `REDIRECT_REFRESH_INTERVAL`. The
+// redirected slots are already patched in the slot map, so the refresh only needs to catch up with
+// the rest of the migration, and requests aren't blocked while it waits or runs.
+async fn refresh_slots_after_redirects<C>(
+    mut requests: mpsc::UnboundedReceiver<()>,
+    core: Weak<InnerCore<C>>,
+) where
+    C: ConnectionLike + Connect + Clone + Send + Sync + 'static,
+{
+    let mut last_refresh: Option<Instant> = None;
+    while requests.recv().await.is_some() {
+        if let Some(last_refresh) = last_refresh {
+            let elapsed = last_refresh.elapsed();
+            if elapsed < REDIRECT_REFRESH_INTERVAL {
+                boxed_sleep(REDIRECT_REFRESH_INTERVAL - elapsed).await;
+            }
+        }
+        // Redirects that arrived while waiting are covered by this refresh.
+        while requests.try_recv().is_ok() {}
+        let core = match core.upgrade() {
+            Some(core) => core,
+            None => return,
+        };
+        if let Err(err) = ClusterConnInner::refresh_slots(core).await {
+            warn!("Can't refresh slots after a redirect: `{err}`");
+        }
+        last_refresh = Some(Instant::now());
+    }
+}
+
+type ConnectionMap<C> = HashMap<String, NodeConnections<C>>;
+
+// A subscription to renew, with the subscription connections of its previous and current owners.
+type SubscriptionRenewal<C> = (
+    Relocation,
+    Option<ConnectionFuture<C>>,
+    Option<ConnectionFuture<C>>,
+);
 
 struct InnerCore<C> {
     conn_lock: RwLock<(ConnectionMap<C>, SlotMap)>,
     cluster_params: ClusterParams,
     pending_requests: Mutex<Vec<PendingRequest<C>>>,
     initial_nodes: Vec<ConnectionInfo>,
+    subscriptions: Mutex<ClusterSubscriptions>,
+    // Requests a rate-limited slot refresh, after a `MOVED` redirect was patched into the slot map.
+    slots_refresh_requests: mpsc::UnboundedSender<()>,
 }
 
 type Core<C> = Arc<InnerCore<C>>;
@@ -200,7 +764,7 @@ enum InternalSingleNodeRouting<C> {
     ByAddress(String),
     Connection {
         identifier: String,
-        conn: ConnectionFuture<C>,
+        conn: NodeConnection<C>,
     },
     Redirect {
         redirect: Redirect,
@@ -277,6 +841,28 @@ fn route_for_pipeline(pipeline: &crate::Pipeline) -> RedisResult<Option<Route>>
     )
 }
 
+// Returns the slot of a transaction's watched keys, which must all belong to the same slot.
+fn transaction_slot<K: ToRedisArgs>(keys: &[K]) -> RedisResult<u16> {
+    let mut slots = keys
+        .iter()
+        .flat_map(|key| key.to_redis_args())
+        .map(|key| get_slot(&key));
+    let slot = match slots.next() {
+        Some(slot) => slot,
+        None => fail!((
+            ErrorKind::ClientError,
+            "Transactions must watch at least one key"
+        )),
+    };
+    if slots.any(|other| other != slot) {
+        fail!((
+            ErrorKind::CrossSlot,
+            "Transaction keys belong to different slots"
+        ));
+    }
+    Ok(slot)
+}
+
 fn boxed_sleep(duration: Duration) -> BoxFuture<'static, ()> {
     #[cfg(feature = "tokio-comp")]
     return Box::pin(tokio::time::sleep(duration));
@@ -285,6 +871,24 @@ fn boxed_sleep(duration: Duration) -> BoxFuture<'static, ()> {
     return Box::pin(async_std::task::sleep(duration));
 }
 
+// Fails the request with a timeout error if the timeout passes before it's done. Its response is
+// discarded when it arrives, and requests that nobody waits for anymore aren't sent again once
+// they're pending.
+async fn with_timeout<T>(
+    timeout: Option<Duration>,
+    request: impl Future<Output = RedisResult<T>>,
+) -> RedisResult<T> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return request.await,
+    };
+    futures::pin_mut!(request);
+    match future::select(request, boxed_sleep(timeout)).await {
+        future::Either::Left((result, _)) => result,
+        future::Either::Right(_) => Err(io::Error::from(io::ErrorKind::TimedOut).into()),
+    }
+}
+
 enum Response {
     Single(Value),
     Multiple(Vec<Value>),
@@ -432,6 +1036,11 @@ enum Next<C> {
         request: PendingRequest<C>,
         sleep_duration: Option<Duration>,
     },
+    Moved {
+        request: PendingRequest<C>,
+        slot: u16,
+        address: String,
+    },
     ReconnectToInitialNodes {
         request: PendingRequest<C>,
     },
@@ -512,13 +1121,21 @@ impl<C> Future for Request<C> {
                     }
                     crate::types::RetryMethod::MovedRedirect => {
                         let mut request = this.request.take().unwrap();
-                        request.info.set_redirect(
-                            err.redirect_node()
-                                .map(|(node, _slot)| Redirect::Moved(node.to_string())),
-                        );
-                        Next::RefreshSlots {
-                            request,
-                            sleep_duration: None,
+                        match err.redirect_node() {
+                            Some((node, slot)) => {
+                                request
+                                    .info
+                                    .set_redirect(Some(Redirect::Moved(node.to_string())));
+                                Next::Moved {
+                                    request,
+                                    slot,
+                                    address: node.to_string(),
+                                }
+                            }
+                            None => Next::RefreshSlots {
+                                request,
+                                sleep_duration: None,
+                            },
                         }
                         .into()
                     }
@@ -573,13 +1190,20 @@ where
     async fn new(
         initial_nodes: &[ConnectionInfo],
         cluster_params: ClusterParams,
+        slots_refresh_requests: mpsc::UnboundedSender<()>,
     ) -> RedisResult<Self> {
         let connections = Self::create_initial_connections(initial_nodes, &cluster_params).await?;
         let inner = Arc::new(InnerCore {
-            conn_lock: RwLock::new((connections, SlotMap::new(cluster_params.read_from_replicas))),
+            conn_lock: RwLock::new((
+                connections,
+                SlotMap::new(cluster_params.read_from_replicas)
+                    .with_read_strategy(cluster_params.read_from_replica_strategy.clone()),
+            )),
             cluster_params,
             pending_requests: Mutex::new(Vec::new()),
             initial_nodes: initial_nodes.to_vec(),
+            subscriptions: Mutex::new(ClusterSubscriptions::default()),
+            slots_refresh_requests,
         });
         let connection = ClusterConnInner {
             inner,
@@ -602,7 +1226,7 @@ where
                     let addr = info.addr.to_string();
                     let result = connect_and_check(&addr, params).await;
                     match result {
-                        Ok(conn) => Some((addr, async { conn }.boxed().shared())),
+                        Ok(conn) => Some((addr, NodeConnections::single(conn))),
                         Err(e) => {
                             trace!("Failed to connect to initial node: {:?}", e);
                             None
@@ -644,7 +1268,8 @@ where
             let mut write_lock = inner.conn_lock.write().await;
             *write_lock = (
                 connection_map,
-                SlotMap::new(inner.cluster_params.read_from_replicas),
+                SlotMap::new(inner.cluster_params.read_from_replicas)
+                    .with_read_strategy(inner.cluster_params.read_from_replica_strategy.clone()),
             );
             drop(write_lock);
             if let Err(err) = Self::refresh_slots(inner.clone()).await {
@@ -661,20 +1286,23 @@ where
                 .fold(
                     mem::take(&mut write_guard.0),
                     |mut connections, addr| async {
-                        let conn = Self::get_or_create_conn(
+                        let node = Self::get_or_create_node_connections(
                             &addr,
                             connections.remove(&addr),
                             &inner.cluster_params,
                         )
                         .await;
-                        if let Ok(conn) = conn {
-                            connections.insert(addr, async { conn }.boxed().shared());
+                        if let Some(node) = node {
+                            connections.insert(addr, node);
                         }
                         connections
                     },
                 )
                 .await;
             write_guard.0 = mem::take(&mut connections);
+            let renewals = Self::subscription_renewals(&inner, &write_guard);
+            drop(write_guard);
+            Self::spawn_subscription_renewals(inner, renewals);
         }
     }
 
@@ -684,18 +1312,16 @@ where
         let mut connections = mem::take(&mut write_guard.0);
         let slots = &mut write_guard.1;
         let mut result = Ok(());
-        for (_, conn) in connections.iter_mut() {
-            let mut conn = conn.clone().await;
-            let value = match conn.req_packed_command(&slot_cmd()).await {
-                Ok(value) => value,
+        for node in connections.values() {
+            let mut conn = node.get(ConnectionKind::Requests).future().await;
+            let slots_data = match query_topology(&mut conn, inner.cluster_params.tls).await {
+                Ok(slots_data) => slots_data,
                 Err(err) => {
                     result = Err(err);
                     continue;
                 }
             };
-            match parse_slots(value, inner.cluster_params.tls)
-                .and_then(|v: Vec<Slot>| Self::build_slot_map(slots, v))
-            {
+            match Self::build_slot_map(slots, slots_data) {
                 Ok(_) => {
                     result = Ok(());
                     break;
@@ -716,20 +1342,145 @@ where
         write_guard.0 = stream::iter(addresses_and_connections_iter)
             .fold(
                 HashMap::with_capacity(nodes_len),
-                |mut connections, (addr, connection)| async {
-                    let conn =
-                        Self::get_or_create_conn(addr, connection, &inner.cluster_params).await;
-                    if let Ok(conn) = conn {
-                        connections.insert(addr.to_string(), async { conn }.boxed().shared());
+                |mut connections, (addr, node)| async {
+                    let node =
+                        Self::get_or_create_node_connections(addr, node, &inner.cluster_params)
+                            .await;
+                    if let Some(node) = node {
+                        connections.insert(addr.to_string(), node);
                     }
                     connections
                 },
             )
             .await;
+        let renewals = Self::subscription_renewals(&inner, &write_guard);
+        drop(write_guard);
+        Self::spawn_subscription_renewals(inner, renewals);
 
         Ok(())
     }
 
+    // Patches the owner of a slot that was reported by a `MOVED` redirect into the slot map, and
+    // requests a full refresh to pick up the rest of the migration later.
+    async fn update_slot_owner(slot: u16, address: String, core: Core<C>) {
+        core.conn_lock
+            .write()
+            .await
+            .1
+            .update_slot_owner(slot, &address);
+        let _ = core.slots_refresh_requests.send(());
+    }
+
+    // Queries the slots from a random node, and checks whether they differ from the slot map.
+    async fn topology_changed(core: &Core<C>) -> RedisResult<bool> {
+        let conn = get_random_connection(&core.conn_lock.read().await.0);
+        let mut conn = match conn {
+            Some((_, conn)) => conn.future().await,
+            None => fail!((ErrorKind::ClusterConnectionNotFound, "No connections found")),
+        };
+        let slot_map = SlotMap::from_slots(
+            query_topology(&mut conn, core.cluster_params.tls).await?,
+            core.cluster_params.read_from_replicas,
+        )
+        .with_read_strategy(core.cluster_params.read_from_replica_strategy.clone());
+        Ok(core.conn_lock.read().await.1 != slot_map)
+    }
+
+    // Returns the subscriptions whose owner changed, or whose owner's subscription connection was
+    // replaced, with the connections to remove them from and to send them to.
+    fn subscription_renewals(
+        inner: &Core<C>,
+        (connections, slot_map): &(ConnectionMap<C>, SlotMap),
+    ) -> Vec<SubscriptionRenewal<C>> {
+        let mut subscriptions = inner.subscriptions.lock().unwrap();
+        if subscriptions.is_empty() {
+            return Vec::new();
+        }
+        let subscriptions_conn = |address: &str| {
+            connections
+                .get(address)
+                .map(|node| node.get(ConnectionKind::Subscriptions).future())
+        };
+        subscriptions
+            .relocate(slot_map, |address| {
+                connections
+                    .get(address)
+                    .map(NodeConnections::subscriptions_generation)
+            })
+            .into_iter()
+            .map(|relocation| {
+                // The subscription is only removed from a previous owner that's still serving it.
+                let previous_conn = relocation
+                    .previous
+                    .as_deref()
+                    .filter(|previous| relocation.current.as_deref() != Some(*previous))
+                    .and_then(subscriptions_conn);
+                let current_conn = relocation.current.as_deref().and_then(subscriptions_conn);
+                (relocation, previous_conn, current_conn)
+            })
+            .collect()
+    }
+
+    // Renews the subscriptions concurrently, in a task of their own, so that the requests that
+    // wait for the slots to be refreshed aren't held up by them.
+    fn spawn_subscription_renewals(inner: Core<C>, renewals: Vec<SubscriptionRenewal<C>>) {
+        if renewals.is_empty() {
+            return;
+        }
+        crate::aio::Runtime::locate().spawn(async move {
+            future::join_all(
+                renewals
+                    .into_iter()
+                    .map(|renewal| Self::renew_subscription(inner.clone(), renewal)),
+            )
+            .await;
+        });
+    }
+
+    // Sends a subscription to the node that currently owns its slot, and removes it from the node
+    // that previously owned it.
+    async fn renew_subscription(
+        inner: Core<C>,
+        (Relocation { kind, name, .. }, previous_conn, current_conn): SubscriptionRenewal<C>,
+    ) {
+        if let Some(conn) = previous_conn {
+            let mut unsubscribe = cmd(kind.unsubscribe_cmd());
+            unsubscribe.arg(&name);
+            // The previous owner might have already dropped the subscription.
+            let _ = conn.await.req_packed_command(&unsubscribe).await;
+        }
+
+        let mut conn = match current_conn {
+            Some(conn) => conn.await,
+            None => {
+                warn!("Can't renew subscription - no connection found for its slot");
+                return;
+            }
+        };
+        let mut subscribe = cmd(kind.subscribe_cmd());
+        subscribe.arg(&name);
+        let result = match conn.req_packed_command(&subscribe).await {
+            Err(err) if err.kind() == ErrorKind::Moved => match err.redirect_node() {
+                Some((node, _slot)) => {
+                    Self::get_redirected_connection(
+                        Redirect::Moved(node.to_string()),
+                        inner,
+                        ConnectionKind::Subscriptions,
+                    )
+                    .and_then(
+                        |(_, mut conn)| async move { conn.req_packed_command(&subscribe).await },
+                    )
+                    .await
+                }
+                None => Err(err),
+            },
+            result => result,
+        };
+        if let Err(err) = result {
+            warn!("Can't renew subscription: `{err}`");
+        }
+    }
+
     fn build_slot_map(slot_map: &mut SlotMap, slots_data: Vec<Slot>) -> RedisResult<()> {
         slot_map.clear();
         slot_map.fill_slots(slots_data);
@@ -855,7 +1606,8 @@ where
         }
         let (receivers, requests): (Vec<_>, Vec<_>) = {
             let to_request = |(addr, cmd): (&str, Arc<Cmd>)| {
-                read_guard.0.get(addr).cloned().map(|conn| {
+                read_guard.0.get(addr).map(|node| {
+                    let conn = node.get(ConnectionKind::Requests);
                     let (sender, receiver) = oneshot::channel();
                     let addr = addr.to_string();
                     (
@@ -936,12 +1688,21 @@ where
             }
         };
 
-        match Self::get_connection(route, core).await {
-            Ok((addr, mut conn)) => conn
-                .req_packed_command(&cmd)
-                .await
-                .map(Response::Single)
-                .map_err(|err| (addr.into(), err)),
+        let kind = ConnectionKind::for_command(&cmd);
+        match Self::get_connection(route, core.clone(), kind).await {
+            Ok((addr, mut conn)) => {
+                let started = Instant::now();
+                let result = instrumented(
+                    &core.cluster_params,
+                    || instrumentation::RequestInfo::for_cmd(&cmd).with_node(Some(&addr)),
+                    conn.req_packed_command(&cmd),
+                )
+                .await;
+                core.cluster_params.record_request(&addr, started, &result);
+                result
+                    .map(Response::Single)
+                    .map_err(|err| (addr.into(), err))
+            }
             Err(err) => Err((OperationTarget::NotFound, err)),
         }
     }
@@ -950,14 +1711,23 @@ where
         pipeline: Arc<crate::Pipeline>,
         offset: usize,
         count: usize,
-        conn: impl Future<Output = RedisResult<(String, C)>>,
+        route: InternalSingleNodeRouting<C>,
+        core: Core<C>,
     ) -> OperationResult {
-        match conn.await {
-            Ok((addr, mut conn)) => conn
-                .req_packed_commands(&pipeline, offset, count)
-                .await
-                .map(Response::Multiple)
-                .map_err(|err| (OperationTarget::Node { address: addr }, err)),
+        match Self::get_connection(route, core.clone(), ConnectionKind::Requests).await {
+            Ok((addr, mut conn)) => {
+                let started = Instant::now();
+                let result = instrumented(
+                    &core.cluster_params,
+                    || instrumentation::RequestInfo::for_pipeline(&pipeline).with_node(Some(&addr)),
+                    conn.req_packed_commands(&pipeline, offset, count),
+                )
+                .await;
+                core.cluster_params.record_request(&addr, started, &result);
+                result
+                    .map(Response::Multiple)
+                    .map_err(|err| (OperationTarget::Node { address: addr }, err))
+            }
             Err(err) => Err((OperationTarget::NotFound, err)),
         }
     }
@@ -970,22 +1740,15 @@ where
                 offset,
                 count,
                 route,
-            } => {
-                Self::try_pipeline_request(
-                    pipeline,
-                    offset,
-                    count,
-                    Self::get_connection(route, core),
-                )
-                .await
-            }
+            } => Self::try_pipeline_request(pipeline, offset, count, route, core).await,
         }
     }
 
     async fn get_connection(
         route: InternalSingleNodeRouting<C>,
         core: Core<C>,
-    ) -> RedisResult<(String, C)> {
+        kind: ConnectionKind,
+    ) -> RedisResult<(String, InFlightConnection<C>)> {
         let read_guard = core.conn_lock.read().await;
 
         let conn = match route {
@@ -995,16 +1758,16 @@ where
                 .slot_addr_for_route(&route)
                 .map(|addr| addr.to_string()),
             InternalSingleNodeRouting::Connection { identifier, conn } => {
-                return Ok((identifier, conn.await));
+                return Ok((identifier, conn.acquire().await));
             }
             InternalSingleNodeRouting::Redirect { redirect, .. } => {
                 drop(read_guard);
                 // redirected requests shouldn't use a random connection, so they have a separate codepath.
-                return Self::get_redirected_connection(redirect, core).await;
+                return Self::get_redirected_connection(redirect, core, kind).await;
             }
             InternalSingleNodeRouting::ByAddress(address) => {
-                if let Some(conn) = read_guard.0.get(&address).cloned() {
-                    return Ok((address, conn.await));
+                if let Some(node) = read_guard.0.get(&address) {
+                    return Ok((address, node.get(kind).acquire().await));
                 } else {
                     return Err((
                         ErrorKind::ClientError,
@@ -1016,7 +1779,7 @@ where
             }
         }
         .map(|addr| {
-            let conn = read_guard.0.get(&addr).cloned();
+            let conn = read_guard.0.get(&addr).map(|node| node.get(kind).acquire());
             (addr, conn)
         });
         drop(read_guard);
@@ -1038,7 +1801,7 @@ where
                     get_random_connection(&read_guard.0)
                 {
                     drop(read_guard);
-                    (random_addr, random_conn_future.await)
+                    (random_addr, random_conn_future.acquire().await)
                 } else {
                     return Err(
                         (ErrorKind::ClusterConnectionNotFound, "No connections found").into(),
@@ -1053,19 +1816,14 @@ where
     async fn get_redirected_connection(
         redirect: Redirect,
         core: Core<C>,
-    ) -> RedisResult<(String, C)> {
+        kind: ConnectionKind,
+    ) -> RedisResult<(String, InFlightConnection<C>)> {
         let asking = matches!(redirect, Redirect::Ask(_));
         let addr = match redirect {
             Redirect::Moved(addr) => addr,
             Redirect::Ask(addr) => addr,
         };
-        let read_guard = core.conn_lock.read().await;
-        let conn = read_guard.0.get(&addr).cloned();
-        drop(read_guard);
-        let mut conn = match conn {
-            Some(conn) => conn.await,
-            None => connect_check_and_add(core.clone(), addr.clone()).await?,
-        };
+        let mut conn = Self::get_connection_by_addr(&addr, core, kind).await?;
         if asking {
             let _ = conn.req_packed_command(&crate::cmd::cmd("ASKING")).await;
         }
@@ -1073,6 +1831,42 @@ where
         Ok((addr, conn))
     }
 
+    async fn get_connection_by_addr(
+        addr: &str,
+        core: Core<C>,
+        kind: ConnectionKind,
+    ) -> RedisResult<InFlightConnection<C>> {
+        let read_guard = core.conn_lock.read().await;
+        let conn = read_guard.0.get(addr).map(|node| node.get(kind).acquire());
+        drop(read_guard);
+        match conn {
+            Some(conn) => Ok(conn.await),
+            None => connect_check_and_add(core, addr.to_string()).await,
+        }
+    }
+
+    // Sends the next `SCAN` of a cluster-wide scan, and returns the keys that it found, or `None`
+    // if the scan is complete.
+    async fn scan_next_batch(
+        core: Core<C>,
+        state: &mut ClusterScanState,
+    ) -> RedisResult<Option<Vec<Value>>> {
+        let request = state.next_request(&core.conn_lock.read().await.1);
+        let (addr, scan) = match request {
+            Some(request) => request,
+            None => return Ok(None),
+        };
+        let mut conn =
+            Self::get_connection_by_addr(&addr, core.clone(), ConnectionKind::Requests).await?;
+        let keys = state.handle_response(conn.req_packed_command(&scan).await?)?;
+        if state.is_node_done() {
+            // Slots that are known to have moved away while the node was scanned are scanned on
+            // their new owner.
+            state.finish_node(&core.conn_lock.read().await.1);
+        }
+        Ok(Some(keys))
+    }
+
     fn poll_recover(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<(), RedisError>> {
         let recover_future = match &mut self.state {
             ConnectionState::PollComplete => return Poll::Ready(Ok(())),
@@ -1167,6 +1961,24 @@ where
                         future,
                     }));
                 }
+                Next::Moved {
+                    request,
+                    slot,
+                    address,
+                } => {
+                    let future = Self::update_slot_owner(slot, address, self.inner.clone()).then({
+                        let info = request.info.clone();
+                        let core = self.inner.clone();
+                        move |_| Self::try_request(info, core)
+                    });
+                    self.in_flight_requests.push(Box::pin(Request {
+                        retry_params: self.inner.cluster_params.retry_params.clone(),
+                        request: Some(request),
+                        future: RequestState::Future {
+                            future: Box::pin(future),
+                        },
+                    }));
+                }
                 Next::Reconnect {
                     request, target, ..
                 } => {
@@ -1205,21 +2017,68 @@ where
         }
     }
 
-    async fn get_or_create_conn(
+    // Checks a node's connections, and replaces the broken or missing ones. Every connection is
+    // handled on its own, so that a broken connection doesn't affect the rest of the node's
+    // connections. Returns `None` if none of the node's connections could be established.
+    async fn get_or_create_node_connections(
         addr: &str,
-        conn_option: Option<ConnectionFuture<C>>,
+        node: Option<NodeConnections<C>>,
         params: &ClusterParams,
-    ) -> RedisResult<C> {
-        if let Some(conn) = conn_option {
-            let mut conn = conn.await;
-            match check_connection(&mut conn).await {
-                Ok(_) => Ok(conn),
-                Err(_) => connect_and_check(addr, params.clone()).await,
+    ) -> Option<NodeConnections<C>> {
+        let mut existing = node
+            .map(NodeConnections::into_connections)
+            .unwrap_or_default()
+            .into_iter();
+        let connections = future::join_all((0..params.connections_per_node.max(1)).map(|_| {
+            let existing = existing.next();
+            async move {
+                let reconnecting = match existing {
+                    Some(existing) => {
+                        let mut conn = existing.future().await;
+                        if check_connection(&mut conn).await.is_ok() {
+                            return Some(existing);
+                        }
+                        true
+                    }
+                    None => false,
+                };
+                let conn = connect_and_check(addr, params.clone()).await.ok()?;
+                if reconnecting {
+                    instrumentation::report_reconnect(params.instrumentation.as_deref(), addr);
+                }
+                Some(NodeConnection::new(conn))
             }
+        }))
+        .await
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+        if connections.is_empty() {
+            None
         } else {
-            connect_and_check(addr, params.clone()).await
+            Some(NodeConnections::new(connections))
+        }
+    }
+}
+
+// Reports a request to the cluster's instrumentation, and whether it was redirected to another node.
+async fn instrumented<'a, T>(
+    params: &ClusterParams,
+    request: impl FnOnce() -> instrumentation::RequestInfo<'a>,
+    send: impl Future<Output = RedisResult<T>>,
+) -> RedisResult<T> {
+    if !instrumentation::is_enabled(&params.instrumentation) {
+        return send.await;
+    }
+    let request = request();
+    let result =
+        instrumentation::instrument_async(params.instrumentation.as_deref(), &request, send).await;
+    if let Err(err) = &result {
+        if let Some((address, _slot)) = err.redirect_node() {
+            instrumentation::report_redirect(params.instrumentation.as_deref(), &request, address);
         }
     }
+    result
 }
 
 enum PollFlushAction {
@@ -1358,9 +2217,16 @@ where
         count: usize,
     ) -> RedisFuture<'a, Vec<Value>> {
         async move {
-            let route = route_for_pipeline(pipeline)?;
-            self.route_pipeline(pipeline, offset, count, route.into())
-                .await
+            if pipeline.is_atomic() {
+                // A transaction must be sent to a single node as a whole.
+                let route = route_for_pipeline(pipeline)?;
+                return self
+                    .route_pipeline(pipeline, offset, count, route.into())
+                    .await;
+            }
+            let values =
+                with_timeout(pipeline.get_timeout(), self.route_split_pipeline(pipeline)).await?;
+            Ok(values.into_iter().skip(offset).take(count).collect())
         }
         .boxed()
     }
@@ -1380,6 +2246,10 @@ pub trait Connect: Sized {
     ) -> RedisFuture<'a, Self>
     where
         T: IntoConnectionInfo + Send + 'a;
+
+    /// Directs the push messages received by the connection to `push_manager`. Connections that
+    /// don't receive push messages can ignore this.
+    fn set_push_manager(&mut self, _push_manager: PushManager) {}
 }
 
 impl Connect for MultiplexedConnection {
@@ -1394,33 +2264,38 @@ impl Connect for MultiplexedConnection {
         async move {
             let connection_info = info.into_connection_info()?;
             let client = crate::Client::open(connection_info)?;
-            client
+            let mut connection = client
                 .get_multiplexed_async_connection_with_timeouts(
                     response_timeout,
                     connection_timeout,
                 )
-                .await
+                .await?;
+            // The cluster connection reports the requests that it sends to its nodes.
+            connection.reports_requests = false;
+            Ok(connection)
         }
         .boxed()
     }
+
+    fn set_push_manager(&mut self, push_manager: PushManager) {
+        self.replace_push_manager(push_manager);
+    }
 }
 
-async fn connect_check_and_add<C>(core: Core<C>, addr: String) -> RedisResult<C>
+// Connects to a node that has no connections yet. Only a single connection is created, so that
+// the request that needs it isn't delayed - the rest are created by the next refresh of the slots.
+async fn connect_check_and_add<C>(core: Core<C>, addr: String) -> RedisResult<InFlightConnection<C>>
 where
     C: ConnectionLike + Connect + Send + Clone + 'static,
 {
-    match connect_and_check::<C>(&addr, core.cluster_params.clone()).await {
-        Ok(conn) => {
-            let conn_clone = conn.clone();
-            core.conn_lock
-                .write()
-                .await
-                .0
-                .insert(addr, async { conn_clone }.boxed().shared());
-            Ok(conn)
-        }
-        Err(err) => Err(err),
-    }
+    let conn =
+        NodeConnection::new(connect_and_check::<C>(&addr, core.cluster_params.clone()).await?);
+    core.conn_lock
+        .write()
+        .await
+        .0
+        .insert(addr, NodeConnections::new(vec![conn.clone()]));
+    Ok(conn.acquire().await)
 }
 
 async fn connect_and_check<C>(node: &str, params: ClusterParams) -> RedisResult<C>
@@ -1430,8 +2305,14 @@ where
     let read_from_replicas = params.read_from_replicas;
     let connection_timeout = params.connection_timeout;
     let response_timeout = params.response_timeout;
-    let info = get_connection_info(node, params)?;
+    let push_manager = params.push_manager.clone();
+    let credentials_provider = params.credentials_provider.clone();
+    let mut info = get_connection_info(node, params)?;
+    if let Some(provider) = credentials_provider {
+        provider.credentials().await?.apply(&mut info.redis);
+    }
     let mut conn: C = C::connect(info, response_timeout, connection_timeout).await?;
+    conn.set_push_manager(push_manager);
     check_connection(&mut conn).await?;
     if read_from_replicas {
         // If READONLY is sent to primary nodes, it will have no effect
@@ -1450,18 +2331,26 @@ where
     Ok(())
 }
 
-fn get_random_connection<C>(connections: &ConnectionMap<C>) -> Option<(String, ConnectionFuture<C>)>
+// Queries the cluster's topology with `CLUSTER SHARDS`, falling back to `CLUSTER SLOTS` on servers
+// that don't support it.
+async fn query_topology<C>(conn: &mut C, tls: Option<crate::TlsMode>) -> RedisResult<Vec<Slot>>
 where
-    C: Clone,
+    C: ConnectionLike + Send + 'static,
+{
+    match parse_shards_reply(conn.req_packed_command(&shards_cmd()).await, tls) {
+        Some(slots) => slots,
+        None => parse_slots(conn.req_packed_command(&slot_cmd()).await?, tls),
+    }
+}
+
+fn get_random_connection<C>(connections: &ConnectionMap<C>) -> Option<(String, NodeConnection<C>)>
+where
+    C: Clone + Send + 'static,
 {
     connections
-        .keys()
+        .iter()
         .choose(&mut thread_rng())
-        .and_then(|addr| {
-            connections
-                .get(addr)
-                .map(|conn| (addr.clone(), conn.clone()))
-        })
+        .map(|(addr, node)| (addr.clone(), node.get(ConnectionKind::Requests)))
 }
 
 #[cfg(test)]
@@ -0,0 +1,222 @@
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use futures::future::{BoxFuture, Future, FutureExt, Shared};
+
+use crate::Cmd;
+
+pub(super) type ConnectionFuture<C> = Shared<BoxFuture<'static, C>>;
+
+// The generation of the next connection that's created.
+static NEXT_GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+/// A connection to a node, and the number of requests that are currently sent over it.
+pub(super) struct NodeConnection<C> {
+    conn: ConnectionFuture<C>,
+    in_flight: Arc<AtomicUsize>,
+    // Tells the connection apart from the connections that replace it.
+    generation: usize,
+}
+
+impl<C> Clone for NodeConnection<C> {
+    fn clone(&self) -> Self {
+        Self {
+            conn: self.conn.clone(),
+            in_flight: self.in_flight.clone(),
+            generation: self.generation,
+        }
+    }
+}
+
+impl<C> NodeConnection<C>
+where
+    C: Clone + Send + 'static,
+{
+    pub(super) fn new(conn: C) -> Self {
+        Self::from_future(async { conn }.boxed().shared())
+    }
+
+    pub(super) fn from_future(conn: ConnectionFuture<C>) -> Self {
+        Self {
+            conn,
+            in_flight: Default::default(),
+            generation: NEXT_GENERATION.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    pub(super) fn future(&self) -> ConnectionFuture<C> {
+        self.conn.clone()
+    }
+
+    /// Counts a request as in flight on the connection until the returned connection is dropped.
+    /// The request is counted right away, so that it's taken into account by the next choice of
+    /// a connection, even before the connection was established.
+    pub(super) fn acquire(self) -> impl Future<Output = InFlightConnection<C>> {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        let in_flight = InFlight(self.in_flight);
+        self.conn.map(|conn| InFlightConnection {
+            conn,
+            _in_flight: in_flight,
+        })
+    }
+
+    fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+}
+
+/// What a connection to a node is used for, which determines which of its connections is chosen.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(super) enum ConnectionKind {
+    /// Requests are sent over the connection with the fewest requests in flight.
+    Requests,
+    /// Subscriptions are always sent over the same connection, so that unsubscribing reaches the
+    /// connection that subscribed, and the subscription's messages arrive in order.
+    Subscriptions,
+}
+
+impl ConnectionKind {
+    pub(super) fn for_command(cmd: &Cmd) -> Self {
+        match cmd.arg_idx(0) {
+            Some(name)
+                if [
+                    &b"SUBSCRIBE"[..],
+                    b"PSUBSCRIBE",
+                    b"SSUBSCRIBE",
+                    b"UNSUBSCRIBE",
+                    b"PUNSUBSCRIBE",
+                    b"SUNSUBSCRIBE",
+                ]
+                .iter()
+                .any(|subscription_cmd| name.eq_ignore_ascii_case(subscription_cmd)) =>
+            {
+                ConnectionKind::Subscriptions
+            }
+            _ => ConnectionKind::Requests,
+        }
+    }
+}
+
+/// The connections to a single node. Requests are spread across them by sending each request over
+/// the connection with the fewest requests in flight.
+pub(super) struct NodeConnections<C> {
+    connections: Vec<NodeConnection<C>>,
+}
+
+impl<C> NodeConnections<C>
+where
+    C: Clone + Send + 'static,
+{
+    pub(super) fn new(connections: Vec<NodeConnection<C>>) -> Self {
+        debug_assert!(!connections.is_empty());
+        Self { connections }
+    }
+
+    pub(super) fn single(conn: C) -> Self {
+        Self::new(vec![NodeConnection::new(conn)])
+    }
+
+    pub(super) fn get(&self, kind: ConnectionKind) -> NodeConnection<C> {
+        match kind {
+            ConnectionKind::Requests => self
+                .connections
+                .iter()
+                .min_by_key(|conn| conn.in_flight())
+                .expect("A node always has at least one connection")
+                .clone(),
+            ConnectionKind::Subscriptions => self.connections[0].clone(),
+        }
+    }
+
+    /// The generation of the connection that subscriptions are sent over, which changes when the
+    /// connection is replaced, and its subscriptions are lost.
+    pub(super) fn subscriptions_generation(&self) -> usize {
+        self.connections[0].generation
+    }
+
+    pub(super) fn into_connections(self) -> Vec<NodeConnection<C>> {
+        self.connections
+    }
+}
+
+struct InFlight(Arc<AtomicUsize>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// A connection that counts as busy with a request while it's held.
+pub(super) struct InFlightConnection<C> {
+    conn: C,
+    _in_flight: InFlight,
+}
+
+impl<C> Deref for InFlightConnection<C> {
+    type Target = C;
+
+    fn deref(&self) -> &C {
+        &self.conn
+    }
+}
+
+impl<C> DerefMut for InFlightConnection<C> {
+    fn deref_mut(&mut self) -> &mut C {
+        &mut self.conn
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_are_sent_over_the_least_busy_connection() {
+        let node = NodeConnections::new(vec![NodeConnection::new(1), NodeConnection::new(2)]);
+        let acquire = |kind| node.get(kind).acquire().now_or_never().unwrap();
+
+        let first = acquire(ConnectionKind::Requests);
+        assert_eq!(*first, 1);
+        let second = acquire(ConnectionKind::Requests);
+        assert_eq!(*second, 2);
+        drop(first);
+        assert_eq!(*acquire(ConnectionKind::Requests), 1);
+    }
+
+    #[test]
+    fn subscriptions_are_always_sent_over_the_first_connection() {
+        let node = NodeConnections::new(vec![NodeConnection::new(1), NodeConnection::new(2)]);
+        let _busy = node.get(ConnectionKind::Requests).acquire();
+
+        assert_eq!(
+            *node
+                .get(ConnectionKind::Subscriptions)
+                .acquire()
+                .now_or_never()
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            node.subscriptions_generation(),
+            node.get(ConnectionKind::Subscriptions).generation
+        );
+        assert_ne!(
+            node.subscriptions_generation(),
+            NodeConnections::single(1).subscriptions_generation()
+        );
+        assert_eq!(
+            ConnectionKind::for_command(crate::cmd("ssubscribe").arg("foo")),
+            ConnectionKind::Subscriptions
+        );
+        assert_eq!(
+            ConnectionKind::for_command(crate::cmd("GET").arg("foo")),
+            ConnectionKind::Requests
+        );
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+
+use crate::{
+    aio::SubscriptionKind,
+    cluster_routing::{get_slot, Route, SlotAddr, SlotMap},
+};
+
+/// The route of a subscription. Every kind of subscription is sent to the primary that owns the
+/// channel's slot - for shard channels this is required, and for the rest it spreads the
+/// subscriptions across the cluster deterministically.
+pub(super) fn subscription_route(name: &[u8]) -> Route {
+    Route::new(get_slot(name), SlotAddr::Master)
+}
+
+/// The node that a subscription was sent to, and the generation of the node's subscription
+/// connection at the time, if the node had a connection.
+#[derive(Clone, Debug, PartialEq)]
+pub(super) struct SubscriptionOwner {
+    pub(super) address: String,
+    pub(super) generation: Option<usize>,
+}
+
+/// A subscription that has to be renewed, since its owner changed, or its owner's subscription
+/// connection was replaced.
+#[derive(Debug, PartialEq)]
+pub(super) struct Relocation {
+    pub(super) kind: SubscriptionKind,
+    pub(super) name: Vec<u8>,
+    /// The node the subscription was last sent to, if it's known.
+    pub(super) previous: Option<String>,
+    /// The node that currently owns the subscription's slot, if the slot is covered.
+    pub(super) current: Option<String>,
+}
+
+/// The subscriptions made through the cluster connection, and the owner each one was sent to.
+#[derive(Default)]
+pub(super) struct ClusterSubscriptions {
+    subscriptions: HashMap<(SubscriptionKind, Vec<u8>), Option<SubscriptionOwner>>,
+}
+
+impl ClusterSubscriptions {
+    pub(super) fn insert(
+        &mut self,
+        kind: SubscriptionKind,
+        name: Vec<u8>,
+        owner: Option<SubscriptionOwner>,
+    ) {
+        self.subscriptions.insert((kind, name), owner);
+    }
+
+    pub(super) fn remove(&mut self, kind: SubscriptionKind, name: &[u8]) {
+        self.subscriptions.remove(&(kind, name.to_vec()));
+    }
+
+    /// Stops tracking all subscriptions of `kind`, and returns their names.
+    pub(super) fn remove_kind(&mut self, kind: SubscriptionKind) -> Vec<Vec<u8>> {
+        let names: Vec<_> = self
+            .subscriptions
+            .keys()
+            .filter(|(subscription_kind, _)| *subscription_kind == kind)
+            .map(|(_, name)| name.clone())
+            .collect();
+        for name in names.iter() {
+            self.remove(kind, name);
+        }
+        names
+    }
+
+    pub(super) fn contains(&self, kind: SubscriptionKind, name: &[u8]) -> bool {
+        self.subscriptions.contains_key(&(kind, name.to_vec()))
+    }
+
+    /// Marks a subscription that the server ended as not sent anywhere, so that the next
+    /// relocation renews it.
+    pub(super) fn forget_owner(&mut self, kind: SubscriptionKind, name: &[u8]) {
+        if let Some(owner) = self.subscriptions.get_mut(&(kind, name.to_vec())) {
+            *owner = None;
+        }
+    }
+
+    /// Assigns every subscription to the node that owns its slot in `slot_map`, and returns the
+    /// subscriptions that have to be renewed. `generation` returns the generation of a node's
+    /// subscription connection.
+    pub(super) fn relocate(
+        &mut self,
+        slot_map: &SlotMap,
+        generation: impl Fn(&str) -> Option<usize>,
+    ) -> Vec<Relocation> {
+        self.subscriptions
+            .iter_mut()
+            .filter_map(|((kind, name), owner)| {
+                let current =
+                    slot_map
+                        .slot_addr_for_route(&subscription_route(name))
+                        .map(|address| SubscriptionOwner {
+                            address: address.to_string(),
+                            generation: generation(address),
+                        });
+                if *owner == current {
+                    return None;
+                }
+                let previous = std::mem::replace(owner, current.clone());
+                Some(Relocation {
+                    kind: *kind,
+                    name: name.clone(),
+                    previous: previous.map(|owner| owner.address),
+                    current: current.map(|owner| owner.address),
+                })
+            })
+            .collect()
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.subscriptions.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster_routing::Slot;
+
+    fn slot_map(first_half: &str, second_half: &str) -> SlotMap {
+        SlotMap::from_slots(
+            vec![
+                Slot::new(0, 8191, first_half.to_string(), vec![]),
+                Slot::new(8192, 16383, second_half.to_string(), vec![]),
+            ],
+            false,
+        )
+    }
+
+    fn owner(address: &str, generation: usize) -> Option<SubscriptionOwner> {
+        Some(SubscriptionOwner {
+            address: address.to_string(),
+            generation: Some(generation),
+        })
+    }
+
+    #[test]
+    fn test_relocate_returns_moved_and_lost_subscriptions() {
+        let mut subscriptions = ClusterSubscriptions::default();
+        // "foo" is in slot 12182, "bar" in slot 5061, and "baz" in slot 4813.
+        subscriptions.insert(SubscriptionKind::Sharded, b"foo".to_vec(), None);
+        subscriptions.insert(
+            SubscriptionKind::Exact,
+            b"bar".to_vec(),
+            owner("node1:6379", 1),
+        );
+        subscriptions.insert(
+            SubscriptionKind::Exact,
+            b"baz".to_vec(),
+            owner("node1:6379", 1),
+        );
+        let generations = |address: &str| match address {
+            "node1:6379" => Some(1),
+            _ => Some(2),
+        };
+
+        let relocations =
+            subscriptions.relocate(&slot_map("node1:6379", "node2:6379"), generations);
+        assert_eq!(
+            relocations,
+            vec![Relocation {
+                kind: SubscriptionKind::Sharded,
+                name: b"foo".to_vec(),
+                previous: None,
+                current: Some("node2:6379".to_string()),
+            }]
+        );
+        assert!(subscriptions
+            .relocate(&slot_map("node1:6379", "node2:6379"), generations)
+            .is_empty());
+
+        // The slots of node1 move to node3.
+        let mut relocations =
+            subscriptions.relocate(&slot_map("node3:6379", "node2:6379"), generations);
+        relocations.sort_by(|a, b| a.name.cmp(&b.name));
+        assert_eq!(relocations.len(), 2);
+        assert_eq!(relocations[0].name, b"bar");
+        assert_eq!(relocations[0].previous.as_deref(), Some("node1:6379"));
+        assert_eq!(relocations[0].current.as_deref(), Some("node3:6379"));
+
+        // node2's subscription connection is replaced.
+        let relocations =
+            subscriptions.relocate(&slot_map("node3:6379", "node2:6379"), |_| Some(3));
+        assert_eq!(relocations.len(), 3);
+        let foo = relocations.iter().find(|r| r.name == b"foo").unwrap();
+        assert_eq!(foo.previous, foo.current);
+
+        // The server ended a subscription.
+        subscriptions.forget_owner(SubscriptionKind::Sharded, b"foo");
+        let relocations =
+            subscriptions.relocate(&slot_map("node3:6379", "node2:6379"), |_| Some(3));
+        assert_eq!(relocations.len(), 1);
+        assert_eq!(relocations[0].previous, None);
+    }
+
+    #[test]
+    fn test_remove_kind_only_removes_given_kind() {
+        let mut subscriptions = ClusterSubscriptions::default();
+        subscriptions.insert(SubscriptionKind::Exact, b"foo".to_vec(), None);
+        subscriptions.insert(SubscriptionKind::Sharded, b"foo".to_vec(), None);
+        subscriptions.insert(SubscriptionKind::Sharded, b"bar".to_vec(), None);
+
+        let mut removed = subscriptions.remove_kind(SubscriptionKind::Sharded);
+        removed.sort();
+        assert_eq!(removed, vec![b"bar".to_vec(), b"foo".to_vec()]);
+        assert!(subscriptions.contains(SubscriptionKind::Exact, b"foo"));
+        assert!(!subscriptions.contains(SubscriptionKind::Sharded, b"foo"));
+
+        subscriptions.remove(SubscriptionKind::Exact, b"foo");
+        assert!(subscriptions.is_empty());
+    }
+}
@@ -1,8 +1,14 @@
+#[cfg(feature = "cluster-async")]
+use crate::aio::CredentialsProvider;
+use crate::cluster_routing::ReadFromReplicaStrategy;
 use crate::connection::{ConnectionAddr, ConnectionInfo, IntoConnectionInfo};
+#[cfg(feature = "cluster-async")]
+use crate::instrumentation::Instrumentation;
 use crate::types::{ErrorKind, ProtocolVersion, RedisError, RedisResult};
 use crate::{cluster, cluster::TlsMode};
 use rand::Rng;
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 #[cfg(feature = "tls-rustls")]
 use crate::tls::TlsConnParams;
@@ -24,6 +30,8 @@ struct BuilderParams {
     password: Option<String>,
     username: Option<String>,
     read_from_replicas: bool,
+    read_from_replica_strategy: Option<Arc<dyn ReadFromReplicaStrategy>>,
+    address_mapper: Option<Arc<dyn AddressMapper>>,
     tls: Option<TlsMode>,
     #[cfg(feature = "tls-rustls")]
     certs: Option<TlsCertificates>,
@@ -31,6 +39,14 @@ struct BuilderParams {
     connection_timeout: Option<Duration>,
     response_timeout: Option<Duration>,
     protocol: ProtocolVersion,
+    #[cfg(feature = "cluster-async")]
+    topology_checks_interval: Option<Duration>,
+    #[cfg(feature = "cluster-async")]
+    connections_per_node: Option<usize>,
+    #[cfg(feature = "cluster-async")]
+    instrumentation: Option<Arc<dyn Instrumentation>>,
+    #[cfg(feature = "cluster-async")]
+    credentials_provider: Option<Arc<dyn CredentialsProvider>>,
 }
 
 #[derive(Clone)]
@@ -76,6 +92,10 @@ pub(crate) struct ClusterParams {
     pub(crate) password: Option<String>,
     pub(crate) username: Option<String>,
     pub(crate) read_from_replicas: bool,
+    /// Chooses the replicas that serve reads. If it's not set, they're chosen randomly.
+    pub(crate) read_from_replica_strategy: Option<Arc<dyn ReadFromReplicaStrategy>>,
+    /// Translates the addresses that nodes advertise into the addresses that are connected to.
+    pub(crate) address_mapper: Option<Arc<dyn AddressMapper>>,
     /// tls indicates tls behavior of connections.
     /// When Some(TlsMode), connections use tls and verify certification depends on TlsMode.
     /// When None, connections do not use tls.
@@ -85,6 +105,21 @@ pub(crate) struct ClusterParams {
     pub(crate) connection_timeout: Duration,
     pub(crate) response_timeout: Duration,
     pub(crate) protocol: ProtocolVersion,
+    /// Receives the push messages of all node connections.
+    #[cfg(feature = "cluster-async")]
+    pub(crate) push_manager: crate::PushManager,
+    /// The interval of the periodic topology checks, if they're enabled.
+    #[cfg(feature = "cluster-async")]
+    pub(crate) topology_checks_interval: Option<Duration>,
+    /// The number of multiplexed connections that are opened to every node.
+    #[cfg(feature = "cluster-async")]
+    pub(crate) connections_per_node: usize,
+    /// Notified of every request that is sent to a node.
+    #[cfg(feature = "cluster-async")]
+    pub(crate) instrumentation: Option<Arc<dyn Instrumentation>>,
+    /// Provides the credentials of every new node connection, instead of `username` and `password`.
+    #[cfg(feature = "cluster-async")]
+    pub(crate) credentials_provider: Option<Arc<dyn CredentialsProvider>>,
 }
 
 impl ClusterParams {
@@ -103,16 +138,117 @@ impl ClusterParams {
             password: value.password,
             username: value.username,
             read_from_replicas: value.read_from_replicas,
+            read_from_replica_strategy: value.read_from_replica_strategy,
+            address_mapper: value.address_mapper,
             tls: value.tls,
             retry_params: value.retries_configuration,
             tls_params,
             connection_timeout: value.connection_timeout.unwrap_or(Duration::from_secs(1)),
             response_timeout: value.response_timeout.unwrap_or(Duration::MAX),
             protocol: value.protocol,
+            #[cfg(feature = "cluster-async")]
+            push_manager: Default::default(),
+            #[cfg(feature = "cluster-async")]
+            topology_checks_interval: value.topology_checks_interval,
+            #[cfg(feature = "cluster-async")]
+            connections_per_node: value.connections_per_node.unwrap_or(1),
+            #[cfg(feature = "cluster-async")]
+            instrumentation: value.instrumentation,
+            #[cfg(feature = "cluster-async")]
+            credentials_provider: value.credentials_provider,
         })
     }
 }
 
+impl ClusterParams {
+    // Tells the replica read strategy how a request to `node` went.
+    pub(crate) fn record_request<T>(&self, node: &str, started: Instant, result: &RedisResult<T>) {
+        if let Some(strategy) = &self.read_from_replica_strategy {
+            match result {
+                Ok(_) => strategy.record_latency(node, started.elapsed()),
+                Err(err) if err.is_io_error() => strategy.record_failure(node),
+                Err(_) => {}
+            }
+        }
+    }
+}
+
+/// The address that is connected to, for a node that the cluster advertises under another address.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MappedAddress {
+    host: String,
+    port: u16,
+    tls_server_name: Option<String>,
+}
+
+impl MappedAddress {
+    /// Connects to `host:port`, and verifies the node's TLS certificate against `host`.
+    pub fn new(host: impl Into<String>, port: u16) -> Self {
+        Self {
+            host: host.into(),
+            port,
+            tls_server_name: None,
+        }
+    }
+
+    /// Verifies the node's TLS certificate against `server_name`, and sends it as the SNI, instead
+    /// of the host that is connected to. It has no effect on connections that don't use TLS.
+    pub fn with_tls_server_name(mut self, server_name: impl Into<String>) -> Self {
+        self.tls_server_name = Some(server_name.into());
+        self
+    }
+
+    /// The host that is connected to.
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// The port that is connected to.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// The name that the node's TLS certificate is verified against, if it's not the host.
+    pub fn tls_server_name(&self) -> Option<&str> {
+        self.tls_server_name.as_deref()
+    }
+}
+
+/// Translates the addresses that cluster nodes advertise - in `CLUSTER SHARDS`, `CLUSTER SLOTS`
+/// and redirects - into the addresses that the client can reach them on, for example through
+/// port-forwarding, a Docker network or a proxy.
+///
+/// Nodes are still identified by their advertised addresses, so routing is unaffected; only the
+/// connections go to the translated addresses. The initial nodes are passed through the mapper as
+/// well, so it should return addresses that it doesn't know as they are.
+///
+/// Closures that take the advertised host and port are mappers too:
+///
+/// ```rust,no_run
+/// use redis::cluster::{ClusterClientBuilder, MappedAddress};
+///
+/// let client = ClusterClientBuilder::new(vec!["redis://127.0.0.1:7000/"])
+///     .address_mapper(|host: &str, port: u16| match host {
+///         "10.0.0.1" => MappedAddress::new("127.0.0.1", port),
+///         _ => MappedAddress::new(host, port),
+///     })
+///     .build()
+///     .unwrap();
+/// ```
+pub trait AddressMapper: Send + Sync {
+    /// Returns the address to connect to, for the node that is advertised as `host:port`.
+    fn map_address(&self, host: &str, port: u16) -> MappedAddress;
+}
+
+impl<F> AddressMapper for F
+where
+    F: Fn(&str, u16) -> MappedAddress + Send + Sync,
+{
+    fn map_address(&self, host: &str, port: u16) -> MappedAddress {
+        self(host, port)
+    }
+}
+
 /// Used to configure and build a [`ClusterClient`].
 pub struct ClusterClientBuilder {
     initial_nodes: RedisResult<Vec<ConnectionInfo>>,
@@ -161,6 +297,20 @@ impl ClusterClientBuilder {
         };
 
         let mut cluster_params = ClusterParams::from(self.builder_params)?;
+        #[cfg(feature = "cluster-async")]
+        if cluster_params.topology_checks_interval == Some(Duration::ZERO) {
+            return Err(RedisError::from((
+                ErrorKind::InvalidClientConfig,
+                "Topology checks interval should be non-zero.",
+            )));
+        }
+        #[cfg(feature = "cluster-async")]
+        if cluster_params.connections_per_node == 0 {
+            return Err(RedisError::from((
+                ErrorKind::InvalidClientConfig,
+                "Connections per node should be non-zero.",
+            )));
+        }
         let password = if cluster_params.password.is_none() {
             cluster_params
                 .password
@@ -303,6 +453,28 @@ impl ClusterClientBuilder {
         self
     }
 
+    /// Enables reading from replicas for all new connections, and sets the strategy that chooses
+    /// which node serves each read (default is a random replica).
+    ///
+    /// See [`cluster_routing`](crate::cluster_routing) for the built-in strategies.
+    pub fn read_from_replica_strategy(
+        mut self,
+        strategy: impl ReadFromReplicaStrategy + 'static,
+    ) -> ClusterClientBuilder {
+        self.builder_params.read_from_replicas = true;
+        self.builder_params.read_from_replica_strategy = Some(Arc::new(strategy));
+        self
+    }
+
+    /// Sets the mapper that translates the addresses that nodes advertise into the addresses that
+    /// are connected to (default is connecting to the advertised addresses).
+    ///
+    /// See [`AddressMapper`] for details.
+    pub fn address_mapper(mut self, mapper: impl AddressMapper + 'static) -> ClusterClientBuilder {
+        self.builder_params.address_mapper = Some(Arc::new(mapper));
+        self
+    }
+
     /// Enables timing out on slow connection time.
     ///
     /// If enabled, the cluster will only wait the given time on each connection attempt to each node.
@@ -319,6 +491,63 @@ impl ClusterClientBuilder {
         self
     }
 
+    /// Enables periodic checks of the cluster's topology by async cluster connections, roughly on
+    /// the given interval (default is disabled).
+    ///
+    /// If enabled, every check queries the slots from a random node, and if they changed - for
+    /// example because nodes were added or removed, or a replica was promoted - refreshes the
+    /// slots and closes the connections to nodes that left the cluster. Otherwise the slots are
+    /// only refreshed after requests fail due to a topology change. Every interval is randomly
+    /// extended or shortened by up to 10%, so that clients that were created together don't check
+    /// the topology together.
+    #[cfg(feature = "cluster-async")]
+    pub fn periodic_topology_checks(mut self, interval: Duration) -> ClusterClientBuilder {
+        self.builder_params.topology_checks_interval = Some(interval);
+        self
+    }
+
+    /// Sets the number of multiplexed connections that async cluster connections open to every
+    /// node (default is 1).
+    ///
+    /// Every request is sent over the node's connection with the fewest requests in flight, which
+    /// spreads the load of busy nodes across several sockets. Subscriptions always use the first
+    /// connection of their node. A broken connection is replaced on its own, without reconnecting
+    /// the rest of the node's connections.
+    #[cfg(feature = "cluster-async")]
+    pub fn connections_per_node(mut self, count: usize) -> ClusterClientBuilder {
+        self.builder_params.connections_per_node = Some(count);
+        self
+    }
+
+    /// Registers an [`Instrumentation`] that async cluster connections notify of every request
+    /// that is sent to a node, of the redirections of requests between nodes, and of the
+    /// reconnections to nodes.
+    ///
+    /// A request that is sent to several nodes is reported once for every node, and a request that
+    /// is retried is reported once for every attempt.
+    #[cfg(feature = "cluster-async")]
+    pub fn instrumentation(
+        mut self,
+        instrumentation: Arc<dyn Instrumentation>,
+    ) -> ClusterClientBuilder {
+        self.builder_params.instrumentation = Some(instrumentation);
+        self
+    }
+
+    /// Sets a [`CredentialsProvider`] that async cluster connections ask for credentials whenever
+    /// they connect to a node, instead of using the password and username of the client.
+    ///
+    /// Node connections aren't authenticated again when their credentials expire, but are
+    /// reconnected with new credentials once the server closes them.
+    #[cfg(feature = "cluster-async")]
+    pub fn credentials_provider(
+        mut self,
+        provider: Arc<dyn CredentialsProvider>,
+    ) -> ClusterClientBuilder {
+        self.builder_params.credentials_provider = Some(provider);
+        self
+    }
+
     /// Sets the protocol with which the client should communicate with the server.
     pub fn use_protocol(mut self, protocol: ProtocolVersion) -> ClusterClientBuilder {
         self.builder_params.protocol = protocol;
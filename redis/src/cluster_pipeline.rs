@@ -1,5 +1,10 @@
-use crate::cluster::ClusterConnection;
+use crate::cluster::{ClusterConnection, Connect};
+use crate::cluster_routing::{
+    aggregate, combine_and_sort_array_results, command_for_multi_slot_indices, logical_aggregate,
+    MultipleNodeRoutingInfo, ResponsePolicy, Route, RoutingInfo, SingleNodeRoutingInfo,
+};
 use crate::cmd::{cmd, Cmd};
+use crate::connection::ConnectionLike;
 use crate::types::{
     from_owned_redis_value, ErrorKind, FromRedisValue, HashSet, RedisResult, ToRedisArgs, Value,
 };
@@ -23,7 +28,7 @@ fn is_illegal_cmd(cmd: &str) -> bool {
         "INFO" |
         "KEYS" |
         "LASTSAVE" |
-        "MGET" | "MOVE" | "MSET" | "MSETNX" |
+        "MOVE" | "MSETNX" |
         "PFMERGE" | "PFCOUNT" | "PING" | "PUBLISH" |
         "RANDOMKEY" | "RENAME" | "RENAMENX" | "RPOPLPUSH" |
         "SAVE" | "SCAN" |
@@ -61,7 +66,7 @@ pub struct ClusterPipeline {
 /// INFO
 /// KEYS
 /// LASTSAVE
-/// MGET, MOVE, MSET, MSETNX
+/// MOVE, MSETNX
 /// PFMERGE, PFCOUNT, PING, PUBLISH
 /// RANDOMKEY, RENAME, RENAMENX, RPOPLPUSH
 /// SAVE, SCAN, SCRIPT EXISTS, SCRIPT FLUSH, SCRIPT KILL, SCRIPT LOAD, SDIFF, SDIFFSTORE,
@@ -70,6 +75,10 @@ pub struct ClusterPipeline {
 /// SINTERSTORE, SLAVEOF, SLOWLOG GET, SLOWLOG LEN, SLOWLOG RESET, SMOVE, SORT, SUNION, SUNIONSTORE
 /// TIME
 /// ```
+///
+/// The commands are sent to the nodes that serve their keys, with one pipeline per node. Commands
+/// whose keys belong to several slots, such as `MGET`, `MSET` and `DEL`, are split into a command
+/// per slot, and their responses are combined back into a single response.
 impl ClusterPipeline {
     /// Create an empty pipeline.
     pub fn new() -> ClusterPipeline {
@@ -102,7 +111,10 @@ impl ClusterPipeline {
     ///     .cmd("GET").arg("key_2").query(&mut con).unwrap();
     /// ```
     #[inline]
-    pub fn query<T: FromRedisValue>(&self, con: &mut ClusterConnection) -> RedisResult<T> {
+    pub fn query<T: FromRedisValue>(
+        &self,
+        con: &mut ClusterConnection<impl ConnectionLike + Connect>,
+    ) -> RedisResult<T> {
         for cmd in &self.commands {
             let cmd_name = std::str::from_utf8(cmd.arg_idx(0).unwrap_or(b""))
                 .unwrap_or("")
@@ -138,11 +150,121 @@ impl ClusterPipeline {
     /// let _ : () = pipe.cmd("SET").arg("key_1").arg(42).ignore().query(&mut con).unwrap();
     /// ```
     #[inline]
-    pub fn execute(&self, con: &mut ClusterConnection) {
+    pub fn execute(&self, con: &mut ClusterConnection<impl ConnectionLike + Connect>) {
         self.query::<()>(con).unwrap();
     }
 }
 
+// The commands of a pipeline, after every multi-slot command was split into one command per slot.
+pub(crate) struct SplitPipeline {
+    commands: Vec<SplitCommand>,
+    originals: Vec<OriginalCommand>,
+}
+
+// A command of a split pipeline, which is routed either to a single node, or to all nodes or all
+// primaries.
+pub(crate) struct SplitCommand {
+    pub(crate) cmd: Cmd,
+    pub(crate) routing: RoutingInfo,
+}
+
+// How the responses of the split commands are turned into the response of an original command.
+enum OriginalCommand {
+    Single(usize),
+    MultiSlot {
+        // The index of the first split command. The commands of the other slots follow it.
+        first: usize,
+        routes: Vec<(Route, Vec<usize>)>,
+        response_policy: Option<ResponsePolicy>,
+    },
+}
+
+impl SplitPipeline {
+    pub(crate) fn new<'a>(cmds: impl IntoIterator<Item = &'a Cmd>) -> RedisResult<Self> {
+        let mut commands = Vec::new();
+        let mut originals = Vec::new();
+        for cmd in cmds {
+            let routing = match RoutingInfo::for_routable(cmd) {
+                Some(RoutingInfo::MultiNode((
+                    MultipleNodeRoutingInfo::MultiSlot(routes),
+                    response_policy,
+                ))) => {
+                    originals.push(OriginalCommand::MultiSlot {
+                        first: commands.len(),
+                        routes: routes.clone(),
+                        response_policy,
+                    });
+                    commands.extend(routes.into_iter().map(|(route, indices)| SplitCommand {
+                        cmd: command_for_multi_slot_indices(cmd, indices.iter()),
+                        routing: RoutingInfo::SingleNode(SingleNodeRoutingInfo::SpecificNode(
+                            route,
+                        )),
+                    }));
+                    continue;
+                }
+                Some(
+                    routing @ RoutingInfo::SingleNode(
+                        SingleNodeRoutingInfo::SpecificNode(_) | SingleNodeRoutingInfo::Random,
+                    ),
+                ) => routing,
+                Some(routing @ RoutingInfo::MultiNode(_)) => routing,
+                _ => fail!(UNROUTABLE_ERROR),
+            };
+            originals.push(OriginalCommand::Single(commands.len()));
+            commands.push(SplitCommand {
+                cmd: cmd.clone(),
+                routing,
+            });
+        }
+        Ok(Self {
+            commands,
+            originals,
+        })
+    }
+
+    pub(crate) fn commands(&self) -> &[SplitCommand] {
+        &self.commands
+    }
+
+    // Turns the responses of the split commands, in the order of `commands`, into the responses
+    // of the original commands.
+    pub(crate) fn combine(&self, mut responses: Vec<Value>) -> RedisResult<Vec<Value>> {
+        self.originals
+            .iter()
+            .map(|original| match original {
+                OriginalCommand::Single(index) => {
+                    Ok(std::mem::replace(&mut responses[*index], Value::Nil))
+                }
+                OriginalCommand::MultiSlot {
+                    first,
+                    routes,
+                    response_policy,
+                } => {
+                    let mut values: Vec<Value> = responses[*first..*first + routes.len()]
+                        .iter_mut()
+                        .map(|response| std::mem::replace(response, Value::Nil))
+                        .collect();
+                    match response_policy {
+                        Some(ResponsePolicy::CombineArrays) => combine_and_sort_array_results(
+                            values,
+                            routes.iter().map(|(_, indices)| indices),
+                        ),
+                        Some(ResponsePolicy::Aggregate(op)) => aggregate(values, *op),
+                        Some(ResponsePolicy::AggregateLogical(op)) => {
+                            logical_aggregate(values, *op)
+                        }
+                        // Every command succeeded, or the pipeline would have failed.
+                        Some(ResponsePolicy::AllSucceeded) => {
+                            Ok(values.pop().unwrap_or(Value::Nil))
+                        }
+                        _ => Ok(Value::Array(values)),
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
 /// Shortcut for creating a new cluster pipeline.
 pub fn cluster_pipe() -> ClusterPipeline {
     ClusterPipeline::new()
@@ -1,5 +1,8 @@
 use std::cmp::min;
 use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use rand::seq::SliceRandom;
 use rand::thread_rng;
@@ -310,6 +313,127 @@ where
     })
 }
 
+/// Returns the indices of the arguments of `routable` that are keys.
+///
+/// This covers the commands whose keys are found by [`RoutingInfo::for_routable`], as well as
+/// the commands that take several keys in other positions, which are needed when all of a
+/// command's keys matter, and not only the one that it's routed by. The `BY` and `GET` patterns of
+/// `SORT` are included, since they name the keys that are looked up for each element.
+pub(crate) fn key_indices<R>(routable: &R) -> Vec<usize>
+where
+    R: Routable + ?Sized,
+{
+    let cmd = match routable.command() {
+        Some(cmd) => cmd,
+        None => return Vec::new(),
+    };
+    let arg_count = (1..)
+        .take_while(|idx| routable.arg_idx(*idx).is_some())
+        .count()
+        + 1;
+    // The keys that follow the key count at `idx`.
+    let counted_keys = |idx: usize| {
+        let key_count = routable
+            .arg_idx(idx)
+            .and_then(|x| std::str::from_utf8(x).ok())
+            .and_then(|x| x.parse::<usize>().ok())
+            .unwrap_or(0);
+        idx + 1..min(idx + 1 + key_count, arg_count)
+    };
+    // The argument that follows each of the given options.
+    let options = |names: &[&[u8]]| {
+        names
+            .iter()
+            .filter_map(|name| routable.position(name))
+            .map(|idx| idx + 1)
+            .filter(|idx| *idx < arg_count)
+            .collect::<Vec<_>>()
+    };
+
+    match &cmd[..] {
+        b"MGET" | b"DEL" | b"EXISTS" | b"UNLINK" | b"TOUCH" | b"WATCH" | b"RENAME"
+        | b"RENAMENX" | b"RPOPLPUSH" | b"SDIFF" | b"SDIFFSTORE" | b"SINTER" | b"SINTERSTORE"
+        | b"SUNION" | b"SUNIONSTORE" | b"PFCOUNT" | b"PFMERGE" => (1..arg_count).collect(),
+        b"MSET" | b"MSETNX" => (1..arg_count).step_by(2).collect(),
+        b"BLPOP" | b"BRPOP" | b"BZPOPMIN" | b"BZPOPMAX" => (1..arg_count - 1).collect(),
+        b"BITOP" => (2..arg_count).collect(),
+        b"SMOVE" | b"LMOVE" | b"BLMOVE" | b"BRPOPLPUSH" | b"COPY" | b"GEOSEARCHSTORE"
+        | b"ZRANGESTORE" | b"LCS" => (1..min(3, arg_count)).collect(),
+        b"EVAL" | b"EVALSHA" | b"EVAL_RO" | b"EVALSHA_RO" | b"FCALL" | b"FCALL_RO" | b"BLMPOP"
+        | b"BZMPOP" => counted_keys(2).collect(),
+        b"ZUNION" | b"ZINTER" | b"ZDIFF" | b"ZINTERCARD" | b"SINTERCARD" | b"LMPOP" | b"ZMPOP" => {
+            counted_keys(1).collect()
+        }
+        b"ZUNIONSTORE" | b"ZINTERSTORE" | b"ZDIFFSTORE" => {
+            std::iter::once(1).chain(counted_keys(2)).collect()
+        }
+        b"SORT" | b"SORT_RO" => {
+            let mut indices = vec![1];
+            let mut idx = 2;
+            while idx + 1 < arg_count {
+                match &routable
+                    .arg_idx(idx)
+                    .unwrap_or_default()
+                    .to_ascii_uppercase()[..]
+                {
+                    b"LIMIT" => idx += 3,
+                    // `GET #` returns the element itself, and a `BY` pattern without a `*`,
+                    // such as `BY nosort`, skips the sorting instead of looking up keys.
+                    b"GET" if routable.arg_idx(idx + 1) == Some(b"#") => idx += 2,
+                    b"BY"
+                        if !routable
+                            .arg_idx(idx + 1)
+                            .unwrap_or_default()
+                            .contains(&b'*') =>
+                    {
+                        idx += 2
+                    }
+                    b"BY" | b"GET" | b"STORE" => {
+                        indices.push(idx + 1);
+                        idx += 2;
+                    }
+                    _ => idx += 1,
+                }
+            }
+            indices
+        }
+        b"GEORADIUS" | b"GEORADIUSBYMEMBER" => std::iter::once(1)
+            .chain(options(&[b"STORE", b"STOREDIST"]))
+            .collect(),
+        b"XREAD" | b"XREADGROUP" => match routable.position(b"STREAMS") {
+            Some(streams_position) => {
+                let first_key = streams_position + 1;
+                (first_key..first_key + (arg_count - first_key) / 2).collect()
+            }
+            None => Vec::new(),
+        },
+        b"XGROUP CREATE"
+        | b"XGROUP CREATECONSUMER"
+        | b"XGROUP DELCONSUMER"
+        | b"XGROUP DESTROY"
+        | b"XGROUP SETID"
+        | b"XINFO CONSUMERS"
+        | b"XINFO GROUPS"
+        | b"XINFO STREAM"
+        | b"OBJECT ENCODING"
+        | b"OBJECT FREQ"
+        | b"OBJECT IDLETIME"
+        | b"OBJECT REFCOUNT"
+        | b"MEMORY USAGE" => (2..min(3, arg_count)).collect(),
+        b"MOVE" => (1..min(2, arg_count)).collect(),
+        // Commands whose first argument is routed on, but isn't a key.
+        b"SELECT" | b"SWAPDB" | b"HELLO" | b"FAILOVER" | b"PUBLISH" | b"SPUBLISH"
+        | b"SUBSCRIBE" | b"SSUBSCRIBE" | b"PSUBSCRIBE" | b"UNSUBSCRIBE" | b"SUNSUBSCRIBE"
+        | b"PUNSUBSCRIBE" => Vec::new(),
+        // The other subcommands don't take keys.
+        cmd if cmd.contains(&b' ') => Vec::new(),
+        _ => match RoutingInfo::for_routable(routable) {
+            Some(RoutingInfo::SingleNode(SingleNodeRoutingInfo::SpecificNode(_))) => vec![1],
+            _ => Vec::new(),
+        },
+    }
+}
+
 impl ResponsePolicy {
     /// Parse the command for the matching response policy.
     pub fn for_command(cmd: &[u8]) -> Option<ResponsePolicy> {
@@ -590,6 +714,7 @@ pub(crate) struct Slot {
     pub(crate) end: u16,
     pub(crate) master: String,
     pub(crate) replicas: Vec<String>,
+    pub(crate) metadata: HashMap<String, NodeMetadata>,
 }
 
 impl Slot {
@@ -599,8 +724,273 @@ impl Slot {
             end: e,
             master: m,
             replicas: r,
+            metadata: HashMap::new(),
         }
     }
+
+    pub(crate) fn with_metadata(
+        mut self,
+        metadata: impl IntoIterator<Item = (String, NodeMetadata)>,
+    ) -> Self {
+        self.metadata.extend(metadata);
+        self
+    }
+}
+
+/// Metadata that the cluster reports about a node in `CLUSTER SHARDS` or `CLUSTER SLOTS`, such as
+/// its hostname, role or health.
+pub type NodeMetadata = HashMap<String, String>;
+
+/// The nodes that serve a slot, out of which a [`ReadFromReplicaStrategy`] chooses the node that
+/// serves a read.
+#[derive(Debug)]
+pub struct SlotNodes<'a> {
+    primary: &'a str,
+    replicas: &'a [String],
+    metadata: &'a HashMap<String, NodeMetadata>,
+}
+
+impl<'a> SlotNodes<'a> {
+    /// Returns the address of the slot's primary.
+    pub fn primary(&self) -> &'a str {
+        self.primary
+    }
+
+    /// Returns the addresses of the slot's replicas. There's always at least one replica.
+    pub fn replicas(&self) -> &'a [String] {
+        self.replicas
+    }
+
+    /// Returns the metadata that the cluster reported for `node`, if there's any.
+    pub fn metadata(&self, node: &str) -> Option<&'a NodeMetadata> {
+        self.metadata.get(node)
+    }
+}
+
+/// Chooses the node that serves each read that may be sent to a replica, when reading from
+/// replicas is enabled with
+/// [`ClusterClientBuilder::read_from_replica_strategy`](crate::cluster::ClusterClientBuilder::read_from_replica_strategy).
+///
+/// A strategy is shared by all connections of a client, and is told how every request to a single
+/// node went, so that it can take the nodes' state into account.
+pub trait ReadFromReplicaStrategy: Send + Sync {
+    /// Returns the address of the node that should serve a read from the slot that `nodes` serve -
+    /// either one of the replicas, or the primary.
+    fn choose_node<'a>(&self, nodes: &SlotNodes<'a>) -> &'a str;
+
+    /// Called when `node` responded to a request after `latency`.
+    fn record_latency(&self, _node: &str, _latency: Duration) {}
+
+    /// Called when a request to `node` failed due to a connection error.
+    fn record_failure(&self, _node: &str) {}
+}
+
+// The positions of round robins, kept separately for every shard, so that the order in which a
+// shard's replicas are chosen doesn't depend on the reads from other shards.
+#[derive(Debug, Default)]
+struct RoundRobinPositions(Mutex<HashMap<String, usize>>);
+
+impl RoundRobinPositions {
+    // Returns the next candidate in the round robin over `candidates` of the shard whose primary is
+    // `primary`. `candidates` must not be empty.
+    fn next<'a>(&self, primary: &str, candidates: &[&'a str]) -> &'a str {
+        let mut positions = self.0.lock().unwrap();
+        let position = match positions.get_mut(primary) {
+            Some(position) => position,
+            None => positions.entry(primary.to_string()).or_default(),
+        };
+        let candidate = candidates[*position % candidates.len()];
+        *position = position.wrapping_add(1);
+        candidate
+    }
+}
+
+/// Spreads reads evenly across the replicas of each shard.
+#[derive(Debug, Default)]
+pub struct RoundRobin {
+    positions: RoundRobinPositions,
+}
+
+impl RoundRobin {
+    /// Creates a new `RoundRobin` strategy.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ReadFromReplicaStrategy for RoundRobin {
+    fn choose_node<'a>(&self, nodes: &SlotNodes<'a>) -> &'a str {
+        let replicas: Vec<&str> = nodes.replicas().iter().map(String::as_str).collect();
+        self.positions.next(nodes.primary(), &replicas)
+    }
+}
+
+/// Sends reads to the replica with the lowest observed latency, which is averaged over recent
+/// requests. Replicas that weren't measured yet are preferred, so that every replica is measured,
+/// and a failed request counts as a response that took a second.
+#[derive(Debug, Default)]
+pub struct LowestLatency {
+    latencies: Mutex<HashMap<String, Duration>>,
+}
+
+impl LowestLatency {
+    const FAILURE_LATENCY: Duration = Duration::from_secs(1);
+    // The weight of a new measurement in the moving average.
+    const SMOOTHING_FACTOR: f64 = 0.2;
+
+    /// Creates a new `LowestLatency` strategy.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ReadFromReplicaStrategy for LowestLatency {
+    fn choose_node<'a>(&self, nodes: &SlotNodes<'a>) -> &'a str {
+        let latencies = self.latencies.lock().unwrap();
+        nodes
+            .replicas()
+            .iter()
+            .min_by_key(|replica| latencies.get(replica.as_str()).copied().unwrap_or_default())
+            .map_or(nodes.primary(), String::as_str)
+    }
+
+    fn record_latency(&self, node: &str, latency: Duration) {
+        let mut latencies = self.latencies.lock().unwrap();
+        let average = match latencies.get(node) {
+            Some(average) => average
+                .mul_f64(1.0 - Self::SMOOTHING_FACTOR)
+                .saturating_add(latency.mul_f64(Self::SMOOTHING_FACTOR)),
+            None => latency,
+        };
+        latencies.insert(node.to_string(), average);
+    }
+
+    fn record_failure(&self, node: &str) {
+        self.record_latency(node, Self::FAILURE_LATENCY);
+    }
+}
+
+/// Sends reads to the replicas in the client's availability zone, to avoid cross-zone traffic.
+///
+/// A node's zone is read from its metadata (see [`SlotNodes::metadata`]), under the
+/// `availability-zone` key by default. If none of a slot's replicas is in the client's zone, the
+/// read is sent to the primary if it's in the client's zone, and to any replica otherwise. Reads
+/// are spread evenly across the chosen nodes.
+#[derive(Debug)]
+pub struct AvailabilityZoneAffinity {
+    zone: String,
+    metadata_key: String,
+    positions: RoundRobinPositions,
+}
+
+impl AvailabilityZoneAffinity {
+    /// Creates a new `AvailabilityZoneAffinity` strategy for a client in `zone`.
+    pub fn new(zone: impl Into<String>) -> Self {
+        Self {
+            zone: zone.into(),
+            metadata_key: "availability-zone".to_string(),
+            positions: RoundRobinPositions::default(),
+        }
+    }
+
+    /// Sets the metadata key under which the nodes report their zone.
+    pub fn with_metadata_key(mut self, metadata_key: impl Into<String>) -> Self {
+        self.metadata_key = metadata_key.into();
+        self
+    }
+
+    fn is_in_zone(&self, nodes: &SlotNodes, node: &str) -> bool {
+        nodes
+            .metadata(node)
+            .and_then(|metadata| metadata.get(&self.metadata_key))
+            .map_or(false, |zone| *zone == self.zone)
+    }
+}
+
+impl ReadFromReplicaStrategy for AvailabilityZoneAffinity {
+    fn choose_node<'a>(&self, nodes: &SlotNodes<'a>) -> &'a str {
+        let replicas: Vec<&str> = nodes.replicas().iter().map(String::as_str).collect();
+        let local_replicas: Vec<&str> = replicas
+            .iter()
+            .copied()
+            .filter(|replica| self.is_in_zone(nodes, replica))
+            .collect();
+        if !local_replicas.is_empty() {
+            self.positions.next(nodes.primary(), &local_replicas)
+        } else if self.is_in_zone(nodes, nodes.primary()) {
+            nodes.primary()
+        } else {
+            self.positions.next(nodes.primary(), &replicas)
+        }
+    }
+}
+
+/// Spreads reads evenly across the healthy replicas of each slot, and sends them to the primary
+/// when all of the slot's replicas are unhealthy.
+///
+/// A replica becomes unhealthy when a request to it fails due to a connection error, and is
+/// considered healthy again when it responds to a request, or after the retry interval passed.
+#[derive(Debug)]
+pub struct PreferReplica {
+    unhealthy_until: Mutex<HashMap<String, Instant>>,
+    retry_interval: Duration,
+    positions: RoundRobinPositions,
+}
+
+impl Default for PreferReplica {
+    fn default() -> Self {
+        Self {
+            unhealthy_until: Default::default(),
+            retry_interval: Duration::from_secs(5),
+            positions: RoundRobinPositions::default(),
+        }
+    }
+}
+
+impl PreferReplica {
+    /// Creates a new `PreferReplica` strategy, which retries unhealthy replicas after 5 seconds.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the time after which an unhealthy replica is retried.
+    pub fn with_retry_interval(mut self, retry_interval: Duration) -> Self {
+        self.retry_interval = retry_interval;
+        self
+    }
+}
+
+impl ReadFromReplicaStrategy for PreferReplica {
+    fn choose_node<'a>(&self, nodes: &SlotNodes<'a>) -> &'a str {
+        let now = Instant::now();
+        let unhealthy_until = self.unhealthy_until.lock().unwrap();
+        let healthy_replicas: Vec<&str> = nodes
+            .replicas()
+            .iter()
+            .map(String::as_str)
+            .filter(|replica| {
+                unhealthy_until
+                    .get(*replica)
+                    .map_or(true, |until| *until <= now)
+            })
+            .collect();
+        if healthy_replicas.is_empty() {
+            nodes.primary()
+        } else {
+            self.positions.next(nodes.primary(), &healthy_replicas)
+        }
+    }
+
+    fn record_latency(&self, node: &str, _latency: Duration) {
+        self.unhealthy_until.lock().unwrap().remove(node);
+    }
+
+    fn record_failure(&self, node: &str) {
+        self.unhealthy_until
+            .lock()
+            .unwrap()
+            .insert(node.to_string(), Instant::now() + self.retry_interval);
+    }
 }
 
 /// What type of node should a request be routed to.
@@ -620,7 +1010,7 @@ pub enum SlotAddr {
 /// which stores only the master and [optional] replica
 /// to avoid the need to choose a replica each time
 /// a command is executed
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Clone)]
 pub(crate) struct SlotAddrs {
     primary: String,
     replicas: Vec<String>,
@@ -667,7 +1057,7 @@ impl<'a> IntoIterator for &'a SlotAddrs {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Clone)]
 struct SlotMapValue {
     start: u16,
     addrs: SlotAddrs,
@@ -682,10 +1072,30 @@ impl SlotMapValue {
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Default)]
 pub(crate) struct SlotMap {
     slots: BTreeMap<u16, SlotMapValue>,
     read_from_replica: bool,
+    // Chooses the replicas that serve reads. If it's not set, they're chosen randomly.
+    read_strategy: Option<Arc<dyn ReadFromReplicaStrategy>>,
+    node_metadata: HashMap<String, NodeMetadata>,
+}
+
+impl fmt::Debug for SlotMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SlotMap")
+            .field("slots", &self.slots)
+            .field("read_from_replica", &self.read_from_replica)
+            .field("node_metadata", &self.node_metadata)
+            .finish_non_exhaustive()
+    }
+}
+
+// Slot maps are equal if they describe the same topology.
+impl PartialEq for SlotMap {
+    fn eq(&self, other: &Self) -> bool {
+        self.slots == other.slots && self.node_metadata == other.node_metadata
+    }
 }
 
 impl SlotMap {
@@ -693,21 +1103,29 @@ impl SlotMap {
         Self {
             slots: Default::default(),
             read_from_replica,
+            read_strategy: None,
+            node_metadata: Default::default(),
         }
     }
 
     pub fn from_slots(slots: Vec<Slot>, read_from_replica: bool) -> Self {
-        Self {
-            slots: slots
-                .into_iter()
-                .map(|slot| (slot.end, SlotMapValue::from_slot(slot)))
-                .collect(),
-            read_from_replica,
-        }
+        let mut slot_map = Self::new(read_from_replica);
+        slot_map.fill_slots(slots);
+        slot_map
+    }
+
+    pub(crate) fn with_read_strategy(
+        mut self,
+        read_strategy: Option<Arc<dyn ReadFromReplicaStrategy>>,
+    ) -> Self {
+        self.read_strategy = read_strategy;
+        self
     }
 
     pub fn fill_slots(&mut self, slots: Vec<Slot>) {
-        for slot in slots {
+        for mut slot in slots {
+            self.node_metadata
+                .extend(std::mem::take(&mut slot.metadata));
             self.slots.insert(slot.end, SlotMapValue::from_slot(slot));
         }
     }
@@ -719,19 +1137,99 @@ impl SlotMap {
             .next()
             .and_then(|(end, slot_value)| {
                 if slot <= *end && slot_value.start <= slot {
-                    Some(
-                        slot_value
-                            .addrs
-                            .slot_addr(route.slot_addr(), self.read_from_replica),
-                    )
+                    Some(self.node_for_slot_addr(&slot_value.addrs, route.slot_addr()))
                 } else {
                     None
                 }
             })
     }
 
+    fn node_for_slot_addr<'a>(&'a self, addrs: &'a SlotAddrs, slot_addr: &SlotAddr) -> &'a str {
+        match &self.read_strategy {
+            Some(read_strategy)
+                if self.read_from_replica
+                    && *slot_addr == SlotAddr::ReplicaOptional
+                    && !addrs.replicas.is_empty() =>
+            {
+                read_strategy.choose_node(&SlotNodes {
+                    primary: &addrs.primary,
+                    replicas: &addrs.replicas,
+                    metadata: &self.node_metadata,
+                })
+            }
+            _ => addrs.slot_addr(slot_addr, self.read_from_replica),
+        }
+    }
+
     pub fn clear(&mut self) {
         self.slots.clear();
+        self.node_metadata.clear();
+    }
+
+    /// Moves a single slot to `primary`, as reported by a `MOVED` redirect, and splits the range
+    /// that contained it. If `primary` was one of the slot's replicas the redirect is most likely
+    /// caused by a failover, so the other replicas are kept. Otherwise the slot was migrated, and
+    /// its replicas are unknown until the slots are refreshed.
+    #[cfg(feature = "cluster-async")]
+    pub(crate) fn update_slot_owner(&mut self, slot: u16, primary: &str) {
+        let (end, value) = match self.slots.range(slot..).next() {
+            Some((end, value)) if value.start <= slot => (*end, value.clone()),
+            _ => {
+                self.slots.insert(
+                    slot,
+                    SlotMapValue {
+                        start: slot,
+                        addrs: SlotAddrs::new(primary.to_string(), Vec::new()),
+                    },
+                );
+                return;
+            }
+        };
+        if value.addrs.primary == primary {
+            return;
+        }
+
+        self.slots.remove(&end);
+        if value.start < slot {
+            self.slots.insert(
+                slot - 1,
+                SlotMapValue {
+                    start: value.start,
+                    addrs: value.addrs.clone(),
+                },
+            );
+        }
+        if slot < end {
+            self.slots.insert(
+                end,
+                SlotMapValue {
+                    start: slot + 1,
+                    addrs: value.addrs.clone(),
+                },
+            );
+        }
+        let replicas = if value
+            .addrs
+            .replicas
+            .iter()
+            .any(|replica| replica == primary)
+        {
+            value
+                .addrs
+                .replicas
+                .into_iter()
+                .filter(|replica| replica != primary)
+                .collect()
+        } else {
+            Vec::new()
+        };
+        self.slots.insert(
+            slot,
+            SlotMapValue {
+                start: slot,
+                addrs: SlotAddrs::new(primary.to_string(), replicas),
+            },
+        );
     }
 
     pub fn values(&self) -> impl Iterator<Item = &SlotAddrs> {
@@ -799,6 +1297,11 @@ impl Route {
 }
 
 fn get_hashtag(key: &[u8]) -> Option<&[u8]> {
+    hashtag_range(key).map(|range| &key[range])
+}
+
+/// Returns the range of the hash tag in `key`, without its braces, if the key has one.
+pub(crate) fn hashtag_range(key: &[u8]) -> Option<std::ops::Range<usize>> {
     let open = key.iter().position(|v| *v == b'{');
     let open = match open {
         Some(open) => open,
@@ -811,22 +1314,24 @@ fn get_hashtag(key: &[u8]) -> Option<&[u8]> {
         None => return None,
     };
 
-    let rv = &key[open + 1..open + close];
-    if rv.is_empty() {
+    if close == 1 {
         None
     } else {
-        Some(rv)
+        Some(open + 1..open + close)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use core::panic;
-    use std::collections::HashSet;
+    use std::collections::{HashMap, HashSet};
+    use std::sync::Arc;
+    use std::time::Duration;
 
     use super::{
-        command_for_multi_slot_indices, get_hashtag, slot, MultipleNodeRoutingInfo, Route,
-        RoutingInfo, SingleNodeRoutingInfo, Slot, SlotAddr, SlotMap,
+        command_for_multi_slot_indices, get_hashtag, key_indices, slot, AvailabilityZoneAffinity,
+        LowestLatency, MultipleNodeRoutingInfo, PreferReplica, ReadFromReplicaStrategy, RoundRobin,
+        Route, RoutingInfo, SingleNodeRoutingInfo, Slot, SlotAddr, SlotMap, SlotNodes,
     };
     use crate::{
         cluster_routing::{AggregateOp, ResponsePolicy},
@@ -842,6 +1347,77 @@ mod tests {
         assert_eq!(get_hashtag(&b"foo{{bar}}zap"[..]), Some(&b"{bar"[..]));
     }
 
+    #[test]
+    fn test_key_indices() {
+        let cases: &[(&[&str], &[usize])] = &[
+            (&["GET", "foo"], &[1]),
+            (&["set", "foo", "bar"], &[1]),
+            (&["MGET", "foo", "bar"], &[1, 2]),
+            (&["MSET", "foo", "1", "bar", "2"], &[1, 3]),
+            (&["BLPOP", "foo", "bar", "0"], &[1, 2]),
+            (&["RENAME", "foo", "bar"], &[1, 2]),
+            (&["EVAL", "script", "2", "foo", "bar", "arg"], &[3, 4]),
+            (&["EVAL", "script", "0", "arg"], &[]),
+            (
+                &[
+                    "ZUNIONSTORE",
+                    "dest",
+                    "2",
+                    "foo",
+                    "bar",
+                    "WEIGHTS",
+                    "1",
+                    "2",
+                ],
+                &[1, 3, 4],
+            ),
+            (&["LMPOP", "2", "foo", "bar", "LEFT"], &[2, 3]),
+            (&["LCS", "foo", "bar", "LEN"], &[1, 2]),
+            (
+                &["SORT", "foo", "LIMIT", "0", "1", "STORE", "dest"],
+                &[1, 6],
+            ),
+            (
+                &[
+                    "SORT",
+                    "foo",
+                    "by",
+                    "weight_*",
+                    "GET",
+                    "#",
+                    "GET",
+                    "obj_*->name",
+                    "ALPHA",
+                    "STORE",
+                    "dest",
+                ],
+                &[1, 3, 7, 10],
+            ),
+            (
+                &["SORT_RO", "foo", "LIMIT", "0", "10", "BY", "nosort", "DESC"],
+                &[1],
+            ),
+            (
+                &["XREAD", "COUNT", "2", "STREAMS", "foo", "bar", "0", "0"],
+                &[4, 5],
+            ),
+            (&["XGROUP", "CREATE", "foo", "group", "$"], &[2]),
+            (&["OBJECT", "ENCODING", "foo"], &[2]),
+            (&["CLIENT", "SETNAME", "name"], &[]),
+            (&["PUBLISH", "channel", "message"], &[]),
+            (&["SELECT", "1"], &[]),
+            (&["KEYS", "*"], &[]),
+            (&["PING"], &[]),
+        ];
+        for (args, expected) in cases {
+            let mut cmd = crate::Cmd::new();
+            for arg in *args {
+                cmd.arg(*arg);
+            }
+            assert_eq!(key_indices(&cmd), *expected, "{args:?}");
+        }
+    }
+
     #[test]
     fn test_routing_info_mixed_capatalization() {
         let mut upper = cmd("XREAD");
@@ -1172,12 +1748,14 @@ mod tests {
                     end: 1000,
                     master: "node1:6379".to_owned(),
                     replicas: vec!["replica1:6379".to_owned()],
+                    metadata: HashMap::new(),
                 },
                 Slot {
                     start: 1001,
                     end: 2000,
                     master: "node2:6379".to_owned(),
                     replicas: vec!["replica2:6379".to_owned()],
+                    metadata: HashMap::new(),
                 },
             ],
             true,
@@ -1238,6 +1816,7 @@ mod tests {
                 end: 1000,
                 master: "node1:6379".to_owned(),
                 replicas: vec!["replica1:6379".to_owned()],
+                metadata: HashMap::new(),
             }],
             false,
         );
@@ -1318,6 +1897,68 @@ mod tests {
         )
     }
 
+    #[test]
+    #[cfg(feature = "cluster-async")]
+    fn test_slot_map_update_slot_owner_splits_range() {
+        let mut slot_map = get_slot_map(true);
+        slot_map.update_slot_owner(500, "node4:6379");
+
+        let master = |slot_map: &SlotMap, slot| {
+            slot_map
+                .slot_addr_for_route(&Route::new(slot, SlotAddr::Master))
+                .map(str::to_owned)
+        };
+        assert_eq!(master(&slot_map, 1), Some("node1:6379".to_owned()));
+        assert_eq!(master(&slot_map, 499), Some("node1:6379".to_owned()));
+        assert_eq!(master(&slot_map, 500), Some("node4:6379".to_owned()));
+        assert_eq!(master(&slot_map, 501), Some("node1:6379".to_owned()));
+        assert_eq!(master(&slot_map, 1000), Some("node1:6379".to_owned()));
+        // The new owner's replicas are unknown, so reads go to the primary.
+        assert_eq!(
+            slot_map.slot_addr_for_route(&Route::new(500, SlotAddr::ReplicaOptional)),
+            Some("node4:6379")
+        );
+        assert_eq!(
+            slot_map.slot_addr_for_route(&Route::new(501, SlotAddr::ReplicaOptional)),
+            Some("replica1:6379")
+        );
+
+        // Slots at the edges of a range, and slots that weren't covered, are moved as well.
+        slot_map.update_slot_owner(2000, "node4:6379");
+        slot_map.update_slot_owner(1001, "node4:6379");
+        assert_eq!(master(&slot_map, 1999), Some("node2:6379".to_owned()));
+        assert_eq!(master(&slot_map, 2000), Some("node4:6379".to_owned()));
+        assert_eq!(master(&slot_map, 1001), Some("node4:6379".to_owned()));
+        assert_eq!(master(&slot_map, 1002), Some("node2:6379".to_owned()));
+    }
+
+    #[test]
+    #[cfg(feature = "cluster-async")]
+    fn test_slot_map_update_slot_owner_keeps_replicas_after_failover() {
+        let mut slot_map = get_slot_map(false);
+        slot_map.update_slot_owner(1500, "replica2:6379");
+
+        let slot_addrs = slot_map
+            .slots
+            .get(&1500)
+            .map(|value| (value.start, &value.addrs));
+        assert_eq!(
+            slot_addrs,
+            Some((
+                1500,
+                &super::SlotAddrs::new(
+                    "replica2:6379".to_owned(),
+                    vec!["replica3:6379".to_owned()]
+                )
+            ))
+        );
+
+        // Moving a slot to its current owner leaves the map unchanged.
+        let mut unchanged = get_slot_map(false);
+        unchanged.update_slot_owner(1500, "node2:6379");
+        assert_eq!(unchanged, get_slot_map(false));
+    }
+
     #[test]
     fn test_slot_map_get_all_primaries() {
         let slot_map = get_slot_map(false);
@@ -1425,4 +2066,157 @@ mod tests {
             vec![Some("replica1:6379"), None, None, Some("node3:6379")]
         );
     }
+
+    fn slot_nodes<'a>(
+        replicas: &'a [String],
+        metadata: &'a HashMap<String, HashMap<String, String>>,
+    ) -> SlotNodes<'a> {
+        SlotNodes {
+            primary: "primary:6379",
+            replicas,
+            metadata,
+        }
+    }
+
+    fn zone_metadata(zones: &[(&str, &str)]) -> HashMap<String, HashMap<String, String>> {
+        zones
+            .iter()
+            .map(|(node, zone)| {
+                let metadata = HashMap::from([("availability-zone".to_string(), zone.to_string())]);
+                (node.to_string(), metadata)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_round_robin_cycles_through_replicas() {
+        let replicas = vec!["replica1:6379".to_string(), "replica2:6379".to_string()];
+        let metadata = HashMap::new();
+        let nodes = slot_nodes(&replicas, &metadata);
+        let strategy = RoundRobin::new();
+
+        let chosen: Vec<_> = (0..4).map(|_| strategy.choose_node(&nodes)).collect();
+        assert_eq!(
+            chosen,
+            vec![
+                "replica1:6379",
+                "replica2:6379",
+                "replica1:6379",
+                "replica2:6379"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_round_robin_of_each_shard_is_independent() {
+        let replicas = vec!["replica1:6379".to_string(), "replica2:6379".to_string()];
+        let other_replicas = vec!["replica3:6379".to_string(), "replica4:6379".to_string()];
+        let metadata = HashMap::new();
+        let nodes = slot_nodes(&replicas, &metadata);
+        let other_nodes = SlotNodes {
+            primary: "primary2:6379",
+            replicas: &other_replicas,
+            metadata: &metadata,
+        };
+        let strategy = RoundRobin::new();
+
+        assert_eq!(strategy.choose_node(&nodes), "replica1:6379");
+        assert_eq!(strategy.choose_node(&other_nodes), "replica3:6379");
+        assert_eq!(strategy.choose_node(&other_nodes), "replica4:6379");
+        assert_eq!(strategy.choose_node(&nodes), "replica2:6379");
+    }
+
+    #[test]
+    fn test_lowest_latency_prefers_unmeasured_then_fastest_replica() {
+        let replicas = vec!["replica1:6379".to_string(), "replica2:6379".to_string()];
+        let metadata = HashMap::new();
+        let nodes = slot_nodes(&replicas, &metadata);
+        let strategy = LowestLatency::new();
+
+        strategy.record_latency("replica1:6379", Duration::from_millis(5));
+        assert_eq!(strategy.choose_node(&nodes), "replica2:6379");
+
+        strategy.record_latency("replica2:6379", Duration::from_millis(10));
+        assert_eq!(strategy.choose_node(&nodes), "replica1:6379");
+
+        strategy.record_failure("replica1:6379");
+        assert_eq!(strategy.choose_node(&nodes), "replica2:6379");
+    }
+
+    #[test]
+    fn test_availability_zone_affinity() {
+        let replicas = vec!["replica1:6379".to_string(), "replica2:6379".to_string()];
+        let metadata = zone_metadata(&[
+            ("primary:6379", "zone-a"),
+            ("replica1:6379", "zone-b"),
+            ("replica2:6379", "zone-c"),
+        ]);
+        let nodes = slot_nodes(&replicas, &metadata);
+
+        let strategy = AvailabilityZoneAffinity::new("zone-c");
+        assert_eq!(strategy.choose_node(&nodes), "replica2:6379");
+        assert_eq!(strategy.choose_node(&nodes), "replica2:6379");
+
+        let strategy = AvailabilityZoneAffinity::new("zone-a");
+        assert_eq!(strategy.choose_node(&nodes), "primary:6379");
+
+        let strategy = AvailabilityZoneAffinity::new("zone-d");
+        let mut chosen: Vec<_> = (0..2).map(|_| strategy.choose_node(&nodes)).collect();
+        chosen.sort();
+        assert_eq!(chosen, vec!["replica1:6379", "replica2:6379"]);
+
+        // None of the nodes reports a zone under this key.
+        let strategy = AvailabilityZoneAffinity::new("zone-a").with_metadata_key("zone");
+        assert_eq!(strategy.choose_node(&nodes), "replica1:6379");
+    }
+
+    #[test]
+    fn test_prefer_replica_falls_back_to_primary() {
+        let replicas = vec!["replica1:6379".to_string(), "replica2:6379".to_string()];
+        let metadata = HashMap::new();
+        let nodes = slot_nodes(&replicas, &metadata);
+        let strategy = PreferReplica::new();
+
+        strategy.record_failure("replica1:6379");
+        assert_eq!(strategy.choose_node(&nodes), "replica2:6379");
+        assert_eq!(strategy.choose_node(&nodes), "replica2:6379");
+
+        strategy.record_failure("replica2:6379");
+        assert_eq!(strategy.choose_node(&nodes), "primary:6379");
+
+        strategy.record_latency("replica1:6379", Duration::from_millis(1));
+        assert_eq!(strategy.choose_node(&nodes), "replica1:6379");
+
+        let strategy = PreferReplica::new().with_retry_interval(Duration::from_millis(1));
+        strategy.record_failure("replica1:6379");
+        strategy.record_failure("replica2:6379");
+        std::thread::sleep(Duration::from_millis(2));
+        assert_ne!(strategy.choose_node(&nodes), "primary:6379");
+    }
+
+    #[test]
+    fn test_slot_map_uses_read_strategy_for_replica_optional_reads() {
+        let slot_map = SlotMap::from_slots(
+            vec![Slot::new(
+                0,
+                16383,
+                "primary:6379".to_owned(),
+                vec!["replica1:6379".to_owned(), "replica2:6379".to_owned()],
+            )
+            .with_metadata(zone_metadata(&[("replica2:6379", "zone-a")]))],
+            true,
+        )
+        .with_read_strategy(Some(Arc::new(AvailabilityZoneAffinity::new("zone-a"))));
+
+        for _ in 0..10 {
+            assert_eq!(
+                slot_map.slot_addr_for_route(&Route::new(1, SlotAddr::ReplicaOptional)),
+                Some("replica2:6379")
+            );
+        }
+        assert_eq!(
+            slot_map.slot_addr_for_route(&Route::new(1, SlotAddr::Master)),
+            Some("primary:6379")
+        );
+    }
 }
@@ -0,0 +1,250 @@
+use crate::cluster_routing::{get_slot, Route, SlotAddr, SlotMap, SLOT_SIZE};
+use crate::cmd::{cmd, Cmd};
+use crate::types::{from_owned_redis_value, RedisResult, Value};
+
+/// The filters of a cluster-wide scan, which are passed on to the `SCAN` of every primary.
+///
+/// ```rust,no_run
+/// use redis::cluster::ScanOptions;
+///
+/// let options = ScanOptions::default()
+///     .with_pattern("user:*")
+///     .with_count(100)
+///     .with_type("hash");
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ScanOptions {
+    pattern: Option<String>,
+    count: Option<usize>,
+    object_type: Option<String>,
+}
+
+impl ScanOptions {
+    /// Only returns the keys that match `pattern` (`MATCH`).
+    pub fn with_pattern(mut self, pattern: impl Into<String>) -> Self {
+        self.pattern = Some(pattern.into());
+        self
+    }
+
+    /// Hints how many keys a node should look at in every call (`COUNT`).
+    pub fn with_count(mut self, count: usize) -> Self {
+        self.count = Some(count);
+        self
+    }
+
+    /// Only returns the keys that hold values of the given type, such as `"hash"` (`TYPE`).
+    pub fn with_type(mut self, object_type: impl Into<String>) -> Self {
+        self.object_type = Some(object_type.into());
+        self
+    }
+}
+
+// The scan of a single primary, for the slots that it owned when its scan started.
+struct NodeScan {
+    address: String,
+    slots: Vec<bool>,
+    // `None` until the first `SCAN` was answered.
+    cursor: Option<u64>,
+}
+
+// The progress of a cluster-wide scan, which is shared by the sync and async cluster connections.
+//
+// Primaries are scanned one after another. When a primary's scan ends, only the slots that it
+// still owns are marked as scanned. Slots that moved in the meantime are scanned again on their
+// new owner, so no slot is skipped, but keys might be returned more than once, as `SCAN` allows.
+pub(crate) struct ClusterScanState {
+    options: ScanOptions,
+    scanned: Vec<bool>,
+    node: Option<NodeScan>,
+}
+
+impl ClusterScanState {
+    pub(crate) fn new(options: ScanOptions) -> Self {
+        Self {
+            options,
+            scanned: vec![false; SLOT_SIZE as usize],
+            node: None,
+        }
+    }
+
+    // Returns the node to scan and the command to send to it, or `None` when every slot was
+    // scanned.
+    pub(crate) fn next_request(&mut self, slot_map: &SlotMap) -> Option<(String, Cmd)> {
+        if self.node.is_none() {
+            self.node = self.next_node(slot_map);
+        }
+        let node = self.node.as_ref()?;
+        let mut scan = cmd("SCAN");
+        scan.arg(node.cursor.unwrap_or(0));
+        if let Some(pattern) = &self.options.pattern {
+            scan.arg("MATCH").arg(pattern);
+        }
+        if let Some(count) = self.options.count {
+            scan.arg("COUNT").arg(count);
+        }
+        if let Some(object_type) = &self.options.object_type {
+            scan.arg("TYPE").arg(object_type);
+        }
+        Some((node.address.clone(), scan))
+    }
+
+    fn next_node(&mut self, slot_map: &SlotMap) -> Option<NodeScan> {
+        loop {
+            let slot = self.scanned.iter().position(|scanned| !scanned)?;
+            match primary_for_slot(slot_map, slot) {
+                Some(address) => {
+                    let slots = (0..SLOT_SIZE as usize)
+                        .map(|slot| {
+                            !self.scanned[slot] && primary_for_slot(slot_map, slot) == Some(address)
+                        })
+                        .collect();
+                    return Some(NodeScan {
+                        address: address.to_string(),
+                        slots,
+                        cursor: None,
+                    });
+                }
+                // No node serves the slot, so it can't hold any keys.
+                None => self.scanned[slot] = true,
+            }
+        }
+    }
+
+    // Handles the current node's `SCAN` response, and returns the keys that belong to the slots
+    // that are scanned on it. Keys of other slots are returned by the scans of their owners.
+    pub(crate) fn handle_response(&mut self, response: Value) -> RedisResult<Vec<Value>> {
+        let node = self
+            .node
+            .as_mut()
+            .expect("A response can only be handled while a node is scanned");
+        let (cursor, keys): (u64, Vec<Value>) = from_owned_redis_value(response)?;
+        node.cursor = Some(cursor);
+        Ok(keys
+            .into_iter()
+            .filter(|key| match key {
+                Value::BulkString(key) => node.slots[get_slot(key) as usize],
+                _ => true,
+            })
+            .collect())
+    }
+
+    // Whether the current node's scan ended, and should be completed with `finish_node`.
+    pub(crate) fn is_node_done(&self) -> bool {
+        self.node
+            .as_ref()
+            .map_or(false, |node| node.cursor == Some(0))
+    }
+
+    // Marks the slots that the current node still owns according to `slot_map` as scanned.
+    pub(crate) fn finish_node(&mut self, slot_map: &SlotMap) {
+        if let Some(node) = self.node.take() {
+            for (slot, _) in node.slots.iter().enumerate().filter(|(_, owned)| **owned) {
+                if primary_for_slot(slot_map, slot) == Some(node.address.as_str()) {
+                    self.scanned[slot] = true;
+                }
+            }
+        }
+    }
+
+    // Starts the current node's scan over, on whichever node owns its slots by then.
+    pub(crate) fn restart_node(&mut self) {
+        self.node = None;
+    }
+}
+
+fn primary_for_slot(slot_map: &SlotMap, slot: usize) -> Option<&str> {
+    slot_map.slot_addr_for_route(&Route::new(slot as u16, SlotAddr::Master))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster_routing::Slot;
+
+    fn slot_map(ranges: &[(u16, u16, &str)]) -> SlotMap {
+        SlotMap::from_slots(
+            ranges
+                .iter()
+                .map(|(start, end, node)| Slot::new(*start, *end, node.to_string(), Vec::new()))
+                .collect(),
+            false,
+        )
+    }
+
+    fn keys(values: &[&str]) -> Value {
+        Value::Array(
+            values
+                .iter()
+                .map(|key| Value::BulkString(key.as_bytes().to_vec()))
+                .collect(),
+        )
+    }
+
+    fn scan_response(cursor: u64, values: &[&str]) -> Value {
+        Value::Array(vec![
+            Value::BulkString(cursor.to_string().into_bytes()),
+            keys(values),
+        ])
+    }
+
+    #[test]
+    fn scans_every_primary_once_with_filters() {
+        let slot_map = slot_map(&[(0, 8191, "node1:6379"), (8192, 16383, "node2:6379")]);
+        let mut state =
+            ClusterScanState::new(ScanOptions::default().with_pattern("*").with_count(10));
+
+        let (address, scan) = state.next_request(&slot_map).unwrap();
+        assert_eq!(address, "node1:6379");
+        assert_eq!(
+            scan.get_packed_command(),
+            cmd("SCAN")
+                .arg(0)
+                .arg("MATCH")
+                .arg("*")
+                .arg("COUNT")
+                .arg(10)
+                .get_packed_command()
+        );
+        // "foo" belongs to node2's slots, and is left for its scan.
+        let found = state
+            .handle_response(scan_response(5, &["bar", "foo"]))
+            .unwrap();
+        assert_eq!(found, vec![Value::BulkString(b"bar".to_vec())]);
+        assert!(!state.is_node_done());
+
+        let (address, scan) = state.next_request(&slot_map).unwrap();
+        assert_eq!(address, "node1:6379");
+        assert_eq!(scan.arg_idx(1), Some(&b"5"[..]));
+        state.handle_response(scan_response(0, &[])).unwrap();
+        assert!(state.is_node_done());
+        state.finish_node(&slot_map);
+
+        let (address, _) = state.next_request(&slot_map).unwrap();
+        assert_eq!(address, "node2:6379");
+        state.handle_response(scan_response(0, &["foo"])).unwrap();
+        state.finish_node(&slot_map);
+        assert!(state.next_request(&slot_map).is_none());
+    }
+
+    #[test]
+    fn rescans_slots_that_moved_during_a_node_scan() {
+        let before = slot_map(&[(0, 8191, "node1:6379"), (8192, 16383, "node2:6379")]);
+        let after = slot_map(&[(0, 99, "node2:6379"), (100, 16383, "node2:6379")]);
+        let mut state = ClusterScanState::new(ScanOptions::default());
+
+        let (address, _) = state.next_request(&before).unwrap();
+        assert_eq!(address, "node1:6379");
+        state.handle_response(scan_response(0, &["bar"])).unwrap();
+        // All of node1's slots moved to node2 while node1 was scanned.
+        state.finish_node(&after);
+
+        let (address, _) = state.next_request(&after).unwrap();
+        assert_eq!(address, "node2:6379");
+        let found = state
+            .handle_response(scan_response(0, &["bar", "foo"]))
+            .unwrap();
+        assert_eq!(found.len(), 2);
+        state.finish_node(&after);
+        assert!(state.next_request(&after).is_none());
+    }
+}
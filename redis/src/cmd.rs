@@ -6,7 +6,7 @@ use futures_util::{
 };
 #[cfg(feature = "aio")]
 use std::pin::Pin;
-use std::{fmt, io};
+use std::{fmt, io, time::Duration};
 
 use crate::connection::ConnectionLike;
 use crate::pipeline::Pipeline;
@@ -30,6 +30,7 @@ pub struct Cmd {
     cursor: Option<u64>,
     // If it's true command's response won't be read from socket. Useful for Pub/Sub.
     no_response: bool,
+    timeout: Option<Duration>,
 }
 
 /// Represents a redis iterator.
@@ -321,6 +322,7 @@ impl Cmd {
             args: vec![],
             cursor: None,
             no_response: false,
+            timeout: None,
         }
     }
 
@@ -331,6 +333,7 @@ impl Cmd {
             args: Vec::with_capacity(arg_count),
             cursor: None,
             no_response: false,
+            timeout: None,
         }
     }
 
@@ -546,23 +549,24 @@ impl Cmd {
     }
 
     // Get a reference to the argument at `idx`
-    #[cfg(feature = "cluster")]
     pub(crate) fn arg_idx(&self, idx: usize) -> Option<&[u8]> {
         if idx >= self.args.len() {
             return None;
         }
 
-        let start = if idx == 0 {
-            0
-        } else {
-            match self.args[idx - 1] {
-                Arg::Simple(n) => n,
-                _ => 0,
-            }
-        };
+        // Cursor arguments have no data, so the argument starts where the last simple argument
+        // before it ends.
+        let start = self.args[..idx]
+            .iter()
+            .rev()
+            .find_map(|arg| match arg {
+                Arg::Simple(n) => Some(*n),
+                Arg::Cursor => None,
+            })
+            .unwrap_or(0);
         let end = match self.args[idx] {
             Arg::Simple(n) => n,
-            _ => 0,
+            Arg::Cursor => return None,
         };
         if start == 0 && end == 0 {
             return None;
@@ -570,6 +574,26 @@ impl Cmd {
         Some(&self.data[start..end])
     }
 
+    // Returns a copy of the command, in which the arguments for which `replace` returns a value
+    // are replaced with that value.
+    #[cfg(feature = "cluster")]
+    pub(crate) fn map_args(&self, mut replace: impl FnMut(usize, &[u8]) -> Option<Vec<u8>>) -> Cmd {
+        let mut mapped = Cmd::with_capacity(self.args.len(), self.data.len());
+        for (idx, arg) in self.args_iter().enumerate() {
+            match arg {
+                Arg::Simple(arg) => match replace(idx, arg) {
+                    Some(replacement) => mapped.write_arg(&replacement),
+                    None => mapped.write_arg(arg),
+                },
+                Arg::Cursor => mapped.args.push(Arg::Cursor),
+            }
+        }
+        mapped.cursor = self.cursor;
+        mapped.no_response = self.no_response;
+        mapped.timeout = self.timeout;
+        mapped
+    }
+
     /// Client won't read and wait for results. Currently only used for Pub/Sub commands in RESP3.
     #[inline]
     pub fn set_no_response(&mut self, nr: bool) -> &mut Cmd {
@@ -582,6 +606,25 @@ impl Cmd {
     pub fn is_no_response(&self) -> bool {
         self.no_response
     }
+
+    /// Sets how long to wait for the command's response, measured from when the command is sent.
+    /// This overrides the response timeout of the connection that the command is sent over.
+    ///
+    /// If the timeout passes, the request fails with a timeout error. The response that arrives
+    /// later is discarded, so it isn't mistaken for the response of another request - a
+    /// multiplexed connection skips it, and a synchronous [`crate::Connection`] is closed, since
+    /// it has no way to skip it.
+    #[inline]
+    pub fn set_timeout(&mut self, timeout: Duration) -> &mut Cmd {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Returns the timeout that was set with [`Cmd::set_timeout`], if any.
+    #[inline]
+    pub fn get_timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
 }
 
 /// Shortcut function to creating a command with a single argument.
@@ -627,7 +670,7 @@ pub fn pipe() -> Pipeline {
 #[cfg(test)]
 #[cfg(feature = "cluster")]
 mod tests {
-    use super::Cmd;
+    use super::{cmd, Cmd};
 
     #[test]
     fn test_cmd_arg_idx() {
@@ -643,5 +686,11 @@ mod tests {
         assert_eq!(c.arg_idx(2), Some(&b"42"[..]));
         assert_eq!(c.arg_idx(3), None);
         assert_eq!(c.arg_idx(4), None);
+
+        let mut c = cmd("SSCAN");
+        c.arg("foo").cursor_arg(0).arg("COUNT");
+        assert_eq!(c.arg_idx(1), Some(&b"foo"[..]));
+        assert_eq!(c.arg_idx(2), None);
+        assert_eq!(c.arg_idx(3), Some(&b"COUNT"[..]));
     }
 }
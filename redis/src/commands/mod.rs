@@ -32,101 +32,236 @@ use crate::streams;
 use crate::acl;
 use crate::RedisConnectionInfo;
 
+// Whether a command only reads, or writes, as flagged by `COMMAND INFO`. Scripts and functions
+// that aren't read-only are counted as writes, since they may write. Commands that are neither,
+// such as connection and server commands, and commands that aren't known, have no flag.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum CommandFlag {
+    ReadOnly,
+    Write,
+}
+
+// The flags are shared by the cluster routing, which may send read-only commands to replicas, and
+// the read-only `CommandPolicy`, so both agree on which commands write. A command that's listed
+// with both flags is reported as an unreachable pattern.
+pub(crate) fn command_flag(cmd: &[u8]) -> Option<CommandFlag> {
+    match cmd {
+        b"BITCOUNT"
+        | b"BITFIELD_RO"
+        | b"BITPOS"
+        | b"DBSIZE"
+        | b"DUMP"
+        | b"EVALSHA_RO"
+        | b"EVAL_RO"
+        | b"EXISTS"
+        | b"EXPIRETIME"
+        | b"FCALL_RO"
+        | b"GEODIST"
+        | b"GEOHASH"
+        | b"GEOPOS"
+        | b"GEORADIUSBYMEMBER_RO"
+        | b"GEORADIUS_RO"
+        | b"GEOSEARCH"
+        | b"GET"
+        | b"GETBIT"
+        | b"GETRANGE"
+        | b"HEXISTS"
+        | b"HGET"
+        | b"HGETALL"
+        | b"HKEYS"
+        | b"HLEN"
+        | b"HMGET"
+        | b"HRANDFIELD"
+        | b"HSCAN"
+        | b"HSTRLEN"
+        | b"HVALS"
+        | b"KEYS"
+        | b"LCS"
+        | b"LINDEX"
+        | b"LLEN"
+        | b"LOLWUT"
+        | b"LPOS"
+        | b"LRANGE"
+        | b"MEMORY USAGE"
+        | b"MGET"
+        | b"OBJECT ENCODING"
+        | b"OBJECT FREQ"
+        | b"OBJECT IDLETIME"
+        | b"OBJECT REFCOUNT"
+        | b"PEXPIRETIME"
+        | b"PFCOUNT"
+        | b"PTTL"
+        | b"RANDOMKEY"
+        | b"SCAN"
+        | b"SCARD"
+        | b"SDIFF"
+        | b"SINTER"
+        | b"SINTERCARD"
+        | b"SISMEMBER"
+        | b"SMEMBERS"
+        | b"SMISMEMBER"
+        | b"SORT_RO"
+        | b"SRANDMEMBER"
+        | b"SSCAN"
+        | b"STRLEN"
+        | b"SUBSTR"
+        | b"SUNION"
+        | b"TOUCH"
+        | b"TTL"
+        | b"TYPE"
+        | b"XINFO CONSUMERS"
+        | b"XINFO GROUPS"
+        | b"XINFO STREAM"
+        | b"XLEN"
+        | b"XPENDING"
+        | b"XRANGE"
+        | b"XREAD"
+        | b"XREVRANGE"
+        | b"ZCARD"
+        | b"ZCOUNT"
+        | b"ZDIFF"
+        | b"ZINTER"
+        | b"ZINTERCARD"
+        | b"ZLEXCOUNT"
+        | b"ZMSCORE"
+        | b"ZRANDMEMBER"
+        | b"ZRANGE"
+        | b"ZRANGEBYLEX"
+        | b"ZRANGEBYSCORE"
+        | b"ZRANK"
+        | b"ZREVRANGE"
+        | b"ZREVRANGEBYLEX"
+        | b"ZREVRANGEBYSCORE"
+        | b"ZREVRANK"
+        | b"ZSCAN"
+        | b"ZSCORE"
+        | b"ZUNION" => Some(CommandFlag::ReadOnly),
+        b"APPEND"
+        | b"BITFIELD"
+        | b"BITOP"
+        | b"BLMOVE"
+        | b"BLMPOP"
+        | b"BLPOP"
+        | b"BRPOP"
+        | b"BRPOPLPUSH"
+        | b"BZMPOP"
+        | b"BZPOPMAX"
+        | b"BZPOPMIN"
+        | b"COPY"
+        | b"DECR"
+        | b"DECRBY"
+        | b"DEL"
+        | b"EVAL"
+        | b"EVALSHA"
+        | b"EXPIRE"
+        | b"EXPIREAT"
+        | b"FCALL"
+        | b"FLUSHALL"
+        | b"FLUSHDB"
+        | b"FUNCTION DELETE"
+        | b"FUNCTION FLUSH"
+        | b"FUNCTION LOAD"
+        | b"FUNCTION RESTORE"
+        | b"GEOADD"
+        | b"GEORADIUS"
+        | b"GEORADIUSBYMEMBER"
+        | b"GEOSEARCHSTORE"
+        | b"GETDEL"
+        | b"GETEX"
+        | b"GETSET"
+        | b"HDEL"
+        | b"HEXPIRE"
+        | b"HEXPIREAT"
+        | b"HGETDEL"
+        | b"HGETEX"
+        | b"HINCRBY"
+        | b"HINCRBYFLOAT"
+        | b"HMSET"
+        | b"HPERSIST"
+        | b"HPEXPIRE"
+        | b"HPEXPIREAT"
+        | b"HSET"
+        | b"HSETEX"
+        | b"HSETNX"
+        | b"INCR"
+        | b"INCRBY"
+        | b"INCRBYFLOAT"
+        | b"LINSERT"
+        | b"LMOVE"
+        | b"LMPOP"
+        | b"LPOP"
+        | b"LPUSH"
+        | b"LPUSHX"
+        | b"LREM"
+        | b"LSET"
+        | b"LTRIM"
+        | b"MIGRATE"
+        | b"MOVE"
+        | b"MSET"
+        | b"MSETNX"
+        | b"PERSIST"
+        | b"PEXPIRE"
+        | b"PEXPIREAT"
+        | b"PFADD"
+        | b"PFDEBUG"
+        | b"PFMERGE"
+        | b"PSETEX"
+        | b"RENAME"
+        | b"RENAMENX"
+        | b"RESTORE"
+        | b"RESTORE-ASKING"
+        | b"RPOP"
+        | b"RPOPLPUSH"
+        | b"RPUSH"
+        | b"RPUSHX"
+        | b"SADD"
+        | b"SDIFFSTORE"
+        | b"SET"
+        | b"SETBIT"
+        | b"SETEX"
+        | b"SETNX"
+        | b"SETRANGE"
+        | b"SINTERSTORE"
+        | b"SMOVE"
+        | b"SORT"
+        | b"SPOP"
+        | b"SREM"
+        | b"SUNIONSTORE"
+        | b"SWAPDB"
+        | b"UNLINK"
+        | b"XACK"
+        | b"XADD"
+        | b"XAUTOCLAIM"
+        | b"XCLAIM"
+        | b"XDEL"
+        | b"XGROUP CREATE"
+        | b"XGROUP CREATECONSUMER"
+        | b"XGROUP DELCONSUMER"
+        | b"XGROUP DESTROY"
+        | b"XGROUP SETID"
+        | b"XREADGROUP"
+        | b"XSETID"
+        | b"XTRIM"
+        | b"ZADD"
+        | b"ZDIFFSTORE"
+        | b"ZINCRBY"
+        | b"ZINTERSTORE"
+        | b"ZMPOP"
+        | b"ZPOPMAX"
+        | b"ZPOPMIN"
+        | b"ZRANGESTORE"
+        | b"ZREM"
+        | b"ZREMRANGEBYLEX"
+        | b"ZREMRANGEBYRANK"
+        | b"ZREMRANGEBYSCORE"
+        | b"ZUNIONSTORE" => Some(CommandFlag::Write),
+        _ => None,
+    }
+}
+
 #[cfg(feature = "cluster")]
 pub(crate) fn is_readonly_cmd(cmd: &[u8]) -> bool {
-    matches!(
-        cmd,
-        b"BITCOUNT"
-            | b"BITFIELD_RO"
-            | b"BITPOS"
-            | b"DBSIZE"
-            | b"DUMP"
-            | b"EVALSHA_RO"
-            | b"EVAL_RO"
-            | b"EXISTS"
-            | b"EXPIRETIME"
-            | b"FCALL_RO"
-            | b"GEODIST"
-            | b"GEOHASH"
-            | b"GEOPOS"
-            | b"GEORADIUSBYMEMBER_RO"
-            | b"GEORADIUS_RO"
-            | b"GEOSEARCH"
-            | b"GET"
-            | b"GETBIT"
-            | b"GETRANGE"
-            | b"HEXISTS"
-            | b"HGET"
-            | b"HGETALL"
-            | b"HKEYS"
-            | b"HLEN"
-            | b"HMGET"
-            | b"HRANDFIELD"
-            | b"HSCAN"
-            | b"HSTRLEN"
-            | b"HVALS"
-            | b"KEYS"
-            | b"LCS"
-            | b"LINDEX"
-            | b"LLEN"
-            | b"LOLWUT"
-            | b"LPOS"
-            | b"LRANGE"
-            | b"MEMORY USAGE"
-            | b"MGET"
-            | b"OBJECT ENCODING"
-            | b"OBJECT FREQ"
-            | b"OBJECT IDLETIME"
-            | b"OBJECT REFCOUNT"
-            | b"PEXPIRETIME"
-            | b"PFCOUNT"
-            | b"PTTL"
-            | b"RANDOMKEY"
-            | b"SCAN"
-            | b"SCARD"
-            | b"SDIFF"
-            | b"SINTER"
-            | b"SINTERCARD"
-            | b"SISMEMBER"
-            | b"SMEMBERS"
-            | b"SMISMEMBER"
-            | b"SORT_RO"
-            | b"SRANDMEMBER"
-            | b"SSCAN"
-            | b"STRLEN"
-            | b"SUBSTR"
-            | b"SUNION"
-            | b"TOUCH"
-            | b"TTL"
-            | b"TYPE"
-            | b"XINFO CONSUMERS"
-            | b"XINFO GROUPS"
-            | b"XINFO STREAM"
-            | b"XLEN"
-            | b"XPENDING"
-            | b"XRANGE"
-            | b"XREAD"
-            | b"XREVRANGE"
-            | b"ZCARD"
-            | b"ZCOUNT"
-            | b"ZDIFF"
-            | b"ZINTER"
-            | b"ZINTERCARD"
-            | b"ZLEXCOUNT"
-            | b"ZMSCORE"
-            | b"ZRANDMEMBER"
-            | b"ZRANGE"
-            | b"ZRANGEBYLEX"
-            | b"ZRANGEBYSCORE"
-            | b"ZRANK"
-            | b"ZREVRANGE"
-            | b"ZREVRANGEBYLEX"
-            | b"ZREVRANGEBYSCORE"
-            | b"ZREVRANK"
-            | b"ZSCAN"
-            | b"ZSCORE"
-            | b"ZUNION"
-    )
+    command_flag(cmd) == Some(CommandFlag::ReadOnly)
 }
 
 implement_commands! {
@@ -963,6 +1098,11 @@ implement_commands! {
         cmd("PUBLISH").arg(channel).arg(message)
     }
 
+    /// Posts a message to the given shard channel.
+    fn spublish<K: ToRedisArgs, E: ToRedisArgs>(channel: K, message: E) {
+        cmd("SPUBLISH").arg(channel).arg(message)
+    }
+
     // Object commands
 
     /// Returns the encoding of a key.
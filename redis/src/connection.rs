@@ -5,7 +5,7 @@ use std::net::{self, SocketAddr, TcpStream, ToSocketAddrs};
 use std::ops::DerefMut;
 use std::path::PathBuf;
 use std::str::{from_utf8, FromStr};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use crate::cmd::{cmd, pipe, Cmd};
 use crate::parser::Parser;
@@ -26,9 +26,9 @@ use native_tls::{TlsConnector, TlsStream};
 
 #[cfg(feature = "tls-rustls")]
 use rustls::{RootCertStore, StreamOwned};
-#[cfg(feature = "tls-rustls")]
 use std::sync::Arc;
 
+use crate::instrumentation::{self, Instrumentation, RequestInfo};
 use crate::push_manager::PushManager;
 use crate::PushInfo;
 
@@ -46,7 +46,34 @@ use crate::tls::TlsConnParams;
 #[cfg(not(feature = "tls-rustls"))]
 #[derive(Clone, Debug)]
 #[non_exhaustive]
-pub struct TlsConnParams;
+pub struct TlsConnParams {
+    // The name that the server's certificate is verified against, if it's not the host.
+    #[cfg(feature = "tls-native-tls")]
+    pub(crate) server_name: Option<String>,
+}
+
+#[cfg(all(
+    feature = "cluster",
+    feature = "tls-native-tls",
+    not(feature = "tls-rustls")
+))]
+impl TlsConnParams {
+    pub(crate) fn with_server_name(_params: Option<Self>, server_name: String) -> Self {
+        Self {
+            server_name: Some(server_name),
+        }
+    }
+}
+
+// Returns the name that the server's TLS certificate is verified against, which is `host` unless
+// the TLS parameters override it.
+#[cfg(any(feature = "tls-native-tls", feature = "tls-rustls"))]
+pub(crate) fn tls_server_name<'a>(host: &'a str, tls_params: &'a Option<TlsConnParams>) -> &'a str {
+    tls_params
+        .as_ref()
+        .and_then(|params| params.server_name.as_deref())
+        .unwrap_or(host)
+}
 
 static DEFAULT_PORT: u16 = 6379;
 
@@ -544,6 +571,15 @@ pub struct Connection {
     /// `PushManager` instance for the connection.
     /// This is used to manage Push messages in RESP3 mode.
     push_manager: PushManager,
+
+    /// The deadline of the request that is currently sent, if it has a timeout.
+    request_deadline: Option<Instant>,
+
+    /// Notified of every request that is sent over the connection.
+    instrumentation: Option<Arc<dyn Instrumentation>>,
+
+    /// The address that the connection is connected to, which is reported to `instrumentation`.
+    address: Option<Arc<str>>,
 }
 
 /// Represents a pubsub connection.
@@ -605,8 +641,9 @@ impl ActualConnection {
                 ref host,
                 port,
                 insecure,
-                ..
+                ref tls_params,
             } => {
+                let server_name = tls_server_name(host, tls_params);
                 let tls_connector = if insecure {
                     TlsConnector::builder()
                         .danger_accept_invalid_certs(true)
@@ -620,7 +657,7 @@ impl ActualConnection {
                 let tls = match timeout {
                     None => {
                         let tcp = connect_tcp(addr)?;
-                        match tls_connector.connect(host, tcp) {
+                        match tls_connector.connect(server_name, tcp) {
                             Ok(res) => res,
                             Err(e) => {
                                 fail!((ErrorKind::IoError, "SSL Handshake error", e.to_string()));
@@ -642,7 +679,7 @@ impl ActualConnection {
                             };
                         }
                         match (tcp, last_error) {
-                            (Some(tcp), _) => tls_connector.connect(host, tcp).unwrap(),
+                            (Some(tcp), _) => tls_connector.connect(server_name, tcp).unwrap(),
                             (None, Some(e)) => {
                                 fail!(e);
                             }
@@ -671,7 +708,8 @@ impl ActualConnection {
                 let config = create_rustls_config(insecure, tls_params.clone())?;
                 let conn = rustls::ClientConnection::new(
                     Arc::new(config),
-                    rustls_pki_types::ServerName::try_from(host)?.to_owned(),
+                    rustls_pki_types::ServerName::try_from(tls_server_name(host, tls_params))?
+                        .to_owned(),
                 )?;
                 let reader = match timeout {
                     None => {
@@ -834,6 +872,47 @@ impl ActualConnection {
         Ok(())
     }
 
+    pub fn read_timeout(&self) -> RedisResult<Option<Duration>> {
+        let timeout = match *self {
+            ActualConnection::Tcp(TcpConnection { ref reader, .. }) => reader.read_timeout()?,
+            #[cfg(all(feature = "tls-native-tls", not(feature = "tls-rustls")))]
+            ActualConnection::TcpNativeTls(ref boxed_tls_connection) => {
+                boxed_tls_connection.reader.get_ref().read_timeout()?
+            }
+            #[cfg(feature = "tls-rustls")]
+            ActualConnection::TcpRustls(ref boxed_tls_connection) => {
+                boxed_tls_connection.reader.get_ref().read_timeout()?
+            }
+            #[cfg(unix)]
+            ActualConnection::Unix(UnixConnection { ref sock, .. }) => sock.read_timeout()?,
+        };
+        Ok(timeout)
+    }
+
+    pub fn shutdown(&mut self) {
+        match *self {
+            ActualConnection::Tcp(ref mut connection) => {
+                let _ = connection.reader.shutdown(net::Shutdown::Both);
+                connection.open = false;
+            }
+            #[cfg(all(feature = "tls-native-tls", not(feature = "tls-rustls")))]
+            ActualConnection::TcpNativeTls(ref mut connection) => {
+                let _ = connection.reader.shutdown();
+                connection.open = false;
+            }
+            #[cfg(feature = "tls-rustls")]
+            ActualConnection::TcpRustls(ref mut connection) => {
+                let _ = connection.reader.get_mut().shutdown(net::Shutdown::Both);
+                connection.open = false;
+            }
+            #[cfg(unix)]
+            ActualConnection::Unix(ref mut connection) => {
+                let _ = connection.sock.shutdown(net::Shutdown::Both);
+                connection.open = false;
+            }
+        }
+    }
+
     pub fn is_open(&self) -> bool {
         match *self {
             ActualConnection::Tcp(TcpConnection { open, .. }) => open,
@@ -962,7 +1041,9 @@ pub fn connect(
     timeout: Option<Duration>,
 ) -> RedisResult<Connection> {
     let con = ActualConnection::new(&connection_info.addr, timeout)?;
-    setup_connection(con, &connection_info.redis)
+    let mut con = setup_connection(con, &connection_info.redis)?;
+    con.address = Some(connection_info.addr.to_string().into());
+    Ok(con)
 }
 
 #[cfg(not(feature = "disable-client-setinfo"))]
@@ -994,6 +1075,9 @@ fn setup_connection(
         pubsub: false,
         protocol: connection_info.protocol,
         push_manager: PushManager::new(),
+        request_deadline: None,
+        instrumentation: None,
+        address: None,
     };
 
     if connection_info.protocol != ProtocolVersion::RESP2 {
@@ -1062,6 +1146,18 @@ pub trait ConnectionLike {
         self.req_packed_command(&pcmd)
     }
 
+    /// Sends a [Pipeline] into the TCP socket and reads `count` responses from it, after skipping
+    /// `offset` responses. The same caveats as for `req_packed_commands` apply.
+    #[doc(hidden)]
+    fn req_pipeline(
+        &mut self,
+        pipeline: &Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisResult<Vec<Value>> {
+        self.req_packed_commands(&pipeline.get_packed_pipeline(), offset, count)
+    }
+
     /// Returns the database this connection is bound to.  Note that this
     /// information might be unreliable because it's initially cached and
     /// also might be incorrect if the connection like object is not
@@ -1211,6 +1307,13 @@ impl Connection {
 
     /// Fetches a single response from the connection.
     fn read_response(&mut self) -> RedisResult<Value> {
+        if let Some(deadline) = self.request_deadline {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(io::Error::from(io::ErrorKind::TimedOut).into());
+            }
+            self.con.set_read_timeout(Some(remaining))?;
+        }
         let result = match self.con {
             ActualConnection::Tcp(TcpConnection { ref mut reader, .. }) => {
                 let result = self.parser.parse_value(reader);
@@ -1245,37 +1348,44 @@ impl Connection {
                 None => false,
             };
             if shutdown {
-                // Notify the PushManager that the connection was lost
-                self.push_manager.try_send_raw(&Value::Push {
-                    kind: PushKind::Disconnection,
-                    data: vec![],
-                });
-                match self.con {
-                    ActualConnection::Tcp(ref mut connection) => {
-                        let _ = connection.reader.shutdown(net::Shutdown::Both);
-                        connection.open = false;
-                    }
-                    #[cfg(all(feature = "tls-native-tls", not(feature = "tls-rustls")))]
-                    ActualConnection::TcpNativeTls(ref mut connection) => {
-                        let _ = connection.reader.shutdown();
-                        connection.open = false;
-                    }
-                    #[cfg(feature = "tls-rustls")]
-                    ActualConnection::TcpRustls(ref mut connection) => {
-                        let _ = connection.reader.get_mut().shutdown(net::Shutdown::Both);
-                        connection.open = false;
-                    }
-                    #[cfg(unix)]
-                    ActualConnection::Unix(ref mut connection) => {
-                        let _ = connection.sock.shutdown(net::Shutdown::Both);
-                        connection.open = false;
-                    }
-                }
+                self.shutdown();
             }
         }
         result
     }
 
+    fn shutdown(&mut self) {
+        // Notify the PushManager that the connection was lost
+        self.push_manager.try_send_raw(&Value::Push {
+            kind: PushKind::Disconnection,
+            data: vec![],
+        });
+        self.con.shutdown();
+    }
+
+    // Runs a request that has to read all of its responses within the given timeout. If the
+    // timeout passes, the connection is closed, since the late responses would otherwise be read as
+    // the responses of the following requests.
+    fn with_request_timeout<T>(
+        &mut self,
+        timeout: Option<Duration>,
+        request: impl FnOnce(&mut Self) -> RedisResult<T>,
+    ) -> RedisResult<T> {
+        let timeout = match timeout {
+            Some(timeout) => timeout,
+            None => return request(self),
+        };
+        let previous_timeout = self.con.read_timeout()?;
+        self.request_deadline = Some(Instant::now() + timeout);
+        let result = request(self);
+        self.request_deadline = None;
+        match &result {
+            Err(err) if err.is_timeout() => self.shutdown(),
+            _ => self.con.set_read_timeout(previous_timeout)?,
+        }
+        result
+    }
+
     /// Returns `PushManager` of Connection, this method is used to subscribe/unsubscribe from Push types
     pub fn get_push_manager(&self) -> PushManager {
         self.push_manager.clone()
@@ -1301,6 +1411,83 @@ impl Connection {
 impl ConnectionLike for Connection {
     /// Sends a [Cmd] into the TCP socket and reads a single response from it.
     fn req_command(&mut self, cmd: &Cmd) -> RedisResult<Value> {
+        self.instrumented(|| RequestInfo::for_cmd(cmd), |con| con.request_command(cmd))
+    }
+
+    fn req_packed_command(&mut self, cmd: &[u8]) -> RedisResult<Value> {
+        self.instrumented(
+            || RequestInfo::for_packed_command(cmd),
+            |con| con.request_packed_command(cmd),
+        )
+    }
+
+    fn req_packed_commands(
+        &mut self,
+        cmd: &[u8],
+        offset: usize,
+        count: usize,
+    ) -> RedisResult<Vec<Value>> {
+        self.instrumented(
+            || RequestInfo::for_packed_pipeline(cmd, offset + count),
+            |con| con.request_packed_commands(cmd, offset, count),
+        )
+    }
+
+    fn req_pipeline(
+        &mut self,
+        pipeline: &Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisResult<Vec<Value>> {
+        let packed = pipeline.get_packed_pipeline();
+        self.instrumented(
+            || RequestInfo::for_pipeline(pipeline),
+            |con| {
+                con.with_request_timeout(pipeline.get_timeout(), |con| {
+                    con.request_packed_commands(&packed, offset, count)
+                })
+            },
+        )
+    }
+
+    fn get_db(&self) -> i64 {
+        self.db
+    }
+
+    fn check_connection(&mut self) -> bool {
+        cmd("PING").query::<String>(self).is_ok()
+    }
+
+    fn is_open(&self) -> bool {
+        self.con.is_open()
+    }
+}
+
+impl Connection {
+    pub(crate) fn with_instrumentation(
+        mut self,
+        instrumentation: Option<Arc<dyn Instrumentation>>,
+    ) -> Self {
+        self.instrumentation = instrumentation;
+        self
+    }
+
+    // Reports the given request to the instrumentation around the call to `send`.
+    fn instrumented<'a, T>(
+        &mut self,
+        request: impl FnOnce() -> RequestInfo<'a>,
+        send: impl FnOnce(&mut Self) -> RedisResult<T>,
+    ) -> RedisResult<T> {
+        if !instrumentation::is_enabled(&self.instrumentation) {
+            return send(self);
+        }
+        let instrumentation = self.instrumentation.clone();
+        let address = self.address.clone();
+        let request = request().with_node(address.as_deref()).with_db(self.db);
+        instrumentation::instrument(instrumentation.as_deref(), &request, || send(self))
+    }
+
+    fn request_command(&mut self, cmd: &Cmd) -> RedisResult<Value> {
         let pcmd = cmd.get_packed_command();
         if self.pubsub {
             self.exit_pubsub()?;
@@ -1310,17 +1497,18 @@ impl ConnectionLike for Connection {
         if cmd.is_no_response() {
             return Ok(Value::Nil);
         }
-        loop {
-            match self.read_response()? {
+        self.with_request_timeout(cmd.get_timeout(), |con| loop {
+            match con.read_response()? {
                 Value::Push {
                     kind: _kind,
                     data: _data,
                 } => continue,
                 val => return Ok(val),
             }
-        }
+        })
     }
-    fn req_packed_command(&mut self, cmd: &[u8]) -> RedisResult<Value> {
+
+    fn request_packed_command(&mut self, cmd: &[u8]) -> RedisResult<Value> {
         if self.pubsub {
             self.exit_pubsub()?;
         }
@@ -1337,7 +1525,7 @@ impl ConnectionLike for Connection {
         }
     }
 
-    fn req_packed_commands(
+    fn request_packed_commands(
         &mut self,
         cmd: &[u8],
         offset: usize,
@@ -1382,18 +1570,6 @@ impl ConnectionLike for Connection {
 
         first_err.map_or(Ok(rv), Err)
     }
-
-    fn get_db(&self) -> i64 {
-        self.db
-    }
-
-    fn check_connection(&mut self) -> bool {
-        cmd("PING").query::<String>(self).is_ok()
-    }
-
-    fn is_open(&self) -> bool {
-        self.con.is_open()
-    }
 }
 
 impl<C, T> ConnectionLike for T
@@ -1418,6 +1594,15 @@ where
         self.deref_mut().req_command(cmd)
     }
 
+    fn req_pipeline(
+        &mut self,
+        pipeline: &Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisResult<Vec<Value>> {
+        self.deref_mut().req_pipeline(pipeline, offset, count)
+    }
+
     fn get_db(&self) -> i64 {
         self.deref().get_db()
     }
@@ -1560,7 +1745,7 @@ impl Msg {
             let raw_msg: Vec<Value> = from_redis_value(value).ok()?;
             let mut iter = raw_msg.into_iter();
             let msg_type: String = from_owned_redis_value(iter.next()?).ok()?;
-            if msg_type == "message" {
+            if msg_type == "message" || msg_type == "smessage" {
                 channel = iter.next()?;
                 payload = iter.next()?;
             } else if msg_type == "pmessage" {
@@ -0,0 +1,421 @@
+//! Hooks for observing the requests that are sent to the server.
+//!
+//! An [`Instrumentation`] receives a callback when every request starts and ends, which can be used
+//! to collect metrics such as latencies and error rates. It's registered with
+//! [`Client::with_instrumentation`](crate::Client::with_instrumentation), and is used by the
+//! connections that the client creates, and by the connection managers that are created from it.
+//! Cluster clients register it with `ClusterClientBuilder::instrumentation`.
+//!
+//! With the `tracing` feature, every request is also wrapped in a [`tracing`](https://docs.rs/tracing)
+//! span named after the command, whose fields follow the
+//! [OpenTelemetry semantic conventions for database clients](https://opentelemetry.io/docs/specs/semconv/database/redis/).
+//!
+//! ```rust,no_run
+//! use redis::instrumentation::{Instrumentation, RequestInfo};
+//! use std::{sync::Arc, time::Duration};
+//!
+//! struct LogLatencies;
+//!
+//! impl Instrumentation for LogLatencies {
+//!     fn on_response(&self, request: &RequestInfo<'_>, latency: Duration) {
+//!         println!("{} took {latency:?}", request.command());
+//!     }
+//! }
+//!
+//! # fn main() -> redis::RedisResult<()> {
+//! let client = redis::Client::open("redis://127.0.0.1/")?.with_instrumentation(Arc::new(LogLatencies));
+//! # Ok(()) }
+//! ```
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::cmd::{cmd_len, Cmd};
+use crate::types::{RedisError, RedisResult};
+use crate::Pipeline;
+
+/// Callbacks that are called on the requests that are sent to the server.
+///
+/// All the methods have empty default implementations, so that only the relevant ones need to be
+/// implemented. The callbacks are called on the task that sends the request, so they should return
+/// quickly.
+pub trait Instrumentation: Send + Sync {
+    /// Called before a request is sent.
+    fn on_request_start(&self, _request: &RequestInfo<'_>) {}
+
+    /// Called when a request receives its response, including responses that contain errors
+    /// from inside a pipeline.
+    fn on_response(&self, _request: &RequestInfo<'_>, _latency: Duration) {}
+
+    /// Called when a request fails.
+    fn on_error(&self, _request: &RequestInfo<'_>, _error: &RedisError, _latency: Duration) {}
+
+    /// Called when a cluster node redirects a request to another node, with the address of that
+    /// node. The redirected request is reported again when it's sent to its new node.
+    fn on_redirect(&self, _request: &RequestInfo<'_>, _redirect_to: &str) {}
+
+    /// Called when a dropped connection to the given address is reconnected.
+    fn on_reconnect(&self, _address: &str) {}
+}
+
+impl fmt::Debug for dyn Instrumentation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Instrumentation")
+    }
+}
+
+/// Describes a request that is sent to the server.
+#[derive(Clone, Debug)]
+pub struct RequestInfo<'a> {
+    command: &'a str,
+    commands: usize,
+    node: Option<&'a str>,
+    bytes: usize,
+    db: i64,
+}
+
+const UNKNOWN_COMMAND: &str = "UNKNOWN";
+
+fn command_name(name: Option<&[u8]>) -> &str {
+    name.and_then(|name| std::str::from_utf8(name).ok())
+        .unwrap_or(UNKNOWN_COMMAND)
+}
+
+// Returns the name of the first command that is encoded in the given bytes, e.g. `GET` out of
+// `*2\r\n$3\r\nGET\r\n$3\r\nfoo\r\n`.
+fn packed_command_name(packed: &[u8]) -> Option<&[u8]> {
+    fn line(bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+        let end = bytes.windows(2).position(|window| window == b"\r\n")?;
+        Some((&bytes[..end], &bytes[end + 2..]))
+    }
+
+    let (header, rest) = line(packed)?;
+    if header.first() != Some(&b'*') {
+        return None;
+    }
+    let (length, rest) = line(rest)?;
+    let length: usize = std::str::from_utf8(length.strip_prefix(b"$")?)
+        .ok()?
+        .parse()
+        .ok()?;
+    rest.get(..length)
+}
+
+impl<'a> RequestInfo<'a> {
+    pub(crate) fn for_cmd(cmd: &'a Cmd) -> Self {
+        Self {
+            command: command_name(cmd.arg_idx(0)),
+            commands: 1,
+            node: None,
+            bytes: cmd_len(cmd),
+            db: 0,
+        }
+    }
+
+    pub(crate) fn for_pipeline(pipeline: &'a Pipeline) -> Self {
+        Self {
+            command: if pipeline.is_atomic() {
+                "MULTI"
+            } else {
+                "PIPELINE"
+            },
+            commands: pipeline.cmd_iter().count(),
+            node: None,
+            bytes: pipeline.cmd_iter().map(cmd_len).sum(),
+            db: 0,
+        }
+    }
+
+    pub(crate) fn for_packed_command(packed: &'a [u8]) -> Self {
+        Self {
+            command: command_name(packed_command_name(packed)),
+            commands: 1,
+            node: None,
+            bytes: packed.len(),
+            db: 0,
+        }
+    }
+
+    pub(crate) fn for_packed_pipeline(packed: &'a [u8], commands: usize) -> Self {
+        Self {
+            command: "PIPELINE",
+            commands,
+            node: None,
+            bytes: packed.len(),
+            db: 0,
+        }
+    }
+
+    pub(crate) fn with_node(mut self, node: Option<&'a str>) -> Self {
+        self.node = node;
+        self
+    }
+
+    pub(crate) fn with_db(mut self, db: i64) -> Self {
+        self.db = db;
+        self
+    }
+
+    /// The name of the command, as it was sent, or `PIPELINE` or `MULTI` for pipelines and
+    /// transactions.
+    pub fn command(&self) -> &str {
+        self.command
+    }
+
+    /// The number of commands in the request, which is 1 for a single command.
+    pub fn commands(&self) -> usize {
+        self.commands
+    }
+
+    /// The address of the server that the request is sent to, if it's known.
+    pub fn node(&self) -> Option<&str> {
+        self.node
+    }
+
+    /// The size of the encoded request in bytes.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// The database that the request is sent to.
+    pub fn db(&self) -> i64 {
+        self.db
+    }
+}
+
+/// Returns whether requests should be reported, so that describing them can be skipped otherwise.
+pub(crate) fn is_enabled(instrumentation: &Option<Arc<dyn Instrumentation>>) -> bool {
+    instrumentation.is_some() || cfg!(feature = "tracing")
+}
+
+// Reports a request from its start to its end.
+struct Report<'a> {
+    instrumentation: Option<&'a dyn Instrumentation>,
+    request: &'a RequestInfo<'a>,
+    started: Instant,
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+impl<'a> Report<'a> {
+    fn start(
+        instrumentation: Option<&'a dyn Instrumentation>,
+        request: &'a RequestInfo<'a>,
+    ) -> Self {
+        if let Some(instrumentation) = instrumentation {
+            instrumentation.on_request_start(request);
+        }
+        Report {
+            instrumentation,
+            request,
+            started: Instant::now(),
+            #[cfg(feature = "tracing")]
+            span: tracing_support::span(request),
+        }
+    }
+
+    fn finish<T>(&self, result: &RedisResult<T>) {
+        let latency = self.started.elapsed();
+        if let Some(instrumentation) = self.instrumentation {
+            match result {
+                Ok(_) => instrumentation.on_response(self.request, latency),
+                Err(err) => instrumentation.on_error(self.request, err, latency),
+            }
+        }
+        #[cfg(feature = "tracing")]
+        if let Err(err) = result {
+            tracing_support::record_error(&self.span, err);
+        }
+    }
+}
+
+/// Reports the given request around the call to `send`.
+pub(crate) fn instrument<T>(
+    instrumentation: Option<&dyn Instrumentation>,
+    request: &RequestInfo<'_>,
+    send: impl FnOnce() -> RedisResult<T>,
+) -> RedisResult<T> {
+    let report = Report::start(instrumentation, request);
+    #[cfg(feature = "tracing")]
+    let result = report.span.in_scope(send);
+    #[cfg(not(feature = "tracing"))]
+    let result = send();
+    report.finish(&result);
+    result
+}
+
+/// Reports the given request around the execution of `send`.
+#[cfg(feature = "aio")]
+pub(crate) async fn instrument_async<T>(
+    instrumentation: Option<&dyn Instrumentation>,
+    request: &RequestInfo<'_>,
+    send: impl std::future::Future<Output = RedisResult<T>>,
+) -> RedisResult<T> {
+    let report = Report::start(instrumentation, request);
+    #[cfg(feature = "tracing")]
+    let result = tracing::Instrument::instrument(send, report.span.clone()).await;
+    #[cfg(not(feature = "tracing"))]
+    let result = send.await;
+    report.finish(&result);
+    result
+}
+
+/// Reports that the given request was redirected to another node.
+#[cfg(feature = "cluster-async")]
+pub(crate) fn report_redirect(
+    instrumentation: Option<&dyn Instrumentation>,
+    request: &RequestInfo<'_>,
+    redirect_to: &str,
+) {
+    if let Some(instrumentation) = instrumentation {
+        instrumentation.on_redirect(request, redirect_to);
+    }
+    #[cfg(feature = "tracing")]
+    tracing::debug!(
+        db.operation.name = request.command(),
+        redirect_to,
+        "Redis request redirected"
+    );
+}
+
+/// Reports that a connection to the given address was reconnected.
+#[cfg(any(feature = "cluster-async", feature = "connection-manager"))]
+pub(crate) fn report_reconnect(instrumentation: Option<&dyn Instrumentation>, address: &str) {
+    if let Some(instrumentation) = instrumentation {
+        instrumentation.on_reconnect(address);
+    }
+    #[cfg(feature = "tracing")]
+    tracing::debug!(server.address = address, "Redis connection reconnected");
+}
+
+#[cfg(feature = "tracing")]
+mod tracing_support {
+    use super::RequestInfo;
+    use crate::types::RedisError;
+    use tracing::field::Empty;
+
+    pub(super) fn span(request: &RequestInfo<'_>) -> tracing::Span {
+        let span = tracing::info_span!(
+            "redis",
+            otel.name = request.command(),
+            otel.kind = "client",
+            otel.status_code = Empty,
+            db.system = "redis",
+            db.operation.name = request.command(),
+            db.namespace = request.db(),
+            db.operation.batch.size = Empty,
+            db.response.status_code = Empty,
+            server.address = Empty,
+            server.port = Empty,
+            "error.type" = Empty,
+        );
+        if request.commands() > 1 {
+            span.record("db.operation.batch.size", request.commands());
+        }
+        if let Some(node) = request.node() {
+            match node.rsplit_once(':') {
+                Some((host, port)) if port.parse::<u16>().is_ok() => {
+                    span.record("server.address", host);
+                    span.record("server.port", port);
+                }
+                _ => {
+                    span.record("server.address", node);
+                }
+            }
+        }
+        span
+    }
+
+    pub(super) fn record_error(span: &tracing::Span, err: &RedisError) {
+        span.record("otel.status_code", "ERROR");
+        span.record("error.type", err.code().unwrap_or_else(|| err.category()));
+        if let Some(code) = err.code() {
+            span.record("db.response.status_code", code);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl Instrumentation for Recorder {
+        fn on_request_start(&self, request: &RequestInfo<'_>) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("start {}", request.command()));
+        }
+
+        fn on_response(&self, request: &RequestInfo<'_>, _latency: Duration) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("response {}", request.command()));
+        }
+
+        fn on_error(&self, request: &RequestInfo<'_>, error: &RedisError, _latency: Duration) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("error {} {:?}", request.command(), error.kind()));
+        }
+    }
+
+    #[test]
+    fn describes_commands_and_pipelines() {
+        let cmd = crate::cmd("GET").arg("foo").clone();
+        let request = RequestInfo::for_cmd(&cmd)
+            .with_node(Some("localhost:6379"))
+            .with_db(2);
+        assert_eq!(request.command(), "GET");
+        assert_eq!(request.commands(), 1);
+        assert_eq!(request.bytes(), cmd.get_packed_command().len());
+        assert_eq!(request.node(), Some("localhost:6379"));
+        assert_eq!(request.db(), 2);
+
+        let packed = cmd.get_packed_command();
+        assert_eq!(RequestInfo::for_packed_command(&packed).command(), "GET");
+        assert_eq!(
+            RequestInfo::for_packed_command(b"PING\r\n").command(),
+            UNKNOWN_COMMAND
+        );
+
+        let mut pipeline = crate::pipe();
+        pipeline.get("foo").set("bar", 1);
+        let request = RequestInfo::for_pipeline(&pipeline);
+        assert_eq!(request.command(), "PIPELINE");
+        assert_eq!(request.commands(), 2);
+        assert_eq!(
+            RequestInfo::for_pipeline(pipeline.atomic()).command(),
+            "MULTI"
+        );
+    }
+
+    #[test]
+    fn reports_the_start_and_end_of_requests() {
+        let recorder = Recorder::default();
+        let cmd = crate::cmd("PING");
+        let request = RequestInfo::for_cmd(&cmd);
+
+        let _ = instrument(Some(&recorder), &request, || Ok(()));
+        let _: RedisResult<()> = instrument(Some(&recorder), &request, || {
+            Err((crate::ErrorKind::ResponseError, "failed").into())
+        });
+
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            [
+                "start PING",
+                "response PING",
+                "start PING",
+                "error PING ResponseError"
+            ]
+        );
+    }
+}
@@ -0,0 +1,420 @@
+use super::{Interceptor, Next};
+use crate::cluster_routing::{hashtag_range, key_indices, Routable};
+use crate::cmd::Cmd;
+use crate::types::{ErrorKind, RedisResult, Value};
+use crate::Pipeline;
+
+/// An interceptor that puts every key in a namespace, by prefixing the keys of each request with
+/// the namespace's prefix, and removing the prefix from the keys in the responses.
+///
+/// Keys are found in the same way that the cluster client finds the keys that it routes requests
+/// by, extended to the commands that take several keys. Every key starts with the prefix, and if
+/// the key has a hash tag, the prefix is also added inside of the hash tag, so keys that share a
+/// hash tag are still hashed to the same slot, but not to the same slot as the same keys in other
+/// namespaces. So `user:{42}:cart` is stored as `app:user:{app:42}:cart` with the `app:` prefix.
+/// The `BY` and `GET` patterns of `SORT` are prefixed in the same way, so they look up keys in the
+/// namespace.
+///
+/// The patterns of `KEYS` and `SCAN`, which doesn't need a pattern, are limited to the namespace,
+/// and the prefix is removed from the keys that are returned by `KEYS`, `SCAN`, `RANDOMKEY`,
+/// `XREAD`, and the popping commands that return the key they popped from, such as `BLPOP`. Keys
+/// that are outside of the namespace, which `RANDOMKEY` can return, are returned unchanged.
+///
+/// Scripts and functions should only access the keys that they're given, since keys that they
+/// create themselves aren't prefixed.
+///
+/// ```rust,no_run
+/// use redis::interceptor::{Intercepted, KeyPrefix};
+/// use redis::Commands;
+///
+/// # fn main() -> redis::RedisResult<()> {
+/// let client = redis::Client::open("redis://127.0.0.1/")?;
+/// let mut con = Intercepted::new(client.get_connection()?).with_interceptor(KeyPrefix::new("app:")?);
+/// // Sets `app:key`.
+/// let _: () = con.set("key", 42)?;
+/// // Returns `["key"]`.
+/// let keys: Vec<String> = con.keys("*")?;
+/// # Ok(()) }
+/// ```
+#[derive(Clone, Debug)]
+pub struct KeyPrefix {
+    prefix: Vec<u8>,
+    // The prefix, with the characters that have a special meaning in patterns escaped.
+    pattern_prefix: Vec<u8>,
+}
+
+impl KeyPrefix {
+    /// Creates an interceptor that prefixes keys with `prefix`.
+    ///
+    /// Fails if the prefix contains braces, since they would change the hash tags of the keys.
+    pub fn new(prefix: impl Into<Vec<u8>>) -> RedisResult<Self> {
+        let prefix = prefix.into();
+        if prefix.iter().any(|byte| matches!(byte, b'{' | b'}')) {
+            fail!((
+                ErrorKind::InvalidClientConfig,
+                "Key prefixes can't contain braces"
+            ));
+        }
+        let mut pattern_prefix = Vec::with_capacity(prefix.len());
+        for byte in &prefix {
+            if matches!(byte, b'*' | b'?' | b'[' | b']' | b'\\') {
+                pattern_prefix.push(b'\\');
+            }
+            pattern_prefix.push(*byte);
+        }
+        Ok(Self {
+            prefix,
+            pattern_prefix,
+        })
+    }
+
+    /// Returns the prefix that's added to keys.
+    pub fn prefix(&self) -> &[u8] {
+        &self.prefix
+    }
+
+    fn add(prefix: &[u8], key: &[u8]) -> Vec<u8> {
+        let mut prefixed = Vec::with_capacity(key.len() + 2 * prefix.len());
+        prefixed.extend_from_slice(prefix);
+        match hashtag_range(key) {
+            Some(hashtag) => {
+                prefixed.extend_from_slice(&key[..hashtag.start]);
+                prefixed.extend_from_slice(prefix);
+                prefixed.extend_from_slice(&key[hashtag.start..]);
+            }
+            None => prefixed.extend_from_slice(key),
+        }
+        prefixed
+    }
+
+    // Returns the key without the prefix, or `None` if the key isn't in the namespace.
+    fn strip(&self, key: &[u8]) -> Option<Vec<u8>> {
+        let key = key.strip_prefix(&self.prefix[..])?;
+        Some(match hashtag_range(key) {
+            Some(hashtag)
+                if hashtag.len() > self.prefix.len()
+                    && key[hashtag.clone()].starts_with(&self.prefix) =>
+            {
+                let mut stripped = key[..hashtag.start].to_vec();
+                stripped.extend_from_slice(&key[hashtag.start + self.prefix.len()..]);
+                stripped
+            }
+            _ => key.to_vec(),
+        })
+    }
+
+    fn prefix_command(&self, cmd: &Cmd) -> Cmd {
+        let command = cmd.command();
+        let keys = key_indices(cmd);
+        let pattern = match command.as_deref() {
+            Some(b"KEYS") => Some(1),
+            Some(b"SCAN") => cmd.position(b"MATCH").map(|idx| idx + 1),
+            _ => None,
+        };
+
+        let mut prefixed = cmd.map_args(|idx, arg| {
+            if keys.contains(&idx) {
+                Some(Self::add(&self.prefix, arg))
+            } else if pattern == Some(idx) {
+                Some(Self::add(&self.pattern_prefix, arg))
+            } else {
+                None
+            }
+        });
+        if command.as_deref() == Some(b"SCAN") && pattern.is_none() {
+            prefixed
+                .arg("MATCH")
+                .arg(Self::add(&self.pattern_prefix, b"*"));
+        }
+        prefixed
+    }
+
+    fn prefix_pipeline(&self, pipeline: &Pipeline) -> Pipeline {
+        let mut prefixed = crate::pipe();
+        if pipeline.is_atomic() {
+            prefixed.atomic();
+        }
+        if let Some(timeout) = pipeline.get_timeout() {
+            prefixed.set_timeout(timeout);
+        }
+        for cmd in pipeline.cmd_iter() {
+            prefixed.add_command(self.prefix_command(cmd));
+        }
+        prefixed
+    }
+
+    fn strip_key(&self, value: Value) -> Value {
+        match value {
+            Value::BulkString(key) => match self.strip(&key) {
+                Some(stripped) => Value::BulkString(stripped),
+                None => Value::BulkString(key),
+            },
+            value => value,
+        }
+    }
+
+    fn strip_keys(&self, value: Value) -> Value {
+        match value {
+            Value::Array(keys) => {
+                Value::Array(keys.into_iter().map(|key| self.strip_key(key)).collect())
+            }
+            value => value,
+        }
+    }
+
+    // Strips the prefix from the first element of an array, which is a key.
+    fn strip_first_key(&self, value: Value) -> Value {
+        match value {
+            Value::Array(mut values) if !values.is_empty() => {
+                let key = std::mem::replace(&mut values[0], Value::Nil);
+                values[0] = self.strip_key(key);
+                Value::Array(values)
+            }
+            value => value,
+        }
+    }
+
+    fn strip_response(&self, cmd: &Cmd, value: Value) -> Value {
+        match cmd.command().as_deref() {
+            Some(b"KEYS") => self.strip_keys(value),
+            Some(b"SCAN") => match value {
+                Value::Array(mut values) if values.len() == 2 => {
+                    let keys = values.pop().unwrap();
+                    values.push(self.strip_keys(keys));
+                    Value::Array(values)
+                }
+                value => value,
+            },
+            Some(b"RANDOMKEY") => self.strip_key(value),
+            Some(
+                b"BLPOP" | b"BRPOP" | b"BZPOPMIN" | b"BZPOPMAX" | b"LMPOP" | b"BLMPOP" | b"ZMPOP"
+                | b"BZMPOP",
+            ) => self.strip_first_key(value),
+            Some(b"XREAD" | b"XREADGROUP") => match value {
+                Value::Array(streams) => Value::Array(
+                    streams
+                        .into_iter()
+                        .map(|stream| self.strip_first_key(stream))
+                        .collect(),
+                ),
+                Value::Map(streams) => Value::Map(
+                    streams
+                        .into_iter()
+                        .map(|(key, entries)| (self.strip_key(key), entries))
+                        .collect(),
+                ),
+                value => value,
+            },
+            _ => value,
+        }
+    }
+
+    fn strip_pipeline_responses(
+        &self,
+        pipeline: &Pipeline,
+        offset: usize,
+        values: Vec<Value>,
+    ) -> Vec<Value> {
+        if pipeline.is_atomic() {
+            // The responses of a transaction are all in the response to its `EXEC`.
+            return values
+                .into_iter()
+                .map(|value| match value {
+                    Value::Array(values) => Value::Array(
+                        values
+                            .into_iter()
+                            .zip(pipeline.cmd_iter())
+                            .map(|(value, cmd)| self.strip_response(cmd, value))
+                            .collect(),
+                    ),
+                    value => value,
+                })
+                .collect();
+        }
+        values
+            .into_iter()
+            .zip(pipeline.cmd_iter().skip(offset))
+            .map(|(value, cmd)| self.strip_response(cmd, value))
+            .collect()
+    }
+}
+
+impl Interceptor for KeyPrefix {
+    fn command(&self, cmd: &Cmd, mut next: Next<'_>) -> RedisResult<Value> {
+        let value = next.command(&self.prefix_command(cmd))?;
+        Ok(self.strip_response(cmd, value))
+    }
+
+    fn pipeline(
+        &self,
+        pipeline: &Pipeline,
+        offset: usize,
+        count: usize,
+        mut next: Next<'_>,
+    ) -> RedisResult<Vec<Value>> {
+        let values = next.pipeline(&self.prefix_pipeline(pipeline), offset, count)?;
+        Ok(self.strip_pipeline_responses(pipeline, offset, values))
+    }
+}
+
+#[cfg(feature = "aio")]
+impl super::AsyncInterceptor for KeyPrefix {
+    fn command<'a>(
+        &'a self,
+        cmd: &'a Cmd,
+        next: super::AsyncNext<'a>,
+    ) -> crate::types::RedisFuture<'a, Value> {
+        Box::pin(async move {
+            let value = next.command(&self.prefix_command(cmd)).await?;
+            Ok(self.strip_response(cmd, value))
+        })
+    }
+
+    fn pipeline<'a>(
+        &'a self,
+        pipeline: &'a Pipeline,
+        offset: usize,
+        count: usize,
+        next: super::AsyncNext<'a>,
+    ) -> crate::types::RedisFuture<'a, Vec<Value>> {
+        Box::pin(async move {
+            let values = next
+                .pipeline(&self.prefix_pipeline(pipeline), offset, count)
+                .await?;
+            Ok(self.strip_pipeline_responses(pipeline, offset, values))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(cmd: &Cmd) -> Vec<String> {
+        cmd.args_iter()
+            .map(|arg| match arg {
+                crate::Arg::Simple(arg) => String::from_utf8(arg.to_vec()).unwrap(),
+                crate::Arg::Cursor => "<cursor>".to_string(),
+            })
+            .collect()
+    }
+
+    fn command(args: &[&str]) -> Cmd {
+        let mut cmd = Cmd::new();
+        for arg in args {
+            cmd.arg(*arg);
+        }
+        cmd
+    }
+
+    fn keys(keys: &[&str]) -> Value {
+        Value::Array(
+            keys.iter()
+                .map(|key| Value::BulkString(key.as_bytes().to_vec()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn prefixes_keys_and_hash_tags() {
+        let prefix = KeyPrefix::new("app:").unwrap();
+        let cases: &[(&[&str], &[&str])] = &[
+            (&["GET", "key"], &["GET", "app:key"]),
+            (
+                &["MSET", "{user}:a", "1", "b{}", "2"],
+                &["MSET", "app:{app:user}:a", "1", "app:b{}", "2"],
+            ),
+            (
+                &["EVAL", "script", "1", "key", "arg"],
+                &["EVAL", "script", "1", "app:key", "arg"],
+            ),
+            (
+                &[
+                    "SORT",
+                    "list",
+                    "BY",
+                    "weight_*",
+                    "GET",
+                    "#",
+                    "GET",
+                    "{obj}_*->name",
+                ],
+                &[
+                    "SORT",
+                    "app:list",
+                    "BY",
+                    "app:weight_*",
+                    "GET",
+                    "#",
+                    "GET",
+                    "app:{app:obj}_*->name",
+                ],
+            ),
+            (&["KEYS", "user:*"], &["KEYS", "app:user:*"]),
+            (
+                &["PUBLISH", "channel", "message"],
+                &["PUBLISH", "channel", "message"],
+            ),
+        ];
+        for (cmd, expected) in cases {
+            assert_eq!(args(&prefix.prefix_command(&command(cmd))), *expected);
+        }
+
+        let mut scan = crate::cmd("SCAN");
+        scan.cursor_arg(0).arg("COUNT").arg(10);
+        assert_eq!(
+            args(&prefix.prefix_command(&scan)),
+            ["SCAN", "<cursor>", "COUNT", "10", "MATCH", "app:*"]
+        );
+        let escaped = KeyPrefix::new("a*b:").unwrap();
+        assert_eq!(
+            args(&escaped.prefix_command(&command(&["SCAN", "0", "match", "{x}*"]))),
+            ["SCAN", "0", "match", "a\\*b:{a\\*b:x}*"]
+        );
+    }
+
+    #[test]
+    fn strips_prefix_from_returned_keys() {
+        let prefix = KeyPrefix::new("app:").unwrap();
+        assert_eq!(
+            prefix.strip_response(
+                &command(&["KEYS", "*"]),
+                keys(&["app:a", "app:{app:user}:b", "other"])
+            ),
+            keys(&["a", "{user}:b", "other"])
+        );
+        assert_eq!(
+            prefix.strip_response(
+                &command(&["SCAN", "0"]),
+                Value::Array(vec![
+                    Value::BulkString(b"17".to_vec()),
+                    keys(&["app:a", "app:b"])
+                ])
+            ),
+            Value::Array(vec![Value::BulkString(b"17".to_vec()), keys(&["a", "b"])])
+        );
+        assert_eq!(
+            prefix.strip_response(&command(&["BLPOP", "a", "0"]), keys(&["app:a", "app:b"])),
+            keys(&["a", "app:b"])
+        );
+
+        let mut pipeline = crate::pipe();
+        pipeline.atomic().cmd("GET").arg("a").cmd("KEYS").arg("*");
+        assert_eq!(
+            prefix.strip_pipeline_responses(
+                &pipeline,
+                3,
+                vec![Value::Array(vec![keys(&["app:a"]), keys(&["app:a"])])]
+            ),
+            [Value::Array(vec![keys(&["app:a"]), keys(&["a"])])]
+        );
+    }
+
+    #[test]
+    fn rejects_prefixes_with_braces() {
+        assert_eq!(
+            KeyPrefix::new("{app}:").unwrap_err().kind(),
+            ErrorKind::InvalidClientConfig
+        );
+    }
+}
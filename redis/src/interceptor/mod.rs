@@ -0,0 +1,672 @@
+//! Middleware that can inspect and change the requests that are sent over a connection.
+//!
+//! An [`Interceptor`] is called with every command and pipeline that is sent through an
+//! [`Intercepted`] connection, together with a [`Next`] handle that passes the request on to the
+//! next interceptor, and eventually to the wrapped connection. An interceptor can change the
+//! request before passing it on, fail it without sending it, send it several times, or change the
+//! response that it returns. [`AsyncInterceptor`] and [`AsyncIntercepted`] are the async
+//! counterparts.
+//!
+//! Intercepted connections implement [`ConnectionLike`] and [`aio::ConnectionLike`](crate::aio::ConnectionLike)
+//! respectively, so [`Commands`](crate::Commands) and [`AsyncCommands`](crate::AsyncCommands) can
+//! be used on them as on any other connection.
+//!
+//! [`CommandPolicy`] rejects the commands that a connection isn't allowed to send, and with the
+//! `cluster` feature, [`KeyPrefix`] puts the keys of a connection in a namespace.
+//!
+//! ```rust,no_run
+//! use redis::interceptor::{Intercepted, Interceptor, Next};
+//! use redis::{Cmd, Commands, ErrorKind, RedisResult, Value};
+//!
+//! struct NoFlushes;
+//!
+//! impl Interceptor for NoFlushes {
+//!     fn command(&self, cmd: &Cmd, mut next: Next<'_>) -> RedisResult<Value> {
+//!         match cmd.args_iter().next() {
+//!             Some(redis::Arg::Simple(name)) if name.eq_ignore_ascii_case(b"FLUSHALL") => {
+//!                 Err((ErrorKind::ClientError, "FLUSHALL isn't allowed").into())
+//!             }
+//!             _ => next.command(cmd),
+//!         }
+//!     }
+//! }
+//!
+//! # fn main() -> RedisResult<()> {
+//! let client = redis::Client::open("redis://127.0.0.1/")?;
+//! let mut con = Intercepted::new(client.get_connection()?).with_interceptor(NoFlushes);
+//! let _: () = con.set("key", 42)?;
+//! # Ok(()) }
+//! ```
+
+use std::sync::Arc;
+
+use crate::cmd::Cmd;
+use crate::connection::ConnectionLike;
+use crate::types::{ErrorKind, RedisResult, Value};
+use crate::Pipeline;
+
+/// Intercepts the requests that are sent over an [`Intercepted`] connection.
+///
+/// Both methods pass the request on unchanged by default, so only the relevant ones need to be
+/// implemented.
+pub trait Interceptor: Send + Sync {
+    /// Called with every single command.
+    fn command(&self, cmd: &Cmd, mut next: Next<'_>) -> RedisResult<Value> {
+        next.command(cmd)
+    }
+
+    /// Called with every pipeline or transaction. `offset` and `count` select the responses that
+    /// are returned, as in [`ConnectionLike::req_packed_commands`], so interceptors that change the
+    /// number of commands in the pipeline need to adjust them.
+    fn pipeline(
+        &self,
+        pipeline: &Pipeline,
+        offset: usize,
+        count: usize,
+        mut next: Next<'_>,
+    ) -> RedisResult<Vec<Value>> {
+        next.pipeline(pipeline, offset, count)
+    }
+}
+
+/// Passes a request on to the rest of the interceptors, and eventually to the connection.
+pub struct Next<'a> {
+    con: &'a mut dyn ConnectionLike,
+    interceptors: &'a [Arc<dyn Interceptor>],
+}
+
+impl Next<'_> {
+    /// Sends the given command through the rest of the interceptors.
+    pub fn command(&mut self, cmd: &Cmd) -> RedisResult<Value> {
+        match self.interceptors.split_first() {
+            Some((interceptor, interceptors)) => interceptor.command(
+                cmd,
+                Next {
+                    con: &mut *self.con,
+                    interceptors,
+                },
+            ),
+            None => self.con.req_command(cmd),
+        }
+    }
+
+    /// Sends the given pipeline through the rest of the interceptors.
+    pub fn pipeline(
+        &mut self,
+        pipeline: &Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisResult<Vec<Value>> {
+        match self.interceptors.split_first() {
+            Some((interceptor, interceptors)) => interceptor.pipeline(
+                pipeline,
+                offset,
+                count,
+                Next {
+                    con: &mut *self.con,
+                    interceptors,
+                },
+            ),
+            None => self.con.req_pipeline(pipeline, offset, count),
+        }
+    }
+
+    /// Returns the database of the connection.
+    pub fn get_db(&self) -> i64 {
+        self.con.get_db()
+    }
+}
+
+/// A connection whose requests pass through a stack of [`Interceptor`]s.
+///
+/// Interceptors are called in the order in which they were added, so the first interceptor sees
+/// the requests before the rest do, and sees the responses after the rest do.
+#[derive(Clone)]
+pub struct Intercepted<C> {
+    con: C,
+    interceptors: Arc<Vec<Arc<dyn Interceptor>>>,
+}
+
+impl<C> Intercepted<C> {
+    /// Wraps the given connection, with no interceptors.
+    pub fn new(con: C) -> Self {
+        Self {
+            con,
+            interceptors: Default::default(),
+        }
+    }
+
+    /// Adds an interceptor after the interceptors that were already added.
+    pub fn with_interceptor(self, interceptor: impl Interceptor + 'static) -> Self {
+        self.with_shared_interceptor(Arc::new(interceptor))
+    }
+
+    /// Adds an interceptor that is shared with other connections after the interceptors that were
+    /// already added.
+    pub fn with_shared_interceptor(mut self, interceptor: Arc<dyn Interceptor>) -> Self {
+        Arc::make_mut(&mut self.interceptors).push(interceptor);
+        self
+    }
+
+    /// Returns the wrapped connection.
+    pub fn into_inner(self) -> C {
+        self.con
+    }
+}
+
+impl<C: ConnectionLike> Intercepted<C> {
+    fn next(&mut self) -> Next<'_> {
+        Next {
+            con: &mut self.con,
+            interceptors: &self.interceptors,
+        }
+    }
+}
+
+impl<C: ConnectionLike> ConnectionLike for Intercepted<C> {
+    fn req_packed_command(&mut self, cmd: &[u8]) -> RedisResult<Value> {
+        match decode_commands(cmd)?.as_slice() {
+            [cmd] => self.next().command(cmd),
+            _ => fail!((ErrorKind::ClientError, "Expected a single packed command")),
+        }
+    }
+
+    fn req_packed_commands(
+        &mut self,
+        cmd: &[u8],
+        offset: usize,
+        count: usize,
+    ) -> RedisResult<Vec<Value>> {
+        let mut pipeline = crate::pipe();
+        for cmd in decode_commands(cmd)? {
+            pipeline.add_command(cmd);
+        }
+        self.next().pipeline(&pipeline, offset, count)
+    }
+
+    fn req_command(&mut self, cmd: &Cmd) -> RedisResult<Value> {
+        self.next().command(cmd)
+    }
+
+    fn req_pipeline(
+        &mut self,
+        pipeline: &Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisResult<Vec<Value>> {
+        self.next().pipeline(pipeline, offset, count)
+    }
+
+    fn get_db(&self) -> i64 {
+        self.con.get_db()
+    }
+
+    fn check_connection(&mut self) -> bool {
+        self.con.check_connection()
+    }
+
+    fn is_open(&self) -> bool {
+        self.con.is_open()
+    }
+}
+
+// Decodes packed commands back into commands, so that interceptors see them as commands even when
+// they're sent already packed.
+fn decode_commands(mut packed: &[u8]) -> RedisResult<Vec<Cmd>> {
+    fn number(packed: &mut &[u8], prefix: u8) -> Option<usize> {
+        let end = packed.windows(2).position(|window| window == b"\r\n")?;
+        let (line, rest) = (packed[..end].strip_prefix(&[prefix])?, &packed[end + 2..]);
+        *packed = rest;
+        std::str::from_utf8(line).ok()?.parse().ok()
+    }
+
+    fn command(packed: &mut &[u8]) -> Option<Cmd> {
+        let mut cmd = Cmd::new();
+        for _ in 0..number(packed, b'*')? {
+            let len = number(packed, b'$')?;
+            if packed.get(len..len + 2)? != b"\r\n" {
+                return None;
+            }
+            cmd.arg(&packed[..len]);
+            *packed = &packed[len + 2..];
+        }
+        Some(cmd)
+    }
+
+    let mut commands = Vec::new();
+    while !packed.is_empty() {
+        match command(&mut packed) {
+            Some(cmd) => commands.push(cmd),
+            None => fail!((ErrorKind::ClientError, "Invalid packed command")),
+        }
+    }
+    Ok(commands)
+}
+
+#[cfg(feature = "aio")]
+pub use self::aio::{AsyncIntercepted, AsyncInterceptor, AsyncNext};
+#[cfg(feature = "cluster")]
+pub use self::key_prefix::KeyPrefix;
+pub use self::policy::CommandPolicy;
+
+#[cfg(feature = "cluster")]
+mod key_prefix;
+mod policy;
+
+#[cfg(feature = "aio")]
+mod aio {
+    use super::*;
+    use crate::aio::ConnectionLike;
+    use crate::types::RedisFuture;
+
+    /// Intercepts the requests that are sent over an [`AsyncIntercepted`] connection.
+    ///
+    /// Both methods pass the request on unchanged by default, so only the relevant ones need to
+    /// be implemented.
+    pub trait AsyncInterceptor: Send + Sync {
+        /// Called with every single command.
+        fn command<'a>(&'a self, cmd: &'a Cmd, next: AsyncNext<'a>) -> RedisFuture<'a, Value> {
+            next.command(cmd)
+        }
+
+        /// Called with every pipeline or transaction. `offset` and `count` select the responses
+        /// that are returned, as in [`Interceptor::pipeline`].
+        fn pipeline<'a>(
+            &'a self,
+            pipeline: &'a Pipeline,
+            offset: usize,
+            count: usize,
+            next: AsyncNext<'a>,
+        ) -> RedisFuture<'a, Vec<Value>> {
+            next.pipeline(pipeline, offset, count)
+        }
+    }
+
+    /// Passes a request on to the rest of the interceptors, and eventually to the connection.
+    pub struct AsyncNext<'a> {
+        con: &'a mut (dyn ConnectionLike + Send),
+        interceptors: &'a [Arc<dyn AsyncInterceptor>],
+    }
+
+    impl<'a> AsyncNext<'a> {
+        /// Reborrows the handle, so that a request can be sent more than once.
+        pub fn by_ref(&mut self) -> AsyncNext<'_> {
+            AsyncNext {
+                con: &mut *self.con,
+                interceptors: self.interceptors,
+            }
+        }
+
+        /// Sends the given command through the rest of the interceptors.
+        pub fn command<'b>(self, cmd: &'b Cmd) -> RedisFuture<'b, Value>
+        where
+            'a: 'b,
+        {
+            match self.interceptors.split_first() {
+                Some((interceptor, interceptors)) => interceptor.command(
+                    cmd,
+                    AsyncNext {
+                        con: self.con,
+                        interceptors,
+                    },
+                ),
+                None => self.con.req_packed_command(cmd),
+            }
+        }
+
+        /// Sends the given pipeline through the rest of the interceptors.
+        pub fn pipeline<'b>(
+            self,
+            pipeline: &'b Pipeline,
+            offset: usize,
+            count: usize,
+        ) -> RedisFuture<'b, Vec<Value>>
+        where
+            'a: 'b,
+        {
+            match self.interceptors.split_first() {
+                Some((interceptor, interceptors)) => interceptor.pipeline(
+                    pipeline,
+                    offset,
+                    count,
+                    AsyncNext {
+                        con: self.con,
+                        interceptors,
+                    },
+                ),
+                None => self.con.req_packed_commands(pipeline, offset, count),
+            }
+        }
+
+        /// Returns the database of the connection.
+        pub fn get_db(&self) -> i64 {
+            self.con.get_db()
+        }
+    }
+
+    /// An async connection whose requests pass through a stack of [`AsyncInterceptor`]s.
+    ///
+    /// Interceptors are called in the order in which they were added, so the first interceptor
+    /// sees the requests before the rest do, and sees the responses after the rest do.
+    #[derive(Clone)]
+    pub struct AsyncIntercepted<C> {
+        con: C,
+        interceptors: Arc<Vec<Arc<dyn AsyncInterceptor>>>,
+    }
+
+    impl<C> AsyncIntercepted<C> {
+        /// Wraps the given connection, with no interceptors.
+        pub fn new(con: C) -> Self {
+            Self {
+                con,
+                interceptors: Default::default(),
+            }
+        }
+
+        /// Adds an interceptor after the interceptors that were already added.
+        pub fn with_interceptor(self, interceptor: impl AsyncInterceptor + 'static) -> Self {
+            self.with_shared_interceptor(Arc::new(interceptor))
+        }
+
+        /// Adds an interceptor that is shared with other connections after the interceptors that
+        /// were already added.
+        pub fn with_shared_interceptor(mut self, interceptor: Arc<dyn AsyncInterceptor>) -> Self {
+            Arc::make_mut(&mut self.interceptors).push(interceptor);
+            self
+        }
+
+        /// Returns the wrapped connection.
+        pub fn into_inner(self) -> C {
+            self.con
+        }
+    }
+
+    impl<C: ConnectionLike + Send> ConnectionLike for AsyncIntercepted<C> {
+        fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+            AsyncNext {
+                con: &mut self.con,
+                interceptors: &self.interceptors,
+            }
+            .command(cmd)
+        }
+
+        fn req_packed_commands<'a>(
+            &'a mut self,
+            cmd: &'a Pipeline,
+            offset: usize,
+            count: usize,
+        ) -> RedisFuture<'a, Vec<Value>> {
+            AsyncNext {
+                con: &mut self.con,
+                interceptors: &self.interceptors,
+            }
+            .pipeline(cmd, offset, count)
+        }
+
+        fn get_db(&self) -> i64 {
+            self.con.get_db()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "aio")]
+    use crate::types::RedisFuture;
+    use std::sync::Mutex;
+
+    // Answers every command with its arguments, and records the commands that it received.
+    #[derive(Clone, Default)]
+    pub(super) struct Echo(Arc<Mutex<Vec<Vec<Vec<u8>>>>>);
+
+    fn args(cmd: &Cmd) -> Vec<Vec<u8>> {
+        cmd.args_iter()
+            .map(|arg| match arg {
+                crate::Arg::Simple(arg) => arg.to_vec(),
+                crate::Arg::Cursor => b"0".to_vec(),
+            })
+            .collect()
+    }
+
+    fn echo(cmd: &Cmd) -> Value {
+        Value::Array(args(cmd).into_iter().map(Value::BulkString).collect())
+    }
+
+    impl Echo {
+        pub(super) fn received(&self) -> Vec<Vec<Vec<u8>>> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    impl ConnectionLike for Echo {
+        fn req_packed_command(&mut self, _cmd: &[u8]) -> RedisResult<Value> {
+            unreachable!("Intercepted connections send commands through req_command")
+        }
+
+        fn req_packed_commands(
+            &mut self,
+            _cmd: &[u8],
+            _offset: usize,
+            _count: usize,
+        ) -> RedisResult<Vec<Value>> {
+            unreachable!("Intercepted connections send pipelines through req_pipeline")
+        }
+
+        fn req_command(&mut self, cmd: &Cmd) -> RedisResult<Value> {
+            self.0.lock().unwrap().push(args(cmd));
+            Ok(echo(cmd))
+        }
+
+        fn req_pipeline(
+            &mut self,
+            pipeline: &Pipeline,
+            offset: usize,
+            count: usize,
+        ) -> RedisResult<Vec<Value>> {
+            Ok(pipeline
+                .cmd_iter()
+                .map(|cmd| self.req_command(cmd).unwrap())
+                .skip(offset)
+                .take(count)
+                .collect())
+        }
+
+        fn get_db(&self) -> i64 {
+            0
+        }
+
+        fn check_connection(&mut self) -> bool {
+            true
+        }
+
+        fn is_open(&self) -> bool {
+            true
+        }
+    }
+
+    // Adds a suffix to the arguments of every command.
+    struct Suffix(&'static str);
+
+    impl Suffix {
+        fn apply(&self, cmd: &Cmd) -> Cmd {
+            let mut suffixed = Cmd::new();
+            for arg in args(cmd) {
+                let mut arg = arg;
+                arg.extend_from_slice(self.0.as_bytes());
+                suffixed.arg(arg);
+            }
+            suffixed
+        }
+    }
+
+    impl Interceptor for Suffix {
+        fn command(&self, cmd: &Cmd, mut next: Next<'_>) -> RedisResult<Value> {
+            next.command(&self.apply(cmd))
+        }
+
+        fn pipeline(
+            &self,
+            pipeline: &Pipeline,
+            offset: usize,
+            count: usize,
+            mut next: Next<'_>,
+        ) -> RedisResult<Vec<Value>> {
+            let mut suffixed = crate::pipe();
+            for cmd in pipeline.cmd_iter() {
+                suffixed.add_command(self.apply(cmd));
+            }
+            next.pipeline(&suffixed, offset, count)
+        }
+    }
+
+    #[cfg(feature = "aio")]
+    impl AsyncInterceptor for Suffix {
+        fn command<'a>(&'a self, cmd: &'a Cmd, next: AsyncNext<'a>) -> RedisFuture<'a, Value> {
+            Box::pin(async move { next.command(&self.apply(cmd)).await })
+        }
+    }
+
+    // Sends every command twice, and fails commands whose name starts with `FAIL` without sending
+    // them.
+    struct Twice;
+
+    impl Interceptor for Twice {
+        fn command(&self, cmd: &Cmd, mut next: Next<'_>) -> RedisResult<Value> {
+            if cmd
+                .arg_idx(0)
+                .map_or(false, |name| name.starts_with(b"FAIL"))
+            {
+                fail!((ErrorKind::ClientError, "Failed"));
+            }
+            next.command(cmd)?;
+            next.command(cmd)
+        }
+    }
+
+    #[cfg(feature = "aio")]
+    impl AsyncInterceptor for Twice {
+        fn command<'a>(&'a self, cmd: &'a Cmd, mut next: AsyncNext<'a>) -> RedisFuture<'a, Value> {
+            Box::pin(async move {
+                next.by_ref().command(cmd).await?;
+                next.command(cmd).await
+            })
+        }
+    }
+
+    #[cfg(feature = "aio")]
+    impl crate::aio::ConnectionLike for Echo {
+        fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+            Box::pin(async move { ConnectionLike::req_command(self, cmd) })
+        }
+
+        fn req_packed_commands<'a>(
+            &'a mut self,
+            cmd: &'a Pipeline,
+            offset: usize,
+            count: usize,
+        ) -> RedisFuture<'a, Vec<Value>> {
+            Box::pin(async move { ConnectionLike::req_pipeline(self, cmd, offset, count) })
+        }
+
+        fn get_db(&self) -> i64 {
+            0
+        }
+    }
+
+    fn bytes(args: &[&str]) -> Vec<Vec<u8>> {
+        args.iter().map(|arg| arg.as_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn interceptors_are_called_in_order() {
+        let echo = Echo::default();
+        let mut con = Intercepted::new(echo.clone())
+            .with_interceptor(Suffix("1"))
+            .with_interceptor(Twice)
+            .with_interceptor(Suffix("2"));
+
+        let value: Vec<String> = crate::cmd("GET").arg("key").query(&mut con).unwrap();
+        assert_eq!(value, ["GET12", "key12"]);
+        assert_eq!(
+            echo.received(),
+            [bytes(&["GET12", "key12"]), bytes(&["GET12", "key12"])]
+        );
+
+        let err = crate::cmd("FAIL").query::<Value>(&mut con).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ClientError);
+        assert_eq!(echo.received().len(), 2);
+    }
+
+    #[test]
+    fn packed_requests_are_intercepted_as_commands() {
+        let echo = Echo::default();
+        let mut con = Intercepted::new(echo.clone()).with_interceptor(Suffix("!"));
+
+        let packed = crate::cmd("SET").arg("key").arg(42).get_packed_command();
+        assert_eq!(
+            con.req_packed_command(&packed).unwrap(),
+            echo_value(&["SET!", "key!", "42!"])
+        );
+
+        let packed = crate::pipe()
+            .cmd("GET")
+            .arg("a")
+            .cmd("GET")
+            .arg("b")
+            .get_packed_pipeline();
+        assert_eq!(
+            con.req_packed_commands(&packed, 1, 1).unwrap(),
+            [echo_value(&["GET!", "b!"])]
+        );
+
+        let (a, b): (Vec<String>, Vec<String>) = crate::pipe()
+            .cmd("GET")
+            .arg("a")
+            .cmd("GET")
+            .arg("b")
+            .query(&mut con)
+            .unwrap();
+        assert_eq!(
+            (a, b),
+            (
+                vec!["GET!".into(), "a!".into()],
+                vec!["GET!".into(), "b!".into()]
+            )
+        );
+
+        assert_eq!(
+            con.req_packed_command(b"*1\r\n$3\r\nGET")
+                .unwrap_err()
+                .kind(),
+            ErrorKind::ClientError
+        );
+    }
+
+    fn echo_value(args: &[&str]) -> Value {
+        Value::Array(bytes(args).into_iter().map(Value::BulkString).collect())
+    }
+
+    #[test]
+    #[cfg(feature = "aio")]
+    fn async_interceptors_are_called_in_order() {
+        use futures_util::FutureExt;
+
+        let echo = Echo::default();
+        let mut con = AsyncIntercepted::new(echo.clone())
+            .with_interceptor(Suffix("1"))
+            .with_interceptor(Twice);
+
+        let value: Vec<String> = crate::cmd("GET")
+            .arg("key")
+            .query_async(&mut con)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        assert_eq!(value, ["GET1", "key1"]);
+        assert_eq!(
+            echo.received(),
+            [bytes(&["GET1", "key1"]), bytes(&["GET1", "key1"])]
+        );
+    }
+}
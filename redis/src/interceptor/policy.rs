@@ -0,0 +1,300 @@
+use std::collections::HashSet;
+
+use super::{Interceptor, Next};
+use crate::cmd::Cmd;
+use crate::commands::{command_flag, CommandFlag};
+use crate::types::{ErrorKind, RedisResult, Value};
+use crate::Pipeline;
+
+/// An interceptor that rejects the commands that a connection isn't allowed to send, before
+/// they're sent.
+///
+/// A policy can deny a set of commands, such as the [`CommandPolicy::DANGEROUS_COMMANDS`] preset,
+/// and can make a connection read-only, which denies the commands that Redis flags as `write`
+/// commands, as well as scripts and functions that aren't read-only. Commands that are explicitly
+/// allowed are never denied. Denied commands fail with [`ErrorKind::CommandDenied`], and a
+/// pipeline with a denied command fails as a whole, without sending any of its commands.
+///
+/// Commands are named by their name, or by their name and subcommand, such as `CONFIG SET`.
+/// Commands that Redis doesn't know, such as the commands of modules, are treated as read-only
+/// commands, and need to be denied explicitly.
+///
+/// ```rust,no_run
+/// use redis::interceptor::{CommandPolicy, Intercepted};
+/// use redis::{Commands, ErrorKind};
+///
+/// # fn main() -> redis::RedisResult<()> {
+/// let client = redis::Client::open("redis://127.0.0.1/")?;
+/// let policy = CommandPolicy::new()
+///     .with_denied_commands(CommandPolicy::DANGEROUS_COMMANDS)
+///     .with_read_only(true);
+/// let mut con = Intercepted::new(client.get_connection()?).with_interceptor(policy);
+/// let _: Option<String> = con.get("key")?;
+/// let err = con.set::<_, _, ()>("key", 42).unwrap_err();
+/// assert_eq!(err.kind(), ErrorKind::CommandDenied);
+/// # Ok(()) }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct CommandPolicy {
+    denied: HashSet<String>,
+    allowed: HashSet<String>,
+    read_only: bool,
+}
+
+impl CommandPolicy {
+    /// Admin commands that can take down or wipe a server, or block it for a long time.
+    pub const DANGEROUS_COMMANDS: &'static [&'static str] = &[
+        "ACL DELUSER",
+        "ACL LOAD",
+        "ACL SAVE",
+        "ACL SETUSER",
+        "BGREWRITEAOF",
+        "BGSAVE",
+        "CLIENT KILL",
+        "CLIENT PAUSE",
+        "CLUSTER ADDSLOTS",
+        "CLUSTER ADDSLOTSRANGE",
+        "CLUSTER DELSLOTS",
+        "CLUSTER DELSLOTSRANGE",
+        "CLUSTER FAILOVER",
+        "CLUSTER FLUSHSLOTS",
+        "CLUSTER FORGET",
+        "CLUSTER MEET",
+        "CLUSTER REPLICATE",
+        "CLUSTER RESET",
+        "CLUSTER SETSLOT",
+        "CONFIG RESETSTAT",
+        "CONFIG REWRITE",
+        "CONFIG SET",
+        "DEBUG",
+        "FAILOVER",
+        "FLUSHALL",
+        "FLUSHDB",
+        "FUNCTION FLUSH",
+        "FUNCTION KILL",
+        "FUNCTION RESTORE",
+        "KEYS",
+        "MIGRATE",
+        "MODULE LOAD",
+        "MODULE LOADEX",
+        "MODULE UNLOAD",
+        "MONITOR",
+        "PSYNC",
+        "REPLICAOF",
+        "SAVE",
+        "SCRIPT FLUSH",
+        "SCRIPT KILL",
+        "SHUTDOWN",
+        "SLAVEOF",
+        "SWAPDB",
+        "SYNC",
+    ];
+
+    /// Creates a policy that allows every command.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Denies the given commands.
+    pub fn with_denied_commands<S: AsRef<str>>(
+        mut self,
+        commands: impl IntoIterator<Item = S>,
+    ) -> Self {
+        self.denied.extend(normalized(commands));
+        self
+    }
+
+    /// Allows the given commands, even if they're denied or the policy is read-only.
+    pub fn with_allowed_commands<S: AsRef<str>>(
+        mut self,
+        commands: impl IntoIterator<Item = S>,
+    ) -> Self {
+        self.allowed.extend(normalized(commands));
+        self
+    }
+
+    /// Sets whether write commands are denied.
+    pub fn with_read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Returns an error if the policy denies the given command.
+    pub fn check(&self, cmd: &Cmd) -> RedisResult<()> {
+        let name = match cmd.arg_idx(0) {
+            Some(name) => String::from_utf8_lossy(name).to_ascii_uppercase(),
+            None => return Ok(()),
+        };
+        let subcommand = cmd
+            .arg_idx(1)
+            .map(|subcommand| format!("{name} {}", String::from_utf8_lossy(subcommand)))
+            .map(|name| name.to_ascii_uppercase());
+        let names = || std::iter::once(&name).chain(subcommand.as_ref());
+
+        if names().any(|name| self.allowed.contains(name)) {
+            return Ok(());
+        }
+        if let Some(name) = names().find(|name| self.denied.contains(*name)) {
+            fail!((
+                ErrorKind::CommandDenied,
+                "Command denied by policy",
+                name.clone()
+            ));
+        }
+        if let Some(name) = names().find(|name| self.read_only && is_write_command(name)) {
+            fail!((
+                ErrorKind::CommandDenied,
+                "Write command denied on a read-only connection",
+                name.clone()
+            ));
+        }
+        Ok(())
+    }
+
+    fn check_pipeline(&self, pipeline: &Pipeline) -> RedisResult<()> {
+        pipeline.cmd_iter().try_for_each(|cmd| self.check(cmd))
+    }
+}
+
+fn normalized<S: AsRef<str>>(commands: impl IntoIterator<Item = S>) -> Vec<String> {
+    commands
+        .into_iter()
+        .map(|command| command.as_ref().to_ascii_uppercase())
+        .collect()
+}
+
+fn is_write_command(name: &str) -> bool {
+    command_flag(name.as_bytes()) == Some(CommandFlag::Write)
+}
+
+impl Interceptor for CommandPolicy {
+    fn command(&self, cmd: &Cmd, mut next: Next<'_>) -> RedisResult<Value> {
+        self.check(cmd)?;
+        next.command(cmd)
+    }
+
+    fn pipeline(
+        &self,
+        pipeline: &Pipeline,
+        offset: usize,
+        count: usize,
+        mut next: Next<'_>,
+    ) -> RedisResult<Vec<Value>> {
+        self.check_pipeline(pipeline)?;
+        next.pipeline(pipeline, offset, count)
+    }
+}
+
+#[cfg(feature = "aio")]
+impl super::AsyncInterceptor for CommandPolicy {
+    fn command<'a>(
+        &'a self,
+        cmd: &'a Cmd,
+        next: super::AsyncNext<'a>,
+    ) -> crate::types::RedisFuture<'a, Value> {
+        match self.check(cmd) {
+            Ok(()) => next.command(cmd),
+            Err(err) => Box::pin(async move { Err(err) }),
+        }
+    }
+
+    fn pipeline<'a>(
+        &'a self,
+        pipeline: &'a Pipeline,
+        offset: usize,
+        count: usize,
+        next: super::AsyncNext<'a>,
+    ) -> crate::types::RedisFuture<'a, Vec<Value>> {
+        match self.check_pipeline(pipeline) {
+            Ok(()) => next.pipeline(pipeline, offset, count),
+            Err(err) => Box::pin(async move { Err(err) }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interceptor::tests::Echo;
+    use crate::interceptor::Intercepted;
+
+    fn denied(policy: &CommandPolicy, cmd: &Cmd) -> Option<String> {
+        policy
+            .check(cmd)
+            .err()
+            .map(|err| err.detail().unwrap().to_string())
+    }
+
+    #[test]
+    fn denies_commands_and_subcommands() {
+        let policy = CommandPolicy::new()
+            .with_denied_commands(CommandPolicy::DANGEROUS_COMMANDS)
+            .with_denied_commands(["json.del"]);
+
+        assert_eq!(
+            denied(&policy, &crate::cmd("flushall")),
+            Some("FLUSHALL".to_string())
+        );
+        assert_eq!(
+            denied(&policy, crate::cmd("config").arg("set").arg("a").arg("b")),
+            Some("CONFIG SET".to_string())
+        );
+        assert_eq!(
+            denied(&policy, crate::cmd("JSON.DEL").arg("key")),
+            Some("JSON.DEL".to_string())
+        );
+        assert_eq!(
+            denied(&policy, crate::cmd("CONFIG").arg("GET").arg("a")),
+            None
+        );
+        assert_eq!(denied(&policy, crate::cmd("SET").arg("key").arg(1)), None);
+
+        let policy = policy.with_allowed_commands(["KEYS"]);
+        assert_eq!(denied(&policy, crate::cmd("KEYS").arg("*")), None);
+    }
+
+    #[test]
+    fn read_only_policy_denies_write_commands() {
+        let policy = CommandPolicy::new()
+            .with_read_only(true)
+            .with_allowed_commands(["EVAL"]);
+
+        assert_eq!(
+            denied(&policy, crate::cmd("SET").arg("key").arg(1)),
+            Some("SET".to_string())
+        );
+        assert_eq!(
+            denied(&policy, crate::cmd("XGROUP").arg("CREATE").arg("key")),
+            Some("XGROUP CREATE".to_string())
+        );
+        assert_eq!(
+            denied(&policy, crate::cmd("HGETDEL").arg("key")),
+            Some("HGETDEL".to_string())
+        );
+        assert_eq!(denied(&policy, crate::cmd("GET").arg("key")), None);
+        assert_eq!(denied(&policy, crate::cmd("PFCOUNT").arg("key")), None);
+        assert_eq!(denied(&policy, crate::cmd("EVAL").arg("script")), None);
+        assert_eq!(
+            denied(&policy, crate::cmd("EVALSHA").arg("sha")),
+            Some("EVALSHA".to_string())
+        );
+    }
+
+    #[test]
+    fn denied_pipelines_are_not_sent() {
+        let echo = Echo::default();
+        let mut con = Intercepted::new(echo.clone())
+            .with_interceptor(CommandPolicy::new().with_read_only(true));
+
+        let err = crate::pipe()
+            .get("key")
+            .set("key", 1)
+            .query::<Value>(&mut con)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::CommandDenied);
+        assert!(echo.received().is_empty());
+
+        let _: Value = crate::pipe().get("key").query(&mut con).unwrap();
+        assert_eq!(echo.received().len(), 1);
+    }
+}
@@ -63,6 +63,7 @@
 //! * `tokio-comp`: enables support for tokio (optional)
 //! * `connection-manager`: enables support for automatic reconnection (optional)
 //! * `keep-alive`: enables keep-alive option on socket by means of `socket2` crate (enabled by default)
+//! * `tracing`: wraps every request in a `tracing` span (optional)
 //!
 //! ## Connection Parameters
 //!
@@ -394,6 +395,7 @@ pub use crate::connection::{
     parse_redis_url, transaction, Connection, ConnectionAddr, ConnectionInfo, ConnectionLike,
     IntoConnectionInfo, Msg, PubSub, RedisConnectionInfo, TlsMode,
 };
+pub use crate::instrumentation::Instrumentation;
 pub use crate::parser::{parse_redis_value, Parser};
 pub use crate::pipeline::Pipeline;
 pub use push_manager::{PushInfo, PushManager};
@@ -472,6 +474,9 @@ mod cluster_client;
 #[cfg(feature = "cluster")]
 mod cluster_pipeline;
 
+#[cfg(feature = "cluster")]
+mod cluster_scan;
+
 /// Routing information for cluster commands.
 #[cfg(feature = "cluster")]
 pub mod cluster_routing;
@@ -484,6 +489,10 @@ mod r2d2;
 #[cfg_attr(docsrs, doc(cfg(feature = "streams")))]
 pub mod streams;
 
+pub mod instrumentation;
+
+pub mod interceptor;
+
 #[cfg(feature = "cluster-async")]
 pub mod cluster_async;
 
@@ -5,6 +5,7 @@ use crate::connection::ConnectionLike;
 use crate::types::{
     from_owned_redis_value, ErrorKind, FromRedisValue, HashSet, RedisResult, ToRedisArgs, Value,
 };
+use std::time::Duration;
 
 /// Represents a redis command pipeline.
 #[derive(Clone)]
@@ -12,6 +13,7 @@ pub struct Pipeline {
     commands: Vec<Cmd>,
     transaction_mode: bool,
     ignored_commands: HashSet<usize>,
+    timeout: Option<Duration>,
 }
 
 /// A pipeline allows you to send multiple commands in one go to the
@@ -48,6 +50,7 @@ impl Pipeline {
             commands: Vec::with_capacity(capacity),
             transaction_mode: false,
             ignored_commands: HashSet::new(),
+            timeout: None,
         }
     }
 
@@ -70,6 +73,27 @@ impl Pipeline {
         self
     }
 
+    /// Sets how long to wait for all of the pipeline's responses, measured from when the pipeline
+    /// is sent. This overrides the response timeout of the connection that the pipeline is sent
+    /// over, as well as the timeouts of the pipeline's commands.
+    ///
+    /// See [`Cmd::set_timeout`] for what happens to the responses that arrive after the timeout.
+    #[inline]
+    pub fn set_timeout(&mut self, timeout: Duration) -> &mut Pipeline {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Returns the timeout that was set with [`Pipeline::set_timeout`], if any.
+    #[inline]
+    pub fn get_timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    pub(crate) fn is_atomic(&self) -> bool {
+        self.transaction_mode
+    }
+
     /// Returns the encoded pipeline commands.
     pub fn get_packed_pipeline(&self) -> Vec<u8> {
         encode_pipeline(&self.commands, self.transaction_mode)
@@ -81,19 +105,11 @@ impl Pipeline {
     }
 
     fn execute_pipelined(&self, con: &mut dyn ConnectionLike) -> RedisResult<Value> {
-        Ok(self.make_pipeline_results(con.req_packed_commands(
-            &encode_pipeline(&self.commands, false),
-            0,
-            self.commands.len(),
-        )?))
+        Ok(self.make_pipeline_results(con.req_pipeline(self, 0, self.commands.len())?))
     }
 
     fn execute_transaction(&self, con: &mut dyn ConnectionLike) -> RedisResult<Value> {
-        let mut resp = con.req_packed_commands(
-            &encode_pipeline(&self.commands, true),
-            self.commands.len() + 1,
-            1,
-        )?;
+        let mut resp = con.req_pipeline(self, self.commands.len() + 1, 1)?;
         match resp.pop() {
             Some(Value::Nil) => Ok(Value::Nil),
             Some(Value::Array(items)) => Ok(self.make_pipeline_results(items)),
@@ -741,6 +741,24 @@ impl SentinelClient {
         let client = self.get_client()?;
         client.get_connection()
     }
+
+    #[cfg(feature = "connection-manager")]
+    pub(crate) fn sentinels_connection_info(&self) -> &[ConnectionInfo] {
+        &self.sentinel.sentinels_connection_info
+    }
+
+    #[cfg(feature = "connection-manager")]
+    pub(crate) fn service_name(&self) -> &str {
+        &self.service_name
+    }
+
+    #[cfg(feature = "connection-manager")]
+    pub(crate) fn redis_connection_info(&self) -> RedisConnectionInfo {
+        self.node_connection_info
+            .redis_connection_info
+            .clone()
+            .unwrap_or_default()
+    }
 }
 
 /// To enable async support you need to chose one of the supported runtimes and active its
@@ -784,3 +802,75 @@ impl SentinelClient {
             .await
     }
 }
+
+#[cfg(feature = "connection-manager")]
+#[cfg_attr(docsrs, doc(cfg(feature = "connection-manager")))]
+impl SentinelClient {
+    /// Returns an async [`ConnectionManager`](crate::aio::ConnectionManager) to the desired
+    /// type of server. Unlike the connections returned by `get_async_connection`, the manager
+    /// asks the sentinels for the current server whenever it reconnects, and follows the
+    /// `+switch-master` events published by the sentinels.
+    pub async fn get_connection_manager(self) -> RedisResult<crate::aio::ConnectionManager> {
+        self.get_connection_manager_with_config(crate::aio::ConnectionManagerConfig::new())
+            .await
+    }
+
+    /// Returns an async [`ConnectionManager`](crate::aio::ConnectionManager) to the desired
+    /// type of server, created with the given config. See
+    /// [`SentinelClient::get_connection_manager`].
+    pub async fn get_connection_manager_with_config(
+        self,
+        config: crate::aio::ConnectionManagerConfig,
+    ) -> RedisResult<crate::aio::ConnectionManager> {
+        crate::aio::ConnectionManager::new_with_sentinel(self, config).await
+    }
+}
+
+/// Returns true if `payload`, the payload of a `+switch-master` message, announces a failover
+/// of `service_name`. The payload's format is `<master name> <old ip> <old port> <new ip> <new port>`.
+#[cfg(feature = "connection-manager")]
+fn is_master_switch_of(payload: &[u8], service_name: &str) -> bool {
+    payload
+        .split(|byte| *byte == b' ')
+        .next()
+        .map_or(false, |name| name == service_name.as_bytes())
+}
+
+/// Listens to the `+switch-master` events of the sentinels, moving to the next sentinel if
+/// the connection is lost, and notifies `signal` whenever `service_name` is failed over.
+/// Returns once `stop` is dropped.
+#[cfg(feature = "connection-manager")]
+pub(crate) async fn listen_for_master_switch(
+    sentinels: Vec<ConnectionInfo>,
+    service_name: String,
+    signal: std::sync::Weak<crate::aio::MasterSwitchSignal>,
+    stop: futures::channel::oneshot::Receiver<()>,
+) {
+    const RETRY_DELAY: std::time::Duration = std::time::Duration::from_millis(500);
+
+    let listen = async {
+        for connection_info in sentinels.iter().cycle() {
+            let pubsub = match Client::open(connection_info.clone()) {
+                Ok(client) => client.get_async_pubsub().await,
+                Err(err) => Err(err),
+            };
+            if let Ok(mut pubsub) = pubsub {
+                if pubsub.subscribe("+switch-master").await.is_ok() {
+                    let mut messages = pubsub.on_message();
+                    while let Some(msg) = messages.next().await {
+                        if !is_master_switch_of(msg.get_payload_bytes(), &service_name) {
+                            continue;
+                        }
+                        match signal.upgrade() {
+                            Some(signal) => signal.notify(),
+                            None => return,
+                        }
+                    }
+                }
+            }
+            crate::aio::Runtime::locate().sleep(RETRY_DELAY).await;
+        }
+    };
+    futures_util::pin_mut!(listen);
+    futures_util::future::select(listen, stop).await;
+}
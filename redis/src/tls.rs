@@ -53,7 +53,12 @@ pub(crate) fn inner_build_with_tls(
         )));
     };
 
-    Ok(Client { connection_info })
+    Ok(Client {
+        connection_info,
+        instrumentation: None,
+        #[cfg(feature = "aio")]
+        credentials_provider: None,
+    })
 }
 
 pub(crate) fn retrieve_tls_certificates(
@@ -110,6 +115,7 @@ pub(crate) fn retrieve_tls_certificates(
     Ok(TlsConnParams {
         client_tls_params,
         root_cert_store,
+        server_name: None,
     })
 }
 
@@ -139,4 +145,19 @@ impl Clone for ClientTlsParams {
 pub struct TlsConnParams {
     pub(crate) client_tls_params: Option<ClientTlsParams>,
     pub(crate) root_cert_store: Option<RootCertStore>,
+    // The name that the server's certificate is verified against, if it's not the host.
+    pub(crate) server_name: Option<String>,
+}
+
+#[cfg(feature = "cluster")]
+impl TlsConnParams {
+    pub(crate) fn with_server_name(params: Option<Self>, server_name: String) -> Self {
+        let mut params = params.unwrap_or(Self {
+            client_tls_params: None,
+            root_cert_store: None,
+            server_name: None,
+        });
+        params.server_name = Some(server_name);
+        params
+    }
 }
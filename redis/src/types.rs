@@ -143,6 +143,12 @@ pub enum ErrorKind {
     /// Redis Servers prior to v6.0.0 doesn't support RESP3.
     /// Try disabling resp3 option
     RESP3NotSupported,
+    /// A request was rejected on the client, because its connection already has the maximal
+    /// number of requests or bytes in flight.
+    ClientOverloaded,
+    /// A command was rejected on the client, because the connection's
+    /// [`CommandPolicy`](crate::interceptor::CommandPolicy) denies it.
+    CommandDenied,
 }
 
 #[derive(PartialEq, Debug)]
@@ -875,6 +881,8 @@ impl RedisError {
             #[cfg(feature = "json")]
             ErrorKind::Serialize => "serializing",
             ErrorKind::RESP3NotSupported => "resp3 is not supported by server",
+            ErrorKind::ClientOverloaded => "client overloaded",
+            ErrorKind::CommandDenied => "command denied",
             ErrorKind::ParseError => "parse error",
         }
     }
@@ -1021,6 +1029,7 @@ impl RedisError {
             ErrorKind::BusyLoadingError => RetryMethod::WaitAndRetry,
             ErrorKind::MasterNameNotFoundBySentinel => RetryMethod::WaitAndRetry,
             ErrorKind::NoValidReplicasFoundBySentinel => RetryMethod::WaitAndRetry,
+            ErrorKind::ClientOverloaded => RetryMethod::WaitAndRetry,
 
             ErrorKind::ResponseError => RetryMethod::NoRetry,
             ErrorKind::ReadOnly => RetryMethod::NoRetry,
@@ -1031,6 +1040,7 @@ impl RedisError {
             ErrorKind::InvalidClientConfig => RetryMethod::NoRetry,
             ErrorKind::CrossSlot => RetryMethod::NoRetry,
             ErrorKind::ClientError => RetryMethod::NoRetry,
+            ErrorKind::CommandDenied => RetryMethod::NoRetry,
             ErrorKind::EmptySentinelList => RetryMethod::NoRetry,
             ErrorKind::NotBusy => RetryMethod::NoRetry,
             #[cfg(feature = "json")]
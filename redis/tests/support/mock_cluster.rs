@@ -1,12 +1,12 @@
 use std::{
-    collections::HashMap,
-    sync::{Arc, RwLock},
-    time::Duration,
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
 };
 
 use redis::{
     cluster::{self, ClusterClient, ClusterClientBuilder},
-    ErrorKind, FromRedisValue,
+    ErrorKind, FromRedisValue, RedisError,
 };
 
 use {
@@ -31,6 +31,11 @@ static HANDLERS: Lazy<RwLock<HashMap<String, Handler>>> = Lazy::new(Default::def
 pub struct MockConnection {
     pub handler: Handler,
     pub port: u16,
+    // Responses to commands sent with `send_packed_command`, waiting for `recv_response`.
+    pub pending_responses: Arc<Mutex<VecDeque<RedisResult<Value>>>>,
+    // The read timeout of the sync connection. A response that the handler takes longer than the
+    // timeout to return is read late, after the read timed out.
+    pub read_timeout: Arc<Mutex<Option<Duration>>>,
 }
 
 #[cfg(feature = "cluster-async")]
@@ -57,6 +62,8 @@ impl cluster_async::Connect for MockConnection {
                 .unwrap_or_else(|| panic!("Handler `{name}` were not installed"))
                 .clone(),
             port,
+            pending_responses: Default::default(),
+            read_timeout: Default::default(),
         }))
     }
 }
@@ -80,10 +87,27 @@ impl cluster::Connect for MockConnection {
                 .unwrap_or_else(|| panic!("Handler `{name}` were not installed"))
                 .clone(),
             port,
+            pending_responses: Default::default(),
+            read_timeout: Default::default(),
         })
     }
 
-    fn send_packed_command(&mut self, _cmd: &[u8]) -> RedisResult<()> {
+    // Pipelines are split into their commands, so that the handler responds to each of them.
+    fn send_packed_command(&mut self, cmd: &[u8]) -> RedisResult<()> {
+        for cmd in split_packed_commands(cmd) {
+            let started = Instant::now();
+            let response =
+                (self.handler)(cmd, self.port).expect_err("Handler did not specify a response");
+            let mut pending_responses = self.pending_responses.lock().unwrap();
+            let read_timeout = *self.read_timeout.lock().unwrap();
+            if read_timeout.map_or(false, |timeout| started.elapsed() > timeout) {
+                pending_responses
+                    .push_back(Err(
+                        std::io::Error::from(std::io::ErrorKind::TimedOut).into()
+                    ));
+            }
+            pending_responses.push_back(response);
+        }
         Ok(())
     }
 
@@ -91,13 +115,56 @@ impl cluster::Connect for MockConnection {
         Ok(())
     }
 
-    fn set_read_timeout(&self, _dur: Option<std::time::Duration>) -> RedisResult<()> {
+    fn set_read_timeout(&self, dur: Option<std::time::Duration>) -> RedisResult<()> {
+        *self.read_timeout.lock().unwrap() = dur;
         Ok(())
     }
 
+    // Returns the responses of sent commands first. Otherwise the handler is called with an
+    // empty command, and can respond with a value that the server pushed. If it doesn't, the read
+    // times out.
     fn recv_response(&mut self) -> RedisResult<Value> {
-        Ok(Value::Nil)
+        if let Some(response) = self.pending_responses.lock().unwrap().pop_front() {
+            return response;
+        }
+        match (self.handler)(b"", self.port) {
+            Err(response) => response,
+            Ok(()) => Err(std::io::Error::from(std::io::ErrorKind::TimedOut).into()),
+        }
+    }
+}
+
+fn split_packed_commands(mut packed: &[u8]) -> Vec<&[u8]> {
+    // Reads the number that follows the type byte at `pos`, and returns it with the position
+    // after its line.
+    fn read_number(packed: &[u8], pos: usize) -> (usize, usize) {
+        let end = pos
+            + packed[pos..]
+                .windows(2)
+                .position(|window| window == b"\r\n")
+                .unwrap();
+        let number = std::str::from_utf8(&packed[pos + 1..end])
+            .unwrap()
+            .parse()
+            .unwrap();
+        (number, end + 2)
+    }
+
+    let mut cmds = Vec::new();
+    while packed.starts_with(b"*") {
+        let (args, mut pos) = read_number(packed, 0);
+        for _ in 0..args {
+            let (len, start) = read_number(packed, pos);
+            pos = start + len + 2;
+        }
+        let (cmd, rest) = packed.split_at(pos);
+        cmds.push(cmd);
+        packed = rest;
+    }
+    if cmds.is_empty() {
+        cmds.push(packed);
     }
+    cmds
 }
 
 pub fn contains_slice(xs: &[u8], ys: &[u8]) -> bool {
@@ -109,7 +176,22 @@ pub fn contains_slice(xs: &[u8], ys: &[u8]) -> bool {
     false
 }
 
+// Rejects `CLUSTER SHARDS` like servers older than Redis 7 do, so that the topology is queried with
+// `CLUSTER SLOTS` instead.
+pub fn respond_shards_unsupported(cmd: &[u8]) -> Result<(), RedisResult<Value>> {
+    if contains_slice(cmd, b"CLUSTER") && contains_slice(cmd, b"SHARDS") {
+        Err(Err(RedisError::from((
+            ErrorKind::ResponseError,
+            "An error was signalled by the server",
+            "unknown subcommand 'SHARDS'. Try CLUSTER HELP.".to_string(),
+        ))))
+    } else {
+        Ok(())
+    }
+}
+
 pub fn respond_startup(name: &str, cmd: &[u8]) -> Result<(), RedisResult<Value>> {
+    respond_shards_unsupported(cmd)?;
     if contains_slice(cmd, b"PING") {
         Err(Ok(Value::SimpleString("OK".into())))
     } else if contains_slice(cmd, b"CLUSTER") && contains_slice(cmd, b"SLOTS") {
@@ -158,11 +240,66 @@ pub fn respond_startup_two_nodes(name: &str, cmd: &[u8]) -> Result<(), RedisResu
     )
 }
 
+// Responds to the keys of `MSET`, `MGET` and `DEL` on the node that serves them in
+// `respond_startup_two_nodes`, and fails if a key was sent to the wrong node. `MGET` returns every
+// key with the port that served it.
+pub fn respond_multi_slot_commands(cmd: &[u8], port: u16) -> Result<(), RedisResult<Value>> {
+    let args: Vec<String> = redis::parse_redis_value(cmd)
+        .and_then(redis::from_owned_redis_value)
+        .unwrap_or_default();
+    let (name, args) = match args.split_first() {
+        Some((name, args)) => (name.as_str(), args),
+        None => return Ok(()),
+    };
+    let keys: Vec<&String> = match name {
+        "MSET" => args.iter().step_by(2).collect(),
+        "MGET" | "DEL" => args.iter().collect(),
+        _ => return Ok(()),
+    };
+    for key in &keys {
+        let owner = if redis::cluster_routing::get_slot(key.as_bytes()) < 8192 {
+            6379
+        } else {
+            6380
+        };
+        assert_eq!(port, owner, "{key} was sent to the wrong node");
+    }
+    Err(Ok(match name {
+        "MSET" => Value::Okay,
+        "MGET" => Value::Array(
+            keys.iter()
+                .map(|key| Value::BulkString(format!("{key}@{port}").into_bytes()))
+                .collect(),
+        ),
+        _ => Value::Int(keys.len() as i64),
+    }))
+}
+
 pub fn respond_startup_with_replica_using_config(
     name: &str,
     cmd: &[u8],
     slots_config: Option<Vec<MockSlotRange>>,
 ) -> Result<(), RedisResult<Value>> {
+    respond_startup_with_slots(name, cmd, slots_config, false)
+}
+
+// Like `respond_startup_with_replica_using_config`, but lists every replica of a slot as a separate
+// node, as servers do, so that slots can have several replicas.
+pub fn respond_startup_with_replicas_using_config(
+    name: &str,
+    cmd: &[u8],
+    slots_config: Option<Vec<MockSlotRange>>,
+) -> Result<(), RedisResult<Value>> {
+    respond_startup_with_slots(name, cmd, slots_config, true)
+}
+
+fn respond_startup_with_slots(
+    name: &str,
+    cmd: &[u8],
+    slots_config: Option<Vec<MockSlotRange>>,
+    separate_replicas: bool,
+) -> Result<(), RedisResult<Value>> {
+    respond_shards_unsupported(cmd)?;
     let slots_config = slots_config.unwrap_or(vec![
         MockSlotRange {
             primary_port: 6379,
@@ -181,25 +318,35 @@ pub fn respond_startup_with_replica_using_config(
         let slots = slots_config
             .into_iter()
             .map(|slot_config| {
-                let replicas = slot_config
-                    .replica_ports
-                    .into_iter()
-                    .flat_map(|replica_port| {
-                        vec![
-                            Value::BulkString(name.as_bytes().to_vec()),
-                            Value::Int(replica_port as i64),
-                        ]
-                    })
-                    .collect();
-                Value::Array(vec![
+                let mut slot = vec![
                     Value::Int(slot_config.slot_range.start as i64),
                     Value::Int(slot_config.slot_range.end as i64),
                     Value::Array(vec![
                         Value::BulkString(name.as_bytes().to_vec()),
                         Value::Int(slot_config.primary_port as i64),
                     ]),
-                    Value::Array(replicas),
-                ])
+                ];
+                if separate_replicas {
+                    slot.extend(slot_config.replica_ports.into_iter().map(|replica_port| {
+                        Value::Array(vec![
+                            Value::BulkString(name.as_bytes().to_vec()),
+                            Value::Int(replica_port as i64),
+                        ])
+                    }));
+                } else {
+                    let replicas = slot_config
+                        .replica_ports
+                        .into_iter()
+                        .flat_map(|replica_port| {
+                            vec![
+                                Value::BulkString(name.as_bytes().to_vec()),
+                                Value::Int(replica_port as i64),
+                            ]
+                        })
+                        .collect();
+                    slot.push(Value::Array(replicas));
+                }
+                Value::Array(slot)
             })
             .collect();
         Err(Ok(Value::Array(slots)))
@@ -219,13 +366,21 @@ impl aio::ConnectionLike for MockConnection {
         ))
     }
 
+    // The handler responds to the whole pipeline with an array, which contains a response to
+    // every command.
     fn req_packed_commands<'a>(
         &'a mut self,
-        _pipeline: &'a redis::Pipeline,
-        _offset: usize,
-        _count: usize,
+        pipeline: &'a redis::Pipeline,
+        offset: usize,
+        count: usize,
     ) -> RedisFuture<'a, Vec<Value>> {
-        Box::pin(future::ok(vec![]))
+        let res = (self.handler)(&pipeline.get_packed_pipeline(), self.port)
+            .expect_err("Handler did not specify a response");
+        Box::pin(future::ready(match res {
+            Ok(Value::Array(results)) => Ok(results.into_iter().skip(offset).take(count).collect()),
+            Ok(_) => Err((ErrorKind::ResponseError, "non-array response").into()),
+            Err(err) => Err(err),
+        }))
     }
 
     fn get_db(&self) -> i64 {
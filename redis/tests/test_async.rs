@@ -235,6 +235,108 @@ mod basic_async {
         .unwrap();
     }
 
+    #[test]
+    fn test_client_side_cache() {
+        use redis::{aio::CacheConfig, AsyncConnectionConfig, ProtocolVersion};
+
+        let ctx = TestContext::new();
+        if ctx.protocol == ProtocolVersion::RESP2 {
+            return;
+        }
+        block_on_all(async move {
+            let config = AsyncConnectionConfig::new().with_cache(CacheConfig::new());
+            let mut con = ctx
+                .client
+                .get_multiplexed_async_connection_with_config(&config)
+                .await?;
+            let mut other_con = ctx.multiplexed_async_connection().await?;
+
+            con.set("key_1", 42).await?;
+            let num: i32 = con.get("key_1").await?;
+            assert_eq!(num, 42);
+            let num: i32 = con.get("key_1").await?;
+            assert_eq!(num, 42);
+            let stats = con.get_cache_statistics().unwrap();
+            assert_eq!((stats.hits, stats.misses), (1, 1));
+
+            // A write from another client invalidates the cached value.
+            other_con.set("key_1", 43).await?;
+            loop {
+                let num: i32 = con.get("key_1").await?;
+                if num == 43 {
+                    break;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+            }
+            assert!(con.get_cache_statistics().unwrap().invalidations >= 1);
+            Ok::<_, redis::RedisError>(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_client_side_cache_is_per_connection_and_database() {
+        use redis::{aio::CacheConfig, AsyncConnectionConfig, ProtocolVersion};
+
+        let ctx = TestContext::new();
+        if ctx.protocol == ProtocolVersion::RESP2 {
+            return;
+        }
+        block_on_all(async move {
+            let config = AsyncConnectionConfig::new().with_cache(CacheConfig::new());
+            let mut con = ctx
+                .client
+                .get_multiplexed_async_connection_with_config(&config)
+                .await?;
+            let mut other_con = ctx
+                .client
+                .get_multiplexed_async_connection_with_config(&config)
+                .await?;
+
+            con.set("key_1", 42).await?;
+            let num: i32 = con.get("key_1").await?;
+            assert_eq!(num, 42);
+            let num: i32 = other_con.get("key_1").await?;
+            assert_eq!(num, 42);
+            assert_eq!(other_con.get_cache_statistics().unwrap().hits, 0);
+
+            redis::cmd("SELECT").arg(1).query_async(&mut con).await?;
+            let num: Option<i32> = con.get("key_1").await?;
+            assert_eq!(num, None);
+
+            redis::cmd("MULTI").query_async(&mut other_con).await?;
+            let queued: redis::Value = other_con.get("key_1").await?;
+            assert_eq!(queued, redis::Value::SimpleString("QUEUED".to_string()));
+            let (num,): (i32,) = redis::cmd("EXEC").query_async(&mut other_con).await?;
+            assert_eq!(num, 42);
+            let num: i32 = other_con.get("key_1").await?;
+            assert_eq!(num, 42);
+            Ok::<_, redis::RedisError>(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_client_side_cache_requires_resp3() {
+        use redis::{aio::CacheConfig, AsyncConnectionConfig, ProtocolVersion};
+
+        let ctx = TestContext::new();
+        if ctx.protocol != ProtocolVersion::RESP2 {
+            return;
+        }
+        block_on_all(async move {
+            let config = AsyncConnectionConfig::new().with_cache(CacheConfig::new());
+            let err = ctx
+                .client
+                .get_multiplexed_async_connection_with_config(&config)
+                .await
+                .unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidClientConfig);
+            Ok(())
+        })
+        .unwrap();
+    }
+
     #[test]
     fn test_pipeline_transaction_with_errors() {
         use redis::RedisError;
@@ -488,6 +590,245 @@ mod basic_async {
         .unwrap();
     }
 
+    #[test]
+    fn test_response_timeout_is_extended_for_blocking_commands() {
+        let ctx = TestContext::new();
+        block_on_all(async move {
+            let mut connection = ctx.multiplexed_async_connection().await.unwrap();
+            connection.set_response_timeout(std::time::Duration::from_millis(100));
+            let result: Option<(String, String)> = redis::cmd("BLPOP")
+                .arg("foo")
+                .arg(0.5)
+                .query_async(&mut connection)
+                .await
+                .unwrap();
+            assert_eq!(result, None);
+            let _: () = connection.set("bar", "baz").await.unwrap();
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_blocking_commands_pool_keeps_the_multiplexed_connection_free() {
+        let ctx = TestContext::new();
+        block_on_all(async move {
+            let mut connection = ctx
+                .client
+                .get_multiplexed_async_connection_with_config(
+                    &redis::AsyncConnectionConfig::new()
+                        .with_blocking_commands_pool(redis::aio::PoolConfig::new()),
+                )
+                .await
+                .unwrap();
+            let mut blocked = connection.clone();
+            let blpop = async move {
+                redis::cmd("BLPOP")
+                    .arg("queue")
+                    .arg(0)
+                    .query_async::<_, (String, String)>(&mut blocked)
+                    .await
+            };
+            let push = async move {
+                // Sent over the multiplexed connection while BLPOP blocks.
+                let _: () = connection.set("foo", "bar").await?;
+                connection.rpush::<_, _, ()>("queue", "item").await
+            };
+            let (popped, pushed) = futures::join!(blpop, push);
+            pushed.unwrap();
+            assert_eq!(popped.unwrap(), ("queue".to_string(), "item".to_string()));
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_auto_batching_keeps_responses_in_order() {
+        let ctx = TestContext::new();
+        block_on_all(async move {
+            let connection = ctx
+                .client
+                .get_multiplexed_async_connection_with_config(
+                    &redis::AsyncConnectionConfig::new().with_auto_batching(
+                        redis::aio::BatchingConfig::new()
+                            .with_max_commands(8)
+                            .with_flush_window(std::time::Duration::from_millis(1)),
+                    ),
+                )
+                .await?;
+            let requests = (0..100).map(|i| {
+                let mut connection = connection.clone();
+                async move {
+                    let key = format!("key{i}");
+                    let _: () = connection.set(&key, i).await?;
+                    let (value,): (i32,) =
+                        redis::pipe().get(&key).query_async(&mut connection).await?;
+                    assert_eq!(value, i);
+                    Ok::<_, redis::RedisError>(())
+                }
+            });
+            future::try_join_all(requests).await?;
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_in_flight_limits_reject_requests_when_overloaded() {
+        let ctx = TestContext::new();
+        block_on_all(async move {
+            let mut connection = ctx
+                .client
+                .get_multiplexed_async_connection_with_config(
+                    &redis::AsyncConnectionConfig::new().with_in_flight_limits(
+                        redis::aio::InFlightLimits::new()
+                            .with_max_requests(1)
+                            .with_overload_behavior(redis::aio::OverloadBehavior::Fail),
+                    ),
+                )
+                .await?;
+            assert_eq!(connection.in_flight_requests(), 0);
+            let mut blocked = connection.clone();
+            let blpop = async move {
+                redis::cmd("BLPOP")
+                    .arg("queue")
+                    .arg(0.2)
+                    .query_async::<_, Option<(String, String)>>(&mut blocked)
+                    .await
+            };
+            let get = async move {
+                // Let BLPOP be sent first.
+                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                assert_eq!(connection.in_flight_requests(), 1);
+                let result: RedisResult<Option<String>> = connection.get("foo").await;
+                (result, connection)
+            };
+            let (popped, (result, mut connection)) = futures::join!(blpop, get);
+            assert_eq!(popped.unwrap(), None);
+            assert_eq!(result.unwrap_err().kind(), ErrorKind::ClientOverloaded);
+
+            assert_eq!(connection.in_flight_requests(), 0);
+            let value: Option<String> = connection.get("foo").await?;
+            assert_eq!(value, None);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_cmd_timeout_discards_the_late_response() {
+        let ctx = TestContext::new();
+        block_on_all(async move {
+            let mut connection = ctx.multiplexed_async_connection().await.unwrap();
+            let _: () = connection.set("foo", "bar").await?;
+            let result: RedisResult<Option<(String, String)>> = redis::cmd("BLPOP")
+                .arg("queue")
+                .arg(0.2)
+                .set_timeout(std::time::Duration::from_millis(50))
+                .query_async(&mut connection)
+                .await;
+            assert!(result.unwrap_err().is_timeout());
+            // The late response to BLPOP arrives first, and isn't mistaken for the response to GET.
+            let value: String = connection.get("foo").await?;
+            assert_eq!(value, "bar");
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "connection-manager")]
+    fn test_connection_manager_pipeline_timeout() {
+        let ctx = TestContext::new();
+        block_on_all(async move {
+            let mut manager = redis::aio::ConnectionManager::new(ctx.client.clone()).await?;
+            let result: RedisResult<(Option<(String, String)>,)> = redis::pipe()
+                .cmd("BLPOP")
+                .arg("queue")
+                .arg(0.2)
+                .set_timeout(std::time::Duration::from_millis(50))
+                .query_async(&mut manager)
+                .await;
+            assert!(result.unwrap_err().is_timeout());
+            let (pong,): (String,) = redis::pipe().cmd("PING").query_async(&mut manager).await?;
+            assert_eq!(pong, "PONG");
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_interceptors_wrap_async_commands() {
+        use redis::interceptor::{AsyncIntercepted, AsyncInterceptor, AsyncNext};
+        use redis::{Cmd, RedisFuture};
+
+        // Rejects writes, and counts the commands that reach the connection.
+        struct ReadOnly(std::sync::atomic::AtomicUsize);
+
+        impl AsyncInterceptor for ReadOnly {
+            fn command<'a>(&'a self, cmd: &'a Cmd, next: AsyncNext<'a>) -> RedisFuture<'a, Value> {
+                let name = cmd.get_packed_command();
+                if name.windows(3).any(|window| window == b"SET") {
+                    return Box::pin(async {
+                        Err((redis::ErrorKind::ClientError, "Read only").into())
+                    });
+                }
+                self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                next.command(cmd)
+            }
+        }
+
+        let ctx = TestContext::new();
+        block_on_all(async move {
+            let read_only = std::sync::Arc::new(ReadOnly(Default::default()));
+            let mut con = AsyncIntercepted::new(ctx.multiplexed_async_connection().await?)
+                .with_shared_interceptor(read_only.clone());
+
+            let result: RedisResult<()> = con.set("key", "value").await;
+            assert_eq!(result.unwrap_err().kind(), redis::ErrorKind::ClientError);
+            let value: Option<String> = con.get("key").await?;
+            assert_eq!(value, None);
+            assert_eq!(read_only.0.load(std::sync::atomic::Ordering::SeqCst), 1);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "connection-manager")]
+    fn test_connection_manager_instrumentation() {
+        #[derive(Default)]
+        struct Requests(std::sync::Mutex<Vec<(String, bool)>>);
+
+        impl redis::Instrumentation for Requests {
+            fn on_request_start(&self, request: &redis::instrumentation::RequestInfo<'_>) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push((request.command().to_string(), request.node().is_some()));
+            }
+        }
+
+        let ctx = TestContext::new();
+        block_on_all(async move {
+            let requests = std::sync::Arc::new(Requests::default());
+            let mut manager = redis::aio::ConnectionManager::new_with_config(
+                ctx.client.clone(),
+                redis::aio::ConnectionManagerConfig::new().with_instrumentation(requests.clone()),
+            )
+            .await?;
+            let _: () = manager.set("key", "value").await?;
+            let _: (String,) = redis::pipe().get("key").query_async(&mut manager).await?;
+
+            assert_eq!(
+                *requests.0.lock().unwrap(),
+                [("SET".to_string(), true), ("PIPELINE".to_string(), true)]
+            );
+            Ok(())
+        })
+        .unwrap();
+    }
+
     #[test]
     #[cfg(feature = "script")]
     fn test_script() {
@@ -994,6 +1335,177 @@ mod basic_async {
         .unwrap();
     }
 
+    #[test]
+    fn test_multiplexed_connection_reauthenticates_before_credentials_expire() {
+        use redis::aio::{Credentials, CredentialsProvider};
+        use redis::RedisFuture;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+        use std::time::{Duration, Instant};
+
+        struct ExpiringToken(AtomicUsize);
+
+        impl CredentialsProvider for ExpiringToken {
+            fn credentials(&self) -> RedisFuture<'_, Credentials> {
+                let calls = self.0.fetch_add(1, Ordering::SeqCst);
+                Box::pin(async move {
+                    // The default user accepts every password, so any token authenticates.
+                    Ok(Credentials::new(format!("token-{calls}"))
+                        .with_username("default")
+                        .with_expiration(Instant::now() + Duration::from_millis(1500)))
+                })
+            }
+        }
+
+        let ctx = TestContext::new();
+        let provider = Arc::new(ExpiringToken(AtomicUsize::new(0)));
+        let client = ctx
+            .client
+            .clone()
+            .with_credentials_provider(provider.clone());
+        block_on_all(async move {
+            let mut con = client.get_multiplexed_async_connection().await?;
+            con.set::<_, _, ()>("foo", "bar").await?;
+            assert_eq!(provider.0.load(Ordering::SeqCst), 1);
+
+            tokio::time::sleep(Duration::from_secs(2)).await;
+            assert!(provider.0.load(Ordering::SeqCst) >= 2);
+            let value: String = con.get("foo").await?;
+            assert_eq!(value, "bar");
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "connection-manager")]
+    fn test_striped_connection_manager_spreads_requests_across_connections() {
+        let ctx = TestContext::new();
+        block_on_all(async move {
+            let mut manager = ctx
+                .client
+                .get_striped_connection_manager(
+                    redis::aio::StripedConnectionManagerConfig::new().with_connections(3),
+                )
+                .await
+                .unwrap();
+            assert_eq!(manager.connections(), 3);
+
+            let ids = future::try_join_all((0..3).map(|_| {
+                let mut manager = manager.clone();
+                async move {
+                    redis::cmd("CLIENT")
+                        .arg("ID")
+                        .query_async::<_, i64>(&mut manager)
+                        .await
+                }
+            }))
+            .await
+            .unwrap();
+            let mut distinct_ids = ids.clone();
+            distinct_ids.sort_unstable();
+            distinct_ids.dedup();
+            assert_eq!(distinct_ids.len(), 3);
+
+            // Killing one of the connections doesn't affect the others.
+            let mut admin = ctx.async_connection().await.unwrap();
+            let _: () = redis::cmd("CLIENT")
+                .arg("KILL")
+                .arg("ID")
+                .arg(ids[0])
+                .query_async(&mut admin)
+                .await
+                .unwrap();
+            for _ in 0..6 {
+                let _: RedisResult<()> = manager.set("foo", "bar").await;
+            }
+            let result: String = manager.get("foo").await.unwrap();
+            assert_eq!(result, "bar");
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_pool_resets_connections_on_return() {
+        use redis::aio::{MultiplexedConnection, Pool, PoolConfig};
+
+        let ctx = TestContext::new();
+        block_on_all(async move {
+            let pool: Pool<MultiplexedConnection> =
+                Pool::new(ctx.client.clone(), PoolConfig::new().with_max_size(1))
+                    .await
+                    .unwrap();
+            let client_id = {
+                let mut con = pool.get().await.unwrap();
+                let _: () = redis::cmd("WATCH")
+                    .arg("foo")
+                    .query_async(&mut con)
+                    .await
+                    .unwrap();
+                let _: () = redis::cmd("SELECT")
+                    .arg(1)
+                    .query_async(&mut con)
+                    .await
+                    .unwrap();
+                let _: () = con.set("foo", "db1").await.unwrap();
+                redis::cmd("CLIENT")
+                    .arg("ID")
+                    .query_async::<_, i64>(&mut con)
+                    .await
+                    .unwrap()
+            };
+
+            // The connection is reused, back on the configured database and without watched keys.
+            let mut con = pool.get().await.unwrap();
+            let reused_id: i64 = redis::cmd("CLIENT")
+                .arg("ID")
+                .query_async(&mut con)
+                .await
+                .unwrap();
+            assert_eq!(reused_id, client_id);
+            let value: Option<String> = con.get("foo").await.unwrap();
+            assert_eq!(value, None);
+            let mut other = ctx.async_connection().await.unwrap();
+            let _: () = other.set("foo", "changed").await.unwrap();
+            let result: Option<(String,)> = redis::pipe()
+                .atomic()
+                .get("foo")
+                .query_async(&mut con)
+                .await
+                .unwrap();
+            assert_eq!(result, Some(("changed".to_string(),)));
+            let state = pool.state();
+            assert_eq!((state.connections, state.idle_connections), (1, 0));
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_pool_waits_for_a_connection_at_max_size() {
+        use redis::aio::{MultiplexedConnection, Pool, PoolConfig};
+
+        let ctx = TestContext::new();
+        block_on_all(async move {
+            let pool: Pool<MultiplexedConnection> = Pool::new(
+                ctx.client.clone(),
+                PoolConfig::new().with_min_size(1).with_max_size(1),
+            )
+            .await
+            .unwrap();
+            assert_eq!(pool.state().idle_connections, 1);
+
+            let con = pool.get().await.unwrap();
+            assert!(pool.get().now_or_never().is_none());
+            drop(con);
+            let mut con = pool.get().await.unwrap();
+            let _: () = redis::cmd("PING").query_async(&mut con).await.unwrap();
+            Ok(())
+        })
+        .unwrap();
+    }
+
     #[cfg(feature = "tls-rustls")]
     mod mtls_test {
         use super::*;
@@ -1057,6 +1569,147 @@ mod basic_async {
         }
     }
 
+    #[test]
+    #[cfg(feature = "connection-manager")]
+    fn test_client_side_cache_cm() {
+        use redis::{
+            aio::{CacheConfig, ConnectionManagerConfig},
+            ProtocolVersion,
+        };
+
+        let ctx = TestContext::new();
+        if ctx.protocol == ProtocolVersion::RESP2 {
+            return;
+        }
+
+        block_on_all(async move {
+            let mut manager = ctx
+                .client
+                .get_connection_manager_with_config(
+                    ConnectionManagerConfig::new().with_cache(CacheConfig::new()),
+                )
+                .await
+                .unwrap();
+
+            manager.set("key_1", 42).await?;
+            let _: i32 = manager.get("key_1").await?;
+            let _: i32 = manager.get("key_1").await?;
+            let stats = manager.get_cache_statistics().unwrap();
+            assert_eq!((stats.hits, stats.misses), (1, 1));
+
+            // Killing the connection forces a reconnect, which flushes the cache.
+            let _: RedisResult<()> = redis::cmd("CLIENT")
+                .arg("KILL")
+                .arg("SKIPME")
+                .arg("NO")
+                .query_async(&mut manager)
+                .await;
+            while redis::cmd("PING")
+                .query_async::<_, ()>(&mut manager)
+                .await
+                .is_err()
+            {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+            let num: i32 = manager.get("key_1").await?;
+            assert_eq!(num, 42);
+            let stats = manager.get_cache_statistics().unwrap();
+            assert_eq!((stats.hits, stats.misses), (1, 2));
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "connection-manager")]
+    fn test_connection_manager_resubscribes_after_reconnect() {
+        use redis::ProtocolVersion;
+
+        let ctx = TestContext::new();
+        if ctx.protocol == ProtocolVersion::RESP2 {
+            return;
+        }
+
+        block_on_all(async move {
+            let mut manager = redis::aio::ConnectionManager::new(ctx.client.clone())
+                .await
+                .unwrap();
+            let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+            manager.get_push_manager().replace_sender(tx);
+            manager.subscribe("phonewave").await?;
+            manager.psubscribe("phone*").await?;
+            assert_eq!(rx.recv().await.unwrap().kind, PushKind::Subscribe);
+            assert_eq!(rx.recv().await.unwrap().kind, PushKind::PSubscribe);
+
+            let _: RedisResult<()> = redis::cmd("CLIENT")
+                .arg("KILL")
+                .arg("SKIPME")
+                .arg("NO")
+                .query_async(&mut manager)
+                .await;
+            while redis::cmd("PING")
+                .query_async::<_, ()>(&mut manager)
+                .await
+                .is_err()
+            {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+
+            assert_eq!(rx.recv().await.unwrap().kind, PushKind::Disconnection);
+            let mut resubscriptions =
+                vec![rx.recv().await.unwrap().kind, rx.recv().await.unwrap().kind];
+            resubscriptions.sort_by_key(|kind| kind.to_string());
+            assert_eq!(
+                resubscriptions,
+                vec![PushKind::PSubscribe, PushKind::Subscribe]
+            );
+
+            let mut publish_conn = ctx.async_connection().await?;
+            publish_conn.publish("phonewave", "banana").await?;
+            let kinds = [rx.recv().await.unwrap().kind, rx.recv().await.unwrap().kind];
+            assert!(kinds.contains(&PushKind::Message));
+            assert!(kinds.contains(&PushKind::PMessage));
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "connection-manager")]
+    fn test_connection_manager_subscribes_to_several_channels() {
+        use redis::ProtocolVersion;
+
+        let ctx = TestContext::new();
+        if ctx.protocol == ProtocolVersion::RESP2 {
+            return;
+        }
+
+        block_on_all(async move {
+            let mut manager = redis::aio::ConnectionManager::new(ctx.client.clone())
+                .await
+                .unwrap();
+            let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+            manager.get_push_manager().replace_sender(tx);
+
+            manager.subscribe(&["phonewave", "banana"]).await?;
+            assert_eq!(rx.recv().await.unwrap().kind, PushKind::Subscribe);
+            assert_eq!(rx.recv().await.unwrap().kind, PushKind::Subscribe);
+            // Every confirmation was consumed by its own command, so the following replies
+            // belong to the following requests.
+            manager.set("key", "value").await?;
+            let value: String = manager.get("key").await?;
+            assert_eq!(value, "value");
+
+            manager.unsubscribe(&[] as &[&str]).await?;
+            assert_eq!(rx.recv().await.unwrap().kind, PushKind::Unsubscribe);
+            assert_eq!(rx.recv().await.unwrap().kind, PushKind::Unsubscribe);
+            let value: String = manager.get("key").await?;
+            assert_eq!(value, "value");
+            Ok(())
+        })
+        .unwrap();
+    }
+
     #[test]
     #[cfg(feature = "connection-manager")]
     fn test_push_manager_cm() {
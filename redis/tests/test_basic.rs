@@ -414,6 +414,114 @@ mod basic {
         assert_eq!(unseen.len(), 0);
     }
 
+    #[test]
+    fn test_cmd_timeout_closes_the_connection() {
+        let ctx = TestContext::new();
+        let mut con = ctx.connection();
+
+        let pong: String = cmd("PING")
+            .set_timeout(Duration::from_secs(1))
+            .query(&mut con)
+            .unwrap();
+        assert_eq!(pong, "PONG");
+
+        let result: RedisResult<Option<(String, String)>> = cmd("BLPOP")
+            .arg("queue")
+            .arg(1)
+            .set_timeout(Duration::from_millis(50))
+            .query(&mut con);
+        assert!(result.unwrap_err().is_timeout());
+        // The late response can't be skipped, so the connection isn't used anymore.
+        assert!(!con.is_open());
+
+        let mut con = ctx.connection();
+        let result: RedisResult<(Option<(String, String)>,)> = redis::pipe()
+            .cmd("BLPOP")
+            .arg("queue")
+            .arg(1)
+            .set_timeout(Duration::from_millis(50))
+            .query(&mut con);
+        assert!(result.unwrap_err().is_timeout());
+        assert!(!con.is_open());
+    }
+
+    #[test]
+    fn test_instrumentation_reports_commands_and_pipelines() {
+        #[derive(Default)]
+        struct Responses(std::sync::Mutex<Vec<(String, usize)>>);
+
+        impl redis::Instrumentation for Responses {
+            fn on_response(&self, request: &redis::instrumentation::RequestInfo<'_>, _: Duration) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push((request.command().to_string(), request.commands()));
+            }
+        }
+
+        let ctx = TestContext::new();
+        let responses = std::sync::Arc::new(Responses::default());
+        let mut con = ctx
+            .client
+            .clone()
+            .with_instrumentation(responses.clone())
+            .get_connection()
+            .unwrap();
+
+        let _: () = con.set("key", 1).unwrap();
+        let _: (i32, i32) = redis::pipe().get("key").get("key").query(&mut con).unwrap();
+
+        assert_eq!(
+            *responses.0.lock().unwrap(),
+            [("SET".to_string(), 1), ("PIPELINE".to_string(), 2)]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "cluster")]
+    fn test_key_prefix_namespaces_keys() {
+        use redis::interceptor::{Intercepted, KeyPrefix};
+
+        let ctx = TestContext::new();
+        let mut raw = ctx.connection();
+        let mut con =
+            Intercepted::new(ctx.connection()).with_interceptor(KeyPrefix::new("app:").unwrap());
+
+        let _: () = raw.set("other", 1).unwrap();
+        let _: () = con
+            .mset(&[("key", 1), ("{user}:cart", 2), ("{user}:list", 3)])
+            .unwrap();
+        let ((key, cart),): ((i32, i32),) = redis::pipe()
+            .atomic()
+            .mget(&["key", "{user}:cart"])
+            .query(&mut con)
+            .unwrap();
+        assert_eq!((key, cart), (1, 2));
+
+        let mut keys: Vec<String> = con.keys("*").unwrap();
+        keys.sort();
+        assert_eq!(keys, ["key", "{user}:cart", "{user}:list"]);
+        let mut scanned: Vec<String> = con.scan().unwrap().collect();
+        scanned.sort();
+        assert_eq!(scanned, keys);
+
+        let mut raw_keys: Vec<String> = raw.keys("*").unwrap();
+        raw_keys.sort();
+        assert_eq!(
+            raw_keys,
+            [
+                "app:key",
+                "app:{app:user}:cart",
+                "app:{app:user}:list",
+                "other"
+            ]
+        );
+        assert_eq!(
+            redis::cluster_routing::get_slot(b"app:{app:user}:cart"),
+            redis::cluster_routing::get_slot(b"app:{app:user}:list")
+        );
+    }
+
     #[test]
     fn test_pipeline() {
         let ctx = TestContext::new();
@@ -12,7 +12,7 @@ mod cluster {
     use redis::{
         cluster::{cluster_pipe, ClusterClient},
         cmd, parse_redis_value, Commands, ConnectionLike, ErrorKind, ProtocolVersion, RedisError,
-        Value,
+        RedisResult, Value,
     };
 
     #[test]
@@ -387,6 +387,7 @@ mod cluster {
             handler: _handler,
             ..
         } = MockEnv::new(name, move |cmd: &[u8], port| {
+            respond_shards_unsupported(cmd)?;
             if !started.load(atomic::Ordering::SeqCst) {
                 respond_startup(name, cmd)?;
             }
@@ -491,6 +492,7 @@ mod cluster {
             handler: _handler,
             ..
         } = MockEnv::new(name, move |cmd: &[u8], port| {
+            respond_shards_unsupported(cmd)?;
             if !started.load(atomic::Ordering::SeqCst) {
                 respond_startup(name, cmd)?;
             }
@@ -924,6 +926,296 @@ mod cluster {
         assert!(res.is_ok());
     }
 
+    fn smessage(channel: &str, payload: &str) -> Value {
+        Value::Array(vec![
+            Value::BulkString(b"smessage".to_vec()),
+            Value::BulkString(channel.as_bytes().to_vec()),
+            Value::BulkString(payload.as_bytes().to_vec()),
+        ])
+    }
+
+    fn ssubscribe_reply(cmd: &[u8]) -> Value {
+        let channel = if contains_slice(cmd, b"foo") {
+            "foo"
+        } else {
+            "bar"
+        };
+        Value::Array(vec![
+            Value::BulkString(b"ssubscribe".to_vec()),
+            Value::BulkString(channel.as_bytes().to_vec()),
+            Value::Int(1),
+        ])
+    }
+
+    #[test]
+    fn test_cluster_pubsub_ssubscribe_uses_slot_owner() {
+        let name = "test_cluster_pubsub_ssubscribe_uses_slot_owner";
+        let MockEnv {
+            mut connection,
+            handler: _handler,
+            ..
+        } = MockEnv::new(name, move |cmd: &[u8], port| {
+            respond_startup_two_nodes(name, cmd)?;
+            if contains_slice(cmd, b"SSUBSCRIBE") {
+                // "foo" is in slot 12182, and "bar" in slot 5061.
+                let owner = if contains_slice(cmd, b"foo") {
+                    6380
+                } else {
+                    6379
+                };
+                assert_eq!(port, owner);
+                return Err(Ok(ssubscribe_reply(cmd)));
+            }
+            if cmd.is_empty() && port == 6380 {
+                return Err(Ok(smessage("foo", "hello")));
+            }
+            Ok(())
+        });
+
+        let mut pubsub = connection.as_pubsub();
+        pubsub.ssubscribe(&["foo", "bar"]).unwrap();
+
+        let msg = pubsub.get_message().unwrap();
+        assert_eq!(msg.get_channel_name(), "foo");
+        assert_eq!(msg.get_payload::<String>().unwrap(), "hello");
+    }
+
+    #[test]
+    fn test_cluster_pubsub_resubscribes_shard_channel_after_slot_migration() {
+        let name = "test_cluster_pubsub_resubscribes_shard_channel_after_slot_migration";
+        let migrated = atomic::AtomicBool::new(false);
+        let MockEnv {
+            mut connection,
+            handler: _handler,
+            ..
+        } = MockEnv::new(name, move |cmd: &[u8], port| {
+            let owner = if migrated.load(Ordering::SeqCst) {
+                6380
+            } else {
+                6379
+            };
+            respond_startup_with_replica_using_config(
+                name,
+                cmd,
+                Some(vec![MockSlotRange {
+                    primary_port: owner,
+                    replica_ports: vec![],
+                    slot_range: (0..16383),
+                }]),
+            )?;
+            if contains_slice(cmd, b"SSUBSCRIBE") {
+                assert_eq!(port, owner);
+                return Err(Ok(ssubscribe_reply(cmd)));
+            }
+            if cmd.is_empty() && port == owner {
+                if owner == 6379 {
+                    // The slot was migrated, so the node ends the subscription.
+                    migrated.store(true, Ordering::SeqCst);
+                    return Err(Ok(Value::Array(vec![
+                        Value::BulkString(b"sunsubscribe".to_vec()),
+                        Value::BulkString(b"foo".to_vec()),
+                        Value::Int(0),
+                    ])));
+                }
+                return Err(Ok(smessage("foo", "moved")));
+            }
+            Ok(())
+        });
+
+        let mut pubsub = connection.as_pubsub();
+        pubsub.ssubscribe("foo").unwrap();
+
+        let msg = pubsub.get_message().unwrap();
+        assert_eq!(msg.get_channel_name(), "foo");
+        assert_eq!(msg.get_payload::<String>().unwrap(), "moved");
+    }
+
+    #[test]
+    fn test_cluster_pubsub_get_message_times_out() {
+        let name = "test_cluster_pubsub_get_message_times_out";
+        let MockEnv {
+            mut connection,
+            handler: _handler,
+            ..
+        } = MockEnv::new(name, move |cmd: &[u8], _| {
+            respond_startup_two_nodes(name, cmd)?;
+            if contains_slice(cmd, b"SSUBSCRIBE") {
+                return Err(Ok(ssubscribe_reply(cmd)));
+            }
+            Ok(())
+        });
+
+        let mut pubsub = connection.as_pubsub();
+        assert_eq!(
+            pubsub.get_message().unwrap_err().kind(),
+            ErrorKind::ClientError
+        );
+        pubsub
+            .set_read_timeout(Some(std::time::Duration::from_millis(50)))
+            .unwrap();
+        pubsub.ssubscribe(&["foo", "bar"]).unwrap();
+        assert!(pubsub.get_message().unwrap_err().is_timeout());
+    }
+
+    #[test]
+    fn test_cluster_pubsub_subscribes_after_get_message_timed_out() {
+        let name = "test_cluster_pubsub_subscribes_after_get_message_timed_out";
+        let slow = Arc::new(atomic::AtomicBool::new(false));
+        let slow_clone = slow.clone();
+        let MockEnv {
+            mut connection,
+            handler: _handler,
+            ..
+        } = MockEnv::new(name, move |cmd: &[u8], _| {
+            respond_startup_two_nodes(name, cmd)?;
+            if contains_slice(cmd, b"SSUBSCRIBE") {
+                if slow_clone.load(Ordering::SeqCst) {
+                    // Slower than the interval that nodes are polled in.
+                    std::thread::sleep(std::time::Duration::from_millis(50));
+                }
+                return Err(Ok(ssubscribe_reply(cmd)));
+            }
+            Ok(())
+        });
+
+        let mut pubsub = connection.as_pubsub();
+        pubsub
+            .set_read_timeout(Some(std::time::Duration::from_millis(50)))
+            .unwrap();
+        pubsub.ssubscribe(&["foo", "bar"]).unwrap();
+        assert!(pubsub.get_message().unwrap_err().is_timeout());
+
+        slow.store(true, Ordering::SeqCst);
+        // "{bar}baz" is served by the same node as "bar".
+        pubsub.ssubscribe("{bar}baz").unwrap();
+    }
+
+    #[test]
+    fn test_cluster_pipeline_splits_multi_slot_commands() {
+        let name = "test_cluster_pipeline_splits_multi_slot_commands";
+        let MockEnv {
+            mut connection,
+            handler: _handler,
+            ..
+        } = MockEnv::new(name, move |cmd: &[u8], port| {
+            respond_startup_two_nodes(name, cmd)?;
+            respond_multi_slot_commands(cmd, port)
+        });
+
+        let (values, deleted): (Vec<String>, i64) = cluster_pipe()
+            .mset(&[("foo", "1"), ("bar", "2")])
+            .ignore()
+            .cmd("MGET")
+            .arg(&["foo", "bar", "baz"])
+            .del(&["foo", "bar"])
+            .query(&mut connection)
+            .unwrap();
+
+        assert_eq!(values, vec!["foo@6380", "bar@6379", "baz@6379"]);
+        assert_eq!(deleted, 2);
+    }
+
+    #[test]
+    fn test_cluster_pipeline_retries_only_redirected_commands() {
+        let name = "test_cluster_pipeline_retries_only_redirected_commands";
+        let bar_requests = Arc::new(AtomicI32::new(0));
+        let bar_requests_clone = bar_requests.clone();
+        let MockEnv {
+            mut connection,
+            handler: _handler,
+            ..
+        } = MockEnv::new(name, move |cmd: &[u8], port| {
+            respond_startup_two_nodes(name, cmd)?;
+            if contains_slice(cmd, b"bar") {
+                bar_requests_clone.fetch_add(1, Ordering::SeqCst);
+                return Err(Ok(Value::BulkString(b"bar-value".to_vec())));
+            }
+            if contains_slice(cmd, b"foo") {
+                return match port {
+                    6380 => Err(parse_redis_value(
+                        format!("-MOVED 12182 {name}:6379\r\n").as_bytes(),
+                    )),
+                    _ => Err(Ok(Value::BulkString(b"foo-value".to_vec()))),
+                };
+            }
+            Ok(())
+        });
+
+        let values: Vec<String> = cluster_pipe()
+            .get("foo")
+            .get("bar")
+            .query(&mut connection)
+            .unwrap();
+
+        assert_eq!(values, vec!["foo-value", "bar-value"]);
+        assert_eq!(bar_requests.load(Ordering::SeqCst), 1);
+    }
+
+    fn scan_reply(cursor: &str, keys: &[&str]) -> Value {
+        Value::Array(vec![
+            Value::BulkString(cursor.as_bytes().to_vec()),
+            Value::Array(
+                keys.iter()
+                    .map(|key| Value::BulkString(key.as_bytes().to_vec()))
+                    .collect(),
+            ),
+        ])
+    }
+
+    #[test]
+    fn test_cluster_scan_rescans_slots_that_moved_during_the_scan() {
+        let name = "test_cluster_scan_rescans_slots_that_moved_during_the_scan";
+        let migrated = atomic::AtomicBool::new(false);
+        let MockEnv {
+            mut connection,
+            handler: _handler,
+            ..
+        } = MockEnv::new(name, move |cmd: &[u8], port| {
+            let slots_config = if migrated.load(Ordering::SeqCst) {
+                vec![MockSlotRange {
+                    primary_port: 6380,
+                    replica_ports: vec![],
+                    slot_range: (0..16383),
+                }]
+            } else {
+                vec![
+                    MockSlotRange {
+                        primary_port: 6379,
+                        replica_ports: vec![],
+                        slot_range: (0..8191),
+                    },
+                    MockSlotRange {
+                        primary_port: 6380,
+                        replica_ports: vec![],
+                        slot_range: (8192..16383),
+                    },
+                ]
+            };
+            respond_startup_with_replica_using_config(name, cmd, Some(slots_config))?;
+            if contains_slice(cmd, b"SCAN") {
+                return match port {
+                    // The node's slots move to 6380 while it's scanned, and the node fails
+                    // before its scan ends.
+                    6379 if migrated.swap(true, Ordering::SeqCst) => Err(Err(RedisError::from(
+                        std::io::Error::new(std::io::ErrorKind::ConnectionReset, "mock-io-error"),
+                    ))),
+                    6379 => Err(Ok(scan_reply("1", &["bar"]))),
+                    _ => Err(Ok(scan_reply("0", &["bar", "baz", "foo", "qux"]))),
+                };
+            }
+            Ok(())
+        });
+
+        let mut keys = connection
+            .scan_cluster::<String>(redis::cluster::ScanOptions::default())
+            .collect::<RedisResult<Vec<_>>>()
+            .unwrap();
+        keys.sort();
+        // The failure refreshes the slots, so "bar" and "baz" are returned by the rescan on 6380,
+        // and nothing is skipped.
+        assert_eq!(keys, vec!["bar", "bar", "baz", "foo", "qux"]);
+    }
+
     #[cfg(feature = "tls-rustls")]
     mod mtls_test {
         use super::*;
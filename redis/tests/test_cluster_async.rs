@@ -6,21 +6,25 @@ mod cluster_async {
     use std::{
         collections::HashMap,
         sync::{
-            atomic::{self, AtomicBool, AtomicI32, AtomicU16, Ordering},
+            atomic::{self, AtomicBool, AtomicI32, AtomicU16, AtomicU32, Ordering},
             Arc,
         },
+        time::Duration,
     };
 
     use futures::prelude::*;
     use once_cell::sync::Lazy;
 
     use redis::{
-        aio::{ConnectionLike, MultiplexedConnection},
+        aio::{ConnectionLike, Credentials, CredentialsProvider, MultiplexedConnection},
         cluster::ClusterClient,
         cluster_async::Connect,
         cluster_routing::{MultipleNodeRoutingInfo, RoutingInfo, SingleNodeRoutingInfo},
-        cmd, from_owned_redis_value, parse_redis_value, AsyncCommands, Cmd, ErrorKind, InfoDict,
-        IntoConnectionInfo, ProtocolVersion, RedisError, RedisFuture, RedisResult, Script, Value,
+        cmd, from_owned_redis_value,
+        instrumentation::RequestInfo,
+        parse_redis_value, AsyncCommands, Cmd, ErrorKind, InfoDict, Instrumentation,
+        IntoConnectionInfo, ProtocolVersion, PushKind, RedisError, RedisFuture, RedisResult,
+        Script, Value,
     };
 
     use crate::support::*;
@@ -623,6 +627,216 @@ mod cluster_async {
         assert_eq!(requests.load(atomic::Ordering::SeqCst), 3);
     }
 
+    #[test]
+    fn test_async_cluster_request_timeout_bounds_retries() {
+        let name = "request_timeout_bounds_retries";
+
+        let requests = Arc::new(atomic::AtomicUsize::new(0));
+
+        let MockEnv {
+            runtime,
+            async_connection: mut connection,
+            handler: _handler,
+            ..
+        } = MockEnv::with_client_builder(
+            ClusterClient::builder(vec![&*format!("redis://{name}")])
+                .retries(1000)
+                .min_retry_wait(10)
+                .max_retry_wait(10),
+            name,
+            {
+                let requests = requests.clone();
+                move |cmd: &[u8], _| {
+                    respond_startup(name, cmd)?;
+                    requests.fetch_add(1, atomic::Ordering::SeqCst);
+                    Err(parse_redis_value(b"-TRYAGAIN mock\r\n"))
+                }
+            },
+        );
+
+        let result = runtime.block_on(
+            cmd("GET")
+                .arg("test")
+                .set_timeout(Duration::from_millis(100))
+                .query_async::<_, Option<i32>>(&mut connection),
+        );
+
+        assert!(result.unwrap_err().is_timeout());
+        let sent = requests.load(atomic::Ordering::SeqCst);
+        assert!(sent > 0 && sent < 1000, "{sent}");
+    }
+
+    #[derive(Default)]
+    struct RecordingInstrumentation(std::sync::Mutex<Vec<String>>);
+
+    impl RecordingInstrumentation {
+        fn record(&self, event: String) {
+            self.0.lock().unwrap().push(event);
+        }
+    }
+
+    impl Instrumentation for RecordingInstrumentation {
+        fn on_request_start(&self, request: &RequestInfo<'_>) {
+            self.record(format!(
+                "start {} {}",
+                request.command(),
+                request.node().unwrap()
+            ));
+        }
+
+        fn on_response(&self, request: &RequestInfo<'_>, _latency: Duration) {
+            self.record(format!(
+                "response {} {}",
+                request.command(),
+                request.node().unwrap()
+            ));
+        }
+
+        fn on_error(&self, request: &RequestInfo<'_>, error: &RedisError, _latency: Duration) {
+            self.record(format!(
+                "error {} {} {:?}",
+                request.command(),
+                request.node().unwrap(),
+                error.kind()
+            ));
+        }
+
+        fn on_redirect(&self, request: &RequestInfo<'_>, redirect_to: &str) {
+            self.record(format!("redirect {} {redirect_to}", request.command()));
+        }
+    }
+
+    #[test]
+    fn test_async_cluster_instrumentation_reports_requests_and_redirects() {
+        let name = "instrumentation_reports_requests_and_redirects";
+        let instrumentation = Arc::new(RecordingInstrumentation::default());
+
+        let MockEnv {
+            runtime,
+            async_connection: mut connection,
+            handler: _handler,
+            ..
+        } = MockEnv::with_client_builder(
+            ClusterClient::builder(vec![&*format!("redis://{name}")])
+                .instrumentation(instrumentation.clone()),
+            name,
+            move |cmd: &[u8], port| {
+                respond_startup(name, cmd)?;
+                if port == 6379 {
+                    Err(parse_redis_value(
+                        format!("-MOVED 123 {name}:6380\r\n").as_bytes(),
+                    ))
+                } else {
+                    Err(Ok(Value::BulkString(b"123".to_vec())))
+                }
+            },
+        );
+
+        let value = runtime.block_on(
+            cmd("GET")
+                .arg("test")
+                .query_async::<_, Option<i32>>(&mut connection),
+        );
+
+        assert_eq!(value, Ok(Some(123)));
+        assert_eq!(
+            *instrumentation.0.lock().unwrap(),
+            [
+                format!("start GET {name}:6379"),
+                format!("error GET {name}:6379 Moved"),
+                format!("redirect GET {name}:6380"),
+                format!("start GET {name}:6380"),
+                format!("response GET {name}:6380"),
+            ]
+        );
+    }
+
+    struct CountingCredentialsProvider(AtomicU32);
+
+    impl CredentialsProvider for CountingCredentialsProvider {
+        fn credentials(&self) -> RedisFuture<'_, Credentials> {
+            let calls = self.0.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async move { Ok(Credentials::new(format!("token-{calls}"))) })
+        }
+    }
+
+    #[test]
+    fn test_async_cluster_credentials_provider_is_asked_for_each_node_connection() {
+        let name = "credentials_provider_is_asked_for_each_node_connection";
+        let provider = Arc::new(CountingCredentialsProvider(AtomicU32::new(0)));
+
+        let MockEnv {
+            runtime,
+            async_connection: mut connection,
+            handler: _handler,
+            ..
+        } = MockEnv::with_client_builder(
+            ClusterClient::builder(vec![&*format!("redis://{name}")])
+                .credentials_provider(provider.clone()),
+            name,
+            move |cmd: &[u8], port| {
+                respond_startup(name, cmd)?;
+                if port == 6379 {
+                    Err(parse_redis_value(
+                        format!("-MOVED 123 {name}:6380\r\n").as_bytes(),
+                    ))
+                } else {
+                    Err(Ok(Value::BulkString(b"123".to_vec())))
+                }
+            },
+        );
+        let initial_calls = provider.0.load(Ordering::SeqCst);
+        assert!(initial_calls > 0);
+
+        let value = runtime.block_on(
+            cmd("GET")
+                .arg("test")
+                .query_async::<_, Option<i32>>(&mut connection),
+        );
+
+        assert_eq!(value, Ok(Some(123)));
+        assert_eq!(provider.0.load(Ordering::SeqCst), initial_calls + 1);
+    }
+
+    #[test]
+    fn test_async_cluster_command_policy_rejects_commands_before_sending() {
+        use redis::interceptor::{AsyncIntercepted, CommandPolicy};
+
+        let name = "command_policy_rejects_commands_before_sending";
+        let requests = Arc::new(AtomicU32::new(0));
+        let requests_clone = requests.clone();
+
+        let MockEnv {
+            runtime,
+            async_connection: connection,
+            handler: _handler,
+            ..
+        } = MockEnv::new(name, move |cmd: &[u8], _| {
+            respond_startup(name, cmd)?;
+            requests_clone.fetch_add(1, Ordering::Relaxed);
+            Err(Ok(Value::BulkString(b"123".to_vec())))
+        });
+        let mut connection = AsyncIntercepted::new(connection).with_interceptor(
+            CommandPolicy::new()
+                .with_denied_commands(CommandPolicy::DANGEROUS_COMMANDS)
+                .with_read_only(true),
+        );
+
+        let err = runtime
+            .block_on(cmd("FLUSHALL").query_async::<_, Value>(&mut connection))
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::CommandDenied);
+        let err = runtime
+            .block_on(connection.set::<_, _, ()>("test", 1))
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::CommandDenied);
+        assert_eq!(requests.load(Ordering::Relaxed), 0);
+
+        let value = runtime.block_on(connection.get::<_, Option<i32>>("test"));
+        assert_eq!(value, Ok(Some(123)));
+        assert_eq!(requests.load(Ordering::Relaxed), 1);
+    }
+
     #[test]
     fn test_async_cluster_move_error_when_new_node_is_added() {
         let name = "rebuild_with_extra_nodes";
@@ -637,6 +851,7 @@ mod cluster_async {
             handler: _handler,
             ..
         } = MockEnv::new(name, move |cmd: &[u8], port| {
+            respond_shards_unsupported(cmd)?;
             if !started.load(atomic::Ordering::SeqCst) {
                 respond_startup(name, cmd)?;
             }
@@ -695,6 +910,64 @@ mod cluster_async {
         assert_eq!(value, Ok(Some(123)));
     }
 
+    #[test]
+    fn test_async_cluster_moved_slot_is_patched_without_blocking_on_refresh() {
+        let name = "test_async_cluster_moved_slot_is_patched_without_blocking_on_refresh";
+        let started = Arc::new(atomic::AtomicBool::new(false));
+        let redirects = Arc::new(atomic::AtomicUsize::new(0));
+        let slot_queries = Arc::new(atomic::AtomicUsize::new(0));
+        let (redirects_clone, slot_queries_clone) = (redirects.clone(), slot_queries.clone());
+
+        let MockEnv {
+            runtime,
+            async_connection: mut connection,
+            handler: _handler,
+            ..
+        } = MockEnv::new(name, move |cmd: &[u8], port| {
+            if started.load(atomic::Ordering::SeqCst) && contains_slice(cmd, b"CLUSTER") {
+                // The full refresh keeps failing, which mustn't affect the redirected requests.
+                if contains_slice(cmd, b"SLOTS") {
+                    slot_queries_clone.fetch_add(1, atomic::Ordering::SeqCst);
+                }
+                return Err(parse_redis_value(b"-ERR cluster is busy\r\n"));
+            }
+            respond_startup_two_nodes(name, cmd)?;
+            started.store(true, atomic::Ordering::SeqCst);
+
+            match port {
+                6380 => {
+                    redirects_clone.fetch_add(1, atomic::Ordering::SeqCst);
+                    let slot = if contains_slice(cmd, b"foo") {
+                        12182
+                    } else {
+                        9995
+                    };
+                    Err(parse_redis_value(
+                        format!("-MOVED {slot} {name}:6379\r\n").as_bytes(),
+                    ))
+                }
+                _ => Err(Ok(Value::BulkString(b"123".to_vec()))),
+            }
+        });
+
+        runtime
+            .block_on(async move {
+                for key in ["foo", "qux", "foo", "qux"] {
+                    let value: Option<i32> =
+                        cmd("GET").arg(key).query_async(&mut connection).await?;
+                    assert_eq!(value, Some(123));
+                }
+                Ok::<_, RedisError>(())
+            })
+            .unwrap();
+
+        // Each slot was redirected once, and then routed to its new owner.
+        assert_eq!(redirects.load(atomic::Ordering::SeqCst), 2);
+        // The refreshes that follow the redirects are rate-limited, so at most one refresh, which
+        // queried both nodes, has run.
+        assert!(slot_queries.load(atomic::Ordering::SeqCst) <= 2);
+    }
+
     #[test]
     fn test_async_cluster_ask_redirect() {
         let name = "test_async_cluster_ask_redirect";
@@ -895,6 +1168,7 @@ mod cluster_async {
             handler: _handler,
             ..
         } = MockEnv::new(name, move |cmd: &[u8], port| {
+            respond_shards_unsupported(cmd)?;
             if !started.load(atomic::Ordering::SeqCst) {
                 respond_startup(name, cmd)?;
             }
@@ -936,6 +1210,293 @@ mod cluster_async {
         assert_eq!(value, Ok(Some(123)));
     }
 
+    #[test]
+    fn test_async_cluster_replica_read_with_strategy() {
+        let name = "test_async_cluster_replica_read_with_strategy";
+        let ports = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let ports_clone = ports.clone();
+
+        let MockEnv {
+            runtime,
+            async_connection: mut connection,
+            handler: _handler,
+            ..
+        } = MockEnv::with_client_builder(
+            ClusterClient::builder(vec![&*format!("redis://{name}")])
+                .retries(0)
+                .read_from_replica_strategy(redis::cluster_routing::RoundRobin::new()),
+            name,
+            move |cmd: &[u8], port| {
+                respond_startup_with_replicas_using_config(
+                    name,
+                    cmd,
+                    Some(vec![MockSlotRange {
+                        primary_port: 6379,
+                        replica_ports: vec![6380, 6381],
+                        slot_range: (0..16383),
+                    }]),
+                )?;
+                ports_clone.lock().unwrap().push(port);
+                Err(Ok(Value::BulkString(b"123".to_vec())))
+            },
+        );
+
+        runtime
+            .block_on(async move {
+                for _ in 0..4 {
+                    let value: Option<i32> =
+                        cmd("GET").arg("test").query_async(&mut connection).await?;
+                    assert_eq!(value, Some(123));
+                }
+                Ok::<_, RedisError>(())
+            })
+            .unwrap();
+        assert_eq!(*ports.lock().unwrap(), vec![6380, 6381, 6380, 6381]);
+    }
+
+    #[test]
+    fn test_async_cluster_discovers_topology_with_cluster_shards() {
+        let name = "test_async_cluster_discovers_topology_with_cluster_shards";
+        let ports = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let ports_clone = ports.clone();
+        let node = |port: i64, role: &str, health: &str| {
+            Value::Array(vec![
+                Value::BulkString(b"port".to_vec()),
+                Value::Int(port),
+                Value::BulkString(b"endpoint".to_vec()),
+                Value::BulkString(name.as_bytes().to_vec()),
+                Value::BulkString(b"role".to_vec()),
+                Value::BulkString(role.as_bytes().to_vec()),
+                Value::BulkString(b"health".to_vec()),
+                Value::BulkString(health.as_bytes().to_vec()),
+            ])
+        };
+
+        let MockEnv {
+            runtime,
+            async_connection: mut connection,
+            handler: _handler,
+            ..
+        } = MockEnv::with_client_builder(
+            ClusterClient::builder(vec![&*format!("redis://{name}")])
+                .retries(0)
+                .read_from_replicas(),
+            name,
+            move |cmd: &[u8], port| {
+                if contains_slice(cmd, b"CLUSTER") && contains_slice(cmd, b"SHARDS") {
+                    return Err(Ok(Value::Array(vec![Value::Array(vec![
+                        Value::BulkString(b"slots".to_vec()),
+                        Value::Array(vec![Value::Int(0), Value::Int(16383)]),
+                        Value::BulkString(b"nodes".to_vec()),
+                        Value::Array(vec![
+                            node(6379, "master", "online"),
+                            node(6380, "replica", "loading"),
+                            node(6381, "replica", "online"),
+                        ]),
+                    ])])));
+                }
+                // `CLUSTER SLOTS` would report 6380 as a replica too.
+                respond_startup_with_replicas_using_config(
+                    name,
+                    cmd,
+                    Some(vec![MockSlotRange {
+                        primary_port: 6379,
+                        replica_ports: vec![6380, 6381],
+                        slot_range: (0..16383),
+                    }]),
+                )?;
+                ports_clone.lock().unwrap().push(port);
+                Err(Ok(Value::BulkString(b"123".to_vec())))
+            },
+        );
+
+        runtime
+            .block_on(async move {
+                for _ in 0..4 {
+                    let value: Option<i32> =
+                        cmd("GET").arg("test").query_async(&mut connection).await?;
+                    assert_eq!(value, Some(123));
+                }
+                Ok::<_, RedisError>(())
+            })
+            .unwrap();
+        assert_eq!(*ports.lock().unwrap(), vec![6381, 6381, 6381, 6381]);
+    }
+
+    #[test]
+    fn test_async_cluster_connects_to_mapped_addresses() {
+        let name = "test_async_cluster_connects_to_mapped_addresses";
+        let ports = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let ports_clone = ports.clone();
+
+        let MockEnv {
+            runtime,
+            async_connection: mut connection,
+            handler: _handler,
+            ..
+        } = MockEnv::with_client_builder(
+            ClusterClient::builder(vec![&*format!("redis://{name}")])
+                .retries(0)
+                .address_mapper(|host: &str, port: u16| {
+                    redis::cluster::MappedAddress::new(host, port + 1000)
+                }),
+            name,
+            move |cmd: &[u8], port| {
+                respond_startup_two_nodes(name, cmd)?;
+                ports_clone.lock().unwrap().push(port);
+                Err(Ok(Value::BulkString(b"123".to_vec())))
+            },
+        );
+
+        runtime
+            .block_on(async move {
+                // "foo" is served by the node that is advertised as 6380, and "bar" by 6379.
+                for key in ["foo", "bar"] {
+                    let value: Option<i32> =
+                        cmd("GET").arg(key).query_async(&mut connection).await?;
+                    assert_eq!(value, Some(123));
+                }
+                Ok::<_, RedisError>(())
+            })
+            .unwrap();
+        assert_eq!(*ports.lock().unwrap(), vec![7380, 7379]);
+    }
+
+    #[test]
+    fn test_async_cluster_scan_visits_every_primary_with_filters() {
+        let name = "test_async_cluster_scan_visits_every_primary_with_filters";
+        let topology_queries = Arc::new(AtomicU32::new(0));
+        let topology_queries_clone = topology_queries.clone();
+
+        let MockEnv {
+            runtime,
+            async_connection: connection,
+            handler: _handler,
+            ..
+        } = MockEnv::new(name, move |cmd: &[u8], port| {
+            if contains_slice(cmd, b"CLUSTER") {
+                topology_queries_clone.fetch_add(1, Ordering::SeqCst);
+            }
+            respond_startup_two_nodes(name, cmd)?;
+            if contains_slice(cmd, b"SCAN") {
+                assert!(contains_slice(cmd, b"MATCH\r\n$1\r\n*\r\n"));
+                assert!(contains_slice(cmd, b"COUNT\r\n$2\r\n10\r\n"));
+                assert!(contains_slice(cmd, b"TYPE\r\n$6\r\nstring\r\n"));
+                let keys: &[&[u8]] = match port {
+                    // "foo" belongs to 6380, so it must only be returned by 6380's scan.
+                    6379 if !contains_slice(cmd, b"SCAN\r\n$1\r\n7\r\n") => {
+                        return Err(Ok(Value::Array(vec![
+                            Value::BulkString(b"7".to_vec()),
+                            Value::Array(vec![
+                                Value::BulkString(b"bar".to_vec()),
+                                Value::BulkString(b"foo".to_vec()),
+                            ]),
+                        ])));
+                    }
+                    6379 => &[b"baz"],
+                    _ => &[b"foo", b"qux"],
+                };
+                return Err(Ok(Value::Array(vec![
+                    Value::BulkString(b"0".to_vec()),
+                    Value::Array(
+                        keys.iter()
+                            .map(|key| Value::BulkString(key.to_vec()))
+                            .collect(),
+                    ),
+                ])));
+            }
+            Ok(())
+        });
+
+        let options = redis::cluster::ScanOptions::default()
+            .with_pattern("*")
+            .with_count(10)
+            .with_type("string");
+        let initial_topology_queries = topology_queries.load(Ordering::SeqCst);
+        let mut keys = runtime
+            .block_on(
+                connection
+                    .scan_cluster::<String>(options)
+                    .try_collect::<Vec<_>>(),
+            )
+            .unwrap();
+        keys.sort();
+        assert_eq!(keys, vec!["bar", "baz", "foo", "qux"]);
+        assert_eq!(
+            topology_queries.load(Ordering::SeqCst),
+            initial_topology_queries
+        );
+    }
+
+    #[test]
+    fn test_async_cluster_pipeline_splits_multi_slot_commands() {
+        let name = "test_async_cluster_pipeline_splits_multi_slot_commands";
+        let MockEnv {
+            runtime,
+            async_connection: mut connection,
+            handler: _handler,
+            ..
+        } = MockEnv::new(name, move |cmd: &[u8], port| {
+            respond_startup_two_nodes(name, cmd)?;
+            respond_multi_slot_commands(cmd, port)
+        });
+
+        let (values, deleted): (Vec<String>, i64) = runtime
+            .block_on(
+                redis::pipe()
+                    .mset(&[("foo", "1"), ("bar", "2")])
+                    .ignore()
+                    .cmd("MGET")
+                    .arg(&["foo", "bar", "baz"])
+                    .del(&["foo", "bar"])
+                    .query_async(&mut connection),
+            )
+            .unwrap();
+
+        assert_eq!(values, vec!["foo@6380", "bar@6379", "baz@6379"]);
+        assert_eq!(deleted, 2);
+    }
+
+    #[test]
+    fn test_async_cluster_pipeline_retries_only_redirected_commands() {
+        let name = "test_async_cluster_pipeline_retries_only_redirected_commands";
+        let bar_requests = Arc::new(AtomicU32::new(0));
+        let bar_requests_clone = bar_requests.clone();
+        let MockEnv {
+            runtime,
+            async_connection: mut connection,
+            handler: _handler,
+            ..
+        } = MockEnv::new(name, move |cmd: &[u8], port| {
+            respond_startup_two_nodes(name, cmd)?;
+            if contains_slice(cmd, b"bar") {
+                bar_requests_clone.fetch_add(1, Ordering::SeqCst);
+                return Err(Ok(Value::BulkString(b"bar-value".to_vec())));
+            }
+            if contains_slice(cmd, b"foo") {
+                return match port {
+                    6380 => Err(parse_redis_value(
+                        format!("-MOVED 12182 {name}:6379\r\n").as_bytes(),
+                    )),
+                    _ => Err(Ok(Value::BulkString(b"foo-value".to_vec()))),
+                };
+            }
+            Ok(())
+        });
+
+        let values: Vec<String> = runtime
+            .block_on(
+                redis::pipe()
+                    .get("foo")
+                    .get("bar")
+                    .query_async(&mut connection),
+            )
+            .unwrap();
+
+        assert_eq!(values, vec!["foo-value", "bar-value"]);
+        assert_eq!(bar_requests.load(Ordering::SeqCst), 1);
+    }
+
     #[test]
     fn test_async_cluster_replica_read() {
         let name = "test_async_cluster_replica_read";
@@ -1860,6 +2421,552 @@ mod cluster_async {
         assert_eq!(ping_attempts.load(Ordering::Acquire), 5);
     }
 
+    #[test]
+    fn test_async_cluster_replaces_only_the_broken_connection_of_a_node() {
+        let name = "test_async_cluster_replaces_only_the_broken_connection_of_a_node";
+        let ping_attempts = Arc::new(AtomicI32::new(0));
+        let ping_attempts_clone = ping_attempts.clone();
+        let get_attempts = AtomicI32::new(0);
+
+        let MockEnv {
+            runtime,
+            async_connection: mut connection,
+            handler: _handler,
+            ..
+        } = MockEnv::with_client_builder(
+            ClusterClient::builder(vec![&*format!("redis://{name}")])
+                .retries(1)
+                .connections_per_node(3),
+            name,
+            move |cmd: &[u8], _| {
+                if contains_slice(cmd, b"PING") {
+                    // PINGs 1-4 are sent on client creation - by the initial connection, by the
+                    // check of the initial connection when the slots are refreshed, and by the two
+                    // new connections to the node. After the first GET fails, the node's three
+                    // connections are checked, and only the first one is found broken.
+                    let attempt = ping_attempts_clone.fetch_add(1, Ordering::Relaxed);
+                    if attempt == 4 {
+                        return Err(Err(RedisError::from(std::io::Error::new(
+                            std::io::ErrorKind::BrokenPipe,
+                            "mock-io-error",
+                        ))));
+                    }
+                }
+                respond_startup(name, cmd)?;
+                if get_attempts.fetch_add(1, Ordering::Relaxed) == 0 {
+                    return Err(Err(RedisError::from(std::io::Error::new(
+                        std::io::ErrorKind::BrokenPipe,
+                        "mock-io-error",
+                    ))));
+                }
+                Err(Ok(Value::BulkString(b"123".to_vec())))
+            },
+        );
+
+        for _ in 0..4 {
+            let value = runtime.block_on(
+                cmd("GET")
+                    .arg("test")
+                    .query_async::<_, Option<i32>>(&mut connection),
+            );
+            assert_eq!(value, Ok(Some(123)));
+        }
+        // 4 PINGs on creation, 3 checks after the failed GET, and a single reconnection.
+        assert_eq!(ping_attempts.load(Ordering::Acquire), 8);
+    }
+
+    #[test]
+    fn test_async_cluster_moves_shard_subscriptions_with_their_slot() {
+        let name = "test_async_cluster_moves_shard_subscriptions_with_their_slot";
+        let slot_migrated = Arc::new(AtomicBool::new(false));
+        let slot_migrated_clone = slot_migrated.clone();
+        let subscription_cmds = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let subscription_cmds_clone = subscription_cmds.clone();
+
+        let MockEnv {
+            runtime,
+            async_connection: mut connection,
+            handler: _handler,
+            ..
+        } = MockEnv::with_client_builder(
+            ClusterClient::builder(vec![&*format!("redis://{name}")])
+                .use_protocol(ProtocolVersion::RESP3),
+            name,
+            move |cmd: &[u8], port| {
+                // "foo" is in slot 12182, which migrates from the second node to the first.
+                let first_node_end = if slot_migrated_clone.load(Ordering::Relaxed) {
+                    13000
+                } else {
+                    8191
+                };
+                respond_startup_with_replica_using_config(
+                    name,
+                    cmd,
+                    Some(vec![
+                        MockSlotRange {
+                            primary_port: 6379,
+                            replica_ports: vec![],
+                            slot_range: (0..first_node_end),
+                        },
+                        MockSlotRange {
+                            primary_port: 6380,
+                            replica_ports: vec![],
+                            slot_range: (first_node_end + 1..16383),
+                        },
+                    ]),
+                )?;
+
+                let channel = if contains_slice(cmd, b"foo") {
+                    "foo"
+                } else {
+                    "bar"
+                };
+                if contains_slice(cmd, b"SUNSUBSCRIBE") {
+                    subscription_cmds_clone
+                        .lock()
+                        .unwrap()
+                        .push((port, "SUNSUBSCRIBE", channel));
+                    return Err(Ok(Value::Push {
+                        kind: PushKind::SUnsubscribe,
+                        data: vec![Value::BulkString(channel.as_bytes().to_vec())],
+                    }));
+                }
+                if contains_slice(cmd, b"SSUBSCRIBE") {
+                    subscription_cmds_clone
+                        .lock()
+                        .unwrap()
+                        .push((port, "SSUBSCRIBE", channel));
+                    return Err(Ok(Value::Push {
+                        kind: PushKind::SSubscribe,
+                        data: vec![Value::BulkString(channel.as_bytes().to_vec())],
+                    }));
+                }
+
+                if port == 6380 && slot_migrated_clone.load(Ordering::Relaxed) {
+                    return Err(parse_redis_value(
+                        format!("-MOVED 12182 {name}:6379\r\n").as_bytes(),
+                    ));
+                }
+                Err(Ok(Value::Nil))
+            },
+        );
+
+        runtime
+            .block_on(connection.ssubscribe(&["foo", "bar"]))
+            .unwrap();
+        assert_eq!(
+            std::mem::take(&mut *subscription_cmds.lock().unwrap()),
+            vec![(6380, "SSUBSCRIBE", "foo"), (6379, "SSUBSCRIBE", "bar")]
+        );
+
+        // The MOVED response refreshes the slots, which moves the subscription.
+        slot_migrated.store(true, Ordering::Relaxed);
+        let value = runtime.block_on(
+            cmd("GET")
+                .arg("foo")
+                .query_async::<_, Option<i32>>(&mut connection),
+        );
+        assert_eq!(value, Ok(None));
+
+        runtime.block_on(async {
+            for _ in 0..100 {
+                if subscription_cmds.lock().unwrap().len() >= 2 {
+                    break;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        });
+        let mut subscription_cmds = std::mem::take(&mut *subscription_cmds.lock().unwrap());
+        subscription_cmds.sort();
+        // "bar" stayed on its node, so it isn't renewed.
+        assert_eq!(
+            subscription_cmds,
+            vec![(6379, "SSUBSCRIBE", "foo"), (6380, "SUNSUBSCRIBE", "foo")]
+        );
+    }
+
+    #[test]
+    fn test_async_cluster_renews_subscriptions_of_replaced_connections() {
+        let name = "test_async_cluster_renews_subscriptions_of_replaced_connections";
+        let connection_lost = Arc::new(AtomicBool::new(false));
+        let connection_lost_clone = connection_lost.clone();
+        let subscription_cmds = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let subscription_cmds_clone = subscription_cmds.clone();
+
+        let MockEnv {
+            runtime,
+            async_connection: mut connection,
+            handler: _handler,
+            ..
+        } = MockEnv::with_client_builder(
+            ClusterClient::builder(vec![&*format!("redis://{name}")])
+                .use_protocol(ProtocolVersion::RESP3),
+            name,
+            move |cmd: &[u8], port| {
+                // The connection to the first node is lost, which the next check notices.
+                if port == 6379
+                    && contains_slice(cmd, b"PING")
+                    && connection_lost_clone.swap(false, Ordering::SeqCst)
+                {
+                    return Err(Err(RedisError::from(std::io::Error::new(
+                        std::io::ErrorKind::ConnectionReset,
+                        "mock-io-error",
+                    ))));
+                }
+                respond_startup_two_nodes(name, cmd)?;
+                if contains_slice(cmd, b"SSUBSCRIBE") {
+                    // "foo" is in slot 12182, and "bar" in slot 5061.
+                    let channel = if contains_slice(cmd, b"foo") {
+                        "foo"
+                    } else {
+                        "bar"
+                    };
+                    subscription_cmds_clone
+                        .lock()
+                        .unwrap()
+                        .push((port, channel));
+                    return Err(Ok(Value::Push {
+                        kind: PushKind::SSubscribe,
+                        data: vec![Value::BulkString(channel.as_bytes().to_vec())],
+                    }));
+                }
+                if port == 6380 {
+                    return Err(parse_redis_value(
+                        format!("-MOVED 12182 {name}:6380\r\n").as_bytes(),
+                    ));
+                }
+                Err(Ok(Value::Nil))
+            },
+        );
+
+        runtime
+            .block_on(connection.ssubscribe(&["foo", "bar"]))
+            .unwrap();
+        assert_eq!(
+            std::mem::take(&mut *subscription_cmds.lock().unwrap()),
+            vec![(6380, "foo"), (6379, "bar")]
+        );
+
+        // The MOVED response refreshes the slots, which replaces the lost connection.
+        connection_lost.store(true, Ordering::SeqCst);
+        let _ = runtime.block_on(
+            cmd("GET")
+                .arg("foo")
+                .query_async::<_, Option<i32>>(&mut connection),
+        );
+
+        runtime.block_on(async {
+            for _ in 0..100 {
+                if !subscription_cmds.lock().unwrap().is_empty() {
+                    break;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+            // Gives unexpected renewals the time to arrive.
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        });
+        assert_eq!(*subscription_cmds.lock().unwrap(), vec![(6379, "bar")]);
+    }
+
+    #[test]
+    fn test_async_cluster_subscribe_requires_resp3() {
+        let name = "test_async_cluster_subscribe_requires_resp3";
+
+        let MockEnv {
+            runtime,
+            async_connection: mut connection,
+            handler: _handler,
+            ..
+        } = MockEnv::new(name, move |cmd: &[u8], _| {
+            respond_startup(name, cmd)?;
+            Err(Ok(Value::Nil))
+        });
+
+        let result = runtime.block_on(connection.subscribe("foo"));
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidClientConfig);
+    }
+
+    #[test]
+    fn test_async_cluster_pubsub() {
+        if use_protocol() == ProtocolVersion::RESP2 {
+            return;
+        }
+        let cluster = TestClusterContext::new_with_cluster_client_builder(|builder| {
+            builder.use_protocol(ProtocolVersion::RESP3)
+        });
+
+        block_on_all(async move {
+            let mut connection = cluster.async_connection().await;
+            let mut publisher = cluster.async_connection().await;
+            let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+            connection.get_push_manager().replace_sender(tx);
+
+            connection.subscribe("regular").await?;
+            connection.ssubscribe(&["foo", "bar"]).await?;
+            let mut confirmations = vec![
+                rx.recv().await.unwrap().kind,
+                rx.recv().await.unwrap().kind,
+                rx.recv().await.unwrap().kind,
+            ];
+            confirmations.sort_by_key(|kind| kind.to_string());
+            assert_eq!(
+                confirmations,
+                vec![
+                    PushKind::SSubscribe,
+                    PushKind::SSubscribe,
+                    PushKind::Subscribe
+                ]
+            );
+
+            let _: () = publisher.publish("regular", "hello").await?;
+            let push = rx.recv().await.unwrap();
+            assert_eq!(push.kind, PushKind::Message);
+            assert_eq!(
+                push.data,
+                vec![
+                    Value::BulkString(b"regular".to_vec()),
+                    Value::BulkString(b"hello".to_vec())
+                ]
+            );
+
+            for channel in ["foo", "bar"] {
+                let _: () = publisher.spublish(channel, "world").await?;
+                let push = rx.recv().await.unwrap();
+                assert_eq!(push.kind, PushKind::SMessage);
+                assert_eq!(
+                    push.data,
+                    vec![
+                        Value::BulkString(channel.as_bytes().to_vec()),
+                        Value::BulkString(b"world".to_vec())
+                    ]
+                );
+            }
+
+            connection.sunsubscribe(&["foo", "bar"]).await?;
+            Ok::<_, RedisError>(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_async_cluster_periodic_topology_checks_detect_new_nodes() {
+        let name = "test_async_cluster_periodic_topology_checks_detect_new_nodes";
+        let scaled_out = Arc::new(AtomicBool::new(false));
+        let scaled_out_clone = scaled_out.clone();
+        let redirects = Arc::new(AtomicU32::new(0));
+        let redirects_clone = redirects.clone();
+        let MockEnv {
+            runtime,
+            async_connection: mut connection,
+            handler: _handler,
+            ..
+        } = MockEnv::with_client_builder(
+            ClusterClient::builder(vec![&*format!("redis://{name}")])
+                .periodic_topology_checks(std::time::Duration::from_millis(20)),
+            name,
+            move |cmd: &[u8], port| {
+                let slots = if scaled_out_clone.load(Ordering::SeqCst) {
+                    vec![
+                        MockSlotRange {
+                            primary_port: 6379,
+                            replica_ports: vec![],
+                            slot_range: (0..8191),
+                        },
+                        MockSlotRange {
+                            primary_port: 6380,
+                            replica_ports: vec![],
+                            slot_range: (8192..16383),
+                        },
+                    ]
+                } else {
+                    vec![MockSlotRange {
+                        primary_port: 6379,
+                        replica_ports: vec![],
+                        slot_range: (0..16383),
+                    }]
+                };
+                respond_startup_with_replica_using_config(name, cmd, Some(slots))?;
+                // "foo" is in slot 12182, so a request that reaches the old owner is redirected.
+                if port == 6379 {
+                    redirects_clone.fetch_add(1, Ordering::SeqCst);
+                    return Err(parse_redis_value(
+                        format!("-MOVED 12182 {name}:6380\r\n").as_bytes(),
+                    ));
+                }
+                Err(Ok(Value::BulkString(b"bar".to_vec())))
+            },
+        );
+
+        scaled_out.store(true, Ordering::SeqCst);
+        let value = runtime.block_on(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            cmd("GET")
+                .arg("foo")
+                .query_async::<_, String>(&mut connection)
+                .await
+        });
+        assert_eq!(value, Ok("bar".to_string()));
+        assert_eq!(redirects.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_async_cluster_transaction() {
+        let cluster = TestClusterContext::new();
+
+        block_on_all(async move {
+            let mut connection = cluster.async_connection().await;
+            connection.set("{tx}counter", 1).await?;
+            let (counter, other): (isize, String) = connection
+                .transaction(&["{tx}counter"], |mut con, mut pipe| async move {
+                    let counter: isize = con.get("{tx}counter").await?;
+                    pipe.set("{tx}counter", counter + 1)
+                        .ignore()
+                        .set("{tx}other", "value")
+                        .ignore()
+                        .get("{tx}counter")
+                        .get("{tx}other");
+                    Ok(pipe)
+                })
+                .await?;
+            assert_eq!(counter, 2);
+            assert_eq!(other, "value");
+            Ok::<_, RedisError>(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_async_cluster_transaction_retries_when_watched_key_changes() {
+        let name = "test_async_cluster_transaction_retries_when_watched_key_changes";
+        let watches = Arc::new(AtomicU32::new(0));
+        let watches_clone = watches.clone();
+        let execs = AtomicU32::new(0);
+        let MockEnv {
+            runtime,
+            async_connection: mut connection,
+            handler: _handler,
+            ..
+        } = MockEnv::new(name, move |cmd: &[u8], _| {
+            respond_startup(name, cmd)?;
+            if contains_slice(cmd, b"WATCH") {
+                watches_clone.fetch_add(1, Ordering::SeqCst);
+                return Err(Ok(Value::Okay));
+            }
+            if contains_slice(cmd, b"MULTI") {
+                // The first execution is aborted, as if the watched key was modified.
+                let exec_result = if execs.fetch_add(1, Ordering::SeqCst) == 0 {
+                    Value::Nil
+                } else {
+                    Value::Array(vec![Value::Okay, Value::BulkString(b"2".to_vec())])
+                };
+                return Err(Ok(Value::Array(vec![
+                    Value::Okay,
+                    Value::SimpleString("QUEUED".to_string()),
+                    Value::SimpleString("QUEUED".to_string()),
+                    exec_result,
+                ])));
+            }
+            if contains_slice(cmd, b"GET") {
+                return Err(Ok(Value::BulkString(b"1".to_vec())));
+            }
+            panic!("unexpected command {}", String::from_utf8_lossy(cmd));
+        });
+
+        let (new_val,): (isize,) = runtime
+            .block_on(
+                connection.transaction(&["foo"], |mut con, mut pipe| async move {
+                    let old_val: isize = con.get("foo").await?;
+                    pipe.set("foo", old_val + 1).ignore().get("foo");
+                    Ok(pipe)
+                }),
+            )
+            .unwrap();
+
+        assert_eq!(new_val, 2);
+        assert_eq!(watches.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_async_cluster_transaction_follows_moved_slot() {
+        let name = "test_async_cluster_transaction_follows_moved_slot";
+        let moved = AtomicBool::new(false);
+        let MockEnv {
+            runtime,
+            async_connection: mut connection,
+            handler: _handler,
+            ..
+        } = MockEnv::new(name, move |cmd: &[u8], port| {
+            let owner = if moved.load(Ordering::SeqCst) {
+                6380
+            } else {
+                6379
+            };
+            respond_startup_with_replica_using_config(
+                name,
+                cmd,
+                Some(vec![MockSlotRange {
+                    primary_port: owner,
+                    replica_ports: vec![],
+                    slot_range: (0..16383),
+                }]),
+            )?;
+            if contains_slice(cmd, b"WATCH") {
+                if port == 6379 {
+                    moved.store(true, Ordering::SeqCst);
+                    return Err(parse_redis_value(
+                        format!("-MOVED 12182 {name}:6380\r\n").as_bytes(),
+                    ));
+                }
+                return Err(Ok(Value::Okay));
+            }
+            if contains_slice(cmd, b"MULTI") {
+                assert_eq!(port, 6380);
+                return Err(Ok(Value::Array(vec![
+                    Value::Okay,
+                    Value::SimpleString("QUEUED".to_string()),
+                    Value::Array(vec![Value::Okay]),
+                ])));
+            }
+            panic!("unexpected command {}", String::from_utf8_lossy(cmd));
+        });
+
+        let result: RedisResult<()> =
+            runtime.block_on(connection.transaction(&["foo"], |_, mut pipe| async move {
+                pipe.set("foo", "bar").ignore();
+                Ok(pipe)
+            }));
+
+        assert_eq!(result, Ok(()));
+    }
+
+    #[test]
+    fn test_async_cluster_transaction_rejects_keys_from_different_slots() {
+        let name = "test_async_cluster_transaction_rejects_keys_from_different_slots";
+        let MockEnv {
+            runtime,
+            async_connection: mut connection,
+            handler: _handler,
+            ..
+        } = MockEnv::new(name, move |cmd: &[u8], _| {
+            respond_startup(name, cmd)?;
+            if contains_slice(cmd, b"WATCH") {
+                return Err(Ok(Value::Okay));
+            }
+            panic!("unexpected command {}", String::from_utf8_lossy(cmd));
+        });
+
+        let result: RedisResult<()> = runtime
+            .block_on(connection.transaction(&["foo", "bar"], |_, pipe| async move { Ok(pipe) }));
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::CrossSlot);
+
+        let result: RedisResult<()> =
+            runtime.block_on(connection.transaction(&["foo"], |_, mut pipe| async move {
+                pipe.set("bar", "foo").ignore();
+                Ok(pipe)
+            }));
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::CrossSlot);
+    }
+
     #[cfg(feature = "tls-rustls")]
     mod mtls_test {
         use crate::support::mtls_test::create_cluster_client_from_cluster;
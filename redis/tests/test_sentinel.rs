@@ -241,6 +241,8 @@ pub mod async_tests {
         AsyncConnectionConfig, Client, ConnectionAddr, RedisError,
     };
 
+    #[cfg(feature = "connection-manager")]
+    use crate::parse_replication_info;
     use crate::{assert_is_master_role, assert_replica_role_and_master_addr, support::*};
 
     async fn async_assert_is_connection_to_master(conn: &mut MultiplexedConnection) {
@@ -649,4 +651,58 @@ pub mod async_tests {
         })
         .unwrap();
     }
+
+    #[test]
+    #[cfg(feature = "connection-manager")]
+    fn test_sentinel_connection_manager_follows_failover() {
+        let master_name = "master1";
+        let context = TestSentinelContext::new(2, 3, 3);
+        let master_client = SentinelClient::build(
+            context.sentinels_connection_info().clone(),
+            String::from(master_name),
+            Some(context.sentinel_node_connection_info()),
+            redis::sentinel::SentinelServerType::Master,
+        )
+        .unwrap();
+
+        block_on_all(async move {
+            let mut manager = master_client.get_connection_manager().await?;
+            let info: String = redis::cmd("INFO")
+                .arg("SERVER")
+                .query_async(&mut manager)
+                .await?;
+            let old_port = parse_replication_info(&info)["tcp_port"].to_string();
+
+            let sentinel = Client::open(context.sentinels_connection_info()[0].clone())?;
+            let mut sentinel_con = sentinel.get_multiplexed_async_connection().await?;
+            let _: () = redis::cmd("SENTINEL")
+                .arg("FAILOVER")
+                .arg(master_name)
+                .query_async(&mut sentinel_con)
+                .await?;
+
+            // The manager should move to the promoted replica without being told to.
+            for _ in 0..100 {
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                let info: String = match redis::cmd("INFO")
+                    .arg("SERVER")
+                    .query_async(&mut manager)
+                    .await
+                {
+                    Ok(info) => info,
+                    Err(_) => continue,
+                };
+                if parse_replication_info(&info)["tcp_port"] != old_port {
+                    let info: String = redis::cmd("INFO")
+                        .arg("REPLICATION")
+                        .query_async(&mut manager)
+                        .await?;
+                    assert_is_master_role(info);
+                    return Ok(());
+                }
+            }
+            panic!("Connection manager didn't follow the failover");
+        })
+        .unwrap();
+    }
 }